@@ -0,0 +1,46 @@
+//! Runs a `MockBitcoind` serving a regtest template, for trying the miner
+//! without a node:
+//!
+//!     cargo run --example mock_bitcoind --features mock
+//!     BITCOIN_RPC_URL=http://127.0.0.1:18443 cargo run
+//!
+//! Every submitted block is printed, and the chain moves on to the next
+//! height so the miner gets a fresh template by long poll.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::blockdata::block::Block;
+use rust_metal_miner::mock_bitcoind::{regtest_template, MockBitcoind};
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32)
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:18443".to_string());
+    let node = MockBitcoind::bind(&addr).await?;
+    let mut height = 1;
+    let mut tip = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206".to_string();
+    node.set_template(regtest_template(height, &tip, now()));
+    println!("mock bitcoind on {}", node.url());
+
+    let mut seen = 0;
+    loop {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let submitted = node.submitted();
+        for hex in &submitted[seen..] {
+            let block: Option<Block> = hex::decode(hex).ok().and_then(|b| bitcoin::consensus::deserialize(&b).ok());
+            match block {
+                Some(block) => {
+                    println!("block {} at height {height}, {} txs", block.block_hash(), block.txdata.len());
+                    height += 1;
+                    tip = block.block_hash().to_string();
+                    node.set_template(regtest_template(height, &tip, now()));
+                }
+                None => println!("undecodable submitblock payload ({} hex chars)", hex.len()),
+            }
+        }
+        seen = submitted.len();
+    }
+}
@@ -1,20 +1,21 @@
 // src/adaptive.rs
 
+use std::time::Instant;
+
+use crate::rpc::{SubmitCounts, TemplateHealth};
+
+#[cfg(feature = "metal")]
 use std::sync::Arc;
+#[cfg(feature = "metal")]
 use tokio::sync::RwLock;
-use std::time::Instant;
-use metal::{Device, CommandQueue, ComputePipelineState, MTLSize, Buffer};
+#[cfg(feature = "metal")]
+use metal::{CommandQueue, ComputePipelineState, MTLSize, Buffer};
+#[cfg(feature = "metal")]
 use tokio::sync::mpsc::UnboundedSender;
 
 // ✅ Correct imports for constants
-use crate::constants::{
-    LANES,
-    NONCES_PER_THREAD,
-    GATE_LUT_SIZE,
-    CHAOS_LUT_SIZE,
-    DEFAULT_GATE_LUT,
-    DEFAULT_CHAOS_LUT,
-};
+#[cfg(feature = "metal")]
+use crate::constants::{LANES, NONCES_PER_THREAD};
 
 // ✅ Single, authoritative MinerMetrics definition
 #[derive(Clone, Debug)]
@@ -29,6 +30,17 @@ pub struct MinerMetrics {
     pub avg_cs: Vec<f32>,
     pub nibble_tree: [[u32; 4]; 4],
     pub hashrate_mhs: f32,
+    /// EWMA MH/s over 10 s, 1 m, 5 m and 15 m.
+    pub hashrate_windows: [f32; 4],
+    /// MH/s implied by the share difficulty found (15 m window).
+    pub effective_mhs: f32,
+    /// 10 s MH/s per lane, for backends with lanes.
+    pub lane_mhs: Vec<f32>,
+    pub shares: u64,
+    /// `submitblock` outcomes so far.
+    pub submits: SubmitCounts,
+    /// BIP23 proposal result for the work being mined.
+    pub template_health: TemplateHealth,
     pub total_hashes: u64,
     pub timestamp: Instant,
     pub last_hashrate: f32,
@@ -50,6 +62,12 @@ impl Default for MinerMetrics {
             avg_cs: vec![],
             nibble_tree: [[0; 4]; 4],
             hashrate_mhs: 0.0,
+            hashrate_windows: [0.0; 4],
+            effective_mhs: 0.0,
+            lane_mhs: vec![],
+            shares: 0,
+            submits: SubmitCounts::default(),
+            template_health: TemplateHealth::Unchecked,
             total_hashes: 0,
             timestamp: Instant::now(),
             last_hashrate: 0.0,
@@ -60,6 +78,7 @@ impl Default for MinerMetrics {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug)]
 pub enum UiMessage {
     Status(String),
@@ -67,6 +86,7 @@ pub enum UiMessage {
 }
 
 // ----------------- Adaptive Feedback Loop -----------------
+#[cfg(feature = "metal")]
 pub fn spawn_adaptive_feedback(
     lane_posteriors_bufs: Vec<Arc<RwLock<Buffer>>>,
     cs_bufs: Vec<Arc<RwLock<Buffer>>>,
@@ -135,6 +155,12 @@ pub fn spawn_adaptive_feedback(
                     avg_cs: vec![mean_cs],
                     nibble_tree: [[0; 4]; 4],
                     hashrate_mhs: 0.0,
+                    hashrate_windows: [0.0; 4],
+                    effective_mhs: 0.0,
+                    lane_mhs: vec![],
+                    shares: 0,
+                    submits: SubmitCounts::default(),
+                    template_health: TemplateHealth::Unchecked,
                     total_hashes: 0,
                     timestamp: Instant::now(),
                     last_hashrate: 0.0,
@@ -153,6 +179,51 @@ pub fn spawn_adaptive_feedback(
 }
 
 // ----------------- GPU Pruning Pass -----------------
+/// Host half of the pruning pass: relaxes the first three adaptive params
+/// toward the kernel's feedback word and derives the entanglement metric
+/// from buffer averages. `adaptive` is the 4-float `adaptive_params` buffer.
+pub fn pruning_metrics(posterior: &[f32], fwht: &[f32], cs: &[f32], adaptive: &mut [f32]) -> MinerMetrics {
+    let avg_post = posterior.iter().copied().sum::<f32>() / posterior.len() as f32;
+    let avg_fwht = fwht.iter().copied().sum::<f32>() / fwht.len() as f32;
+    let avg_cs = cs.iter().copied().sum::<f32>() / cs.len() as f32;
+    let gpu_feedback = adaptive[3];
+
+    for param in adaptive.iter_mut().take(3) {
+        *param = (*param * 0.95 + gpu_feedback * 0.05).clamp(0.01, 1.0);
+    }
+
+    let hamming_corr = (avg_post - avg_cs).abs();
+    let coherence = avg_fwht * (1.0 - hamming_corr);
+    let entanglement_coeff = 1.0 - (-coherence).exp();
+
+    MinerMetrics {
+        mask: adaptive[0],
+        prune: adaptive[1],
+        gain: adaptive[2],
+        entanglement: entanglement_coeff,
+        avg_post: vec![avg_post],
+        avg_fwht: vec![avg_fwht],
+        avg_cs: vec![avg_cs],
+        nibble_tree: [[0; 4]; 4],
+        hashrate_mhs: 0.0,
+        hashrate_windows: [0.0; 4],
+        effective_mhs: 0.0,
+        lane_mhs: vec![],
+        shares: 0,
+        submits: SubmitCounts::default(),
+        template_health: TemplateHealth::Unchecked,
+        total_hashes: 0,
+        timestamp: Instant::now(),
+        last_hashrate: 0.0,
+        last_gpu_time: 0.0,
+        last_cycle_time: 0.0,
+        last_debug_flags: vec![],
+        adaptive_factor: entanglement_coeff,
+    }
+}
+
+#[cfg(feature = "metal")]
+#[allow(clippy::too_many_arguments)]
 pub fn dispatch_pruning_pass(
     queue: &CommandQueue,
     prune_pipeline: &ComputePipelineState,
@@ -201,40 +272,39 @@ pub fn dispatch_pruning_pass(
         let nibble_slice = std::slice::from_raw_parts(nibble_probs_buf.contents() as *const f32, LANES * NONCES_PER_THREAD * 16);
         let adaptive_slice = std::slice::from_raw_parts_mut(adaptive_params_buf.contents() as *mut f32, 4);
 
-        let avg_post = posterior_slice.iter().copied().sum::<f32>() / posterior_slice.len() as f32;
-        let avg_fwht = fwht_slice.iter().copied().sum::<f32>() / fwht_slice.len() as f32;
-        let avg_cs = cs_slice.iter().copied().sum::<f32>() / cs_slice.len() as f32;
-        let gpu_feedback = adaptive_slice[3];
-
-        for i in 0..3 {
-            adaptive_slice[i] = (adaptive_slice[i] * 0.95 + gpu_feedback * 0.05).clamp(0.01, 1.0);
-        }
-
-        let hamming_corr = (avg_post - avg_cs).abs();
-        let coherence = avg_fwht * (1.0 - hamming_corr);
-        let entanglement_coeff = 1.0 - (-coherence).exp();
-
-        let metrics = MinerMetrics {
-            mask: adaptive_slice[0],
-            prune: adaptive_slice[1],
-            gain: adaptive_slice[2],
-            entanglement: entanglement_coeff,
-            avg_post: vec![avg_post],
-            avg_fwht: vec![avg_fwht],
-            avg_cs: vec![avg_cs],
-            nibble_tree: [[0; 4]; 4],
-            hashrate_mhs: 0.0,
-            total_hashes: 0,
-            timestamp: Instant::now(),
-            last_hashrate: 0.0,
-            last_gpu_time: 0.0,
-            last_cycle_time: 0.0,
-            last_debug_flags: vec![],
-            adaptive_factor: entanglement_coeff,
-        };
+        let metrics = pruning_metrics(posterior_slice, fwht_slice, cs_slice, adaptive_slice);
         let _ = metrics_tx.send(metrics);
 
         let avg_nibble = nibble_slice.iter().copied().sum::<f32>() / nibble_slice.len() as f32;
         println!("🌿 GPU pruning pass complete — avg nibble weight = {:.6}", avg_nibble);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{unit_f32, FusedKernelEmulator, KernelBuffers, KernelInputs};
+    use crate::sha_helpers::SHA256_IV;
+
+    #[test]
+    fn pruning_metrics_from_emulated_kernel() {
+        let mut input = KernelInputs::new(2, SHA256_IV);
+        input.posterior_in.fill(0xFFFF);
+        input.schedule[0] = 0xdead_beef;
+        let mut out = KernelBuffers::new(2);
+        FusedKernelEmulator::new().dispatch(&input, &mut out);
+
+        let mut adaptive = [0.5, 0.5, 0.5, 1.0];
+        let m = pruning_metrics(
+            &unit_f32(&out.posterior_out),
+            &unit_f32(&out.fwht_out),
+            &unit_f32(&out.cs_out),
+            &mut adaptive,
+        );
+
+        assert_eq!(adaptive, [0.525, 0.525, 0.525, 1.0]);
+        assert_eq!(m.mask, 0.525);
+        assert!(m.avg_fwht[0] > 0.0 && m.avg_fwht[0] < 1.0);
+        assert!((0.0..1.0).contains(&m.entanglement));
+    }
+}
@@ -0,0 +1,122 @@
+// src/backend/cpu.rs
+//! Single-threaded CPU reference backend.
+//!
+//! Deliberately plain: one `compress256` for the second header block and a
+//! full `Sha256::digest` for the outer hash, per nonce. Faster backends are
+//! checked against `sha256d_from_midstate`.
+
+use std::time::Instant;
+
+use sha2::{compress256, Digest, Sha256};
+
+use super::{hash_meets_target, BackendError, HashBackend, ScanResult, Telemetry, WorkUnit};
+
+/// Header length in bits, as it appears in the SHA-256 padding of block two.
+const HEADER_BITS: u64 = 80 * 8;
+
+/// Second 64-byte block of the header hash: tail, nonce and padding.
+pub fn header_tail_block(tail: &[u32; 3], nonce: u32) -> [u8; 64] {
+    let mut block = [0u8; 64];
+    for (i, w) in tail.iter().enumerate() {
+        block[i * 4..i * 4 + 4].copy_from_slice(&w.to_le_bytes());
+    }
+    block[12..16].copy_from_slice(&nonce.to_le_bytes());
+    block[16] = 0x80;
+    block[56..64].copy_from_slice(&HEADER_BITS.to_be_bytes());
+    block
+}
+
+/// Double SHA-256 of a header given its first-block midstate.
+pub fn sha256d_from_midstate(midstate: &[u32; 8], tail: &[u32; 3], nonce: u32) -> [u8; 32] {
+    let mut state = *midstate;
+    let block = header_tail_block(tail, nonce);
+    compress256(&mut state, &[block.into()]);
+
+    let mut first = [0u8; 32];
+    for (i, w) in state.iter().enumerate() {
+        first[i * 4..i * 4 + 4].copy_from_slice(&w.to_be_bytes());
+    }
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&Sha256::digest(first));
+    out
+}
+
+pub struct CpuBackend {
+    batch_size: u32,
+}
+
+impl CpuBackend {
+    pub fn new() -> Self {
+        Self { batch_size: 1 << 16 }
+    }
+}
+
+impl Default for CpuBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashBackend for CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    fn scan(&mut self, work: &WorkUnit) -> Result<ScanResult, BackendError> {
+        let start = Instant::now();
+        let nonces = work
+            .nonces()
+            .filter(|&n| hash_meets_target(&sha256d_from_midstate(&work.midstate, &work.tail, n), &work.target))
+            .collect();
+        Ok(ScanResult {
+            nonces,
+            telemetry: Telemetry {
+                hashes: work.nonce_count as u64,
+                elapsed: start.elapsed(),
+                avg_post: vec![],
+                // One thread, one lane.
+                lane_hashes: vec![work.nonce_count as u64],
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_helpers::{double_sha256_bytes, target_from_bits};
+
+    fn sample_header() -> [u8; 80] {
+        let mut header = [0u8; 80];
+        for (i, b) in header.iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(37).wrapping_add(11);
+        }
+        header
+    }
+
+    #[test]
+    fn midstate_hash_matches_full_header_hash() {
+        let mut header = sample_header();
+        let work = WorkUnit::from_header_bytes(&header, 0, 1, [0xff; 32]);
+        for nonce in [0u32, 1, 0xdead_beef, u32::MAX] {
+            header[76..80].copy_from_slice(&nonce.to_le_bytes());
+            assert_eq!(work.hash(nonce), double_sha256_bytes(&header));
+        }
+    }
+
+    #[test]
+    fn scan_reports_only_nonces_under_target() {
+        // bits 0x207fffff: roughly every other hash qualifies.
+        let work = WorkUnit::from_header_bytes(&sample_header(), 100, 64, target_from_bits(0x207f_ffff));
+        let result = CpuBackend::new().scan(&work).unwrap();
+        assert_eq!(result.telemetry.hashes, 64);
+        assert!(!result.nonces.is_empty());
+        for nonce in work.nonces() {
+            assert_eq!(result.nonces.contains(&nonce), work.verify(nonce));
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// src/backend/metal.rs
+//! Metal backend driving the `fused_sha256d_fwht_cs` pipeline.
+//!
+//! One thread per (lane, nibble), `LANES * NIBBLES` in a 1-D grid, each
+//! looping over `KERNEL_NONCES_PER_THREAD` cells laid out as
+//! `emulator::cell_index`. Lane `l` starts at `work.nonce_start + l *
+//! LANE_SPAN` and cell (lane, nibble, iter) stands for nonce
+//! `lane_start + nibble * KERNEL_NONCES_PER_THREAD + iter`. Cells flagged in
+//! `submit_mask` come back as candidates and are re-verified on the host.
+
+use std::time::Instant;
+
+use metal::*;
+
+use super::{cpu::header_tail_block, BackendError, HashBackend, ScanResult, Telemetry, WorkUnit};
+use crate::constants::{
+    CHAOS_LUT_SIZE, DEFAULT_CHAOS_LUT, DEFAULT_GATE_LUT, GATE_LUT_SIZE, LANES, MITM_STATE_U32_WORDS, NIBBLES,
+};
+use crate::emulator::{cell_position, KernelBuffers, KernelInputs, KERNEL_NONCES_PER_THREAD};
+use crate::sha_helpers::{aligned_f32_buffer, aligned_u32_buffer, aligned_ushort_buffer};
+
+const TOTAL_THREADS: usize = LANES * NIBBLES;
+/// Nonces covered by one lane.
+const LANE_SPAN: usize = NIBBLES * KERNEL_NONCES_PER_THREAD;
+const CELLS: usize = LANES * LANE_SPAN;
+
+pub struct MetalBackend {
+    queue: CommandQueue,
+    pipeline: ComputePipelineState,
+    active_buffer: bool,
+    digest_buf_a: Buffer,
+    digest_buf_b: Buffer,
+    posterior_buf_a: Buffer,
+    posterior_buf_b: Buffer,
+    fwht_buf_a: Buffer,
+    fwht_buf_b: Buffer,
+    cs_buf_a: Buffer,
+    cs_buf_b: Buffer,
+    nibble_probs_buf: Buffer,
+    midstate_buf: Buffer,
+    schedule_buf: Buffer,
+    start_nonce_buf: Buffer,
+    adaptive_params_buf: Buffer,
+    mitm_states_buf: Buffer,
+    adaptive_feedback_buf: Buffer,
+    debug_flags_buf: Buffer,
+    submit_mask_buf: Buffer,
+    shannon_entropy_buf: Buffer,
+    hamming_buf: Buffer,
+    monte_buf: Buffer,
+    digest_out_len_buf: Buffer,
+    nibble_probs_len_buf: Buffer,
+    global_lane_min_int_buf: Buffer,
+    gate_lut_buf: Buffer,
+    chaos_lut_buf: Buffer,
+}
+
+impl MetalBackend {
+    pub fn new() -> Result<Self, BackendError> {
+        let device = Device::system_default()
+            .ok_or_else(|| BackendError::Unavailable("no Metal device found".into()))?;
+        let queue = device.new_command_queue();
+
+        let metallib_path = format!("{}/shaders/kernels.metallib", env!("CARGO_MANIFEST_DIR"));
+        let library = device
+            .new_library_with_file(&metallib_path)
+            .map_err(|e| BackendError::Init(format!("failed to load {metallib_path}: {e}")))?;
+        let fused_fn = library
+            .get_function("fused_sha256d_fwht_cs", None)
+            .map_err(|e| BackendError::Init(format!("kernel not found: {e}")))?;
+        let pipeline = device
+            .new_compute_pipeline_state_with_function(&fused_fn)
+            .map_err(|e| BackendError::Init(format!("failed to create compute pipeline: {e}")))?;
+
+        let gate_lut_buf = aligned_f32_buffer(&device, GATE_LUT_SIZE, true);
+        let chaos_lut_buf = aligned_f32_buffer(&device, CHAOS_LUT_SIZE, true);
+        unsafe {
+            let gate_ptr = gate_lut_buf.contents() as *mut f32;
+            let chaos_ptr = chaos_lut_buf.contents() as *mut f32;
+            gate_ptr.copy_from_nonoverlapping(DEFAULT_GATE_LUT.as_ptr(), GATE_LUT_SIZE);
+            chaos_ptr.copy_from_nonoverlapping(DEFAULT_CHAOS_LUT.as_ptr(), CHAOS_LUT_SIZE);
+        }
+
+        Ok(Self {
+            active_buffer: true,
+            digest_buf_a: aligned_u32_buffer(&device, CELLS * 8, false),
+            digest_buf_b: aligned_u32_buffer(&device, CELLS * 8, false),
+            posterior_buf_a: aligned_ushort_buffer(&device, CELLS, false),
+            posterior_buf_b: aligned_ushort_buffer(&device, CELLS, false),
+            // 16 nibble outputs per cell, as `KernelBuffers::new` sizes them.
+            fwht_buf_a: aligned_ushort_buffer(&device, CELLS * 16, false),
+            fwht_buf_b: aligned_ushort_buffer(&device, CELLS * 16, false),
+            cs_buf_a: aligned_ushort_buffer(&device, CELLS * 16, false),
+            cs_buf_b: aligned_ushort_buffer(&device, CELLS * 16, false),
+            nibble_probs_buf: aligned_ushort_buffer(&device, CELLS * 16, false),
+            midstate_buf: aligned_u32_buffer(&device, LANES * 8, false),
+            schedule_buf: aligned_u32_buffer(&device, LANES * 64, false),
+            start_nonce_buf: aligned_u32_buffer(&device, LANES, false),
+            adaptive_params_buf: aligned_u32_buffer(&device, 4, false),
+            mitm_states_buf: aligned_u32_buffer(&device, CELLS * MITM_STATE_U32_WORDS, false),
+            adaptive_feedback_buf: aligned_ushort_buffer(&device, CELLS, false),
+            debug_flags_buf: aligned_u32_buffer(&device, CELLS, false),
+            submit_mask_buf: aligned_u32_buffer(&device, CELLS, false),
+            shannon_entropy_buf: aligned_ushort_buffer(&device, CELLS, false),
+            hamming_buf: aligned_ushort_buffer(&device, CELLS, false),
+            monte_buf: aligned_ushort_buffer(&device, CELLS, false),
+            digest_out_len_buf: aligned_u32_buffer(&device, 1, false),
+            nibble_probs_len_buf: aligned_u32_buffer(&device, 1, false),
+            global_lane_min_int_buf: aligned_u32_buffer(&device, 1, false),
+            gate_lut_buf,
+            chaos_lut_buf,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// Upload midstate, expanded second-block schedule and per-lane nonce bases.
+    fn upload_work(&self, work: &WorkUnit) {
+        unsafe {
+            let mid_ptr = self.midstate_buf.contents() as *mut u32;
+            let sched_ptr = self.schedule_buf.contents() as *mut u32;
+            let start_ptr = self.start_nonce_buf.contents() as *mut u32;
+            for lane in 0..LANES {
+                let lane_start = lane_start(work, lane);
+                let schedule = expand_schedule(&header_tail_block(&work.tail, lane_start));
+                std::ptr::copy_nonoverlapping(work.midstate.as_ptr(), mid_ptr.add(lane * 8), 8);
+                std::ptr::copy_nonoverlapping(schedule.as_ptr(), sched_ptr.add(lane * 64), 64);
+                *start_ptr.add(lane) = lane_start;
+            }
+        }
+    }
+
+    /// The inputs the last `scan` bound, for `FusedKernelEmulator::dispatch`.
+    /// Buffer 19 (`lane_count`) is bound to the chaos LUT, so its first word
+    /// is what the kernel saw.
+    pub fn kernel_inputs(&self) -> KernelInputs {
+        let last = !self.active_buffer;
+        let posterior_in = if last { &self.posterior_buf_b } else { &self.posterior_buf_a };
+        unsafe {
+            let mut adaptive_params = [0u32; 4];
+            adaptive_params.copy_from_slice(&read_buffer(&self.adaptive_params_buf, 4));
+            KernelInputs {
+                midstates: read_buffer(&self.midstate_buf, LANES * 8),
+                schedule: read_buffer(&self.schedule_buf, LANES * 64),
+                nonce_start: read_buffer(&self.start_nonce_buf, LANES),
+                posterior_in: read_buffer(posterior_in, CELLS),
+                adaptive_params,
+                lane_count: read_buffer::<u32>(&self.chaos_lut_buf, 1)[0],
+            }
+        }
+    }
+
+    /// Copy of every buffer the last `scan` wrote, in the emulator's layout,
+    /// to diff against `FusedKernelEmulator::dispatch` over `kernel_inputs`.
+    pub fn read_back(&self) -> KernelBuffers {
+        let last = !self.active_buffer;
+        let (digest, posterior_out) =
+            if last { (&self.digest_buf_a, &self.posterior_buf_a) } else { (&self.digest_buf_b, &self.posterior_buf_b) };
+        let (fwht, cs) = if last { (&self.fwht_buf_b, &self.cs_buf_b) } else { (&self.fwht_buf_a, &self.cs_buf_a) };
+        unsafe {
+            KernelBuffers {
+                digest_out: read_buffer(digest, CELLS * 8),
+                posterior_out: read_buffer(posterior_out, CELLS),
+                mitm_states: read_buffer(&self.mitm_states_buf, CELLS * MITM_STATE_U32_WORDS),
+                fwht_out: read_buffer(fwht, CELLS * 16),
+                cs_out: read_buffer(cs, CELLS * 16),
+                nibble_probs: read_buffer(&self.nibble_probs_buf, CELLS * 16),
+                debug_flags: read_buffer(&self.debug_flags_buf, CELLS),
+                submit_mask: read_buffer(&self.submit_mask_buf, CELLS),
+                adaptive_feedback: read_buffer(&self.adaptive_feedback_buf, CELLS),
+                shannon_entropy: read_buffer(&self.shannon_entropy_buf, CELLS),
+                hamming: read_buffer(&self.hamming_buf, CELLS),
+                monte: read_buffer(&self.monte_buf, CELLS),
+                global_lane_min: read_buffer::<u32>(&self.global_lane_min_int_buf, 1)[0],
+            }
+        }
+    }
+}
+
+/// Copy the first `len` elements of a shared buffer.
+///
+/// # Safety
+/// The buffer must hold at least `len` `T`s and no dispatch may be writing it.
+unsafe fn read_buffer<T: Copy>(buf: &Buffer, len: usize) -> Vec<T> {
+    std::slice::from_raw_parts(buf.contents() as *const T, len).to_vec()
+}
+
+fn lane_start(work: &WorkUnit, lane: usize) -> u32 {
+    work.nonce_start.wrapping_add((lane * LANE_SPAN) as u32)
+}
+
+/// Nonce a `submit_mask` index stands for.
+fn cell_nonce(work: &WorkUnit, idx: usize) -> u32 {
+    let (lane, nibble, iter) = cell_position(idx);
+    lane_start(work, lane).wrapping_add((nibble * KERNEL_NONCES_PER_THREAD + iter) as u32)
+}
+
+fn expand_schedule(block: &[u8; 64]) -> [u32; 64] {
+    let mut w = [0u32; 64];
+    for (i, chunk) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+    }
+    w
+}
+
+impl HashBackend for MetalBackend {
+    fn name(&self) -> &'static str {
+        "metal"
+    }
+
+    fn batch_size(&self) -> u32 {
+        CELLS as u32
+    }
+
+    fn scan(&mut self, work: &WorkUnit) -> Result<ScanResult, BackendError> {
+        let start = Instant::now();
+        self.upload_work(work);
+
+        let active = self.active_buffer;
+        let cmd_buf = self.queue.new_command_buffer();
+        let encoder = cmd_buf.new_compute_command_encoder();
+        encoder.set_compute_pipeline_state(&self.pipeline);
+
+        encoder.set_buffer(0, Some(&self.midstate_buf), 0);
+        encoder.set_buffer(1, Some(&self.schedule_buf), 0);
+        encoder.set_buffer(2, Some(&self.start_nonce_buf), 0);
+        encoder.set_buffer(3, Some(if active { &self.digest_buf_a } else { &self.digest_buf_b }), 0);
+        encoder.set_buffer(4, Some(if active { &self.posterior_buf_b } else { &self.posterior_buf_a }), 0);
+        encoder.set_buffer(5, Some(&self.mitm_states_buf), 0);
+        encoder.set_buffer(6, Some(if active { &self.fwht_buf_b } else { &self.fwht_buf_a }), 0);
+        encoder.set_buffer(7, Some(if active { &self.cs_buf_b } else { &self.cs_buf_a }), 0);
+        encoder.set_buffer(8, Some(&self.nibble_probs_buf), 0);
+        encoder.set_buffer(9, Some(&self.adaptive_params_buf), 0);
+        encoder.set_buffer(10, Some(&self.debug_flags_buf), 0);
+        encoder.set_buffer(11, Some(&self.digest_out_len_buf), 0);
+        encoder.set_buffer(12, Some(&self.nibble_probs_len_buf), 0);
+        encoder.set_buffer(13, Some(&self.adaptive_feedback_buf), 0);
+        encoder.set_buffer(14, Some(&self.shannon_entropy_buf), 0);
+        encoder.set_buffer(15, Some(&self.submit_mask_buf), 0);
+        encoder.set_buffer(16, Some(&self.hamming_buf), 0);
+        encoder.set_buffer(17, Some(&self.monte_buf), 0);
+        encoder.set_buffer(18, Some(&self.gate_lut_buf), 0);
+        encoder.set_buffer(19, Some(&self.chaos_lut_buf), 0);
+        encoder.set_buffer(20, Some(if active { &self.posterior_buf_a } else { &self.posterior_buf_b }), 0);
+        encoder.set_buffer(21, Some(&self.global_lane_min_int_buf), 0);
+
+        let tg_mem_size = (LANES * std::mem::size_of::<u16>()) as u64;
+        encoder.set_threadgroup_memory_length(0, tg_mem_size);
+
+        let threads_per_group = MTLSize {
+            width: self.pipeline.thread_execution_width(),
+            height: 1,
+            depth: 1,
+        };
+        // The kernel only reads `thread_position_in_grid.x`.
+        let grid_size = MTLSize {
+            width: TOTAL_THREADS as u64,
+            height: 1,
+            depth: 1,
+        };
+
+        encoder.dispatch_threads(grid_size, threads_per_group);
+        encoder.end_encoding();
+        cmd_buf.commit();
+        cmd_buf.wait_until_completed();
+
+        if cmd_buf.status() != MTLCommandBufferStatus::Completed {
+            return Err(BackendError::Dispatch(format!("command buffer ended in {:?}", cmd_buf.status())));
+        }
+
+        let (nonces, avg_post) = unsafe {
+            let submit_mask = std::slice::from_raw_parts(self.submit_mask_buf.contents() as *const u32, CELLS);
+            let posterior_out = if active { &self.posterior_buf_a } else { &self.posterior_buf_b };
+            let posterior = std::slice::from_raw_parts(posterior_out.contents() as *const u16, CELLS);
+
+            let nonces: Vec<u32> = submit_mask
+                .iter()
+                .enumerate()
+                .filter(|(_, &flag)| flag != 0)
+                .map(|(idx, _)| cell_nonce(work, idx))
+                .collect();
+            let avg_post = posterior.iter().map(|&v| v as f32).sum::<f32>() / posterior.len() as f32;
+            (nonces, avg_post)
+        };
+
+        self.active_buffer = !self.active_buffer;
+
+        Ok(ScanResult {
+            nonces,
+            telemetry: Telemetry {
+                hashes: CELLS as u64,
+                elapsed: start.elapsed(),
+                avg_post: vec![avg_post],
+                lane_hashes: vec![LANE_SPAN as u64; LANES],
+            },
+        })
+    }
+}
@@ -0,0 +1,233 @@
+// src/backend/mod.rs
+//! Hashing backends.
+//!
+//! The mining loop never talks to a device directly. It builds a `WorkUnit`
+//! (SHA-256 midstate of the first 64 header bytes, the three tail words and a
+//! nonce range) and hands it to whatever `HashBackend` was selected at
+//! startup. The backend returns the nonces it believes meet the target and
+//! some telemetry about the scan.
+//!
+//! Backends are allowed to over-report: every candidate is re-checked on the
+//! CPU with `WorkUnit::verify` before anything is submitted.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::header::HeaderWork;
+use crate::sha_helpers::{hash_le_target, target_from_bits};
+
+pub mod cpu;
+#[cfg(feature = "metal")]
+pub mod metal;
+pub mod parallel;
+pub mod sha_ni;
+pub mod simd;
+
+pub use cpu::CpuBackend;
+pub use parallel::ParallelCpuBackend;
+pub use sha_ni::ShaNiBackend;
+pub use simd::{SimdBackend, SimdVariant};
+#[cfg(feature = "metal")]
+pub use self::metal::MetalBackend;
+
+pub use crate::header::NONCE_OFFSET;
+
+// ----------------- Work Unit -----------------
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkUnit {
+    /// SHA-256 state after compressing header bytes 0..64.
+    pub midstate: [u32; 8],
+    /// Header bytes 64..76 (merkle root tail, time, bits), read little-endian.
+    pub tail: [u32; 3],
+    pub nonce_start: u32,
+    pub nonce_count: u32,
+    /// Big-endian 256-bit target a hash must not exceed.
+    pub target: [u8; 32],
+}
+
+impl WorkUnit {
+    /// Build a work unit from a serialized 80-byte header. The nonce field of
+    /// the header is ignored.
+    pub fn from_header_bytes(header: &[u8; 80], nonce_start: u32, nonce_count: u32, target: [u8; 32]) -> Self {
+        let work = HeaderWork::from_bytes(header);
+        Self { target, ..work.work_unit(nonce_start, nonce_count) }
+    }
+
+    /// Same as `from_header_bytes` for the `[u32; 19]` word layout produced by
+    /// `prepare_block_header`; the target comes from the header's bits word.
+    pub fn from_header_words(header_words: &[u32; 19], nonce_start: u32, nonce_count: u32) -> Self {
+        HeaderWork::from_words(header_words).work_unit(nonce_start, nonce_count)
+    }
+
+    /// Nonces covered by this unit. Wraps at `u32::MAX`.
+    pub fn nonces(&self) -> impl Iterator<Item = u32> {
+        let start = self.nonce_start;
+        (0..self.nonce_count).map(move |i| start.wrapping_add(i))
+    }
+
+    /// Double SHA-256 of the header with `nonce`, in internal byte order.
+    pub fn hash(&self, nonce: u32) -> [u8; 32] {
+        cpu::sha256d_from_midstate(&self.midstate, &self.tail, nonce)
+    }
+
+    /// CPU re-check of a backend candidate against the unit's target.
+    pub fn verify(&self, nonce: u32) -> bool {
+        hash_meets_target(&self.hash(nonce), &self.target)
+    }
+}
+
+/// Compare a digest in internal byte order against a big-endian target.
+pub fn hash_meets_target(digest: &[u8; 32], target_be: &[u8; 32]) -> bool {
+    let mut hash_be = *digest;
+    hash_be.reverse();
+    hash_le_target(&hash_be, target_be)
+}
+
+// ----------------- Scan Results -----------------
+#[derive(Clone, Debug, Default)]
+pub struct Telemetry {
+    /// Hashes actually computed during the scan.
+    pub hashes: u64,
+    pub elapsed: Duration,
+    /// Mean posterior per lane, for backends that produce one.
+    pub avg_post: Vec<f32>,
+    /// Hashes computed by each lane, for backends with lanes.
+    pub lane_hashes: Vec<u64>,
+}
+
+/// Hashes per rayon worker during one scan: each chunk credits the worker
+/// that ran it. Becomes `Telemetry::lane_hashes` for the pooled backends.
+pub(crate) struct WorkerHashes(Vec<AtomicU64>);
+
+impl WorkerHashes {
+    pub(crate) fn new() -> Self {
+        Self((0..rayon::current_num_threads().max(1)).map(|_| AtomicU64::new(0)).collect())
+    }
+
+    pub(crate) fn add(&self, hashes: u64) {
+        let worker = rayon::current_thread_index().unwrap_or(0).min(self.0.len() - 1);
+        self.0[worker].fetch_add(hashes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn into_counts(self) -> Vec<u64> {
+        self.0.into_iter().map(AtomicU64::into_inner).collect()
+    }
+}
+
+impl Telemetry {
+    pub fn hashes_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.hashes as f64 / secs } else { 0.0 }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ScanResult {
+    /// Candidate nonces, not yet verified on the CPU.
+    pub nonces: Vec<u32>,
+    pub telemetry: Telemetry,
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    Unavailable(String),
+    Init(String),
+    Dispatch(String),
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendError::Unavailable(msg) => write!(f, "backend unavailable: {msg}"),
+            BackendError::Init(msg) => write!(f, "backend init failed: {msg}"),
+            BackendError::Dispatch(msg) => write!(f, "backend dispatch failed: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+// ----------------- Backend Trait -----------------
+pub trait HashBackend {
+    fn name(&self) -> &'static str;
+
+    /// Nonces the backend likes to receive per `scan` call.
+    fn batch_size(&self) -> u32;
+
+    /// Hash every nonce in `work` and report candidates.
+    fn scan(&mut self, work: &WorkUnit) -> Result<ScanResult, BackendError>;
+}
+
+/// Names accepted by `select_backend`, in order of preference.
+pub fn available_backends() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    if cfg!(feature = "metal") {
+        names.push("metal");
+    }
+    if sha_ni::sha_ni_supported() {
+        names.push("sha-ni");
+    }
+    names.push("simd");
+    names.push("rayon");
+    names.push("cpu");
+    names
+}
+
+/// Like `available_backends`, but with `simd` expanded into every variant
+/// the CPU supports so each gets its own throughput line.
+pub fn bench_backends() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    for name in available_backends() {
+        if name == "simd" {
+            names.extend(SimdVariant::supported().into_iter().rev().map(SimdVariant::name));
+        } else {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Build a backend by name; `None` picks the first available one.
+pub fn select_backend(name: Option<&str>) -> Result<Box<dyn HashBackend>, BackendError> {
+    let name = name.unwrap_or_else(|| available_backends()[0]);
+    match name {
+        "cpu" => Ok(Box::new(CpuBackend::new())),
+        "rayon" => Ok(Box::new(ParallelCpuBackend::new())),
+        "simd" => Ok(Box::new(SimdBackend::new())),
+        "sha-ni" => Ok(Box::new(ShaNiBackend::new()?)),
+        #[cfg(feature = "metal")]
+        "metal" => Ok(Box::new(MetalBackend::new()?)),
+        other => match SimdVariant::from_name(other) {
+            Some(variant) => Ok(Box::new(SimdBackend::with_variant(variant)?)),
+            None => Err(BackendError::Unavailable(format!(
+                "'{other}' (available: {})",
+                available_backends().join(", ")
+            ))),
+        },
+    }
+}
+
+/// Scan at least `nonce_count` nonces of a fixed synthetic header, in the
+/// backend's own batch size, and report the combined throughput and hits
+/// (about one in 65536 nonces qualifies). Used by `--bench` to compare backends on
+/// the same work.
+pub fn benchmark(backend: &mut dyn HashBackend, nonce_count: u32) -> Result<ScanResult, BackendError> {
+    let mut header = [0u8; 80];
+    for (i, b) in header.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    let target = target_from_bits(0x1f00_ffff);
+    let batch = backend.batch_size().max(1);
+
+    let mut total = ScanResult::default();
+    let mut nonce_start = 0u32;
+    while total.telemetry.hashes < nonce_count as u64 {
+        let work = WorkUnit::from_header_bytes(&header, nonce_start, batch, target);
+        let result = backend.scan(&work)?;
+        total.nonces.extend(result.nonces.into_iter().filter(|&n| work.verify(n)));
+        total.telemetry.hashes += result.telemetry.hashes;
+        total.telemetry.elapsed += result.telemetry.elapsed;
+        nonce_start = nonce_start.wrapping_add(batch);
+    }
+    Ok(total)
+}
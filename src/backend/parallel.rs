@@ -0,0 +1,136 @@
+// src/backend/parallel.rs
+//! Multi-threaded CPU backend on the rayon global pool.
+//!
+//! The work unit already carries the first-block midstate, so per nonce we
+//! only compress the second header block (tail + nonce + padding) and the
+//! single padded block of the outer hash. Both blocks are built once per
+//! scan; the hot loop only patches the nonce and the inner digest into them.
+
+use std::time::Instant;
+
+use rayon::prelude::*;
+use sha2::compress256;
+
+use super::cpu::header_tail_block;
+use super::{hash_meets_target, BackendError, HashBackend, ScanResult, Telemetry, WorkUnit, WorkerHashes};
+use crate::sha_helpers::SHA256_IV;
+
+/// Nonces handed to a rayon task at a time; keeps scheduling overhead low.
+const CHUNK: u32 = 4096;
+
+/// Padding block for hashing a 32-byte digest: 0x80 terminator and a
+/// 256-bit length. The first 32 bytes are overwritten per nonce.
+fn outer_block_template() -> [u8; 64] {
+    let mut block = [0u8; 64];
+    block[32] = 0x80;
+    block[56..64].copy_from_slice(&256u64.to_be_bytes());
+    block
+}
+
+/// SHA-256d with both message blocks supplied by the caller as scratch.
+#[inline]
+pub(crate) fn sha256d_scratch(midstate: &[u32; 8], inner: &mut [u8; 64], outer: &mut [u8; 64], nonce: u32) -> [u8; 32] {
+    inner[12..16].copy_from_slice(&nonce.to_le_bytes());
+    let mut state = *midstate;
+    compress256(&mut state, std::slice::from_ref((&*inner).into()));
+
+    for (i, w) in state.iter().enumerate() {
+        outer[i * 4..i * 4 + 4].copy_from_slice(&w.to_be_bytes());
+    }
+    let mut state = SHA256_IV;
+    compress256(&mut state, std::slice::from_ref((&*outer).into()));
+
+    let mut out = [0u8; 32];
+    for (i, w) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&w.to_be_bytes());
+    }
+    out
+}
+
+pub struct ParallelCpuBackend {
+    batch_size: u32,
+}
+
+impl ParallelCpuBackend {
+    pub fn new() -> Self {
+        let threads = rayon::current_num_threads() as u32;
+        Self { batch_size: threads.max(1) * (1 << 18) }
+    }
+}
+
+impl Default for ParallelCpuBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashBackend for ParallelCpuBackend {
+    fn name(&self) -> &'static str {
+        "rayon"
+    }
+
+    fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    fn scan(&mut self, work: &WorkUnit) -> Result<ScanResult, BackendError> {
+        let start = Instant::now();
+        let inner_template = header_tail_block(&work.tail, 0);
+        let outer_template = outer_block_template();
+
+        let workers = WorkerHashes::new();
+
+        let mut nonces: Vec<u32> = (0..work.nonce_count.div_ceil(CHUNK))
+            .into_par_iter()
+            .flat_map_iter(|c| {
+                let offset = c * CHUNK;
+                let count = (work.nonce_count - offset).min(CHUNK);
+                workers.add(count as u64);
+                let (mut inner, mut outer) = (inner_template, outer_template);
+                (offset..offset + count).filter_map(move |i| {
+                    let nonce = work.nonce_start.wrapping_add(i);
+                    let digest = sha256d_scratch(&work.midstate, &mut inner, &mut outer, nonce);
+                    hash_meets_target(&digest, &work.target).then_some(nonce)
+                })
+            })
+            .collect();
+        nonces.sort_unstable();
+
+        Ok(ScanResult {
+            nonces,
+            telemetry: Telemetry {
+                hashes: work.nonce_count as u64,
+                elapsed: start.elapsed(),
+                avg_post: vec![],
+                lane_hashes: workers.into_counts(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::CpuBackend;
+    use crate::sha_helpers::target_from_bits;
+
+    #[test]
+    fn matches_reference_backend() {
+        let mut header = [0u8; 80];
+        for (i, b) in header.iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(13) ^ 0x5a;
+        }
+        // Range straddles u32::MAX to exercise wrap-around.
+        let work = WorkUnit::from_header_bytes(&header, u32::MAX - 5000, 20_000, target_from_bits(0x2000_ffff));
+
+        let reference = CpuBackend::new().scan(&work).unwrap();
+        let parallel = ParallelCpuBackend::new().scan(&work).unwrap();
+
+        let mut expected = reference.nonces.clone();
+        expected.sort_unstable();
+        assert!(!expected.is_empty());
+        assert_eq!(parallel.nonces, expected);
+        assert_eq!(parallel.telemetry.hashes, 20_000);
+        assert_eq!(parallel.telemetry.lane_hashes.iter().sum::<u64>(), 20_000);
+    }
+}
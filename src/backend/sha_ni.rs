@@ -0,0 +1,289 @@
+// src/backend/sha_ni.rs
+//! CPU backend on the x86 SHA extensions (SHA-NI).
+//!
+//! The midstate and the SHA-256 IV are packed into the ABEF/CDGH register
+//! layout the `sha256rnds2` instruction wants once per scan; per nonce only
+//! the second header block and the outer hash are run. The outer state is
+//! unpacked and its last word (the most significant bytes of the hash) is
+//! compared first, so almost every nonce is rejected without building a
+//! digest.
+//!
+//! On CPUs without the extension, or outside x86, the backend runs the
+//! `compress256` path from `parallel.rs` instead. Either way `new` runs a
+//! known-answer check against `double_sha256_bytes` before returning.
+
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use super::cpu::header_tail_block;
+use super::parallel::sha256d_scratch;
+use super::{hash_meets_target, BackendError, HashBackend, ScanResult, Telemetry, WorkUnit, WorkerHashes};
+use crate::sha_helpers::double_sha256_bytes;
+
+/// Nonces per rayon task.
+const CHUNK: u32 = 1 << 14;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod ni {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use crate::backend::WorkUnit;
+    use crate::sha_helpers::{SHA256_IV, SHA256_K};
+
+    /// State packed as (ABEF, CDGH).
+    type Packed = (__m128i, __m128i);
+
+    #[inline]
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    unsafe fn pack(state: &[u32; 8]) -> Packed {
+        let abcd = _mm_loadu_si128(state.as_ptr() as *const __m128i);
+        let efgh = _mm_loadu_si128(state.as_ptr().add(4) as *const __m128i);
+        let cdab = _mm_shuffle_epi32(abcd, 0xB1);
+        let hgfe = _mm_shuffle_epi32(efgh, 0x1B);
+        (_mm_alignr_epi8(cdab, hgfe, 8), _mm_blend_epi16(hgfe, cdab, 0xF0))
+    }
+
+    #[inline]
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    unsafe fn unpack((abef, cdgh): Packed) -> (__m128i, __m128i) {
+        let feba = _mm_shuffle_epi32(abef, 0x1B);
+        let dchg = _mm_shuffle_epi32(cdgh, 0xB1);
+        (_mm_blend_epi16(feba, dchg, 0xF0), _mm_alignr_epi8(dchg, feba, 8))
+    }
+
+    /// One compression of a 16-word block (already big-endian words).
+    #[inline]
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    unsafe fn compress((abef_in, cdgh_in): Packed, block: [__m128i; 4]) -> Packed {
+        let mut m = block;
+        let (mut abef, mut cdgh) = (abef_in, cdgh_in);
+        for g in 0..16 {
+            if g >= 4 {
+                let t = _mm_add_epi32(
+                    _mm_sha256msg1_epu32(m[g % 4], m[(g + 1) % 4]),
+                    _mm_alignr_epi8(m[(g + 3) % 4], m[(g + 2) % 4], 4),
+                );
+                m[g % 4] = _mm_sha256msg2_epu32(t, m[(g + 3) % 4]);
+            }
+            let k = _mm_loadu_si128(SHA256_K.as_ptr().add(g * 4) as *const __m128i);
+            let msg = _mm_add_epi32(m[g % 4], k);
+            cdgh = _mm_sha256rnds2_epu32(cdgh, abef, msg);
+            abef = _mm_sha256rnds2_epu32(abef, cdgh, _mm_shuffle_epi32(msg, 0x0E));
+        }
+        (_mm_add_epi32(abef, abef_in), _mm_add_epi32(cdgh, cdgh_in))
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    unsafe fn words(a: &[u32; 4]) -> __m128i {
+        _mm_loadu_si128(a.as_ptr() as *const __m128i)
+    }
+
+    #[inline]
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    unsafe fn sha256d_state(mid: Packed, iv: Packed, tail: &[u32; 3], nonce: u32) -> [u32; 8] {
+        let inner_block = [
+            words(&[tail[0].swap_bytes(), tail[1].swap_bytes(), tail[2].swap_bytes(), nonce.swap_bytes()]),
+            words(&[0x8000_0000, 0, 0, 0]),
+            _mm_setzero_si128(),
+            words(&[0, 0, 0, 80 * 8]),
+        ];
+        let (abcd, efgh) = unpack(compress(mid, inner_block));
+        let outer_block = [abcd, efgh, words(&[0x8000_0000, 0, 0, 0]), words(&[0, 0, 0, 32 * 8])];
+        let (abcd, efgh) = unpack(compress(iv, outer_block));
+
+        let mut out = [0u32; 8];
+        _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, abcd);
+        _mm_storeu_si128(out.as_mut_ptr().add(4) as *mut __m128i, efgh);
+        out
+    }
+
+    /// Final SHA-256d state words for one nonce.
+    ///
+    /// # Safety
+    /// The CPU must support the `sha`, `ssse3` and `sse4.1` features.
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    pub unsafe fn sha256d(work: &WorkUnit, nonce: u32) -> [u32; 8] {
+        sha256d_state(pack(&work.midstate), pack(&SHA256_IV), &work.tail, nonce)
+    }
+
+    /// Scan `count` nonces from `work.nonce_start + offset`.
+    ///
+    /// # Safety
+    /// The CPU must support the `sha`, `ssse3` and `sse4.1` features.
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    pub unsafe fn scan_range(work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+        let top_target = u32::from_be_bytes([work.target[0], work.target[1], work.target[2], work.target[3]]);
+        let mid = pack(&work.midstate);
+        let iv = pack(&SHA256_IV);
+        let mut hits = Vec::new();
+        for i in 0..count {
+            let nonce = work.nonce_start.wrapping_add(offset + i);
+            let state = sha256d_state(mid, iv, &work.tail, nonce);
+            if state[7].swap_bytes() <= top_target && super::meets_target(&state, &work.target) {
+                hits.push(nonce);
+            }
+        }
+        hits
+    }
+
+    pub fn is_supported() -> bool {
+        is_x86_feature_detected!("sha") && is_x86_feature_detected!("ssse3") && is_x86_feature_detected!("sse4.1")
+    }
+}
+
+fn state_digest(state: &[u32; 8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (i, w) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&w.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn meets_target(state: &[u32; 8], target: &[u8; 32]) -> bool {
+    hash_meets_target(&state_digest(state), target)
+}
+
+/// True when the running CPU has the SHA extensions.
+pub fn sha_ni_supported() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        ni::is_supported()
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        false
+    }
+}
+
+pub struct ShaNiBackend {
+    accelerated: bool,
+    batch_size: u32,
+}
+
+impl ShaNiBackend {
+    /// Detects SHA-NI, falling back to `compress256` when it is missing, and
+    /// fails if the chosen path does not reproduce `double_sha256_bytes`.
+    pub fn new() -> Result<Self, BackendError> {
+        let threads = rayon::current_num_threads() as u32;
+        let backend = Self { accelerated: sha_ni_supported(), batch_size: threads.max(1) * (1 << 20) };
+        backend.known_answer_check()?;
+        Ok(backend)
+    }
+
+    pub fn is_accelerated(&self) -> bool {
+        self.accelerated
+    }
+
+    fn digest(&self, work: &WorkUnit, nonce: u32) -> [u8; 32] {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if self.accelerated {
+            // SAFETY: `accelerated` is only set when `ni::is_supported()`.
+            return state_digest(&unsafe { ni::sha256d(work, nonce) });
+        }
+        let mut inner = header_tail_block(&work.tail, nonce);
+        let mut outer = [0u8; 64];
+        outer[32] = 0x80;
+        outer[56..64].copy_from_slice(&256u64.to_be_bytes());
+        sha256d_scratch(&work.midstate, &mut inner, &mut outer, nonce)
+    }
+
+    /// Hash a few fixed headers and compare against the plain `sha2` path.
+    pub fn known_answer_check(&self) -> Result<(), BackendError> {
+        for seed in 0u8..4 {
+            let mut header = [0u8; 80];
+            for (i, b) in header.iter_mut().enumerate() {
+                *b = (i as u8).wrapping_mul(seed.wrapping_add(3)).wrapping_add(seed << 5);
+            }
+            let work = WorkUnit::from_header_bytes(&header, 0, 0, [0; 32]);
+            for nonce in [0u32, 1, 0x8000_0000, u32::MAX - seed as u32] {
+                header[76..80].copy_from_slice(&nonce.to_le_bytes());
+                if self.digest(&work, nonce) != double_sha256_bytes(&header) {
+                    return Err(BackendError::Init(format!(
+                        "{} known-answer check failed (seed {seed}, nonce {nonce:#x})",
+                        self.name()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_range(&self, work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if self.accelerated {
+            // SAFETY: `accelerated` is only set when `ni::is_supported()`.
+            return unsafe { ni::scan_range(work, offset, count) };
+        }
+        (0..count)
+            .map(|i| work.nonce_start.wrapping_add(offset + i))
+            .filter(|&n| hash_meets_target(&self.digest(work, n), &work.target))
+            .collect()
+    }
+}
+
+impl HashBackend for ShaNiBackend {
+    fn name(&self) -> &'static str {
+        if self.accelerated { "sha-ni" } else { "sha-ni (fallback)" }
+    }
+
+    fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    fn scan(&mut self, work: &WorkUnit) -> Result<ScanResult, BackendError> {
+        let start = Instant::now();
+        let chunks = work.nonce_count.div_ceil(CHUNK);
+        let workers = WorkerHashes::new();
+        let mut nonces: Vec<u32> = (0..chunks)
+            .into_par_iter()
+            .flat_map_iter(|c| {
+                let offset = c * CHUNK;
+                let count = (work.nonce_count - offset).min(CHUNK);
+                workers.add(count as u64);
+                self.scan_range(work, offset, count)
+            })
+            .collect();
+        nonces.sort_unstable();
+
+        Ok(ScanResult {
+            nonces,
+            telemetry: Telemetry {
+                hashes: work.nonce_count as u64,
+                elapsed: start.elapsed(),
+                avg_post: vec![],
+                lane_hashes: workers.into_counts(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_helpers::target_from_bits;
+
+    #[test]
+    fn accelerated_and_fallback_agree_with_reference() {
+        let mut header = [0u8; 80];
+        for (i, b) in header.iter_mut().enumerate() {
+            *b = (i as u8) ^ 0xa5;
+        }
+        let work = WorkUnit::from_header_bytes(&header, 7, 30_000, target_from_bits(0x2000_ffff));
+        let expected: Vec<u32> = work.nonces().filter(|&n| work.verify(n)).collect();
+        assert!(!expected.is_empty());
+
+        let mut detected = ShaNiBackend::new().unwrap();
+        assert_eq!(detected.is_accelerated(), sha_ni_supported());
+        assert_eq!(detected.scan(&work).unwrap().nonces, expected);
+
+        let mut fallback = ShaNiBackend { accelerated: false, batch_size: 1 << 16 };
+        fallback.known_answer_check().unwrap();
+        assert_eq!(fallback.scan(&work).unwrap().nonces, expected);
+    }
+}
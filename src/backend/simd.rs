@@ -0,0 +1,351 @@
+// src/backend/simd.rs
+//! Lane-parallel SHA-256d backend.
+//!
+//! Each SHA-256 word is a `Lanes<N>` holding the same word for N different
+//! nonces, so one pass of the round function hashes N headers. The lane
+//! code is plain array arithmetic that LLVM turns into vector instructions;
+//! which instructions depends on the target features of the entry point it
+//! gets inlined into. `SimdVariant::detect` picks the widest entry point the
+//! running CPU supports (AVX-512 x16, AVX2 x8, SSE2 x4) and falls back to the
+//! baseline-compiled x4 path on other architectures.
+//!
+//! Work is additionally split across cores with rayon.
+
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use super::{hash_meets_target, BackendError, HashBackend, ScanResult, Telemetry, WorkUnit, WorkerHashes};
+use crate::sha_helpers::{SHA256_IV, SHA256_K};
+
+/// Nonces per rayon task. Multiple of every lane width.
+const CHUNK: u32 = 1 << 14;
+
+// ----------------- Lane Vector -----------------
+#[derive(Clone, Copy)]
+struct Lanes<const N: usize>([u32; N]);
+
+impl<const N: usize> Lanes<N> {
+    #[inline(always)]
+    fn splat(x: u32) -> Self {
+        Lanes([x; N])
+    }
+    #[inline(always)]
+    fn add(self, o: Self) -> Self {
+        Lanes(std::array::from_fn(|i| self.0[i].wrapping_add(o.0[i])))
+    }
+    #[inline(always)]
+    fn xor(self, o: Self) -> Self {
+        Lanes(std::array::from_fn(|i| self.0[i] ^ o.0[i]))
+    }
+    #[inline(always)]
+    fn and(self, o: Self) -> Self {
+        Lanes(std::array::from_fn(|i| self.0[i] & o.0[i]))
+    }
+    #[inline(always)]
+    fn andnot(self, o: Self) -> Self {
+        Lanes(std::array::from_fn(|i| !self.0[i] & o.0[i]))
+    }
+    #[inline(always)]
+    fn rotr(self, n: u32) -> Self {
+        Lanes(std::array::from_fn(|i| self.0[i].rotate_right(n)))
+    }
+    #[inline(always)]
+    fn shr(self, n: u32) -> Self {
+        Lanes(std::array::from_fn(|i| self.0[i] >> n))
+    }
+}
+
+#[inline(always)]
+fn compress<const N: usize>(state: &mut [Lanes<N>; 8], block: &[Lanes<N>; 16]) {
+    // Rolling 16-word schedule: w[i & 15] is expanded in place as the
+    // rounds consume it.
+    let mut w = *block;
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        if i >= 16 {
+            let w15 = w[(i + 1) & 15];
+            let w2 = w[(i + 14) & 15];
+            let s0 = w15.rotr(7).xor(w15.rotr(18)).xor(w15.shr(3));
+            let s1 = w2.rotr(17).xor(w2.rotr(19)).xor(w2.shr(10));
+            w[i & 15] = w[i & 15].add(s0).add(w[(i + 9) & 15]).add(s1);
+        }
+        let s1 = e.rotr(6).xor(e.rotr(11)).xor(e.rotr(25));
+        let ch = e.and(f).xor(e.andnot(g));
+        let t1 = h.add(s1).add(ch).add(Lanes::splat(SHA256_K[i])).add(w[i & 15]);
+        let s0 = a.rotr(2).xor(a.rotr(13)).xor(a.rotr(22));
+        let maj = a.and(b).xor(a.and(c)).xor(b.and(c));
+        let t2 = s0.add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.add(t2);
+    }
+    for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *s = s.add(v);
+    }
+}
+
+/// Final SHA-256d state words for N consecutive nonces starting at `first`.
+#[inline(always)]
+fn sha256d_lanes<const N: usize>(midstate: &[u32; 8], tail: &[u32; 3], first: u32) -> [Lanes<N>; 8] {
+    let mut block = [Lanes::splat(0); 16];
+    for (dst, w) in block.iter_mut().zip(tail) {
+        *dst = Lanes::splat(w.swap_bytes());
+    }
+    block[3] = Lanes(std::array::from_fn(|i| first.wrapping_add(i as u32).swap_bytes()));
+    block[4] = Lanes::splat(0x8000_0000);
+    block[15] = Lanes::splat(80 * 8);
+
+    let mut inner = midstate.map(Lanes::splat);
+    compress(&mut inner, &block);
+
+    let mut outer_block = [Lanes::splat(0); 16];
+    outer_block[..8].copy_from_slice(&inner);
+    outer_block[8] = Lanes::splat(0x8000_0000);
+    outer_block[15] = Lanes::splat(32 * 8);
+
+    let mut outer = SHA256_IV.map(Lanes::splat);
+    compress(&mut outer, &outer_block);
+    outer
+}
+
+/// Digest bytes of lane `i`, in the same order as `double_sha256_bytes`.
+#[inline(always)]
+fn lane_digest<const N: usize>(state: &[Lanes<N>; 8], i: usize) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (j, w) in state.iter().enumerate() {
+        out[j * 4..j * 4 + 4].copy_from_slice(&w.0[i].to_be_bytes());
+    }
+    out
+}
+
+#[inline(always)]
+fn scan_range<const N: usize>(work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+    // The last state word holds the most significant bytes of the hash;
+    // only lanes that pass it get the full 256-bit comparison.
+    let top_target = u32::from_be_bytes([work.target[0], work.target[1], work.target[2], work.target[3]]);
+    let mut hits = Vec::new();
+    let mut done = 0u32;
+    while done < count {
+        let first = work.nonce_start.wrapping_add(offset + done);
+        let state = sha256d_lanes::<N>(&work.midstate, &work.tail, first);
+        let live = (count - done).min(N as u32) as usize;
+        for i in 0..live {
+            if state[7].0[i].swap_bytes() <= top_target
+                && hash_meets_target(&lane_digest(&state, i), &work.target)
+            {
+                hits.push(first.wrapping_add(i as u32));
+            }
+        }
+        done += live as u32;
+    }
+    hits
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn scan_sse2(work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+    scan_range::<4>(work, offset, count)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn scan_avx2(work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+    scan_range::<8>(work, offset, count)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512f")]
+unsafe fn scan_avx512(work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+    scan_range::<16>(work, offset, count)
+}
+
+// ----------------- Variants -----------------
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimdVariant {
+    /// x4 lanes compiled for the baseline target.
+    Scalar,
+    Sse2,
+    Avx2,
+    Avx512,
+}
+
+impl SimdVariant {
+    pub fn lanes(self) -> usize {
+        match self {
+            SimdVariant::Scalar | SimdVariant::Sse2 => 4,
+            SimdVariant::Avx2 => 8,
+            SimdVariant::Avx512 => 16,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SimdVariant::Scalar => "simd-scalar",
+            SimdVariant::Sse2 => "simd-sse2",
+            SimdVariant::Avx2 => "simd-avx2",
+            SimdVariant::Avx512 => "simd-avx512",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [SimdVariant::Scalar, SimdVariant::Sse2, SimdVariant::Avx2, SimdVariant::Avx512]
+            .into_iter()
+            .find(|v| v.name() == name)
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            SimdVariant::Scalar => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdVariant::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdVariant::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdVariant::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            _ => false,
+        }
+    }
+
+    /// Every variant the running CPU can execute, narrowest first.
+    pub fn supported() -> Vec<Self> {
+        [SimdVariant::Scalar, SimdVariant::Sse2, SimdVariant::Avx2, SimdVariant::Avx512]
+            .into_iter()
+            .filter(|v| v.is_supported())
+            .collect()
+    }
+
+    /// Widest supported variant.
+    pub fn detect() -> Self {
+        *Self::supported().last().unwrap_or(&SimdVariant::Scalar)
+    }
+
+    fn scan_range(self, work: &WorkUnit, offset: u32, count: u32) -> Vec<u32> {
+        // SAFETY: only reachable for variants whose features `is_supported`
+        // confirmed at construction time.
+        match self {
+            SimdVariant::Scalar => scan_range::<4>(work, offset, count),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdVariant::Sse2 => unsafe { scan_sse2(work, offset, count) },
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdVariant::Avx2 => unsafe { scan_avx2(work, offset, count) },
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            SimdVariant::Avx512 => unsafe { scan_avx512(work, offset, count) },
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            _ => scan_range::<4>(work, offset, count),
+        }
+    }
+}
+
+// ----------------- Backend -----------------
+pub struct SimdBackend {
+    variant: SimdVariant,
+    batch_size: u32,
+}
+
+impl SimdBackend {
+    /// Backend using the widest variant the CPU supports.
+    pub fn new() -> Self {
+        Self::with_variant(SimdVariant::detect()).expect("detected variant is supported")
+    }
+
+    pub fn with_variant(variant: SimdVariant) -> Result<Self, BackendError> {
+        if !variant.is_supported() {
+            return Err(BackendError::Unavailable(format!("{} not supported by this CPU", variant.name())));
+        }
+        let threads = rayon::current_num_threads() as u32;
+        Ok(Self { variant, batch_size: threads.max(1) * (1 << 20) })
+    }
+
+    pub fn variant(&self) -> SimdVariant {
+        self.variant
+    }
+}
+
+impl Default for SimdBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashBackend for SimdBackend {
+    fn name(&self) -> &'static str {
+        self.variant.name()
+    }
+
+    fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    fn scan(&mut self, work: &WorkUnit) -> Result<ScanResult, BackendError> {
+        let start = Instant::now();
+        let variant = self.variant;
+        let chunks = work.nonce_count.div_ceil(CHUNK);
+        let workers = WorkerHashes::new();
+        let mut nonces: Vec<u32> = (0..chunks)
+            .into_par_iter()
+            .flat_map_iter(|c| {
+                let offset = c * CHUNK;
+                let count = (work.nonce_count - offset).min(CHUNK);
+                workers.add(count as u64);
+                variant.scan_range(work, offset, count)
+            })
+            .collect();
+        nonces.sort_unstable();
+
+        Ok(ScanResult {
+            nonces,
+            telemetry: Telemetry {
+                hashes: work.nonce_count as u64,
+                elapsed: start.elapsed(),
+                avg_post: vec![],
+                lane_hashes: workers.into_counts(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_helpers::{double_sha256_bytes, target_from_bits};
+
+    fn header(seed: u8) -> [u8; 80] {
+        let mut h = [0u8; 80];
+        for (i, b) in h.iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(29).wrapping_add(seed);
+        }
+        h
+    }
+
+    #[test]
+    fn lane_digests_match_double_sha256_bytes() {
+        let mut h = header(7);
+        let work = WorkUnit::from_header_bytes(&h, 0, 0, [0; 32]);
+        let first = u32::MAX - 9; // lanes wrap past u32::MAX
+        let state = sha256d_lanes::<16>(&work.midstate, &work.tail, first);
+        for i in 0..16 {
+            let nonce = first.wrapping_add(i as u32);
+            h[76..80].copy_from_slice(&nonce.to_le_bytes());
+            assert_eq!(lane_digest(&state, i), double_sha256_bytes(&h), "lane {i}");
+        }
+    }
+
+    #[test]
+    fn every_supported_variant_finds_the_same_nonces() {
+        // Odd count so the last group of lanes is partial.
+        let work = WorkUnit::from_header_bytes(&header(3), 1_000, 8_001, target_from_bits(0x2000_ffff));
+        let expected: Vec<u32> = work.nonces().filter(|&n| work.verify(n)).collect();
+        assert!(!expected.is_empty());
+        for variant in SimdVariant::supported() {
+            let result = SimdBackend::with_variant(variant).unwrap().scan(&work).unwrap();
+            assert_eq!(result.nonces, expected, "{}", variant.name());
+        }
+    }
+}
@@ -1,147 +1,470 @@
 use bitcoin::{
     blockdata::{
-        block::{Block as BtcBlock, BlockHeader},
-        script::Builder as ScriptBuilder,
+        block::Block as BtcBlock,
+        script::{Builder as ScriptBuilder, Script},
         transaction::{Transaction, TxIn, TxOut},
     },
-    consensus::{deserialize, serialize},
-    hash_types::TxMerkleNode,
+    consensus::encode::{serialize, VarInt},
+    hash_types::{TxMerkleNode, Txid, WitnessMerkleNode},
     hashes::{sha256d, Hash},
-    Address, OutPoint, Script,
+    OutPoint,
 };
-use serde_json::Value;
 use hex;
-use std::str::FromStr;
-use crate::merkle_root;
-
-/// ------------------------------------------------------------------------
-/// Build a coinbase transaction from a block template,
-/// embedding a custom UTF-8 message (e.g. “∞ Power Of My Quettahashes”)
-/// ------------------------------------------------------------------------
-pub fn build_coinbase_from_template(template: &Value, message: &[u8]) -> Transaction {
-    let height = template["result"]["height"].as_u64().unwrap_or(0) as u32;
-    let coinbase_value = template["result"]["coinbasevalue"].as_u64().unwrap_or(0);
-
-    // ✅ Miner payout address
-    let payout_addr = Address::from_str("bc1qyux0tnvrusd9deq89h8er0ml4clhdwetp6ljp2")
-        .expect("Invalid payout address");
-    let output = TxOut {
-        value: coinbase_value,
-        script_pubkey: payout_addr.script_pubkey(),
-    };
-
-    // ✅ Build coinbase input (includes block height + custom message)
-    let script_sig = ScriptBuilder::new()
-        .push_int(height as i64)
-        .push_slice(message)
-        .into_script();
-
-    let input = TxIn {
-        previous_output: OutPoint::default(),
-        script_sig,
-        sequence: 0xFFFFFFFF,
-        witness: vec![vec![0u8; 32]], // placeholder nonce
-    };
-
-    Transaction {
-        version: 2,
-        lock_time: 0,
-        input: vec![input],
-        output: vec![output],
+use crate::header::HeaderWork;
+use crate::payout::{Payout, PayoutError};
+use crate::rpc::BlockTemplate;
+use crate::sha_helpers::MerkleBranch;
+
+/// Default bytes of the extranonce slot in the coinbase scriptSig.
+pub const EXTRANONCE_SIZE: usize = 8;
+
+/// Consensus bounds on the coinbase scriptSig length (`bad-cb-length`).
+pub const MIN_SCRIPT_SIG_LEN: usize = 2;
+pub const MAX_SCRIPT_SIG_LEN: usize = 100;
+
+/// The coinbase's witness reserved value (BIP141), the one
+/// `getblocktemplate` assumes in `default_witness_commitment`.
+pub const WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+
+/// `OP_RETURN` push of the commitment header `aa21a9ed` and the 32-byte
+/// commitment.
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+#[derive(Debug)]
+pub enum CoinbaseError {
+    /// BIP34 heights start at 1.
+    Height(u32),
+    /// The scriptSig would fall outside 2..=100 bytes.
+    ScriptSigLength(usize),
+    /// `default_witness_commitment` is not an `OP_RETURN aa21a9ed` commitment.
+    WitnessCommitment(String),
+    Payout(PayoutError),
+}
+
+impl std::fmt::Display for CoinbaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoinbaseError::Height(height) => write!(f, "block template height {height} has no BIP34 coinbase"),
+            CoinbaseError::ScriptSigLength(len) => write!(
+                f,
+                "coinbase scriptSig of {len} bytes is outside {MIN_SCRIPT_SIG_LEN}..={MAX_SCRIPT_SIG_LEN}"
+            ),
+            CoinbaseError::WitnessCommitment(script) => {
+                write!(f, "block template witness commitment {script} is malformed")
+            }
+            CoinbaseError::Payout(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CoinbaseError {}
+
+impl From<PayoutError> for CoinbaseError {
+    fn from(e: PayoutError) -> Self {
+        CoinbaseError::Payout(e)
     }
 }
 
 /// ------------------------------------------------------------------------
-/// Insert GPU nonce directly into witness (for adaptive or hardware feedback)
+/// The coinbase scriptSig after the BIP34 height:
+/// `height | extranonce1 | extranonce2 | tag`. Both extranonce parts share
+/// one push, so the slot is contiguous in the serialized transaction.
 /// ------------------------------------------------------------------------
-pub fn insert_nonce_into_coinbase(coinbase: &mut Transaction, nonce: u32) {
-    coinbase.input[0].witness[0] = nonce.to_le_bytes().to_vec();
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinbaseLayout {
+    pub extranonce1_size: usize,
+    pub extranonce2_size: usize,
+    /// Free-form bytes, e.g. a UTF-8 message like “∞ Power Of My Quettahashes”.
+    pub tag: Vec<u8>,
+}
+
+impl CoinbaseLayout {
+    /// `tag` after an `EXTRANONCE_SIZE` slot that is all extranonce2.
+    pub fn new(tag: &[u8]) -> Self {
+        Self { extranonce1_size: 0, extranonce2_size: EXTRANONCE_SIZE, tag: tag.to_vec() }
+    }
+
+    pub fn with_extranonce_sizes(mut self, extranonce1: usize, extranonce2: usize) -> Self {
+        self.extranonce1_size = extranonce1;
+        self.extranonce2_size = extranonce2;
+        self
+    }
+
+    pub fn extranonce_size(&self) -> usize {
+        self.extranonce1_size + self.extranonce2_size
+    }
 }
 
 /// ------------------------------------------------------------------------
-/// Assemble full block hex with embedded coinbase
+/// Builds every coinbase for one template: the checks, the outputs (payout
+/// plus witness commitment) and the merkle branch are done once in `new`,
+/// and each `build` only fills in the extranonce.
 /// ------------------------------------------------------------------------
-pub fn assemble_block_hex(template: &Value, coinbase: &Transaction, nonce: u32) -> String {
-    // Build transaction list
-    let mut txs = vec![coinbase.clone()];
-    if let Some(txs_json) = template["result"]["transactions"].as_array() {
-        for tx in txs_json {
-            let raw_tx = hex::decode(tx["data"].as_str().unwrap()).unwrap();
-            txs.push(deserialize(&raw_tx).unwrap());
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinbaseBuilder {
+    height: u32,
+    layout: CoinbaseLayout,
+    outputs: Vec<TxOut>,
+    witness: Vec<Vec<u8>>,
+    branch: MerkleBranch,
+}
+
+impl CoinbaseBuilder {
+    /// Coinbases for `template` paying `payout`, laid out as `layout`.
+    /// Fails when the template's height, commitment or value do not allow
+    /// a valid coinbase, or the scriptSig would be too short or too long.
+    pub fn new(template: &BlockTemplate, payout: &Payout, layout: CoinbaseLayout) -> Result<Self, CoinbaseError> {
+        if template.height == 0 {
+            return Err(CoinbaseError::Height(template.height));
+        }
+        let mut outputs = payout.tx_outputs(template.coinbase_value)?;
+        // The commitment goes last, and with it the witness reserved value.
+        // Without one no transaction, the coinbase included, may carry a
+        // witness.
+        let witness = match witness_commitment_script(template)? {
+            Some(script_pubkey) => {
+                outputs.push(TxOut { value: 0, script_pubkey });
+                vec![WITNESS_RESERVED_VALUE.to_vec()]
+            }
+            None => Vec::new(),
+        };
+        let branch = MerkleBranch::from_txids(&template_txids(template));
+        let builder = Self { height: template.height, layout, outputs, witness, branch };
+        let len = builder.script_sig(&vec![0; builder.layout.extranonce_size()]).len();
+        if !(MIN_SCRIPT_SIG_LEN..=MAX_SCRIPT_SIG_LEN).contains(&len) {
+            return Err(CoinbaseError::ScriptSigLength(len));
         }
+        Ok(builder)
     }
 
-    let version = template["result"]["version"].as_i64().unwrap_or(2) as i32;
-    let prevhash = sha256d::Hash::from_str(template["result"]["previousblockhash"].as_str().unwrap())
-        .unwrap();
-    let time = template["result"]["curtime"].as_u64().unwrap_or(0) as u32;
-    let bits = u32::from_str_radix(template["result"]["bits"].as_str().unwrap(), 16).unwrap_or(0);
+    pub fn layout(&self) -> &CoinbaseLayout {
+        &self.layout
+    }
 
-    // Compute Merkle root
-    let nodes: Vec<TxMerkleNode> = txs
-        .iter()
-        .map(|tx| TxMerkleNode::from(sha256d::Hash::from_inner(tx.txid().into_inner())))
-        .collect();
+    /// Payout outputs followed by the witness commitment, if any.
+    pub fn outputs(&self) -> &[TxOut] {
+        &self.outputs
+    }
 
-    let merkle_root_node: TxMerkleNode =
-        merkle_root(nodes.iter().map(|n| sha256d::Hash::from_inner(n.into_inner())).collect());
+    /// Branch from the coinbase txid to the block's merkle root.
+    pub fn merkle_branch(&self) -> &MerkleBranch {
+        &self.branch
+    }
 
-    // Construct Bitcoin block
-    let block = BtcBlock {
-        header: BlockHeader {
-            version,
-            prev_blockhash: prevhash.into(),
-            merkle_root: merkle_root_node,
-            time,
-            bits,
-            nonce,
-        },
-        txdata: txs,
-    };
+    /// The coinbase's non-witness serialization cut around the extranonce
+    /// slot, for work handed to miners that fill in the extranonce
+    /// themselves.
+    pub fn split(&self) -> CoinbaseSplit {
+        let mut stripped = self.build(&vec![0; self.layout.extranonce_size()]);
+        stripped.input[0].witness.clear();
+        let bytes = serialize(&stripped);
+        let script_len = stripped.input[0].script_sig.len();
+        // version, input count, outpoint, script length
+        let start = 4 + 1 + 36 + VarInt(script_len as u64).len() + self.extranonce_offset();
+        let end = start + self.layout.extranonce_size();
+        CoinbaseSplit {
+            coinb1: bytes[..start].to_vec(),
+            coinb2: bytes[end..].to_vec(),
+            extranonce_size: self.layout.extranonce_size(),
+            merkle_branch: self.branch.clone(),
+        }
+    }
 
-    hex::encode(serialize(&block))
+    /// The coinbase with `extranonce` (extranonce1 then extranonce2) in the
+    /// slot. Panics unless it is `layout().extranonce_size()` bytes.
+    pub fn build(&self, extranonce: &[u8]) -> Transaction {
+        assert_eq!(extranonce.len(), self.layout.extranonce_size(), "extranonce does not fit the coinbase layout");
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: self.script_sig(extranonce),
+                sequence: 0xFFFFFFFF,
+                witness: self.witness.clone(),
+            }],
+            output: self.outputs.clone(),
+        }
+    }
+
+    /// The coinbase for `extranonce1` followed by extranonce counter
+    /// `counter` as little-endian extranonce2, zero-padded. Panics unless
+    /// `extranonce1` is `extranonce1_size` bytes and `counter` fits in
+    /// `extranonce2_size`.
+    pub fn build_with_counter(&self, extranonce1: &[u8], counter: u64) -> Transaction {
+        let size = self.layout.extranonce2_size;
+        assert_eq!(extranonce1.len(), self.layout.extranonce1_size, "extranonce1 does not fit the coinbase layout");
+        assert!(size >= 8 || counter >> (8 * size) == 0, "extranonce counter {counter} does not fit in {size} bytes");
+        let mut extranonce2 = counter.to_le_bytes().to_vec();
+        extranonce2.resize(size, 0);
+        self.build(&[extranonce1, &extranonce2].concat())
+    }
+
+    /// Byte offset of the extranonce slot in the scriptSig.
+    pub fn extranonce_offset(&self) -> usize {
+        let height = ScriptBuilder::new().push_int(self.height as i64).into_script().len();
+        let push = match self.layout.extranonce_size() {
+            0 => 0,
+            1..=75 => 1,
+            _ => 2, // OP_PUSHDATA1 and a length byte
+        };
+        height + push
+    }
+
+    /// Minimal BIP34 height push, then the extranonce and the tag, each
+    /// left out when empty.
+    fn script_sig(&self, extranonce: &[u8]) -> Script {
+        let mut script = ScriptBuilder::new().push_int(self.height as i64);
+        if !extranonce.is_empty() {
+            script = script.push_slice(extranonce);
+        }
+        if !self.layout.tag.is_empty() {
+            script = script.push_slice(&self.layout.tag);
+        }
+        script.into_script()
+    }
 }
 
 /// ------------------------------------------------------------------------
-/// Patch JSON coinbase (for testing via RPC template injection)
+/// A coinbase as Stratum jobs carry it: `coinb1 || extranonce || coinb2` is
+/// the non-witness serialization, so its hash is the txid
 /// ------------------------------------------------------------------------
-pub fn patch_insert_nonce_into_coinbase(coinbase: &mut serde_json::Value, nonce: u32) {
-    if let Some(tx) = coinbase["result"]["transactions"].get_mut(0) {
-        if let Some(vin) = tx["vin"].get_mut(0) {
-            let mut script_bytes = hex::decode(vin["coinbase"].as_str().unwrap_or(""))
-                .unwrap_or_default();
-            let extra_nonce = nonce.to_le_bytes().to_vec();
-            let len = (1 + (nonce % 4) as usize).min(extra_nonce.len());
-            script_bytes.extend_from_slice(&extra_nonce[..len]);
-            if script_bytes.len() < 32 {
-                script_bytes.extend(vec![0u8; 32 - script_bytes.len()]);
-            }
-            vin["coinbase"] = serde_json::Value::String(hex::encode(script_bytes));
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinbaseSplit {
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+    /// Bytes of extranonce1 plus extranonce2 between the halves.
+    pub extranonce_size: usize,
+    pub merkle_branch: MerkleBranch,
+}
+
+impl CoinbaseSplit {
+    /// `coinb1 || extranonce1 || extranonce2 || coinb2`. Panics unless the
+    /// extranonce parts add up to `extranonce_size` bytes.
+    pub fn reconstruct(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Vec<u8> {
+        assert_eq!(extranonce1.len() + extranonce2.len(), self.extranonce_size, "extranonce does not fit the coinbase split");
+        [&self.coinb1[..], extranonce1, extranonce2, &self.coinb2].concat()
+    }
+
+    pub fn txid(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Txid {
+        Txid::hash(&self.reconstruct(extranonce1, extranonce2))
+    }
+
+    /// Merkle root of the block with this coinbase.
+    pub fn merkle_root(&self, extranonce1: &[u8], extranonce2: &[u8]) -> TxMerkleNode {
+        self.merkle_branch.root(self.txid(extranonce1, extranonce2).as_hash())
+    }
+}
+
+/// ------------------------------------------------------------------------
+/// Witness commitment output script: the template's
+/// `default_witness_commitment`, or else one computed from the wtxids when
+/// any template transaction has a witness. `None` when the block needs no
+/// commitment.
+/// ------------------------------------------------------------------------
+pub fn witness_commitment_script(template: &BlockTemplate) -> Result<Option<Script>, CoinbaseError> {
+    if let Some(script) = &template.default_witness_commitment {
+        if script.len() != WITNESS_COMMITMENT_PREFIX.len() + 32 || !script.starts_with(&WITNESS_COMMITMENT_PREFIX) {
+            return Err(CoinbaseError::WitnessCommitment(hex::encode(script)));
         }
+        return Ok(Some(Script::from(script.clone())));
+    }
+    if template.transactions.iter().all(|tx| tx.hash.as_hash() == tx.txid.as_hash()) {
+        return Ok(None);
     }
+    // The coinbase's wtxid counts as zero.
+    let wtxids: Vec<sha256d::Hash> = template.transactions.iter().map(|tx| tx.hash.as_hash()).collect();
+    let witness_root = WitnessMerkleNode::from_inner(MerkleBranch::from_txids(&wtxids).root(Default::default()).into_inner());
+    let commitment = BtcBlock::compute_witness_commitment(&witness_root, &WITNESS_RESERVED_VALUE);
+    let mut script = WITNESS_COMMITMENT_PREFIX.to_vec();
+    script.extend_from_slice(&commitment[..]);
+    Ok(Some(Script::from(script)))
+}
+
+/// ------------------------------------------------------------------------
+/// Merkle root of the coinbase followed by the template's transactions
+/// ------------------------------------------------------------------------
+pub fn block_merkle_root(template: &BlockTemplate, coinbase: &Transaction) -> TxMerkleNode {
+    MerkleBranch::from_txids(&template_txids(template)).root(coinbase.txid().as_hash())
+}
+
+/// ------------------------------------------------------------------------
+/// Txids of the template's transactions, in block order
+/// ------------------------------------------------------------------------
+pub fn template_txids(template: &BlockTemplate) -> Vec<sha256d::Hash> {
+    template.txids().map(|txid| txid.as_hash()).collect()
+}
+
+/// ------------------------------------------------------------------------
+/// Assemble full block hex with embedded coinbase. `header` is the header
+/// that was hashed (rolled version and time included); only the nonce is
+/// filled in here.
+/// ------------------------------------------------------------------------
+pub fn assemble_block_hex(template: &BlockTemplate, coinbase: &Transaction, header: &HeaderWork, nonce: u32) -> String {
+    // Build transaction list
+    let mut txs = vec![coinbase.clone()];
+    txs.extend(template.transactions.iter().map(|tx| tx.transaction()));
+
+    let mut header = header.clone();
+    header.set_nonce(nonce);
+
+    // Construct Bitcoin block
+    let block = BtcBlock { header: header.to_header(), txdata: txs };
+
+    hex::encode(serialize(&block))
 }
 
 /// ------------------------------------------------------------------------
-/// BIP34 height injection helper
+/// A `getblocktemplate` reply with `coinbase` (from `CoinbaseBuilder`)
+/// inserted as the first of `result.transactions`, in the template's own
+/// `data`/`txid`/`hash` shape, for the JSON-fed header helpers in
+/// `sha_helpers`.
 /// ------------------------------------------------------------------------
-pub fn build_coinbase_from_template_with_height(template: &serde_json::Value) -> serde_json::Value {
-    let mut coinbase = template.clone();
-    let height = template["result"]["height"].as_u64().unwrap_or(0) as u32;
+pub fn template_json_with_coinbase(gbt: &serde_json::Value, coinbase: &Transaction) -> serde_json::Value {
+    let mut gbt = gbt.clone();
+    let entry = serde_json::json!({
+        "data": hex::encode(serialize(coinbase)),
+        "txid": coinbase.txid().to_string(),
+        "hash": coinbase.wtxid().to_string(),
+    });
+    match gbt["result"]["transactions"].as_array_mut() {
+        Some(transactions) => transactions.insert(0, entry),
+        None => gbt["result"]["transactions"] = serde_json::json!([entry]),
+    }
+    gbt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_bitcoind::regtest_template;
+    use crate::sha_helpers::{compute_midstate, compute_midstate_with_nonce, prepare_block_header};
+
+    const GENESIS: &str = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
 
-    let height_bytes = height.to_le_bytes();
-    let height_prefix = vec![height_bytes.len() as u8];
-    let mut coinbase_script = height_prefix;
-    coinbase_script.extend_from_slice(&height_bytes);
+    fn template(height: u32) -> BlockTemplate {
+        BlockTemplate::from_result(regtest_template(height, GENESIS, 1_700_000_000)).unwrap()
+    }
+
+    fn payout() -> Payout {
+        Payout::parse("script:51").unwrap()
+    }
 
-    if let Some(tx) = coinbase["result"]["transactions"].get_mut(0) {
-        if let Some(vin) = tx["vin"].get_mut(0) {
-            let prev_script =
-                hex::decode(vin["coinbase"].as_str().unwrap_or("")).unwrap_or_default();
-            let mut final_script = coinbase_script;
-            final_script.extend(prev_script);
-            vin["coinbase"] = serde_json::Value::String(hex::encode(final_script));
+    #[test]
+    fn height_push_is_minimal() {
+        let cases: [(u32, &[u8]); 6] = [
+            (1, &[0x51]),
+            (16, &[0x60]),
+            (17, &[0x01, 0x11]),
+            (128, &[0x02, 0x80, 0x00]),
+            (65_535, &[0x03, 0xff, 0xff, 0x00]),
+            (800_000, &[0x03, 0x00, 0x35, 0x0c]),
+        ];
+        for (height, push) in cases {
+            let coinbase = CoinbaseBuilder::new(&template(height), &payout(), CoinbaseLayout::new(b"tag")).unwrap();
+            let script_sig = coinbase.build_with_counter(&[], 0x0807_0605_0403_0201).input[0].script_sig.to_bytes();
+            let expected = [push, &[8, 1, 2, 3, 4, 5, 6, 7, 8], &[3], b"tag"].concat();
+            assert_eq!(script_sig, expected, "height {height}");
+            assert_eq!(coinbase.extranonce_offset(), push.len() + 1);
         }
     }
 
-    coinbase
+    #[test]
+    fn counter_fills_extranonce2_after_extranonce1() {
+        let layout = CoinbaseLayout::new(b"tag").with_extranonce_sizes(4, 2);
+        let coinbase = CoinbaseBuilder::new(&template(17), &payout(), layout).unwrap();
+        let script_sig = coinbase.build_with_counter(&[0xa, 0xb, 0xc, 0xd], 0x0201).input[0].script_sig.to_bytes();
+        assert_eq!(script_sig, [&[0x01, 0x11, 6, 0xa, 0xb, 0xc, 0xd, 1, 2, 3][..], b"tag"].concat());
+
+        let overflow = std::panic::catch_unwind(|| coinbase.build_with_counter(&[0; 4], 0x1_0000));
+        assert!(overflow.is_err());
+    }
+
+    #[test]
+    fn script_sig_stays_within_consensus_bounds() {
+        let bare = CoinbaseLayout::new(b"").with_extranonce_sizes(0, 0);
+        let too_short = CoinbaseBuilder::new(&template(1), &payout(), bare.clone());
+        assert!(matches!(too_short, Err(CoinbaseError::ScriptSigLength(1))), "{too_short:?}");
+        assert!(CoinbaseBuilder::new(&template(17), &payout(), bare).is_ok());
+
+        // 4 (height) + 9 (extranonce) + 2 + 85 (tag) = 100 bytes.
+        assert!(CoinbaseBuilder::new(&template(800_000), &payout(), CoinbaseLayout::new(&[b'x'; 85])).is_ok());
+        let too_long = CoinbaseBuilder::new(&template(800_000), &payout(), CoinbaseLayout::new(&[b'x'; 86]));
+        assert!(matches!(too_long, Err(CoinbaseError::ScriptSigLength(101))), "{too_long:?}");
+    }
+
+    #[test]
+    fn rejects_templates_without_a_valid_coinbase() {
+        let layout = CoinbaseLayout::new(b"tag");
+        assert!(matches!(CoinbaseBuilder::new(&template(0), &payout(), layout.clone()), Err(CoinbaseError::Height(0))));
+
+        let mut bad_commitment = template(1);
+        bad_commitment.default_witness_commitment = Some([&[0x6a, 0x20][..], &[0; 32]].concat());
+        let result = CoinbaseBuilder::new(&bad_commitment, &payout(), layout.clone());
+        assert!(matches!(result, Err(CoinbaseError::WitnessCommitment(_))), "{result:?}");
+
+        let greedy = Payout::parse("script:51=6000000000sat,script:52").unwrap();
+        let result = CoinbaseBuilder::new(&template(1), &greedy, layout);
+        assert!(matches!(result, Err(CoinbaseError::Payout(PayoutError::Insufficient { .. }))), "{result:?}");
+    }
+
+    #[test]
+    fn split_reconstructs_the_built_txid() {
+        for (height, en1, en2, tag) in [(300, 4, 4, &b"tag"[..]), (1, 0, 8, b""), (800_000, 8, 2, b"a longer tag"), (17, 3, 0, b"x")] {
+            let layout = CoinbaseLayout::new(tag).with_extranonce_sizes(en1, en2);
+            let coinbase = CoinbaseBuilder::new(&template(height), &payout(), layout).unwrap();
+            let split = coinbase.split();
+            assert_eq!(split.extranonce_size, en1 + en2);
+            let extranonce: Vec<u8> = (1..=(en1 + en2) as u8).collect();
+            let (extranonce1, extranonce2) = extranonce.split_at(en1);
+            let built = coinbase.build(&extranonce);
+            assert_eq!(split.txid(extranonce1, extranonce2), built.txid(), "height {height}");
+            let reconstructed: Transaction = bitcoin::consensus::deserialize(&split.reconstruct(extranonce1, extranonce2)).unwrap();
+            assert_eq!(reconstructed.input[0].script_sig, built.input[0].script_sig);
+            assert_eq!(reconstructed.output, built.output);
+        }
+    }
+
+    #[test]
+    fn split_merkle_root_matches_the_block() {
+        let mut template = template(2);
+        for i in 0..5u32 {
+            let tx = Transaction {
+                version: 2,
+                lock_time: i,
+                input: vec![TxIn { previous_output: OutPoint::default(), script_sig: Script::new(), sequence: 0, witness: Vec::new() }],
+                output: Vec::new(),
+            };
+            template.transactions.push(crate::rpc::TemplateTransaction {
+                data: serialize(&tx),
+                txid: tx.txid(),
+                hash: tx.wtxid(),
+                fee: 0,
+                sigops: 0,
+                weight: 0,
+                depends: Vec::new(),
+            });
+        }
+        let coinbase = CoinbaseBuilder::new(&template, &payout(), CoinbaseLayout::new(b"tag")).unwrap();
+        let split = coinbase.split();
+        assert_eq!(split.merkle_branch.steps.len(), 3);
+        let extranonce = [9, 8, 7, 6, 5, 4, 3, 2];
+        let root = split.merkle_root(&extranonce[..3], &extranonce[3..]);
+        assert_eq!(root, block_merkle_root(&template, &coinbase.build(&extranonce)));
+    }
+
+    #[test]
+    fn template_json_carries_the_coinbase_first() {
+        let template = template(1);
+        let coinbase = CoinbaseBuilder::new(&template, &payout(), CoinbaseLayout::new(b"tag")).unwrap().build_with_counter(&[], 9);
+        let gbt = serde_json::json!({ "result": regtest_template(1, GENESIS, 1_700_000_000), "error": null });
+        let with_coinbase = template_json_with_coinbase(&gbt, &coinbase);
+        assert_eq!(with_coinbase["result"]["transactions"][0]["txid"], coinbase.txid().to_string());
+
+        // The JSON-fed midstate is the one over the block's merkle root.
+        let empty_root = TxMerkleNode::default();
+        let words = prepare_block_header(&template, &empty_root);
+        let root = block_merkle_root(&template, &coinbase);
+        assert_eq!(compute_midstate_with_nonce(&words, &with_coinbase), compute_midstate(&prepare_block_header(&template, &root)));
+    }
 }
@@ -4,9 +4,12 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::time::Instant;
-use metal::{Device, CommandQueue, ComputePipelineState, MTLSize, Buffer};
+#[cfg(feature = "metal")]
+use metal::{Device, Buffer};
+#[cfg(feature = "metal")]
 use crate::constants::{LANES, NONCES_PER_THREAD};
 use crate::MinerMetrics;
+use crate::rpc::{SubmitCounts, TemplateHealth};
 
 #[derive(Clone, Debug)]
 pub struct DistinguishedPoint {
@@ -43,7 +46,7 @@ impl DPTable {
             if self.table.len() >= self.max_entries {
                 if let Some((lowest_key, _)) = self.table.iter()
                     .min_by(|a, b| a.1.probability.partial_cmp(&b.1.probability).unwrap())
-                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .map(|(k, v)| (*k, v.clone()))
                 {
                     self.table.remove(&lowest_key);
                 }
@@ -64,6 +67,8 @@ impl DPTable {
     }
 
     pub fn len(&self) -> usize { self.table.len() }
+
+    pub fn is_empty(&self) -> bool { self.table.is_empty() }
 }
 
 #[derive(Clone, Debug)]
@@ -103,6 +108,7 @@ fn candidate_probability(
         + 0.1 * shannon_slice.get(lane).copied().unwrap_or(0.0)
 }
 
+#[cfg(feature = "metal")]
 fn gpu_submit_lane(device: &metal::Device, lane: usize, candidates: &[CandidateDP], lane_buffers: &Vec<Buffer>) {
     let buffer = &lane_buffers[lane];
     unsafe {
@@ -113,36 +119,35 @@ fn gpu_submit_lane(device: &metal::Device, lane: usize, candidates: &[CandidateD
     }
 }
 
-/// ==================== Async DP Table Update with Real Submission ====================
-pub async fn update_dp_table_from_gpu_async(
+/// ==================== Async DP Table Update ====================
+/// Lane averages, DP-table inserts and per-lane top-N candidate selection
+/// from host copies of the kernel's telemetry. Slices are laid out
+/// `lane * nonces_per_lane + nonce`. Sends the resulting metrics and returns
+/// the candidates to hand back to each lane, best first.
+#[allow(clippy::too_many_arguments)]
+pub async fn update_dp_table_from_slices(
     dp_table: &Arc<RwLock<DPTable>>,
+    lanes: usize,
+    nonces_per_lane: usize,
     posterior_slice: &[f32],
-    nibble_slice: &[f32],
     fwht_slice: &[f32],
     cs_slice: &[f32],
     shannon_slice: &[f32],
     digest_slice: &[u32],
     metrics_tx: &tokio::sync::mpsc::UnboundedSender<MinerMetrics>,
-    device: &Device,
-    lane_buffers: &Vec<Buffer>,
-) {
+) -> Vec<Vec<CandidateDP>> {
     const BASE_TOP_N: usize = 2;
 
-    let mut avg_post = vec![0.0f32; LANES];
-    let mut avg_fwht = vec![0.0f32; LANES];
-    let mut avg_cs = vec![0.0f32; LANES];
+    let mut avg_post = vec![0.0f32; lanes];
+    let mut avg_fwht = vec![0.0f32; lanes];
+    let mut avg_cs = vec![0.0f32; lanes];
 
     // Compute lane averages
-    for lane in 0..LANES {
-        for nonce in 0..NONCES_PER_THREAD {
-            let idx = lane * NONCES_PER_THREAD + nonce;
-            avg_post[lane] += posterior_slice[idx];
-            avg_fwht[lane] += fwht_slice[idx];
-            avg_cs[lane] += cs_slice[idx];
-        }
-        avg_post[lane] /= NONCES_PER_THREAD as f32;
-        avg_fwht[lane] /= NONCES_PER_THREAD as f32;
-        avg_cs[lane] /= NONCES_PER_THREAD as f32;
+    for lane in 0..lanes {
+        let range = lane * nonces_per_lane..(lane + 1) * nonces_per_lane;
+        avg_post[lane] = posterior_slice[range.clone()].iter().sum::<f32>() / nonces_per_lane as f32;
+        avg_fwht[lane] = fwht_slice[range.clone()].iter().sum::<f32>() / nonces_per_lane as f32;
+        avg_cs[lane] = cs_slice[range].iter().sum::<f32>() / nonces_per_lane as f32;
     }
 
     // Adaptive threshold
@@ -152,11 +157,11 @@ pub async fn update_dp_table_from_gpu_async(
     };
 
     // Lane-wise top-N queues
-    let mut lane_queues: Vec<Vec<HeapEntry>> = vec![Vec::new(); LANES];
+    let mut lane_queues: Vec<Vec<HeapEntry>> = vec![Vec::new(); lanes];
 
     {
         let mut dp = dp_table.write().await;
-        for lane in 0..LANES {
+        for (lane, queue) in lane_queues.iter_mut().enumerate() {
             let entropy = shannon_slice.get(lane).copied().unwrap_or(0.0);
             if entropy < 0.15 { continue; }
 
@@ -164,7 +169,7 @@ pub async fn update_dp_table_from_gpu_async(
             let prob = candidate_probability(lane, &avg_post, &avg_fwht, &avg_cs, shannon_slice);
             if prob < dp_threshold { continue; }
 
-            let start = lane * NONCES_PER_THREAD * 8;
+            let start = lane * nonces_per_lane * 8;
             let slice = &digest_slice[start..start + 8];
             dp.update_from_digest(slice, prob);
 
@@ -173,15 +178,15 @@ pub async fn update_dp_table_from_gpu_async(
                 value[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
             }
 
-            lane_queues[lane].push(HeapEntry {
+            queue.push(HeapEntry {
                 probability: prob,
                 lane,
                 dp: CandidateDP { value, seed: 0, steps: 0, probability: prob },
             });
 
-            lane_queues[lane].sort_by(|a, b| b.probability.partial_cmp(&a.probability).unwrap());
-            if lane_queues[lane].len() > top_n {
-                lane_queues[lane].truncate(top_n);
+            queue.sort_by(|a, b| b.probability.partial_cmp(&a.probability).unwrap());
+            if queue.len() > top_n {
+                queue.truncate(top_n);
             }
         }
     }
@@ -190,25 +195,18 @@ pub async fn update_dp_table_from_gpu_async(
     let mut global_queue: Vec<HeapEntry> = lane_queues.iter().flatten().cloned().collect();
     global_queue.sort_by(|a, b| b.probability.partial_cmp(&a.probability).unwrap());
 
-    // Batch submit top candidates per lane to GPU
-    let mut lane_batch: Vec<Vec<CandidateDP>> = vec![Vec::new(); LANES];
+    let mut lane_batch: Vec<Vec<CandidateDP>> = vec![Vec::new(); lanes];
     for entry in global_queue {
         lane_batch[entry.lane].push(entry.dp);
     }
 
-    for lane in 0..LANES {
-        if !lane_batch[lane].is_empty() {
-            gpu_submit_lane(device, lane, &lane_batch[lane], lane_buffers);
-        }
-    }
-
     // Metrics update
     let mut nibble_tree = [[0u32; 4]; 4];
-    for i in 0..4 {
-        for j in 0..4 {
-            let post_val = (avg_post.get(i % LANES).copied().unwrap_or(0.0) * 255.0) as u32;
-            let fwht_val = (avg_fwht.get(j % LANES).copied().unwrap_or(0.0) * 255.0) as u32;
-            nibble_tree[i][j] = post_val.wrapping_add(fwht_val);
+    for (i, row) in nibble_tree.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            let post_val = (avg_post.get(i % lanes.max(1)).copied().unwrap_or(0.0) * 255.0) as u32;
+            let fwht_val = (avg_fwht.get(j % lanes.max(1)).copied().unwrap_or(0.0) * 255.0) as u32;
+            *cell = post_val.wrapping_add(fwht_val);
         }
     }
 
@@ -222,6 +220,12 @@ pub async fn update_dp_table_from_gpu_async(
         avg_cs,
         nibble_tree,
         hashrate_mhs: 0.0,
+        hashrate_windows: [0.0; 4],
+        effective_mhs: 0.0,
+        lane_mhs: vec![],
+        shares: 0,
+        submits: SubmitCounts::default(),
+        template_health: TemplateHealth::Unchecked,
         total_hashes: 0,
         timestamp: Instant::now(),
         last_hashrate: 0.0,
@@ -231,4 +235,92 @@ pub async fn update_dp_table_from_gpu_async(
         adaptive_factor: 0.0,
     };
     let _ = metrics_tx.send(metrics);
+
+    lane_batch
+}
+
+/// ==================== Async DP Table Update with Real Submission ====================
+#[cfg(feature = "metal")]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_dp_table_from_gpu_async(
+    dp_table: &Arc<RwLock<DPTable>>,
+    posterior_slice: &[f32],
+    _nibble_slice: &[f32],
+    fwht_slice: &[f32],
+    cs_slice: &[f32],
+    shannon_slice: &[f32],
+    digest_slice: &[u32],
+    metrics_tx: &tokio::sync::mpsc::UnboundedSender<MinerMetrics>,
+    device: &Device,
+    lane_buffers: &Vec<Buffer>,
+) {
+    let lane_batch = update_dp_table_from_slices(
+        dp_table,
+        LANES,
+        NONCES_PER_THREAD,
+        posterior_slice,
+        fwht_slice,
+        cs_slice,
+        shannon_slice,
+        digest_slice,
+        metrics_tx,
+    )
+    .await;
+
+    // Batch submit top candidates per lane to GPU
+    for (lane, batch) in lane_batch.iter().enumerate() {
+        if !batch.is_empty() {
+            gpu_submit_lane(device, lane, batch, lane_buffers);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::NIBBLES;
+    use crate::emulator::{unit_f32, FusedKernelEmulator, KernelBuffers, KernelInputs, KERNEL_NONCES_PER_THREAD};
+    use crate::sha_helpers::SHA256_IV;
+
+    #[tokio::test]
+    async fn driven_by_emulated_kernel_output() {
+        const EMU_LANES: usize = 4;
+        let mut input = KernelInputs::new(EMU_LANES, SHA256_IV);
+        for (i, w) in input.schedule.iter_mut().enumerate() {
+            *w = (i as u32).wrapping_mul(0x9e37_79b9);
+        }
+        let mut out = KernelBuffers::new(EMU_LANES);
+        FusedKernelEmulator::new().dispatch(&input, &mut out);
+
+        // One value per cell: the first of each cell's 16 FWHT / CS entries.
+        let per_cell = |v: &[u16]| unit_f32(&v.iter().step_by(16).copied().collect::<Vec<_>>());
+        let nonces_per_lane = NIBBLES * KERNEL_NONCES_PER_THREAD;
+        let posterior = vec![1.0f32; out.cells()];
+        let shannon = vec![0.9f32; EMU_LANES];
+
+        let dp_table = Arc::new(RwLock::new(DPTable::new(16)));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let batches = update_dp_table_from_slices(
+            &dp_table,
+            EMU_LANES,
+            nonces_per_lane,
+            &posterior,
+            &per_cell(&out.fwht_out),
+            &per_cell(&out.cs_out),
+            &shannon,
+            &out.digest_out,
+            &tx,
+        )
+        .await;
+
+        assert_eq!(batches.len(), EMU_LANES);
+        assert!(batches.iter().all(|b| b.len() == 1));
+        // Lanes share a midstate but not a schedule row, so each lane's
+        // first digest is a distinct DP-table entry.
+        assert_eq!(dp_table.read().await.len(), EMU_LANES);
+
+        let metrics = rx.try_recv().unwrap();
+        assert_eq!(metrics.avg_post, vec![1.0; EMU_LANES]);
+        assert_eq!(metrics.avg_fwht.len(), EMU_LANES);
+    }
 }
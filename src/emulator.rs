@@ -0,0 +1,357 @@
+// src/emulator.rs
+//! Host-side emulator of the `fused_sha256d_fwht_cs` kernel
+//! (shaders/sha256_stage.metal).
+//!
+//! `FusedKernelEmulator::dispatch` runs the kernel body for every
+//! (lane, nibble) thread and every iteration of its inner nonce loop, and
+//! writes host-memory copies of the buffers the kernel binds. Index math,
+//! integer widths and truncations follow the shader line for line so a GPU
+//! readback can be diffed against it with `KernelBuffers::diff`.
+//!
+//! Cross-thread state is modelled barrier-synchronously: within one
+//! `nonce_iter` every thread first updates its lane minimum, then every
+//! thread folds into the global atomic minimum, then every thread reads it
+//! back. The GPU interleaves threadgroups freely, so the global minimum can
+//! legitimately differ there; all per-cell values that do not depend on it
+//! are exact.
+//!
+//! The kernel binds `mitm_states`, `shannon_entropy_buf`, `hamming_buf` and
+//! `monte_buf` but never writes them; the emulator leaves them as given.
+
+use crate::constants::NIBBLES;
+
+/// `NONCES_PER_THREAD` constexpr inside the kernel.
+pub const KERNEL_NONCES_PER_THREAD: usize = 32;
+
+/// Buffer index of cell (`lane`, `nibble`, `nonce_iter`), as the kernel's
+/// `base_idx`.
+pub fn cell_index(lane: usize, nibble: usize, nonce_iter: usize) -> usize {
+    lane * (NIBBLES * KERNEL_NONCES_PER_THREAD) + nibble * KERNEL_NONCES_PER_THREAD + nonce_iter
+}
+
+/// The inverse of `cell_index`: (lane, nibble, nonce_iter).
+pub fn cell_position(idx: usize) -> (usize, usize, usize) {
+    let per_lane = NIBBLES * KERNEL_NONCES_PER_THREAD;
+    (idx / per_lane, idx % per_lane / KERNEL_NONCES_PER_THREAD, idx % KERNEL_NONCES_PER_THREAD)
+}
+
+/// Only the first eight round constants are used by the kernel.
+const K8: [u32; 8] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+];
+
+#[inline]
+fn gate_bitmask(nib: u32) -> u16 {
+    (((nib & 0x1) << 15) | ((nib & 0x2) << 13) | ((nib & 0x4) << 11) | ((nib & 0x8) << 9) | 0x7FFF) as u16
+}
+
+/// `sha256_round8` from the shader.
+#[inline]
+fn sha256_round8(s: &mut [u32; 8], w: &[u32; 8]) {
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *s;
+    for i in 0..8 {
+        let sig0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let sig1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t1 = h.wrapping_add(sig1).wrapping_add(ch).wrapping_add(K8[i]).wrapping_add(w[i]);
+        let t2 = sig0.wrapping_add(maj).wrapping_add(t1);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t2;
+    }
+    *s = [a, b, c, d, e, f, g, h];
+}
+
+// ----------------- Buffers -----------------
+
+/// Read-only kernel inputs (buffers 0, 1, 2, 4, 9, 19).
+#[derive(Clone, Debug)]
+pub struct KernelInputs {
+    /// `midstates`, 8 words per lane.
+    pub midstates: Vec<u32>,
+    /// `preexp_schedule`, 64 words per lane (only the first 16 are read).
+    pub schedule: Vec<u32>,
+    /// `nonce_start`, one per lane. Bound but not read by the kernel.
+    pub nonce_start: Vec<u32>,
+    /// `posterior_in`, one per cell.
+    pub posterior_in: Vec<u16>,
+    /// `adaptive_params`; word 0 truncated to `ushort` is the mask threshold.
+    pub adaptive_params: [u32; 4],
+    /// `lane_count`.
+    pub lane_count: u32,
+}
+
+impl KernelInputs {
+    /// Zeroed inputs for `lanes` lanes, every lane starting from `midstate`.
+    pub fn new(lanes: usize, midstate: [u32; 8]) -> Self {
+        Self {
+            midstates: midstate.repeat(lanes),
+            schedule: vec![0; lanes * 64],
+            nonce_start: vec![0; lanes],
+            posterior_in: vec![0; lanes * NIBBLES * KERNEL_NONCES_PER_THREAD],
+            adaptive_params: [0; 4],
+            lane_count: lanes as u32,
+        }
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.midstates.len() / 8
+    }
+}
+
+/// Buffers the kernel writes (or binds for writing), sized the way the
+/// kernel indexes them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KernelBuffers {
+    pub digest_out: Vec<u32>,
+    pub posterior_out: Vec<u16>,
+    pub mitm_states: Vec<u32>,
+    pub fwht_out: Vec<u16>,
+    pub cs_out: Vec<u16>,
+    pub nibble_probs: Vec<u16>,
+    pub debug_flags: Vec<u32>,
+    pub submit_mask: Vec<u32>,
+    pub adaptive_feedback: Vec<u16>,
+    pub shannon_entropy: Vec<u16>,
+    pub hamming: Vec<u16>,
+    pub monte: Vec<u16>,
+    pub global_lane_min: u32,
+}
+
+impl KernelBuffers {
+    pub fn new(lanes: usize) -> Self {
+        let cells = lanes * NIBBLES * KERNEL_NONCES_PER_THREAD;
+        Self {
+            digest_out: vec![0; cells * 8],
+            posterior_out: vec![0; cells],
+            mitm_states: vec![0; cells * crate::constants::MITM_STATE_U32_WORDS],
+            fwht_out: vec![0; cells * 16],
+            cs_out: vec![0; cells * 16],
+            nibble_probs: vec![0; cells * 16],
+            debug_flags: vec![0; cells],
+            submit_mask: vec![0; cells],
+            adaptive_feedback: vec![0; cells],
+            shannon_entropy: vec![0; cells],
+            hamming: vec![0; cells],
+            monte: vec![0; cells],
+            // Host allocations start zeroed, so the atomic minimum starts at 0.
+            global_lane_min: 0,
+        }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.submit_mask.len()
+    }
+
+    /// Cells the kernel flagged in `submit_mask`.
+    pub fn submitted(&self) -> impl Iterator<Item = usize> + '_ {
+        self.submit_mask.iter().enumerate().filter(|(_, &m)| m != 0).map(|(i, _)| i)
+    }
+
+    /// Names of buffers whose contents differ, with the first differing index.
+    pub fn diff(&self, other: &KernelBuffers) -> Vec<(&'static str, usize)> {
+        fn first<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+            a.iter().zip(b).position(|(x, y)| x != y).or((a.len() != b.len()).then(|| a.len().min(b.len())))
+        }
+        let checks = [
+            ("digest_out", first(&self.digest_out, &other.digest_out)),
+            ("posterior_out", first(&self.posterior_out, &other.posterior_out)),
+            ("mitm_states", first(&self.mitm_states, &other.mitm_states)),
+            ("fwht_out", first(&self.fwht_out, &other.fwht_out)),
+            ("cs_out", first(&self.cs_out, &other.cs_out)),
+            ("nibble_probs", first(&self.nibble_probs, &other.nibble_probs)),
+            ("debug_flags", first(&self.debug_flags, &other.debug_flags)),
+            ("submit_mask", first(&self.submit_mask, &other.submit_mask)),
+            ("adaptive_feedback", first(&self.adaptive_feedback, &other.adaptive_feedback)),
+            ("shannon_entropy", first(&self.shannon_entropy, &other.shannon_entropy)),
+            ("hamming", first(&self.hamming, &other.hamming)),
+            ("monte", first(&self.monte, &other.monte)),
+            ("global_lane_min", (self.global_lane_min != other.global_lane_min).then_some(0)),
+        ];
+        checks.into_iter().filter_map(|(name, idx)| idx.map(|i| (name, i))).collect()
+    }
+}
+
+/// Map a `ushort` buffer to `[0, 1]` floats, the form the adaptive and
+/// DP-table code consumes.
+pub fn unit_f32(values: &[u16]) -> Vec<f32> {
+    values.iter().map(|&v| v as f32 / u16::MAX as f32).collect()
+}
+
+// ----------------- Emulator -----------------
+
+/// Registers and cursor of one kernel thread.
+struct ThreadState {
+    state: [u32; 8],
+    post_mean: Option<u16>,
+}
+
+#[derive(Default)]
+pub struct FusedKernelEmulator;
+
+impl FusedKernelEmulator {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Run one dispatch: `lanes * NIBBLES` threads, each looping over
+    /// `KERNEL_NONCES_PER_THREAD` cells.
+    pub fn dispatch(&self, input: &KernelInputs, out: &mut KernelBuffers) {
+        let lanes = input.lanes();
+        let threads = lanes * NIBBLES;
+        let mask_threshold = input.adaptive_params[0] as u16;
+
+        let mut regs: Vec<ThreadState> = (0..threads)
+            .map(|tid| {
+                let lane = tid / NIBBLES;
+                let mut state = [0u32; 8];
+                state.copy_from_slice(&input.midstates[lane * 8..lane * 8 + 8]);
+                ThreadState { state, post_mean: None }
+            })
+            .collect();
+        // `if (nibble_idx == 0) tg_lane_min[lane] = 0xFFFF;`
+        let mut tg_lane_min = vec![0xFFFFu16; lanes];
+
+        for nonce_iter in 0..KERNEL_NONCES_PER_THREAD {
+            // Phase 1: hash, FWHT / count-sketch / nibble outputs, lane minimum.
+            for (tid, t) in regs.iter_mut().enumerate() {
+                let (lane, nibble_idx) = (tid / NIBBLES, tid % NIBBLES);
+                let base_idx = cell_index(lane, nibble_idx, nonce_iter);
+                t.post_mean = None;
+
+                let pred_post = input.posterior_in[base_idx];
+                if pred_post < mask_threshold {
+                    out.submit_mask[base_idx] = 0;
+                    out.debug_flags[base_idx] = 3;
+                    out.posterior_out[base_idx] = pred_post;
+                    continue;
+                }
+
+                let mut w = [0u32; 8];
+                w.copy_from_slice(&input.schedule[lane * 64..lane * 64 + 8]);
+                let mut rounds = t.state;
+                sha256_round8(&mut rounds, &w);
+                for (s, r) in t.state.iter_mut().zip(rounds) {
+                    *s = s.wrapping_add(r);
+                }
+
+                let [a, _, c, _, e, _, g, _] = t.state;
+                let mut sum = 0u32;
+                for i in 0..4 {
+                    let pick = |x: u32| (if i == 0 { x } else { x >> 4 }) & 0xF;
+                    let nibs = [pick(a), pick(c), pick(e), pick(g)];
+                    for (j, &nib) in nibs.iter().enumerate() {
+                        let at = base_idx * 16 + i + j * 4;
+                        let gate = gate_bitmask(nib);
+                        out.fwht_out[at] = (nib << 12) as u16;
+                        out.cs_out[at] = (((nib as i32) - 8).unsigned_abs() << 12) as u16;
+                        out.nibble_probs[at] = gate;
+                        sum += gate as u32;
+                    }
+                }
+                let post_mean = (sum >> 4) as u16;
+                if post_mean < tg_lane_min[lane] {
+                    tg_lane_min[lane] = post_mean;
+                }
+                t.post_mean = Some(post_mean);
+            }
+
+            // Phase 2: `if (tid < lane_count) atomic_min(global, tg_lane_min[tid])`.
+            // Reads past the threadgroup array are treated as 0xFFFF (no-op).
+            for (tid, t) in regs.iter().enumerate() {
+                if t.post_mean.is_some() && (tid as u32) < input.lane_count {
+                    let v = tg_lane_min.get(tid).copied().unwrap_or(0xFFFF) as u32;
+                    out.global_lane_min = out.global_lane_min.min(v);
+                }
+            }
+
+            // Phase 3: combine with the global minimum and flag submissions.
+            let min_entropy = out.global_lane_min as u16;
+            for (tid, t) in regs.iter().enumerate() {
+                let Some(post_mean) = t.post_mean else { continue };
+                let (lane, nibble_idx) = (tid / NIBBLES, tid % NIBBLES);
+                let base_idx = cell_index(lane, nibble_idx, nonce_iter);
+
+                let combined_post = post_mean.min(min_entropy);
+                out.posterior_out[base_idx] = combined_post;
+                out.adaptive_feedback[base_idx] = combined_post;
+                if combined_post < mask_threshold {
+                    continue;
+                }
+                out.digest_out[base_idx * 8..base_idx * 8 + 8].copy_from_slice(&t.state);
+                out.debug_flags[base_idx] = 0;
+                out.submit_mask[base_idx] = 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_helpers::SHA256_IV;
+
+    #[test]
+    fn masked_cells_pass_posterior_through() {
+        let mut input = KernelInputs::new(2, SHA256_IV);
+        input.adaptive_params[0] = 0x1_8000; // truncates to 0x8000
+        for (i, p) in input.posterior_in.iter_mut().enumerate() {
+            *p = if i % 2 == 0 { 0x7FFF } else { 0xFFFF };
+        }
+        let mut out = KernelBuffers::new(2);
+        FusedKernelEmulator::new().dispatch(&input, &mut out);
+
+        for i in (0..out.cells()).step_by(2) {
+            assert_eq!(out.submit_mask[i], 0);
+            assert_eq!(out.debug_flags[i], 3);
+            assert_eq!(out.posterior_out[i], 0x7FFF);
+            assert!(out.fwht_out[i * 16..i * 16 + 16].iter().all(|&v| v == 0));
+        }
+    }
+
+    #[test]
+    fn unmasked_cells_fill_every_written_buffer() {
+        let input = KernelInputs::new(1, SHA256_IV);
+        let mut out = KernelBuffers::new(1);
+        FusedKernelEmulator::new().dispatch(&input, &mut out);
+
+        // Threshold 0: nothing is masked, but the global minimum starts at 0
+        // so every combined posterior collapses to 0 and still submits.
+        assert_eq!(out.submitted().count(), out.cells());
+        assert!(out.posterior_out.iter().all(|&p| p == 0));
+
+        // Every nibble thread of a lane runs the same rounds, so cell 0 of
+        // each nibble holds midstate + round8(midstate).
+        let mut expect = SHA256_IV;
+        sha256_round8(&mut expect, &[0; 8]);
+        for (s, iv) in expect.iter_mut().zip(SHA256_IV) {
+            *s = s.wrapping_add(iv);
+        }
+        for nibble in 0..NIBBLES {
+            let idx = nibble * KERNEL_NONCES_PER_THREAD;
+            assert_eq!(&out.digest_out[idx * 8..idx * 8 + 8], &expect);
+            let nib = expect[0] & 0xF;
+            assert_eq!(out.fwht_out[idx * 16], (nib << 12) as u16);
+            assert_eq!(out.nibble_probs[idx * 16], gate_bitmask(nib));
+        }
+        assert!(KernelBuffers::new(1).diff(&out).contains(&("submit_mask", 0)));
+        assert!(out.diff(&out.clone()).is_empty());
+    }
+
+    #[test]
+    fn cell_position_inverts_cell_index() {
+        let cells = KernelBuffers::new(3).cells();
+        for idx in 0..cells {
+            let (lane, nibble, iter) = cell_position(idx);
+            assert!(lane < 3 && nibble < NIBBLES && iter < KERNEL_NONCES_PER_THREAD);
+            assert_eq!(cell_index(lane, nibble, iter), idx);
+        }
+        assert_eq!(cell_position(KERNEL_NONCES_PER_THREAD + 1), (0, 1, 1));
+    }
+}
@@ -0,0 +1,244 @@
+// src/hashrate.rs
+//! Hashrate from what the backends actually finished.
+//!
+//! Every scan is fed in with the hashes it completed. Rates are
+//! exponentially weighted over 10 s, 1 m, 5 m and 15 m — the same decay
+//! `uptime` uses for load averages — overall, per backend and per lane.
+//!
+//! Shares are a second, independent estimate: a hash meets a difficulty-D
+//! target once per `D * HASHES_PER_DIFF1` tries on average, so share
+//! difficulty found per second is an "effective" hashrate. A backend that
+//! over-reports its hash count shows a reported rate well above it.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::backend::Telemetry;
+
+/// Expected hashes per difficulty-1 share: 2^256 / (diff-1 target + 1).
+pub const HASHES_PER_DIFF1: f64 = 4_295_032_833.0;
+
+/// Window lengths of `Rates`, in order.
+pub const WINDOWS: [Duration; 4] = [
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(15 * 60),
+];
+
+/// Hashes per second over each of `WINDOWS`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rates {
+    pub s10: f64,
+    pub m1: f64,
+    pub m5: f64,
+    pub m15: f64,
+}
+
+impl Rates {
+    pub fn as_mhs(&self) -> [f32; 4] {
+        [self.s10, self.m1, self.m5, self.m15].map(|r| (r / 1e6) as f32)
+    }
+}
+
+/// A running total with EWMA rates, advanced on wall-clock time.
+#[derive(Clone, Debug)]
+struct Counter {
+    total: f64,
+    pending: f64,
+    last: Instant,
+    rates: [f64; 4],
+}
+
+impl Counter {
+    fn new(since: Instant) -> Self {
+        Self { total: 0.0, pending: 0.0, last: since, rates: [0.0; 4] }
+    }
+
+    /// `amount` finished since the last call. Same-instant calls are folded
+    /// into the next interval.
+    fn add(&mut self, amount: f64, now: Instant) {
+        self.total += amount;
+        self.pending += amount;
+        let dt = now.saturating_duration_since(self.last).as_secs_f64();
+        if dt <= 0.0 {
+            return;
+        }
+        let rate = std::mem::take(&mut self.pending) / dt;
+        for (value, window) in self.rates.iter_mut().zip(WINDOWS) {
+            let alpha = 1.0 - (-dt / window.as_secs_f64()).exp();
+            *value += alpha * (rate - *value);
+        }
+        self.last = now;
+    }
+
+    fn rates(&self) -> Rates {
+        let [s10, m1, m5, m15] = self.rates;
+        Rates { s10, m1, m5, m15 }
+    }
+}
+
+pub struct HashrateMeter {
+    started: Instant,
+    all: Counter,
+    backends: BTreeMap<&'static str, Counter>,
+    lanes: Vec<Counter>,
+    effective: Counter,
+    pending_share_work: f64,
+    shares: u64,
+}
+
+impl HashrateMeter {
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    pub fn starting_at(started: Instant) -> Self {
+        Self {
+            started,
+            all: Counter::new(started),
+            backends: BTreeMap::new(),
+            lanes: Vec::new(),
+            effective: Counter::new(started),
+            pending_share_work: 0.0,
+            shares: 0,
+        }
+    }
+
+    pub fn record_scan(&mut self, backend: &'static str, telemetry: &Telemetry) {
+        self.record_scan_at(backend, telemetry, Instant::now());
+    }
+
+    /// Count a finished scan. This is also the clock tick for the effective
+    /// rate, so shares recorded since the previous scan are folded in here.
+    pub fn record_scan_at(&mut self, backend: &'static str, telemetry: &Telemetry, now: Instant) {
+        let hashes = telemetry.hashes as f64;
+        self.all.add(hashes, now);
+        let since = now.checked_sub(telemetry.elapsed).unwrap_or(self.started).max(self.started);
+        self.backends.entry(backend).or_insert_with(|| Counter::new(since)).add(hashes, now);
+
+        if self.lanes.len() < telemetry.lane_hashes.len() {
+            self.lanes.resize(telemetry.lane_hashes.len(), Counter::new(since));
+        }
+        for (lane, &h) in self.lanes.iter_mut().zip(&telemetry.lane_hashes) {
+            lane.add(h as f64, now);
+        }
+
+        let work = std::mem::take(&mut self.pending_share_work);
+        self.effective.add(work, now);
+    }
+
+    /// A verified hash meeting a target of `difficulty` (1.0 = diff-1).
+    pub fn record_share(&mut self, difficulty: f64) {
+        self.shares += 1;
+        self.pending_share_work += difficulty * HASHES_PER_DIFF1;
+    }
+
+    pub fn total_hashes(&self) -> u64 {
+        self.all.total as u64
+    }
+
+    pub fn shares(&self) -> u64 {
+        self.shares
+    }
+
+    pub fn rates(&self) -> Rates {
+        self.all.rates()
+    }
+
+    pub fn backend_rates(&self) -> impl Iterator<Item = (&'static str, Rates)> + '_ {
+        self.backends.iter().map(|(name, c)| (*name, c.rates()))
+    }
+
+    pub fn lane_rates(&self) -> Vec<Rates> {
+        self.lanes.iter().map(Counter::rates).collect()
+    }
+
+    /// Share-derived hashrate. Noisy until a few dozen shares are in.
+    pub fn effective(&self) -> Rates {
+        self.effective.rates()
+    }
+
+    /// Reported over effective hashrate on the 15-minute window, once
+    /// `min_shares` shares make the estimate meaningful. Near 1.0 for an
+    /// honest backend.
+    pub fn reported_to_effective(&self, min_shares: u64) -> Option<f64> {
+        let effective = self.effective().m15;
+        (self.shares >= min_shares && effective > 0.0).then(|| self.rates().m15 / effective)
+    }
+}
+
+impl Default for HashrateMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{available_backends, select_backend, WorkUnit};
+
+    fn scan(hashes: u64, lanes: usize) -> Telemetry {
+        Telemetry {
+            hashes,
+            elapsed: Duration::from_millis(500),
+            avg_post: vec![],
+            lane_hashes: vec![hashes / lanes.max(1) as u64; lanes],
+        }
+    }
+
+    #[test]
+    fn windows_converge_at_their_own_speed() {
+        let t0 = Instant::now();
+        let mut meter = HashrateMeter::starting_at(t0);
+        // 1 MH/s for 60 seconds, one scan a second.
+        for s in 1..=60 {
+            meter.record_scan_at("cpu", &scan(1_000_000, 4), t0 + Duration::from_secs(s));
+        }
+        let r = meter.rates();
+        assert_eq!(meter.total_hashes(), 60_000_000);
+        assert!((r.s10 - 1e6).abs() < 0.01e6, "{r:?}");
+        assert!(r.s10 > r.m1 && r.m1 > r.m5 && r.m5 > r.m15);
+        assert!((r.m1 - 0.632e6).abs() < 0.01e6, "{r:?}");
+
+        let (name, backend) = meter.backend_rates().next().unwrap();
+        assert_eq!(name, "cpu");
+        assert!((backend.s10 - 1e6).abs() < 0.01e6);
+        let lanes = meter.lane_rates();
+        assert_eq!(lanes.len(), 4);
+        assert!((lanes[3].s10 - 0.25e6).abs() < 0.01e6);
+    }
+
+    #[test]
+    fn cpu_backends_report_lane_rates() {
+        let work = WorkUnit::from_header_bytes(&[7; 80], 0, 50_000, [0; 32]);
+        for name in available_backends().into_iter().filter(|&n| n != "metal") {
+            let mut backend = select_backend(Some(name)).unwrap();
+            let telemetry = backend.scan(&work).unwrap().telemetry;
+            assert_eq!(telemetry.lane_hashes.iter().sum::<u64>(), 50_000, "{name}");
+
+            let mut meter = HashrateMeter::new();
+            meter.record_scan(backend.name(), &telemetry);
+            assert!(!meter.lane_rates().is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn effective_rate_exposes_over_reporting() {
+        let t0 = Instant::now();
+        let mut meter = HashrateMeter::starting_at(t0);
+        // Claims 10x the hashes its shares account for.
+        let true_rate = HASHES_PER_DIFF1 / 10.0;
+        for s in 1..=3_600u64 {
+            if s % 10 == 0 {
+                meter.record_share(1.0);
+            }
+            meter.record_scan_at("liar", &scan((true_rate * 10.0) as u64, 0), t0 + Duration::from_secs(s));
+        }
+        assert_eq!(meter.shares(), 360);
+        let ratio = meter.reported_to_effective(100).unwrap();
+        assert!((ratio - 10.0).abs() < 0.5, "{ratio}");
+        assert_eq!(meter.reported_to_effective(1_000), None);
+    }
+}
@@ -0,0 +1,223 @@
+// src/header.rs
+//! The 80-byte block header as the hashing backends see it.
+//!
+//! SHA-256d of a header is two compressions for the inner hash plus one for
+//! the outer. Bytes 0..64 (version, prevhash, most of the merkle root) do
+//! not change while scanning nonces, so their compression state — the
+//! midstate — is computed once. The remaining 12 bytes before the nonce
+//! (merkle root tail, time, bits) are the three tail words.
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::consensus::{deserialize, serialize};
+
+use crate::backend::{cpu::sha256d_from_midstate, WorkUnit};
+use crate::sha_helpers::{sha256_midstate, target_from_bits};
+
+/// Byte offset of the nonce inside the 80-byte block header.
+pub const NONCE_OFFSET: usize = 76;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderWork {
+    bytes: [u8; 80],
+    midstate: [u32; 8],
+}
+
+impl HeaderWork {
+    pub fn from_bytes(bytes: &[u8; 80]) -> Self {
+        let mut first = [0u8; 64];
+        first.copy_from_slice(&bytes[..64]);
+        Self { bytes: *bytes, midstate: sha256_midstate(&first) }
+    }
+
+    /// Header bytes 0..76 as little-endian words, the layout produced by
+    /// `prepare_block_header`. The nonce is zero.
+    pub fn from_words(words: &[u32; 19]) -> Self {
+        let mut bytes = [0u8; 80];
+        for (i, w) in words.iter().enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&w.to_le_bytes());
+        }
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn from_header(header: &BlockHeader) -> Self {
+        let mut bytes = [0u8; 80];
+        bytes.copy_from_slice(&serialize(header));
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_header(&self) -> BlockHeader {
+        deserialize(&self.bytes).expect("80 bytes always decode as a header")
+    }
+
+    pub fn bytes(&self) -> &[u8; 80] {
+        &self.bytes
+    }
+
+    pub fn words(&self) -> [u32; 19] {
+        let mut words = [0u32; 19];
+        for (i, w) in words.iter_mut().enumerate() {
+            *w = self.word(i * 4);
+        }
+        words
+    }
+
+    /// SHA-256 compression state after header bytes 0..64.
+    pub fn midstate(&self) -> [u32; 8] {
+        self.midstate
+    }
+
+    /// Header bytes 64..76 (merkle root tail, time, bits), read little-endian.
+    pub fn tail(&self) -> [u32; 3] {
+        [self.word(64), self.word(68), self.word(72)]
+    }
+
+    pub fn version(&self) -> u32 {
+        self.word(0)
+    }
+
+    /// The version is in the first block, so this recomputes the midstate.
+    pub fn with_version(&self, version: u32) -> Self {
+        let mut bytes = self.bytes;
+        bytes[..4].copy_from_slice(&version.to_le_bytes());
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn time(&self) -> u32 {
+        self.word(68)
+    }
+
+    /// The time is in the tail, so the midstate is kept.
+    pub fn with_time(&self, time: u32) -> Self {
+        let mut work = self.clone();
+        work.bytes[68..72].copy_from_slice(&time.to_le_bytes());
+        work
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.word(72)
+    }
+
+    pub fn nonce(&self) -> u32 {
+        self.word(NONCE_OFFSET)
+    }
+
+    /// Setting the nonce does not touch the midstate.
+    pub fn set_nonce(&mut self, nonce: u32) {
+        self.bytes[NONCE_OFFSET..].copy_from_slice(&nonce.to_le_bytes());
+    }
+
+    /// Double SHA-256 of the header with `nonce`, in internal byte order.
+    pub fn hash(&self, nonce: u32) -> [u8; 32] {
+        sha256d_from_midstate(&self.midstate, &self.tail(), nonce)
+    }
+
+    /// A backend scan of `nonce_count` nonces from `nonce_start` against
+    /// the header's own bits.
+    pub fn work_unit(&self, nonce_start: u32, nonce_count: u32) -> WorkUnit {
+        WorkUnit {
+            midstate: self.midstate,
+            tail: self.tail(),
+            nonce_start,
+            nonce_count,
+            target: target_from_bits(self.bits()),
+        }
+    }
+
+    fn word(&self, at: usize) -> u32 {
+        u32::from_le_bytes([self.bytes[at], self.bytes[at + 1], self.bytes[at + 2], self.bytes[at + 3]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::hash_meets_target;
+    use crate::sha_helpers::{compute_midstate, prepare_block_header};
+
+    struct Vector {
+        header: &'static str,
+        hash: &'static str,
+        time: u32,
+        bits: u32,
+        nonce: u32,
+    }
+
+    const GENESIS: Vector = Vector {
+        header: "0100000000000000000000000000000000000000000000000000000000000000\
+                 000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa\
+                 4b1e5e4a29ab5f49ffff001d1dac2b7c",
+        hash: "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        time: 1231006505,
+        bits: 0x1d00ffff,
+        nonce: 2083236893,
+    };
+
+    const BLOCK_125552: Vector = Vector {
+        header: "0100000081cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a3080000\
+                 00000000e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0\
+                 f1fc122bc7f5d74df2b9441a42a14695",
+        hash: "00000000000000001e8d6829a8a21adc5d38d0a473b144b6765798e61f98bd1d",
+        time: 1305998791,
+        bits: 0x1a44b9f2,
+        nonce: 2504433986,
+    };
+
+    fn header_bytes(v: &Vector) -> [u8; 80] {
+        hex::decode(v.header).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn mainnet_headers_hash_from_midstate() {
+        for v in [GENESIS, BLOCK_125552] {
+            let bytes = header_bytes(&v);
+            let work = HeaderWork::from_bytes(&bytes);
+            assert_eq!(work.time(), v.time);
+            assert_eq!(work.bits(), v.bits);
+            assert_eq!(work.nonce(), v.nonce);
+            assert_eq!(work.tail()[1..], [v.time, v.bits]);
+
+            let mut hash = work.hash(v.nonce);
+            hash.reverse();
+            assert_eq!(hex::encode(hash), v.hash);
+            assert!(work.work_unit(v.nonce, 1).verify(v.nonce));
+            assert!(!hash_meets_target(&work.hash(v.nonce ^ 1), &target_from_bits(v.bits)));
+        }
+    }
+
+    #[test]
+    fn round_trips_through_block_header() {
+        for v in [GENESIS, BLOCK_125552] {
+            let work = HeaderWork::from_bytes(&header_bytes(&v));
+            let header = work.to_header();
+            assert_eq!(header.block_hash().to_string(), v.hash);
+            assert_eq!(HeaderWork::from_header(&header), work);
+
+            // The word layout drops the nonce and nothing else.
+            let mut from_words = HeaderWork::from_words(&work.words());
+            assert_eq!(from_words.midstate(), work.midstate());
+            from_words.set_nonce(v.nonce);
+            assert_eq!(from_words, work);
+        }
+    }
+
+    #[test]
+    fn template_header_matches_mainnet() {
+        let v = BLOCK_125552;
+        let work = HeaderWork::from_bytes(&header_bytes(&v));
+        let header = work.to_header();
+        let template = crate::rpc::BlockTemplate::from_result(serde_json::json!({
+            "version": 1,
+            "previousblockhash": header.prev_blockhash.to_string(),
+            "transactions": [],
+            "coinbasevalue": 5_000_000_000u64,
+            "curtime": v.time,
+            "bits": format!("{:08x}", v.bits),
+            "height": 125_552,
+        }))
+        .unwrap();
+
+        let words = prepare_block_header(&template, &header.merkle_root);
+        assert_eq!(words, work.words());
+        assert_eq!(compute_midstate(&words), work.midstate());
+    }
+}
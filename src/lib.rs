@@ -0,0 +1,31 @@
+// src/lib.rs
+//! Library half of the miner. `main.rs` wires these modules together; tests
+//! and tools link against them directly.
+
+pub mod adaptive;
+pub mod backend;
+pub mod coinbase;
+pub mod constants;
+pub mod dp_table;
+pub mod emulator;
+#[cfg(feature = "metal")]
+pub mod gpu;
+pub mod hashrate;
+pub mod header;
+pub mod longpoll;
+pub mod mitm;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock_bitcoind;
+pub mod ntime;
+pub mod payout;
+pub mod pipeline;
+pub mod rpc;
+pub mod stratum;
+pub mod stratum_v2;
+pub mod sha_helpers;
+pub mod ui;
+pub mod version_rolling;
+pub mod work;
+
+pub use adaptive::MinerMetrics;
+pub use sha_helpers::merkle_root;
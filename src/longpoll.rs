@@ -0,0 +1,135 @@
+// src/longpoll.rs
+//! BIP22 long polling.
+//!
+//! A background task keeps a `getblocktemplate` call with the last
+//! `longpollid` open. The node answers it when the chain tip moves (or, in
+//! Bitcoin Core, when the mempool has changed and a minute has passed), and
+//! the new template is published on a watch channel. If nothing comes back
+//! within `REFRESH_INTERVAL` the call is dropped and a plain request picks
+//! up fee changes. Nodes that hand out no `longpollid` are polled.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::rpc::{Backoff, BitcoinRpc, BlockTemplate};
+
+/// HTTP timeout of a held long-poll request. Longer than `REFRESH_INTERVAL`
+/// so the refresh, not the timeout, is what ends a quiet poll.
+pub const LONGPOLL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How long a template may go without a refresh for fee updates.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Poll interval for nodes without long polling.
+pub const FALLBACK_POLL: Duration = Duration::from_secs(2);
+
+/// The newest template. `None` until the node has answered once.
+pub type TemplateReceiver = watch::Receiver<Option<Arc<BlockTemplate>>>;
+
+pub struct TemplateFeed {
+    pub templates: TemplateReceiver,
+    /// One message per distinct failure, cleared by the next success.
+    pub errors: mpsc::UnboundedReceiver<String>,
+    pub task: JoinHandle<()>,
+}
+
+impl TemplateFeed {
+    /// Start the long-poll task. It stops once every template receiver is
+    /// dropped.
+    pub fn spawn(rpc: BitcoinRpc, refresh: Duration) -> Self {
+        let (tx, templates) = watch::channel(None);
+        let (error_tx, errors) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(rpc, tx, error_tx, refresh));
+        Self { templates, errors, task }
+    }
+}
+
+async fn run(
+    rpc: BitcoinRpc,
+    tx: watch::Sender<Option<Arc<BlockTemplate>>>,
+    errors: mpsc::UnboundedSender<String>,
+    refresh: Duration,
+) {
+    // The client retries a single call; this is for a node that stays down.
+    let backoff = Backoff { max: Duration::from_secs(30), ..Backoff::default() };
+    let mut longpollid: Option<String> = None;
+    let mut failures = 0;
+    let mut last_error: Option<String> = None;
+
+    while !tx.is_closed() {
+        let fetched = match &longpollid {
+            Some(id) => match tokio::time::timeout(refresh, rpc.long_poll_template(id, LONGPOLL_TIMEOUT)).await {
+                Ok(reply) => reply,
+                Err(_) => rpc.get_block_template().await,
+            },
+            None => rpc.get_block_template().await,
+        };
+        match fetched {
+            Ok(template) => {
+                failures = 0;
+                last_error = None;
+                longpollid = template.longpollid.clone();
+                publish(&tx, template);
+                if longpollid.is_none() {
+                    tokio::time::sleep(FALLBACK_POLL.min(refresh)).await;
+                }
+            }
+            Err(e) => {
+                longpollid = None;
+                let message = e.to_string();
+                if last_error.as_ref() != Some(&message) {
+                    let _ = errors.send(message.clone());
+                    last_error = Some(message);
+                }
+                tokio::time::sleep(backoff.delay(failures, rand::random())).await;
+                failures += 1;
+            }
+        }
+    }
+}
+
+/// Replace the current template, waking receivers only if it changed.
+fn publish(tx: &watch::Sender<Option<Arc<BlockTemplate>>>, template: BlockTemplate) -> bool {
+    tx.send_if_modified(|current| {
+        if current.as_deref() == Some(&template) {
+            return false;
+        }
+        *current = Some(Arc::new(template));
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(curtime: u32) -> BlockTemplate {
+        BlockTemplate::from_result(serde_json::json!({
+            "version": 0x2000_0000,
+            "previousblockhash": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054",
+            "transactions": [],
+            "coinbasevalue": 625_000_000u64,
+            "longpollid": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a0541",
+            "curtime": curtime,
+            "bits": "17053894",
+            "height": 800_000,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_changed_templates_wake_the_miner() {
+        let (tx, mut rx) = watch::channel(None);
+        assert!(publish(&tx, template(1)));
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().as_ref().unwrap().curtime, 1);
+
+        assert!(!publish(&tx, template(1)));
+        assert!(!rx.has_changed().unwrap());
+        assert!(publish(&tx, template(2)));
+        assert!(rx.has_changed().unwrap());
+    }
+}
@@ -1,56 +1,30 @@
 use std::fs;
 use std::path::PathBuf;
-use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use reqwest::Client;
-use metal::*;
-use crate::constants::LANES;
-use metal::MTLCommandBufferStatus;
-
-mod dp_table;
-use dp_table::*;
-mod adaptive;
-use adaptive::*;
-mod constants;
-use constants::{
-    MITM_STATE_U32_WORDS, NIBBLES, GATE_LUT_SIZE, CHAOS_LUT_SIZE,
-    DEFAULT_GATE_LUT, DEFAULT_CHAOS_LUT
+
+use rust_metal_miner::adaptive::{MinerMetrics, UiMessage};
+use rust_metal_miner::backend::{bench_backends, benchmark, hash_meets_target, select_backend, ScanResult};
+use rust_metal_miner::hashrate::HashrateMeter;
+use rust_metal_miner::longpoll::{TemplateFeed, REFRESH_INTERVAL};
+use rust_metal_miner::payout::{Payout, RemainderRule};
+use rust_metal_miner::pipeline::mine_template;
+use rust_metal_miner::rpc::{
+    propose_template, try_and_submit_nonce, BitcoinRpc, BlockTemplate, BlockchainInfo, SubmitCounts, SubmitOutcome,
+    TemplateHealth, DEFAULT_RPC_URL,
 };
-mod ui;
-use ui::*;
-mod coinbase;
-use coinbase::*;
-mod rpc;
-use rpc::*;
-mod sha_helpers;
-use sha_helpers::*;
-mod mitm;
-use bitcoin::consensus::deserialize;
-use bitcoin_hashes::sha256d;
-use crate::sha_helpers::merkle_root;
-
-// ----------------- SHA / Crypto -----------------
-use generic_array::GenericArray;
-use sha2::compress256;
-
-// ----------------- Adaptive Masks -----------------
-struct AdaptiveMasks {
-    pub mask16: u32,
-}
-impl AdaptiveMasks {
-    fn new() -> Self {
-        Self { mask16: 0xFFFF_FFFF }
-    }
-    fn update_from_telemetry(&mut self, weights: &[f32], lanes: usize) {
-        let avg = weights.iter().copied().sum::<f32>() / lanes as f32;
-        if avg > 0.05 {
-            self.mask16 = (self.mask16 >> 1) | 1;
-        } else if avg < 0.01 {
-            self.mask16 = (self.mask16 << 1) | 1;
-        }
-    }
-}
+use rust_metal_miner::sha_helpers::{target_difficulty, target_from_bits, DIFF1_BITS};
+use rust_metal_miner::stratum::client::{ClientConfig, ClientEvent, StratumClient, StratumWork};
+use rust_metal_miner::stratum::server::{ServerConfig, StratumServer};
+use rust_metal_miner::stratum::vardiff::VardiffConfig;
+use rust_metal_miner::stratum_v2::client::{ChannelKind, Sv2Client, Sv2Config, Sv2Event, Sv2Work};
+use rust_metal_miner::stratum_v2::noise::Authority;
+use rust_metal_miner::ui::run_ui;
+use rust_metal_miner::version_rolling::VersionRolling;
+use rust_metal_miner::work::TemplateWork;
+
+const COINBASE_MESSAGE: &str = "Power Of My Quettahashes / Jace 2020–∞";
 
 // ----------------- Helpers -----------------
 fn read_cookie(datadir: &str) -> String {
@@ -62,13 +36,293 @@ fn read_cookie(datadir: &str) -> String {
         .to_string()
 }
 
+/// Node at `BITCOIN_RPC_URL`, with the cookie from `BITCOIN_DATADIR`. The
+/// second value is a warning when no cookie was found.
+fn rpc_from_env() -> (BitcoinRpc, Option<String>) {
+    let datadir = std::env::var("BITCOIN_DATADIR").unwrap_or_else(|_| {
+        format!(
+            "{}/Library/Application Support/Bitcoin-Pruned",
+            std::env::var("HOME").unwrap_or_default()
+        )
+    });
+    let cookie = read_cookie(&datadir);
+    let warning = cookie.is_empty().then(|| format!("⚠️ Bitcoin cookie not found in {datadir}"));
+    let mut parts = cookie.trim().splitn(2, ':');
+    let rpc_user = parts.next().unwrap_or("__cookie__").to_string();
+    let rpc_pass = parts.next().unwrap_or("").to_string();
+    let rpc_url = std::env::var("BITCOIN_RPC_URL").unwrap_or_else(|_| DEFAULT_RPC_URL.to_string());
+    (BitcoinRpc::new(&rpc_url, &rpc_user, &rpc_pass), warning)
+}
+
+fn unix_now() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+/// The pool `STRATUM_URL` names, by protocol.
+enum PoolConfig {
+    V1(ClientConfig),
+    V2(Sv2Config),
+}
+
+/// `STRATUM_URL` (with `STRATUM_USER` and `STRATUM_PASS`) switches from
+/// solo mining against a node to mining shares for a pool. A
+/// `stratum2+tcp://` URL speaks Stratum V2 and needs the pool's
+/// `STRATUM_AUTHORITY_KEY`; only `STRATUM_V2_INSECURE=1` connects without
+/// one, accepting any pool key.
+fn stratum_from_env() -> Result<Option<PoolConfig>, String> {
+    let Some(address) = std::env::var("STRATUM_URL").ok().filter(|url| !url.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let user = std::env::var("STRATUM_USER").unwrap_or_default();
+    if !address.starts_with("stratum2+tcp://") {
+        return Ok(Some(PoolConfig::V1(ClientConfig {
+            address,
+            user,
+            pass: std::env::var("STRATUM_PASS").unwrap_or_else(|_| "x".to_string()),
+            version_rolling: version_rolling_from_env(),
+        })));
+    }
+    let insecure = std::env::var("STRATUM_V2_INSECURE").is_ok_and(|v| matches!(v.trim(), "1" | "on"));
+    let authority = match std::env::var("STRATUM_AUTHORITY_KEY") {
+        Ok(key) => Authority::parse(&key).map_err(|e| e.to_string())?,
+        Err(_) if insecure => Authority::Insecure,
+        Err(_) => {
+            return Err(format!(
+                "{address} needs the pool's STRATUM_AUTHORITY_KEY (or STRATUM_V2_INSECURE=1 to trust any pool key)"
+            ))
+        }
+    };
+    Ok(Some(PoolConfig::V2(Sv2Config {
+        address,
+        authority,
+        user_identity: user,
+        nominal_hash_rate: 0.0,
+        channel: ChannelKind::Standard,
+    })))
+}
+
+/// `MINER_VERSION_ROLLING`: unset, `0` or `off` disables it, `1` or `on`
+/// rolls the full BIP320 mask, anything else is read as a hex mask.
+fn version_rolling_from_env() -> Option<VersionRolling> {
+    let setting = std::env::var("MINER_VERSION_ROLLING").ok()?;
+    match setting.trim() {
+        "" | "0" | "off" => None,
+        "1" | "on" => Some(VersionRolling::bip320()),
+        mask => u32::from_str_radix(mask.trim_start_matches("0x"), 16).ok().map(VersionRolling::new),
+    }
+}
+
+/// `MINER_PAYOUT`: comma-separated `destination[=split]` entries, where a
+/// destination is an address or `script:<hex>` and a split is `12.5%` or
+/// `50000sat`; the entry without a split takes the rest.
+/// `MINER_PAYOUT_REMAINDER=first` gives rounding leftovers to the first
+/// output instead of the largest, and `MINER_PAYOUT_DUST` sets the dust
+/// limit in satoshis.
+fn payout_from_env() -> Result<Payout, String> {
+    let spec = std::env::var("MINER_PAYOUT").map_err(|_| "MINER_PAYOUT is not set".to_string())?;
+    let mut payout = Payout::parse(&spec).map_err(|e| format!("MINER_PAYOUT: {e}"))?;
+    if std::env::var("MINER_PAYOUT_REMAINDER").is_ok_and(|r| r.trim() == "first") {
+        payout = payout.with_remainder(RemainderRule::First);
+    }
+    if let Ok(dust) = std::env::var("MINER_PAYOUT_DUST") {
+        let limit = dust.trim().parse().map_err(|_| format!("MINER_PAYOUT_DUST: not a satoshi amount: {dust}"))?;
+        payout = payout.with_dust_limit(limit);
+    }
+    Ok(payout)
+}
+
+/// Refuse to mine when a payout address is for another chain than the
+/// node's. Waits out a node that is still starting.
+async fn check_payout_network(rpc: &BitcoinRpc, payout: &Payout) -> Result<(), String> {
+    loop {
+        match rpc.call::<BlockchainInfo>("getblockchaininfo", serde_json::json!([])).await {
+            Ok(info) => return payout.check_network(&info.chain).map_err(|e| e.to_string()),
+            Err(e) if e.is_transient() => tokio::time::sleep(Duration::from_secs(5)).await,
+            Err(e) => return Err(format!("{}: {e}", rpc.url())),
+        }
+    }
+}
+
+/// `--bench`: run every compiled-in backend over the same synthetic work and
+/// print its throughput, then exit.
+fn run_bench() {
+    const BENCH_NONCES: u32 = 1 << 22;
+    for name in bench_backends() {
+        let mut backend = match select_backend(Some(name)) {
+            Ok(b) => b,
+            Err(e) => {
+                println!("⚠️ {name}: {e}");
+                continue;
+            }
+        };
+        match benchmark(backend.as_mut(), BENCH_NONCES) {
+            Ok(r) => println!(
+                "⏱️ {:<8} {:>10.3} MH/s  {:>10} hashes  {:>8} hits  {:>8.3}s",
+                name,
+                r.telemetry.hashes_per_sec() / 1e6,
+                r.telemetry.hashes,
+                r.nonces.len(),
+                r.telemetry.elapsed.as_secs_f64()
+            ),
+            Err(e) => println!("❌ {name}: {e}"),
+        }
+    }
+}
+
+/// `--once`: fetch one template, mine it on the selected backend and submit
+/// the block, printing each step, then exit. Meant for regtest difficulty.
+async fn run_once() {
+    let (rpc, cookie_warning) = rpc_from_env();
+    if let Some(warning) = cookie_warning {
+        println!("{warning}");
+    }
+    let payout = match payout_from_env() {
+        Ok(p) => p,
+        Err(e) => return println!("❌ {e}"),
+    };
+    if let Err(e) = check_payout_network(&rpc, &payout).await {
+        return println!("❌ {e}");
+    }
+    let backend_name = std::env::var("MINER_BACKEND").ok();
+    let mut backend = match select_backend(backend_name.as_deref()) {
+        Ok(b) => b,
+        Err(e) => return println!("❌ {e}"),
+    };
+    let template = match rpc.get_block_template().await {
+        Ok(t) => t,
+        Err(e) => return println!("❌ {}: {e}", rpc.url()),
+    };
+    println!(
+        "📄 Template for height {} with {} transactions, bits {:08x}",
+        template.height,
+        template.transactions.len(),
+        template.bits
+    );
+    let message = COINBASE_MESSAGE.as_bytes();
+    let mined = mine_template(&rpc, template, backend.as_mut(), &payout, message, unix_now(), u64::MAX).await;
+    match mined {
+        Ok((found, outcome)) => {
+            println!("🎯 Nonce {:#010x} after {} hashes on {}", found.nonce, found.hashes, backend.name());
+            println!("{outcome}");
+        }
+        Err(e) => println!("❌ {e}"),
+    }
+}
+
+/// `STRATUM_VARDIFF=off` fixes the share difficulty; otherwise it is
+/// retargeted for one share every `STRATUM_SHARE_INTERVAL` seconds
+/// (default 10).
+fn vardiff_from_env() -> Option<VardiffConfig> {
+    if std::env::var("STRATUM_VARDIFF").is_ok_and(|v| matches!(v.trim(), "0" | "off")) {
+        return None;
+    }
+    let mut config = VardiffConfig::default();
+    if let Some(secs) = std::env::var("STRATUM_SHARE_INTERVAL").ok().and_then(|s| s.parse::<f64>().ok()) {
+        config.target_interval = Duration::from_secs_f64(secs.max(0.1));
+    }
+    Some(config)
+}
+
+/// `--serve`: run the Stratum server on `STRATUM_LISTEN` (default
+/// `0.0.0.0:3333`) for external miners, with jobs from the node's templates
+/// and shares starting at `STRATUM_DIFFICULTY`. Prints found blocks and
+/// per-worker counts.
+async fn run_server() {
+    let (rpc, cookie_warning) = rpc_from_env();
+    if let Some(warning) = cookie_warning {
+        println!("{warning}");
+    }
+    let payout = match payout_from_env() {
+        Ok(p) => p,
+        Err(e) => return println!("❌ {e}"),
+    };
+    if let Err(e) = check_payout_network(&rpc, &payout).await {
+        return println!("❌ {e}");
+    }
+    let config = ServerConfig {
+        listen: std::env::var("STRATUM_LISTEN").unwrap_or_else(|_| "0.0.0.0:3333".to_string()),
+        message: COINBASE_MESSAGE.as_bytes().to_vec(),
+        difficulty: std::env::var("STRATUM_DIFFICULTY").ok().and_then(|d| d.parse().ok()).unwrap_or(1024.0),
+        vardiff: vardiff_from_env(),
+        payout,
+    };
+    let mut feed = TemplateFeed::spawn(rpc.clone(), REFRESH_INTERVAL);
+    let server = match StratumServer::start(config.clone(), rpc, feed.templates.clone()).await {
+        Ok(s) => s,
+        Err(e) => return println!("❌ {}: {e}", config.listen),
+    };
+    println!("🏊 Stratum server on {} at difficulty {}", server.local_addr(), config.difficulty);
+
+    let mut reported = 0;
+    let mut template_errors_reported = 0;
+    let mut stats_time = Instant::now();
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        while let Ok(message) = feed.errors.try_recv() {
+            println!("❌ {message}");
+        }
+        let blocks = server.blocks();
+        for outcome in &blocks[reported..] {
+            println!("🎯 {outcome}");
+        }
+        reported = blocks.len();
+        let template_errors = server.template_errors();
+        for error in &template_errors[template_errors_reported..] {
+            println!("❌ {error}");
+        }
+        template_errors_reported = template_errors.len();
+        if stats_time.elapsed() >= Duration::from_secs(60) {
+            for (worker, s) in server.workers() {
+                println!(
+                    "👷 {worker}: {} accepted, {} stale, {} duplicate, {} low difficulty, {} invalid, {} blocks",
+                    s.accepted, s.stale, s.duplicate, s.low_difficulty, s.invalid, s.blocks
+                );
+            }
+            stats_time = Instant::now();
+        }
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    if std::env::args().any(|a| a == "--bench") {
+        run_bench();
+        return;
+    }
+    if std::env::args().any(|a| a == "--once") {
+        run_once().await;
+        return;
+    }
+    if std::env::args().any(|a| a == "--serve") {
+        run_server().await;
+        return;
+    }
+    let pool = match stratum_from_env() {
+        Ok(p) => p,
+        Err(e) => return println!("❌ {e}"),
+    };
+    // Pool mining pays out through the pool; solo mining needs a payout
+    // before the UI takes over the terminal.
+    let payout = match pool {
+        Some(_) => None,
+        None => {
+            let payout = match payout_from_env() {
+                Ok(p) => p,
+                Err(e) => return println!("❌ {e}"),
+            };
+            if let Err(e) = check_payout_network(&rpc_from_env().0, &payout).await {
+                return println!("❌ {e}");
+            }
+            Some(payout)
+        }
+    };
     let local = tokio::task::LocalSet::new();
-    local.run_until(async_main()).await;
+    local.run_until(async_main(pool, payout)).await;
 }
 
-async fn async_main() {
+async fn async_main(pool: Option<PoolConfig>, payout: Option<Payout>) {
     // ---------------- Metrics + UI Channels ----------------
     let metrics = Arc::new(RwLock::new(MinerMetrics::default()));
     let (metrics_tx, mut metrics_rx) = tokio::sync::mpsc::unbounded_channel::<MinerMetrics>();
@@ -95,202 +349,189 @@ async fn async_main() {
         }
     });
 
-    // ---------------- RPC Cookie ----------------
-    let cookie_path = PathBuf::from(format!(
-        "{}/Library/Application Support/Bitcoin-Pruned/.cookie",
-        std::env::var("HOME").unwrap()
-    ));
-    let cookie = std::fs::read_to_string(&cookie_path)
-        .expect("⚠️ Bitcoin cookie not found.");
-    let mut parts = cookie.trim().splitn(2, ':');
-    let rpc_user = parts.next().unwrap_or("__cookie__").to_string();
-    let rpc_pass = parts.next().unwrap_or("").to_string();
+    match pool {
+        Some(PoolConfig::V1(config)) => return pool_main(config, metrics_tx, ui_tx).await,
+        Some(PoolConfig::V2(config)) => return pool_v2_main(config, metrics_tx, ui_tx).await,
+        None => {}
+    }
+    let payout = payout.expect("checked in main");
 
-    // ---------------- Metal Setup ----------------
-    let device = Device::system_default().expect("❌ No Metal device found");
-    let command_queue = Arc::new(device.new_command_queue());
-
-    let metallib_path = format!("{}/shaders/kernels.metallib", env!("CARGO_MANIFEST_DIR"));
-    let library = device
-        .new_library_with_file(&metallib_path)
-        .expect("❌ Failed to load Metal library");
-
-    let fused_fn = library
-        .get_function("fused_sha256d_fwht_cs", None)
-        .expect("❌ Kernel not found");
-    let fused_pipeline = Arc::new(
-        device
-            .new_compute_pipeline_state_with_function(&fused_fn)
-            .expect("❌ Failed to create compute pipeline"),
-    );
+    // ---------------- RPC ----------------
+    let (rpc, cookie_warning) = rpc_from_env();
+    if let Some(warning) = cookie_warning {
+        let _ = ui_tx.send(UiMessage::Status(warning));
+    }
+    match rpc.snapshot().await {
+        Ok(node) => {
+            let sync = if node.chain.initialblockdownload {
+                format!(" (syncing, {:.1}%)", node.chain.verificationprogress * 100.0)
+            } else {
+                String::new()
+            };
+            let _ = ui_tx.send(UiMessage::Status(format!(
+                "⛓️ {} {} at height {}, difficulty {:.3e}{sync}",
+                rpc.url(),
+                node.chain.chain,
+                node.chain.blocks,
+                node.mining.difficulty
+            )));
+        }
+        Err(e) => {
+            let _ = ui_tx.send(UiMessage::Status(format!("⚠️ {}: {e}", rpc.url())));
+        }
+    }
 
-    // ---------------- Constants ----------------
-    const NONCES_PER_NIBBLE: usize = 128;
-    let total_threads = LANES * NIBBLES;
-    let nonce_base = Arc::new(AtomicU32::new(0));
-
-    // ---------------- GPU Buffers ----------------
-    let digest_buf_a = Arc::new(aligned_u32_buffer(&device, total_threads * NONCES_PER_NIBBLE * 8, false));
-    let digest_buf_b = Arc::new(aligned_u32_buffer(&device, total_threads * NONCES_PER_NIBBLE * 8, false));
-    let posterior_buf_a = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let posterior_buf_b = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let fwht_buf_a = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let fwht_buf_b = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let cs_buf_a = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let cs_buf_b = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let nibble_probs_buf = Arc::new(aligned_ushort_buffer(&device, total_threads * NIBBLES * 16, false));
-    let midstate_buf = Arc::new(aligned_u32_buffer(&device, LANES * 8, false));
-    let schedule_buf = Arc::new(aligned_u32_buffer(&device, LANES * 16, false));
-    let start_nonce_buf = Arc::new(aligned_u32_buffer(&device, LANES, false));
-    let adaptive_params_buf = Arc::new(aligned_u32_buffer(&device, 4, false));
-    let mitm_states_buf = Arc::new(aligned_u32_buffer(
-        &device,
-        total_threads * NONCES_PER_NIBBLE * MITM_STATE_U32_WORDS,
-        false,
-    ));
-    let adaptive_feedback_buf = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let debug_flags_buf = Arc::new(aligned_u32_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let submit_mask_buf = Arc::new(aligned_u32_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let shannon_entropy_buf = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let hamming_buf = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let monte_buf = Arc::new(aligned_ushort_buffer(&device, total_threads * NONCES_PER_NIBBLE, false));
-    let digest_out_len_buf = Arc::new(aligned_u32_buffer(&device, 1, false));
-    let nibble_probs_len_buf = Arc::new(aligned_u32_buffer(&device, 1, false));
-    let lane_count_buf = Arc::new(aligned_u32_buffer(&device, 1, false));
-    let iteration_counter_buf = Arc::new(aligned_u32_buffer(&device, 1, false));
-    let global_lane_min_int_buf = Arc::new(aligned_u32_buffer(&device, 1, false));
-    let gate_lut_buf = Arc::new(aligned_f32_buffer(&device, GATE_LUT_SIZE, true));
-    let chaos_lut_buf = Arc::new(aligned_f32_buffer(&device, CHAOS_LUT_SIZE, true));
-
-    unsafe {
-        let gate_ptr = gate_lut_buf.contents() as *mut f32;
-        let chaos_ptr = chaos_lut_buf.contents() as *mut f32;
-        gate_ptr.copy_from_nonoverlapping(DEFAULT_GATE_LUT.as_ptr(), GATE_LUT_SIZE);
-        chaos_ptr.copy_from_nonoverlapping(DEFAULT_CHAOS_LUT.as_ptr(), CHAOS_LUT_SIZE);
+    // ---------------- Hash Backend ----------------
+    let backend_name = std::env::var("MINER_BACKEND").ok();
+    let mut backend = select_backend(backend_name.as_deref()).expect("❌ Failed to start hash backend");
+    let _ = ui_tx.send(UiMessage::Status(format!("⚙️ Hash backend: {}", backend.name())));
+    let version_rolling = version_rolling_from_env();
+    if let Some(rolling) = version_rolling {
+        let _ = ui_tx.send(UiMessage::Status(format!("🔀 Version rolling mask {:#010x}", rolling.mask())));
     }
+    let mut work_source: Option<TemplateWork> = None;
 
     // ---------------- Main Mining Loop ----------------
-    let mut active_buffer = true;
-    let client = Client::new();
-    let mut in_flight_cmds: Vec<metal::CommandBuffer> = Vec::new();
     let mut last_metrics_time = Instant::now();
+    let mut meter = HashrateMeter::new();
+    let mut misreport_warned = false;
+    let mut submits = SubmitCounts::default();
+    let mut proposed_root = None;
+    let mut template_health = TemplateHealth::Unchecked;
+    let mut feed = TemplateFeed::spawn(rpc.clone(), REFRESH_INTERVAL);
+    let diff1_target = target_from_bits(DIFF1_BITS);
 
     loop {
         let loop_start = Instant::now();
 
         // ---------------- Coinbase & Block Template ----------------
-        let template = match fetch_block_template(
-            &client,
-            "http://127.0.0.1:8332",
-            &rpc_user,
-            &rpc_pass,
-        )
-        .await
-        {
-            Some(t) => t,
-            None => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+        while let Ok(message) = feed.errors.try_recv() {
+            let _ = ui_tx.send(UiMessage::Status(format!("❌ {message}")));
+        }
+        if work_source.is_none() || feed.templates.has_changed().unwrap_or(false) {
+            let Some(template) = feed.templates.borrow_and_update().clone() else {
+                // Nothing from the node yet; wait for the feed.
+                let _ = tokio::time::timeout(Duration::from_secs(1), feed.templates.changed()).await;
                 continue;
+            };
+            let template = BlockTemplate::clone(&template);
+            match work_source.as_mut() {
+                Some(w) => {
+                    if w.template().previous_block_hash != template.previous_block_hash {
+                        let _ = ui_tx.send(UiMessage::Status(format!("🆕 New block, mining height {}", template.height)));
+                    }
+                    if let Err(e) = w.update(template, unix_now()) {
+                        let _ = ui_tx.send(UiMessage::Status(format!("❌ {e}")));
+                    }
+                }
+                None => match TemplateWork::new(template, &payout, COINBASE_MESSAGE.as_bytes(), unix_now()) {
+                    Ok(w) => work_source = Some(w.with_version_rolling(version_rolling)),
+                    Err(e) => {
+                        let _ = ui_tx.send(UiMessage::Status(format!("❌ {e}")));
+                        let _ = tokio::time::timeout(Duration::from_secs(1), feed.templates.changed()).await;
+                        continue;
+                    }
+                },
             }
-        };
+        }
+        let source = work_source.as_mut().expect("set above");
 
-        let mut coinbase = build_coinbase_from_template_with_height(&template);
-        patch_insert_nonce_into_coinbase(&mut coinbase, 0);
-        if let Some(tx) = coinbase["result"]["transactions"].get_mut(0) {
-            if let Some(vin) = tx["vin"].get_mut(0) {
-                let mut script_bytes = hex::decode(vin["coinbase"].as_str().unwrap_or("")).unwrap_or_default();
-                script_bytes.extend_from_slice("Power Of My Quettahashes / Jace 2020–∞".as_bytes());
-                vin["coinbase"] = serde_json::Value::String(hex::encode(script_bytes));
-            }
+        // ---------------- Backend Scan ----------------
+        let extranonce = source.extranonce();
+        let mut work = source.next_unit(backend.batch_size(), unix_now());
+        if source.extranonce() != extranonce {
+            let _ = ui_tx.send(UiMessage::Status(format!("🔁 Extranonce rolled to {}", source.extranonce())));
         }
 
-        let header_words = prepare_block_header(&template);
-        let midstate = compute_midstate_with_nonce(&header_words, &template);
-        let schedule = precompute_schedule_with_nonce(&header_words, &template);
-
-        unsafe {
-            std::ptr::copy_nonoverlapping(midstate.as_ptr(), midstate_buf.contents() as *mut u32, midstate.len());
-            std::ptr::copy_nonoverlapping(schedule.as_ptr(), schedule_buf.contents() as *mut u32, schedule.len());
-            let start_ptr = start_nonce_buf.contents() as *mut u32;
-            for lane in 0..LANES {
-                *start_ptr.add(lane) = nonce_base.fetch_add(
-                    NONCES_PER_NIBBLE as u32 * NIBBLES as u32,
-                    Ordering::Relaxed,
-                );
+        // ---------------- BIP23 Proposal ----------------
+        // The merkle root changes with every new coinbase/template pairing.
+        let merkle_root = source.header().to_header().merkle_root;
+        if proposed_root != Some(merkle_root) {
+            proposed_root = Some(merkle_root);
+            let health = match propose_template(&rpc, source.template(), source.coinbase(), source.header()).await {
+                Ok(health) => health,
+                Err(e) => {
+                    let _ = ui_tx.send(UiMessage::Status(format!("⚠️ Template proposal failed: {e}")));
+                    TemplateHealth::Unchecked
+                }
+            };
+            if health != template_health {
+                let _ = ui_tx.send(UiMessage::Status(format!("🩺 Template {health}")));
+                template_health = health;
             }
         }
+        if !template_health.is_minable() {
+            // Wait for a template the node will take.
+            let _ = tokio::time::timeout(Duration::from_secs(1), feed.templates.changed()).await;
+            continue;
+        }
+        // Scan down to diff-1 (or the block target, if easier) so shares keep
+        // the effective hashrate honest; only block-target hits are submitted.
+        let block_target = work.target;
+        work.target = work.target.max(diff1_target);
+        let share_difficulty = target_difficulty(&work.target);
 
-        // ---------------- GPU Dispatch (Async) ----------------
-        let next_cmd_buf = command_queue.new_command_buffer();
-        let encoder = next_cmd_buf.new_compute_command_encoder();
-        encoder.set_compute_pipeline_state(&fused_pipeline);
-
-        encoder.set_buffer(0, Some(&*midstate_buf), 0);
-        encoder.set_buffer(1, Some(&*schedule_buf), 0);
-        encoder.set_buffer(2, Some(&*start_nonce_buf), 0);
-        encoder.set_buffer(3, Some(if active_buffer { &*digest_buf_a } else { &*digest_buf_b }), 0);
-        encoder.set_buffer(4, Some(if active_buffer { &*posterior_buf_b } else { &*posterior_buf_a }), 0);
-        encoder.set_buffer(5, Some(&*mitm_states_buf), 0);
-        encoder.set_buffer(6, Some(if active_buffer { &*fwht_buf_b } else { &*fwht_buf_a }), 0);
-        encoder.set_buffer(7, Some(if active_buffer { &*cs_buf_b } else { &*cs_buf_a }), 0);
-        encoder.set_buffer(8, Some(&*nibble_probs_buf), 0);
-        encoder.set_buffer(9, Some(&*adaptive_params_buf), 0);
-        encoder.set_buffer(10, Some(&*debug_flags_buf), 0);
-        encoder.set_buffer(11, Some(&*digest_out_len_buf), 0);
-        encoder.set_buffer(12, Some(&*nibble_probs_len_buf), 0);
-        encoder.set_buffer(13, Some(&*adaptive_feedback_buf), 0);
-        encoder.set_buffer(14, Some(&*shannon_entropy_buf), 0);
-        encoder.set_buffer(15, Some(&*submit_mask_buf), 0);
-        encoder.set_buffer(16, Some(&*hamming_buf), 0);
-        encoder.set_buffer(17, Some(&*monte_buf), 0);
-        encoder.set_buffer(18, Some(&*gate_lut_buf), 0);
-        encoder.set_buffer(19, Some(&*chaos_lut_buf), 0);
-        encoder.set_buffer(20, Some(if active_buffer { &*posterior_buf_a } else { &*posterior_buf_b }), 0);
-        encoder.set_buffer(21, Some(&*global_lane_min_int_buf), 0);
-
-        let tg_mem_size = (LANES * std::mem::size_of::<u16>()) as u64;
-        encoder.set_threadgroup_memory_length(tg_mem_size, 0);
-
-        let threads_per_group = MTLSize {
-            width: fused_pipeline.thread_execution_width() as u64,
-            height: 1,
-            depth: 1,
-        };
-        let grid_size = MTLSize {
-            width: NIBBLES as u64,
-            height: LANES as u64,
-            depth: NONCES_PER_NIBBLE as u64,
+        let result = match backend.scan(&work) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = ui_tx.send(UiMessage::Status(format!("❌ {e}")));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
         };
 
-        encoder.dispatch_threads(grid_size, threads_per_group);
-        encoder.end_encoding();
-        next_cmd_buf.commit();
-
-        in_flight_cmds.push(next_cmd_buf.to_owned());
-
-        // Remove completed buffers to prevent buildup
-        in_flight_cmds.retain(|cmd| cmd.status() != MTLCommandBufferStatus::Completed);
-
-        active_buffer = !active_buffer;
+        for nonce in result.nonces.iter().copied().filter(|&n| work.verify(n)) {
+            meter.record_share(share_difficulty);
+            if !hash_meets_target(&work.hash(nonce), &block_target) {
+                continue;
+            }
+            let _ = ui_tx.send(UiMessage::Status(format!("🎯 Candidate nonce {nonce:#010x}")));
+            let status = match try_and_submit_nonce(&rpc, source.template(), source.coinbase(), source.header(), nonce).await {
+                Ok(outcome) => {
+                    submits.record(&outcome);
+                    let icon = match outcome {
+                        SubmitOutcome::Accepted { .. } => "✅",
+                        SubmitOutcome::Duplicate { .. } | SubmitOutcome::Inconclusive { .. } => "⚠️",
+                        SubmitOutcome::BelowTarget | SubmitOutcome::Rejected { .. } => "❌",
+                    };
+                    format!("{icon} {outcome}")
+                }
+                Err(e) => format!("❌ submitblock failed: {e}"),
+            };
+            let _ = ui_tx.send(UiMessage::Status(status));
+        }
+        meter.record_scan(backend.name(), &result.telemetry);
 
         // ---------------- Metrics every 1000ms ----------------
         if last_metrics_time.elapsed() >= Duration::from_millis(1000) {
-            unsafe {
-                let posterior_slice = std::slice::from_raw_parts(
-                    if active_buffer { posterior_buf_b.contents() } else { posterior_buf_a.contents() } as *const u16,
-                    total_threads * NONCES_PER_NIBBLE
-                );
-                let avg_post: f32 = posterior_slice.iter().map(|&v| v as f32).sum::<f32>() / posterior_slice.len() as f32;
-                
-                let updated_metrics = MinerMetrics {
-                    avg_post: vec![avg_post],
-                    hashrate_mhs: 26.0, // maintain existing MH/s
-                    timestamp: Instant::now(),
-                    ..Default::default()
-                };
-                let _ = metrics_tx.send(updated_metrics);
-            }
+            let windows = meter.rates().as_mhs();
+            let updated_metrics = MinerMetrics {
+                avg_post: result.telemetry.avg_post.clone(),
+                hashrate_mhs: windows[0],
+                hashrate_windows: windows,
+                effective_mhs: meter.effective().as_mhs()[3],
+                lane_mhs: meter.lane_rates().iter().map(|r| r.as_mhs()[0]).collect(),
+                shares: meter.shares(),
+                submits,
+                template_health: template_health.clone(),
+                total_hashes: meter.total_hashes(),
+                last_hashrate: (result.telemetry.hashes_per_sec() / 1e6) as f32,
+                timestamp: Instant::now(),
+                ..Default::default()
+            };
+            let _ = metrics_tx.send(updated_metrics);
             last_metrics_time = Instant::now();
+
+            if let Some(ratio) = meter.reported_to_effective(32) {
+                let off = !(0.5..=2.0).contains(&ratio);
+                if off && !misreport_warned {
+                    let _ = ui_tx.send(UiMessage::Status(format!(
+                        "⚠️ {} reports {ratio:.2}x the hashrate its shares imply",
+                        backend.name()
+                    )));
+                }
+                misreport_warned = off;
+            }
         }
 
         // ---------------- Loop throttle (~4ms tick) ----------------
@@ -300,3 +541,235 @@ async fn async_main() {
         }
     }
 }
+
+/// Pool mining: scan the current job at the pool's share difficulty and
+/// submit every share. Reconnects after the connection drops.
+async fn pool_main(
+    config: ClientConfig,
+    metrics_tx: tokio::sync::mpsc::UnboundedSender<MinerMetrics>,
+    ui_tx: tokio::sync::mpsc::UnboundedSender<UiMessage>,
+) {
+    let backend_name = std::env::var("MINER_BACKEND").ok();
+    let mut backend = select_backend(backend_name.as_deref()).expect("❌ Failed to start hash backend");
+    let _ = ui_tx.send(UiMessage::Status(format!("⚙️ Hash backend: {}", backend.name())));
+    let mut meter = HashrateMeter::new();
+    let mut last_metrics_time = Instant::now();
+
+    loop {
+        let mut client = match StratumClient::connect(&config).await {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = ui_tx.send(UiMessage::Status(format!("❌ {}: {e}", config.address)));
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        let _ = ui_tx.send(UiMessage::Status(format!(
+            "🏊 {} as {}, extranonce1 {}",
+            config.address,
+            config.user,
+            hex::encode(client.extranonce1())
+        )));
+        let mut difficulty = 1.0;
+        let mut work: Option<StratumWork> = None;
+
+        let error = loop {
+            // Take everything the pool has sent without waiting on it.
+            let event = match work {
+                Some(_) => tokio::time::timeout(Duration::ZERO, client.next_event()).await.ok(),
+                None => Some(client.next_event().await),
+            };
+            if let Some(event) = event {
+                match event {
+                    // The new difficulty applies from the next job on.
+                    Ok(ClientEvent::Difficulty(d)) => difficulty = d,
+                    Ok(ClientEvent::Job(job)) => {
+                        if job.clean_jobs || work.is_none() {
+                            let _ = ui_tx.send(UiMessage::Status(format!(
+                                "🆕 Job {} at difficulty {difficulty}",
+                                job.job_id
+                            )));
+                        }
+                        work = Some(StratumWork::for_client(job, &client, difficulty));
+                    }
+                    Ok(ClientEvent::VersionMask(rolling)) => {
+                        if let Some(work) = work.as_mut() {
+                            work.set_version_rolling(rolling);
+                        }
+                        let mask = rolling.map_or(0, |r| r.mask());
+                        let _ = ui_tx.send(UiMessage::Status(format!("🔀 Pool version mask {mask:#010x}")));
+                    }
+                    Ok(ClientEvent::ShareResult { accepted: true, .. }) => {}
+                    Ok(ClientEvent::ShareResult { reason, .. }) => {
+                        let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+                        let _ = ui_tx.send(UiMessage::Status(format!("❌ Share rejected: {reason}")));
+                    }
+                    Ok(ClientEvent::Reconnect { .. }) => break "pool asked for a reconnect".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+                continue;
+            }
+            let source = work.as_mut().expect("a job arrived");
+
+            let unit = source.next_unit(backend.batch_size());
+            let result = match backend.scan(&unit) {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = ui_tx.send(UiMessage::Status(format!("❌ {e}")));
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let mut submit_error = None;
+            for nonce in result.nonces.iter().copied().filter(|&n| unit.verify(n)) {
+                meter.record_share(source.difficulty());
+                if let Err(e) = client.submit(&source.share(nonce)).await {
+                    submit_error = Some(e.to_string());
+                    break;
+                }
+            }
+            if let Some(e) = submit_error {
+                break e;
+            }
+            meter.record_scan(backend.name(), &result.telemetry);
+
+            if last_metrics_time.elapsed() >= Duration::from_millis(1000) {
+                let _ = metrics_tx.send(pool_metrics(&meter, &result));
+                last_metrics_time = Instant::now();
+            }
+            // Let the pool's notifications in between scans.
+            tokio::task::yield_now().await;
+        };
+        let _ = ui_tx.send(UiMessage::Status(format!("⚠️ {}: {error}, reconnecting", config.address)));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+fn pool_metrics(meter: &HashrateMeter, result: &ScanResult) -> MinerMetrics {
+    let windows = meter.rates().as_mhs();
+    MinerMetrics {
+        avg_post: result.telemetry.avg_post.clone(),
+        hashrate_mhs: windows[0],
+        hashrate_windows: windows,
+        effective_mhs: meter.effective().as_mhs()[3],
+        lane_mhs: meter.lane_rates().iter().map(|r| r.as_mhs()[0]).collect(),
+        shares: meter.shares(),
+        total_hashes: meter.total_hashes(),
+        last_hashrate: (result.telemetry.hashes_per_sec() / 1e6) as f32,
+        timestamp: Instant::now(),
+        ..Default::default()
+    }
+}
+
+/// Pool mining over Stratum V2: one standard channel, scanned at the
+/// channel's target, every share submitted. Reconnects after the
+/// connection drops.
+async fn pool_v2_main(
+    mut config: Sv2Config,
+    metrics_tx: tokio::sync::mpsc::UnboundedSender<MinerMetrics>,
+    ui_tx: tokio::sync::mpsc::UnboundedSender<UiMessage>,
+) {
+    let backend_name = std::env::var("MINER_BACKEND").ok();
+    let mut backend = select_backend(backend_name.as_deref()).expect("❌ Failed to start hash backend");
+    let _ = ui_tx.send(UiMessage::Status(format!("⚙️ Hash backend: {}", backend.name())));
+    if config.authority == Authority::Insecure {
+        let _ = ui_tx.send(UiMessage::Status(format!("⚠️ {}: pool key not checked", config.address)));
+    }
+    // The pool sets the first target from this.
+    let batch = backend.batch_size();
+    if let Ok(r) = benchmark(backend.as_mut(), batch) {
+        config.nominal_hash_rate = r.telemetry.hashes_per_sec() as f32;
+    }
+    let mut meter = HashrateMeter::new();
+    let mut last_metrics_time = Instant::now();
+
+    loop {
+        let mut client = match Sv2Client::connect(&config).await {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = ui_tx.send(UiMessage::Status(format!("❌ {}: {e}", config.address)));
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        let _ = ui_tx.send(UiMessage::Status(format!(
+            "🏊 {} as {}, channel {}",
+            config.address,
+            config.user_identity,
+            client.channel().id
+        )));
+        let mut work: Option<Sv2Work> = None;
+
+        let error = loop {
+            // Take everything the pool has sent without waiting on it.
+            let event = match work {
+                Some(_) => tokio::time::timeout(Duration::ZERO, client.next_event()).await.ok(),
+                None => Some(client.next_event().await),
+            };
+            if let Some(event) = event {
+                match event {
+                    Ok(Sv2Event::Job { job, clean }) => {
+                        if clean || work.is_none() {
+                            let difficulty = target_difficulty(&client.channel().target);
+                            let _ = ui_tx.send(UiMessage::Status(format!(
+                                "🆕 Job {} at difficulty {difficulty}",
+                                job.job_id
+                            )));
+                        }
+                        work = Some(Sv2Work::new(job, client.channel()));
+                    }
+                    Ok(Sv2Event::Target(target)) => {
+                        if let Some(work) = work.as_mut() {
+                            work.set_target(target);
+                        }
+                    }
+                    Ok(Sv2Event::ExtranoncePrefix(_)) => {
+                        if let Some(work) = work.as_mut() {
+                            *work = Sv2Work::new(work.job().clone(), client.channel());
+                        }
+                    }
+                    Ok(Sv2Event::SharesAccepted { .. }) => {}
+                    Ok(Sv2Event::ShareRejected { reason, .. }) => {
+                        let _ = ui_tx.send(UiMessage::Status(format!("❌ Share rejected: {reason}")));
+                    }
+                    Ok(Sv2Event::ChannelClosed { reason }) => break format!("pool closed the channel: {reason}"),
+                    Ok(Sv2Event::Reconnect { .. }) => break "pool asked for a reconnect".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+                continue;
+            }
+            let source = work.as_mut().expect("a job arrived");
+
+            let unit = source.next_unit(backend.batch_size());
+            let result = match backend.scan(&unit) {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = ui_tx.send(UiMessage::Status(format!("❌ {e}")));
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let mut submit_error = None;
+            for nonce in result.nonces.iter().copied().filter(|&n| unit.verify(n)) {
+                meter.record_share(target_difficulty(source.target()));
+                if let Err(e) = client.submit(&source.share(nonce)).await {
+                    submit_error = Some(e.to_string());
+                    break;
+                }
+            }
+            if let Some(e) = submit_error {
+                break e;
+            }
+            meter.record_scan(backend.name(), &result.telemetry);
+
+            if last_metrics_time.elapsed() >= Duration::from_millis(1000) {
+                let _ = metrics_tx.send(pool_metrics(&meter, &result));
+                last_metrics_time = Instant::now();
+            }
+            // Let the pool's frames in between scans.
+            tokio::task::yield_now().await;
+        };
+        let _ = ui_tx.send(UiMessage::Status(format!("⚠️ {}: {error}, reconnecting", config.address)));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
@@ -12,8 +12,10 @@
 //! 10: steps_low  (u32)
 //! 11: steps_high (u32)     -> steps = steps_low | (steps_high << 32)
 use std::convert::TryInto;
+#[cfg(feature = "metal")]
 use std::sync::Arc;
 
+#[cfg(feature = "metal")]
 use metal::{Buffer, Device, MTLResourceOptions};
 
 /// Number of u32 words used to represent a single RhoState in the GPU buffer.
@@ -97,6 +99,7 @@ pub fn deserialize_u32_to_rho_states(slice: &[u32]) -> Vec<RhoState> {
 
 /// Allocate a Metal buffer sized to hold `count` RhoStates with `StorageModeShared`.
 /// Returns an `Arc<Buffer>` for convenient sharing.
+#[cfg(feature = "metal")]
 pub fn create_mitm_buffer(device: &Device, count: usize) -> Arc<Buffer> {
     let bytes = (count * MITM_STATE_BYTES) as u64;
     Arc::new(device.new_buffer(bytes, MTLResourceOptions::StorageModeShared))
@@ -104,6 +107,7 @@ pub fn create_mitm_buffer(device: &Device, count: usize) -> Arc<Buffer> {
 
 /// Write `states` into the provided Metal `buffer` (which must be large enough).
 /// This performs a direct memory write into the buffer `contents()` area.
+#[cfg(feature = "metal")]
 pub unsafe fn write_rho_states_to_buffer(buffer: &Buffer, states: &[RhoState]) {
     let u32_slice = serialize_rho_states_to_u32(states);
     let dst = buffer.contents() as *mut u32;
@@ -121,6 +125,7 @@ pub unsafe fn write_rho_states_to_buffer(buffer: &Buffer, states: &[RhoState]) {
 
 /// Read RhoStates from the provided Metal `buffer` and return Vec<RhoState>.
 /// This will read up to `count` states (or fewer if buffer is smaller).
+#[cfg(feature = "metal")]
 pub unsafe fn read_rho_states_from_buffer(buffer: &Buffer, count: usize) -> Vec<RhoState> {
     let available_u32 = (buffer.length() as usize) / 4;
     let want_u32 = count.saturating_mul(MITM_STATE_U32_WORDS);
@@ -131,6 +136,7 @@ pub unsafe fn read_rho_states_from_buffer(buffer: &Buffer, count: usize) -> Vec<
 }
 
 /// Convenience: initialize a Metal mitm buffer with `count` zeroed states.
+#[cfg(feature = "metal")]
 pub unsafe fn init_zeroed_mitm_buffer(device: &Device, count: usize) -> Arc<Buffer> {
     let buf = create_mitm_buffer(device, count);
     // zero the memory
@@ -147,10 +153,10 @@ mod tests {
         let mut states = Vec::new();
         for i in 0..3u64 {
             let mut val = [0u8; 32];
-            for j in 0..32 {
-                val[j] = (i as u8).wrapping_add(j as u8);
+            for (j, v) in val.iter_mut().enumerate() {
+                *v = (i as u8).wrapping_add(j as u8);
             }
-            states.push(RhoState::new(i * 0xFF00_FFu64, val, i * 12345));
+            states.push(RhoState::new(i * 0x00FF_00FF_u64, val, i * 12345));
         }
 
         let u32s = serialize_rho_states_to_u32(&states);
@@ -0,0 +1,394 @@
+// src/mock_bitcoind.rs
+//! A scripted bitcoind for tests. Only built with the `mock` feature (and
+//! for this crate's unit tests), so it never ships in the library.
+//!
+//! `MockBitcoind::start` serves JSON-RPC over HTTP on a local port, enough of
+//! it for `BitcoinRpc`, `TemplateFeed` and the miner binary: single and
+//! batched calls, basic auth, `getblocktemplate` with long polling and BIP23
+//! proposals, `submitblock`, `getmininginfo` and `getblockchaininfo`. Replies
+//! can be scripted per method (results, error objects, bare HTTP statuses,
+//! delays), and every submitted or proposed block is recorded.
+//!
+//! `examples/mock_bitcoind.rs` runs one on its own so the binary can be
+//! pointed at it with `BITCOIN_RPC_URL`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::hash_types::WitnessMerkleNode;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::sha_helpers::target_from_bits;
+
+/// Compact bits of the regtest proof-of-work limit.
+pub const REGTEST_BITS: u32 = 0x207f_ffff;
+
+/// Regtest block subsidy before the first halving at height 150.
+pub const REGTEST_SUBSIDY: u64 = 50 * 100_000_000;
+
+/// A scripted reply, used once in place of the default handler.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockReply {
+    Result(Value),
+    Error { code: i64, message: String },
+    /// A non-JSON reply with this HTTP status, e.g. 401 or 503.
+    Status(u16),
+    Delayed(Duration, Box<MockReply>),
+}
+
+#[derive(Default)]
+struct State {
+    template: Option<Value>,
+    template_seq: u64,
+    scripted: HashMap<String, VecDeque<MockReply>>,
+    submit_result: Value,
+    proposal_result: Value,
+    submitted: Vec<String>,
+    proposals: Vec<String>,
+    calls: Vec<String>,
+    auth: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Bumped with every new template; long polls wait on it.
+    seq: watch::Sender<u64>,
+}
+
+pub struct MockBitcoind {
+    url: String,
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+impl MockBitcoind {
+    /// Listen on an ephemeral local port. No template is set, so
+    /// `getblocktemplate` answers -10 (initial download) until one is.
+    pub async fn start() -> std::io::Result<Self> {
+        Self::bind("127.0.0.1:0").await
+    }
+
+    pub async fn bind(addr: &str) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let shared = Arc::new(Shared { state: Mutex::new(State::default()), seq: watch::channel(0).0 });
+        let task = tokio::spawn(serve(listener, shared.clone()));
+        Ok(Self { url, shared, task })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Reject calls without these basic-auth credentials with HTTP 401.
+    pub fn require_auth(&self, user: &str, pass: &str) {
+        self.shared.state().auth = Some(format!("Basic {}", base64(format!("{user}:{pass}").as_bytes())));
+    }
+
+    /// Serve `result` from now on and answer any waiting long polls. The
+    /// `longpollid` is filled in.
+    pub fn set_template(&self, result: Value) {
+        let mut state = self.shared.state();
+        state.template_seq += 1;
+        state.template = Some(result);
+        let seq = state.template_seq;
+        drop(state);
+        self.shared.seq.send_replace(seq);
+    }
+
+    /// The template being served, with its `longpollid`.
+    pub fn template(&self) -> Option<Value> {
+        self.shared.current_template()
+    }
+
+    /// Queue `reply` for the next call of `method`.
+    pub fn script(&self, method: &str, reply: MockReply) {
+        self.shared.state().scripted.entry(method.to_string()).or_default().push_back(reply);
+    }
+
+    /// What `submitblock` returns: `null` (accepted) by default, or a reject
+    /// reason such as `"high-hash"`.
+    pub fn set_submit_result(&self, result: Value) {
+        self.shared.state().submit_result = result;
+    }
+
+    /// What a BIP23 proposal returns; `null` by default.
+    pub fn set_proposal_result(&self, result: Value) {
+        self.shared.state().proposal_result = result;
+    }
+
+    /// Block hex of every `submitblock` call, in order.
+    pub fn submitted(&self) -> Vec<String> {
+        self.shared.state().submitted.clone()
+    }
+
+    /// Block hex of every proposal, in order.
+    pub fn proposals(&self) -> Vec<String> {
+        self.shared.state().proposals.clone()
+    }
+
+    /// Method names called so far, batched calls included.
+    pub fn calls(&self) -> Vec<String> {
+        self.shared.state().calls.clone()
+    }
+}
+
+impl Drop for MockBitcoind {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Status and body for one HTTP request body.
+    async fn handle(&self, auth: Option<&str>, body: &[u8]) -> (u16, String) {
+        if let Some(expected) = &self.state().auth {
+            if auth != Some(expected.as_str()) {
+                return (401, String::new());
+            }
+        }
+        let Ok(request) = serde_json::from_slice::<Value>(body) else {
+            let error = MockReply::Error { code: -32700, message: "Parse error".into() };
+            return (500, reply(&Value::Null, error).to_string());
+        };
+        match request {
+            Value::Array(calls) => {
+                let mut replies = Vec::new();
+                for call in &calls {
+                    match self.call(call).await {
+                        MockReply::Status(status) => return (status, format!("mock status {status}")),
+                        r => replies.push(reply(&call["id"], r)),
+                    }
+                }
+                (200, Value::Array(replies).to_string())
+            }
+            call => match self.call(&call).await {
+                MockReply::Status(status) => (status, format!("mock status {status}")),
+                r @ MockReply::Error { code: -32601, .. } => (404, reply(&call["id"], r).to_string()),
+                r @ MockReply::Error { .. } => (500, reply(&call["id"], r).to_string()),
+                r => (200, reply(&call["id"], r).to_string()),
+            },
+        }
+    }
+
+    /// Scripted or default reply to one call, after any delay.
+    async fn call(&self, call: &Value) -> MockReply {
+        let method = call["method"].as_str().unwrap_or_default();
+        let scripted = {
+            let mut state = self.state();
+            state.calls.push(method.to_string());
+            state.scripted.get_mut(method).and_then(VecDeque::pop_front)
+        };
+        let mut r = match scripted {
+            Some(r) => r,
+            None => self.default_reply(method, &call["params"]).await,
+        };
+        while let MockReply::Delayed(delay, next) = r {
+            tokio::time::sleep(delay).await;
+            r = *next;
+        }
+        r
+    }
+
+    async fn default_reply(&self, method: &str, params: &Value) -> MockReply {
+        match method {
+            "getblocktemplate" => self.getblocktemplate(&params[0]).await,
+            "submitblock" => {
+                let mut state = self.state();
+                state.submitted.push(params[0].as_str().unwrap_or_default().to_string());
+                MockReply::Result(state.submit_result.clone())
+            }
+            "getmininginfo" | "getblockchaininfo" => match self.state().template.as_ref() {
+                Some(template) => MockReply::Result(chain_info(method, template)),
+                None => MockReply::Result(chain_info(method, &json!({ "height": 0, "transactions": [] }))),
+            },
+            _ => MockReply::Error { code: -32601, message: "Method not found".into() },
+        }
+    }
+
+    async fn getblocktemplate(&self, request: &Value) -> MockReply {
+        if request["mode"] == "proposal" {
+            let mut state = self.state();
+            state.proposals.push(request["data"].as_str().unwrap_or_default().to_string());
+            return MockReply::Result(state.proposal_result.clone());
+        }
+        // A long poll waits until the template it saw has been replaced.
+        if let Some(id) = request["longpollid"].as_str() {
+            let mut seq = self.seq.subscribe();
+            while self.current_template().is_some_and(|t| t["longpollid"] == id) {
+                if seq.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+        match self.current_template() {
+            Some(template) => MockReply::Result(template),
+            None => MockReply::Error {
+                code: -10,
+                message: "Bitcoin Core is in initial sync and waiting for blocks...".into(),
+            },
+        }
+    }
+
+    fn current_template(&self) -> Option<Value> {
+        let state = self.state();
+        state.template.as_ref().map(|t| with_longpollid(t, state.template_seq))
+    }
+}
+
+fn with_longpollid(template: &Value, seq: u64) -> Value {
+    let mut template = template.clone();
+    let prev = template["previousblockhash"].as_str().unwrap_or_default().to_string();
+    template["longpollid"] = format!("{prev}{seq}").into();
+    template
+}
+
+fn chain_info(method: &str, template: &Value) -> Value {
+    let blocks = template["height"].as_u64().unwrap_or(1).saturating_sub(1);
+    if method == "getmininginfo" {
+        json!({
+            "blocks": blocks,
+            "difficulty": 4.656542373906925e-10,
+            "networkhashps": 0.0,
+            "pooledtx": template["transactions"].as_array().map_or(0, Vec::len),
+            "chain": "regtest",
+        })
+    } else {
+        json!({
+            "chain": "regtest",
+            "blocks": blocks,
+            "headers": blocks,
+            "bestblockhash": template["previousblockhash"],
+            "initialblockdownload": false,
+            "verificationprogress": 1.0,
+        })
+    }
+}
+
+fn reply(id: &Value, r: MockReply) -> Value {
+    match r {
+        MockReply::Error { code, message } => {
+            json!({ "result": null, "error": { "code": code, "message": message }, "id": id })
+        }
+        MockReply::Result(result) => json!({ "result": result, "error": null, "id": id }),
+        MockReply::Status(_) | MockReply::Delayed(..) => unreachable!("resolved by Shared::call"),
+    }
+}
+
+/// A regtest `getblocktemplate` result with no transactions: minimum
+/// difficulty, full subsidy, time mutable, and the witness commitment of
+/// an empty block.
+pub fn regtest_template(height: u32, previous_block_hash: &str, curtime: u32) -> Value {
+    let commitment = Block::compute_witness_commitment(&WitnessMerkleNode::default(), &[0u8; 32]);
+    let mut script = vec![0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+    script.extend_from_slice(&commitment[..]);
+    json!({
+        "capabilities": ["proposal"],
+        "version": 0x2000_0000,
+        "rules": ["csv", "!segwit", "taproot"],
+        "vbavailable": {},
+        "vbrequired": 0,
+        "previousblockhash": previous_block_hash,
+        "transactions": [],
+        "coinbaseaux": {},
+        "coinbasevalue": REGTEST_SUBSIDY,
+        "target": hex::encode(target_from_bits(REGTEST_BITS)),
+        "mintime": curtime - 600,
+        "mutable": ["time", "transactions", "prevblock"],
+        "noncerange": "00000000ffffffff",
+        "sigoplimit": 80_000,
+        "sizelimit": 4_000_000,
+        "weightlimit": 4_000_000,
+        "curtime": curtime,
+        "bits": format!("{REGTEST_BITS:08x}"),
+        "height": height,
+        "default_witness_commitment": hex::encode(script),
+    })
+}
+
+async fn serve(listener: TcpListener, shared: Arc<Shared>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(connection(stream, shared.clone()));
+    }
+}
+
+/// HTTP/1.1 with keep-alive, one request at a time.
+async fn connection(stream: TcpStream, shared: Arc<Shared>) {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let mut content_length = 0;
+        let mut auth = None;
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else { continue };
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => auth = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+        let mut body = vec![0u8; content_length];
+        if stream.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let (status, body) = shared.handle(auth.as_deref(), &body).await;
+        let response = format!(
+            "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            reason(status),
+            body.len()
+        );
+        if stream.get_mut().write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Mock",
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
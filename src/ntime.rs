@@ -0,0 +1,143 @@
+// src/ntime.rs
+//! Which header times a template allows.
+//!
+//! BIP22/23 templates give `curtime`, `mintime` (median time past + 1),
+//! optionally `maxtime`, and a `mutable` list that says whether the miner
+//! may move the time at all (`time`), only forward (`time/increment`) or
+//! only back (`time/decrement`). Consensus adds an upper bound of two hours
+//! past the (network-adjusted) clock.
+//!
+//! The policy picks the header time for the current clock, and lets the
+//! work generator roll it a little further ahead as extra nonce space.
+
+use crate::rpc::BlockTemplate;
+
+/// Blocks more than this far ahead of network time are rejected.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// How far past the wall clock the time may be rolled for nonce space. Well
+/// inside `MAX_FUTURE_BLOCK_TIME` so found blocks propagate normally.
+pub const MAX_ROLL_AHEAD: u32 = 10 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NTimePolicy {
+    curtime: u32,
+    mintime: u32,
+    maxtime: Option<u32>,
+    can_increase: bool,
+    can_decrease: bool,
+}
+
+impl NTimePolicy {
+    pub fn from_template(template: &BlockTemplate) -> Self {
+        let has = |name: &str| template.mutable.iter().any(|m| m == "time" || m == name);
+        Self {
+            curtime: template.curtime,
+            mintime: template.mintime.unwrap_or(template.curtime),
+            maxtime: template.maxtime,
+            can_increase: has("time/increment"),
+            can_decrease: has("time/decrement"),
+        }
+    }
+
+    /// Earliest time the template accepts.
+    pub fn lower(&self) -> u32 {
+        if self.can_decrease { self.mintime } else { self.curtime }
+    }
+
+    /// Latest time the template and the future-time rule accept at `now`;
+    /// never below `lower()`.
+    pub fn upper(&self, now: u32) -> u32 {
+        let upper = if self.can_increase {
+            let limit = now.saturating_add(MAX_FUTURE_BLOCK_TIME);
+            self.maxtime.map_or(limit, |max| max.min(limit))
+        } else {
+            self.curtime
+        };
+        upper.max(self.lower())
+    }
+
+    /// Header time to use when the wall clock reads `now`.
+    pub fn time_at(&self, now: u32) -> u32 {
+        now.clamp(self.lower(), self.upper(now))
+    }
+
+    /// The time after `current` when rolling for nonce space, if allowed.
+    pub fn roll(&self, current: u32, now: u32) -> Option<u32> {
+        let limit = self.upper(now).min(now.saturating_add(MAX_ROLL_AHEAD));
+        let next = current.checked_add(1)?;
+        (next <= limit).then_some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(mutable: &[&str], maxtime: Option<u32>) -> BlockTemplate {
+        let mut t = BlockTemplate::from_result(serde_json::json!({
+            "version": 0x2000_0000,
+            "previousblockhash": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054",
+            "transactions": [],
+            "coinbasevalue": 625_000_000u64,
+            "curtime": 1_000_000,
+            "mintime": 999_000,
+            "mutable": mutable,
+            "bits": "17053894",
+            "height": 800_000,
+        }))
+        .unwrap();
+        t.maxtime = maxtime;
+        t
+    }
+
+    #[test]
+    fn time_follows_the_clock_inside_the_window() {
+        let p = NTimePolicy::from_template(&template(&["time", "transactions", "prevblock"], None));
+        assert_eq!(p.time_at(1_000_500), 1_000_500);
+        // A slow clock cannot go below mintime.
+        assert_eq!(p.time_at(998_000), 999_000);
+        assert_eq!(p.upper(1_000_500), 1_000_500 + MAX_FUTURE_BLOCK_TIME);
+
+        let capped = NTimePolicy::from_template(&template(&["time"], Some(1_000_100)));
+        assert_eq!(capped.time_at(1_000_500), 1_000_100);
+        assert_eq!(capped.roll(1_000_100, 1_000_500), None);
+    }
+
+    #[test]
+    fn mutable_flags_restrict_direction() {
+        let fixed = NTimePolicy::from_template(&template(&["transactions"], None));
+        assert_eq!(fixed.time_at(1_000_500), 1_000_000);
+        assert_eq!(fixed.time_at(990_000), 1_000_000);
+        assert_eq!(fixed.roll(1_000_000, 1_000_500), None);
+
+        let forward = NTimePolicy::from_template(&template(&["time/increment"], None));
+        assert_eq!(forward.time_at(990_000), 1_000_000);
+        assert_eq!(forward.time_at(1_000_500), 1_000_500);
+
+        let back = NTimePolicy::from_template(&template(&["time/decrement"], None));
+        assert_eq!(back.time_at(999_500), 999_500);
+        assert_eq!(back.time_at(1_000_500), 1_000_000);
+    }
+
+    #[test]
+    fn rolling_stops_at_the_roll_ahead_limit() {
+        let p = NTimePolicy::from_template(&template(&["time"], None));
+        let now = 1_000_000;
+        assert_eq!(p.roll(now, now), Some(now + 1));
+        assert_eq!(p.roll(now + MAX_ROLL_AHEAD - 1, now), Some(now + MAX_ROLL_AHEAD));
+        assert_eq!(p.roll(now + MAX_ROLL_AHEAD, now), None);
+    }
+
+    #[test]
+    fn window_never_inverts() {
+        // `BlockTemplate::from_result` refuses both windows; build them by hand.
+        let late_min = NTimePolicy { mintime: 1_000_100, ..NTimePolicy::from_template(&template(&["time/decrement"], None)) };
+        assert_eq!(late_min.upper(1_000_500), late_min.lower());
+        assert_eq!(late_min.time_at(1_000_500), 1_000_100);
+
+        let early_max = NTimePolicy::from_template(&template(&["time"], Some(998_000)));
+        assert!(early_max.upper(1_000_500) >= early_max.lower());
+        assert_eq!(early_max.time_at(1_000_500), early_max.lower());
+    }
+}
@@ -0,0 +1,369 @@
+// src/payout.rs
+//! Where the coinbase value goes.
+//!
+//! A payout is one or more outputs, each to an address or a raw
+//! scriptPubKey, with a fixed amount, a percentage or the remainder. Fixed
+//! amounts come off first and percentages are of what is left, rounded
+//! down. Whatever is still unpaid (everything left for a remainder output,
+//! or the rounding when the percentages add up to 100%) goes to the
+//! remainder output, or to the one `RemainderRule` picks. An output below
+//! the dust limit is dropped and its value goes the same way, so the
+//! outputs always add up to exactly the coinbase value.
+
+use std::str::FromStr;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::TxOut;
+use bitcoin::util::address::Payload;
+use bitcoin::{Address, Network};
+
+/// Percentages are kept in hundredths of a percent.
+const FULL_SHARE: u32 = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Address(Address),
+    Script(Script),
+}
+
+impl Destination {
+    pub fn script_pubkey(&self) -> Script {
+        match self {
+            Destination::Address(address) => address.script_pubkey(),
+            Destination::Script(script) => script.clone(),
+        }
+    }
+}
+
+impl FromStr for Destination {
+    type Err = PayoutError;
+
+    /// An address, or `script:` followed by a hex scriptPubKey.
+    fn from_str(s: &str) -> Result<Self, PayoutError> {
+        match s.strip_prefix("script:") {
+            Some(script) => hex::decode(script)
+                .map(|bytes| Destination::Script(Script::from(bytes)))
+                .map_err(|e| PayoutError::Parse(format!("{s}: {e}"))),
+            None => Address::from_str(s).map(Destination::Address).map_err(|e| PayoutError::Parse(format!("{s}: {e}"))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// Satoshis.
+    Fixed(u64),
+    /// Hundredths of a percent of what the fixed amounts leave.
+    Percent(u32),
+    /// Whatever the other outputs leave.
+    Remainder,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayoutOutput {
+    pub destination: Destination,
+    pub split: Split,
+}
+
+/// Who gets the rounding when no output takes the remainder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RemainderRule {
+    /// The largest output, the first of them on a tie.
+    #[default]
+    Largest,
+    First,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayoutError {
+    NoOutputs,
+    MultipleRemainders,
+    /// The percentages add up to more than 100% (in hundredths).
+    OverAllocated(u32),
+    /// No remainder output and the percentages leave some of the value
+    /// unpaid (in hundredths).
+    Unallocated(u32),
+    Parse(String),
+    UnknownChain(String),
+    WrongNetwork { address: String, chain: String },
+    /// The fixed amounts are more than the coinbase pays.
+    Insufficient { fixed: u64, coinbase_value: u64 },
+}
+
+impl std::fmt::Display for PayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |hundredths: &u32| *hundredths as f64 / 100.0;
+        match self {
+            PayoutError::NoOutputs => write!(f, "payout has no outputs"),
+            PayoutError::MultipleRemainders => write!(f, "payout has more than one remainder output"),
+            PayoutError::OverAllocated(p) => write!(f, "payout percentages add up to {}%", percent(p)),
+            PayoutError::Unallocated(p) => {
+                write!(f, "payout percentages add up to {}% and there is no remainder output", percent(p))
+            }
+            PayoutError::Parse(msg) => write!(f, "invalid payout: {msg}"),
+            PayoutError::UnknownChain(chain) => write!(f, "unknown chain {chain:?}"),
+            PayoutError::WrongNetwork { address, chain } => write!(f, "payout address {address} is not for {chain}"),
+            PayoutError::Insufficient { fixed, coinbase_value } => {
+                write!(f, "fixed payouts of {fixed} sat exceed the coinbase value of {coinbase_value} sat")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PayoutError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Payout {
+    outputs: Vec<PayoutOutput>,
+    remainder: RemainderRule,
+    /// `None` uses each script's own dust value.
+    dust_limit: Option<u64>,
+}
+
+impl Payout {
+    pub fn new(outputs: Vec<PayoutOutput>) -> Result<Self, PayoutError> {
+        if outputs.is_empty() {
+            return Err(PayoutError::NoOutputs);
+        }
+        let remainders = outputs.iter().filter(|o| o.split == Split::Remainder).count();
+        if remainders > 1 {
+            return Err(PayoutError::MultipleRemainders);
+        }
+        let percent: u64 = outputs
+            .iter()
+            .map(|o| match o.split {
+                Split::Percent(p) => p as u64,
+                _ => 0,
+            })
+            .sum();
+        if percent > FULL_SHARE as u64 {
+            return Err(PayoutError::OverAllocated(percent.min(u32::MAX as u64) as u32));
+        }
+        if remainders == 0 && percent < FULL_SHARE as u64 {
+            return Err(PayoutError::Unallocated(percent as u32));
+        }
+        Ok(Self { outputs, remainder: RemainderRule::default(), dust_limit: None })
+    }
+
+    /// Everything to one destination.
+    pub fn single(destination: Destination) -> Self {
+        Self::new(vec![PayoutOutput { destination, split: Split::Remainder }]).expect("one remainder output")
+    }
+
+    /// Comma-separated `destination[=split]` entries, where the split is a
+    /// percentage (`12.5%`, at most two decimals) or satoshis (`50000sat`).
+    /// An entry without a split takes the remainder.
+    pub fn parse(spec: &str) -> Result<Self, PayoutError> {
+        let outputs = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (destination, split) = match entry.split_once('=') {
+                    Some((destination, split)) => (destination, parse_split(split.trim())?),
+                    None => (entry, Split::Remainder),
+                };
+                Ok(PayoutOutput { destination: destination.trim().parse()?, split })
+            })
+            .collect::<Result<_, PayoutError>>()?;
+        Self::new(outputs)
+    }
+
+    pub fn with_remainder(mut self, rule: RemainderRule) -> Self {
+        self.remainder = rule;
+        self
+    }
+
+    /// Drop outputs below `limit` satoshis instead of below their script's
+    /// dust value. Zero keeps every output.
+    pub fn with_dust_limit(mut self, limit: u64) -> Self {
+        self.dust_limit = Some(limit);
+        self
+    }
+
+    pub fn outputs(&self) -> &[PayoutOutput] {
+        &self.outputs
+    }
+
+    /// Refuse addresses for another network than `chain`, as
+    /// `getblockchaininfo` names it. Raw scripts are not checked.
+    pub fn check_network(&self, chain: &str) -> Result<(), PayoutError> {
+        let network = match chain {
+            "main" => Network::Bitcoin,
+            "test" | "testnet4" => Network::Testnet,
+            "signet" => Network::Signet,
+            "regtest" => Network::Regtest,
+            _ => return Err(PayoutError::UnknownChain(chain.to_string())),
+        };
+        for output in &self.outputs {
+            if let Destination::Address(address) = &output.destination {
+                if !address_is_for(address, network) {
+                    return Err(PayoutError::WrongNetwork { address: address.to_string(), chain: chain.to_string() });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The coinbase outputs for `coinbase_value`, in configured order.
+    pub fn tx_outputs(&self, coinbase_value: u64) -> Result<Vec<TxOut>, PayoutError> {
+        let fixed = self
+            .outputs
+            .iter()
+            .map(|o| match o.split {
+                Split::Fixed(amount) => amount,
+                _ => 0,
+            })
+            .fold(0u64, u64::saturating_add);
+        if fixed > coinbase_value {
+            return Err(PayoutError::Insufficient { fixed, coinbase_value });
+        }
+        let rest = coinbase_value - fixed;
+        let mut outputs: Vec<TxOut> = self
+            .outputs
+            .iter()
+            .map(|o| TxOut {
+                value: match o.split {
+                    Split::Fixed(amount) => amount,
+                    Split::Percent(p) => (rest as u128 * p as u128 / FULL_SHARE as u128) as u64,
+                    Split::Remainder => 0,
+                },
+                script_pubkey: o.destination.script_pubkey(),
+            })
+            .collect();
+
+        let recipient = match self.outputs.iter().position(|o| o.split == Split::Remainder) {
+            Some(index) => index,
+            None => match self.remainder {
+                RemainderRule::First => 0,
+                // `max_by_key` keeps the last of equals, so search reversed.
+                RemainderRule::Largest => {
+                    outputs.iter().enumerate().rev().max_by_key(|(_, o)| o.value).map_or(0, |(i, _)| i)
+                }
+            },
+        };
+        let paid: u64 = outputs.iter().map(|o| o.value).sum();
+        outputs[recipient].value += coinbase_value - paid;
+
+        // Dust goes to the recipient too.
+        let mut kept = Vec::with_capacity(outputs.len());
+        let mut folded = 0;
+        let mut recipient_at = 0;
+        for (i, output) in outputs.into_iter().enumerate() {
+            let limit = self.dust_limit.unwrap_or_else(|| output.script_pubkey.dust_value());
+            if i != recipient && output.value < limit {
+                folded += output.value;
+                continue;
+            }
+            if i == recipient {
+                recipient_at = kept.len();
+            }
+            kept.push(output);
+        }
+        kept[recipient_at].value += folded;
+        if kept[recipient_at].value == 0 && kept.len() > 1 {
+            kept.remove(recipient_at);
+        }
+        debug_assert_eq!(kept.iter().map(|o| o.value).sum::<u64>(), coinbase_value);
+        Ok(kept)
+    }
+}
+
+fn parse_split(split: &str) -> Result<Split, PayoutError> {
+    let bad = || PayoutError::Parse(format!("split {split:?} is not a percentage or a satoshi amount"));
+    if let Some(sats) = split.strip_suffix("sat") {
+        return sats.trim().parse().map(Split::Fixed).map_err(|_| bad());
+    }
+    let percent = split.strip_suffix('%').ok_or_else(bad)?.trim();
+    let (whole, fraction) = percent.split_once('.').unwrap_or((percent, ""));
+    if fraction.len() > 2 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(bad());
+    }
+    let whole: u32 = whole.parse().map_err(|_| bad())?;
+    let fraction: u32 = format!("{fraction:0<2}").parse().map_err(|_| bad())?;
+    whole.checked_mul(100).and_then(|w| w.checked_add(fraction)).map(Split::Percent).ok_or_else(bad)
+}
+
+/// Testnet and signet share prefixes, and regtest shares testnet's base58
+/// ones; only `bcrt1` is regtest's own.
+fn address_is_for(address: &Address, network: Network) -> bool {
+    match (network, &address.payload) {
+        (Network::Bitcoin, _) => address.network == Network::Bitcoin,
+        (Network::Regtest, Payload::WitnessProgram { .. }) => address.network == Network::Regtest,
+        (Network::Regtest, _) => address.network != Network::Bitcoin,
+        (Network::Testnet | Network::Signet, _) => matches!(address.network, Network::Testnet | Network::Signet),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAINNET: &str = "bc1qyux0tnvrusd9deq89h8er0ml4clhdwetp6ljp2";
+    const REGTEST: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+    const OP_RETURN: &str = "script:6a0474657374";
+
+    #[test]
+    fn splits_total_the_coinbase_value() {
+        let payout = Payout::parse(&format!("{MAINNET}=33.33%, script:0014{}=1000sat, {REGTEST}", "00".repeat(20))).unwrap();
+        let outputs = payout.tx_outputs(625_001_234).unwrap();
+        let values: Vec<u64> = outputs.iter().map(|o| o.value).collect();
+        // 33.33% of what the 1000 sat leave, rounded down; the rest to the remainder.
+        assert_eq!(values, [208_312_577, 1000, 416_687_657]);
+        assert_eq!(outputs[0].script_pubkey, Address::from_str(MAINNET).unwrap().script_pubkey());
+
+        // Percentages that cover everything leave only the rounding.
+        let two_ways = |rule| {
+            let payout = Payout::parse(&format!("{MAINNET}=40%,{REGTEST}=60%")).unwrap().with_remainder(rule);
+            payout.tx_outputs(1_000_003).unwrap().iter().map(|o| o.value).collect::<Vec<_>>()
+        };
+        assert_eq!(two_ways(RemainderRule::Largest), [400_001, 600_002]);
+        assert_eq!(two_ways(RemainderRule::First), [400_002, 600_001]);
+
+        // Dust is folded into the remainder, with the default or a set limit.
+        let payout = Payout::parse(&format!("{MAINNET}=0.01%,{REGTEST}")).unwrap();
+        assert_eq!(payout.tx_outputs(1_000_000).unwrap().len(), 1);
+        assert_eq!(payout.clone().with_dust_limit(0).tx_outputs(1_000_000).unwrap()[0].value, 100);
+        assert_eq!(payout.with_dust_limit(101).tx_outputs(1_000_000).unwrap()[0].value, 1_000_000);
+        // An OP_RETURN has no dust value.
+        let payout = Payout::parse(&format!("{OP_RETURN}=0sat,{MAINNET}")).unwrap();
+        assert_eq!(payout.tx_outputs(5000).unwrap().iter().map(|o| o.value).collect::<Vec<_>>(), [0, 5000]);
+
+        assert_eq!(
+            Payout::parse(&format!("{MAINNET}=6000sat,{REGTEST}")).unwrap().tx_outputs(5000),
+            Err(PayoutError::Insufficient { fixed: 6000, coinbase_value: 5000 })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_configurations() {
+        assert_eq!(Payout::parse(""), Err(PayoutError::NoOutputs));
+        assert_eq!(Payout::parse(&format!("{MAINNET},{REGTEST}")), Err(PayoutError::MultipleRemainders));
+        assert_eq!(Payout::parse(&format!("{MAINNET}=60.5%,{REGTEST}=40%")), Err(PayoutError::OverAllocated(10_050)));
+        assert_eq!(Payout::parse(&format!("{MAINNET}=60%,{REGTEST}=1000sat")), Err(PayoutError::Unallocated(6000)));
+        for split in ["1.005%", "ten%", "5", "-1sat"] {
+            assert!(matches!(Payout::parse(&format!("{MAINNET}={split},{REGTEST}")), Err(PayoutError::Parse(_))), "{split}");
+        }
+        assert!(matches!(Payout::parse("bc1qnotanaddress"), Err(PayoutError::Parse(_))));
+    }
+
+    #[test]
+    fn addresses_must_match_the_node_network() {
+        let mainnet = Payout::parse(MAINNET).unwrap();
+        mainnet.check_network("main").unwrap();
+        assert!(matches!(mainnet.check_network("regtest"), Err(PayoutError::WrongNetwork { .. })));
+        assert!(matches!(mainnet.check_network("moon"), Err(PayoutError::UnknownChain(_))));
+
+        let regtest = Payout::parse(REGTEST).unwrap();
+        regtest.check_network("regtest").unwrap();
+        assert!(regtest.check_network("test").is_err());
+
+        // Base58 testnet addresses are valid on regtest, testnet and signet.
+        let base58 = Payout::parse("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn").unwrap();
+        for chain in ["regtest", "test", "signet"] {
+            base58.check_network(chain).unwrap();
+        }
+        assert!(base58.check_network("main").is_err());
+        Payout::parse(OP_RETURN).unwrap().check_network("main").unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+// src/pipeline.rs
+//! Template in, block out. Builds the work for one template, scans it on a
+//! single backend until a hash meets the block target, and submits the
+//! block, with none of the main loop's UI, shares or long polling around
+//! it. At regtest difficulty (`0x207fffff`) that is a handful of hashes, so
+//! the whole find→submit path can run in a test or from `--once`.
+
+use bitcoin::blockdata::transaction::Transaction;
+
+use crate::backend::{BackendError, HashBackend};
+use crate::header::HeaderWork;
+use crate::coinbase::CoinbaseError;
+use crate::payout::Payout;
+use crate::rpc::{try_and_submit_nonce, BitcoinRpc, BlockTemplate, RpcError, SubmitOutcome};
+use crate::work::TemplateWork;
+
+/// A nonce meeting the block target, with the coinbase and header it was
+/// found under.
+#[derive(Clone, Debug)]
+pub struct FoundBlock {
+    pub coinbase: Transaction,
+    pub header: HeaderWork,
+    pub nonce: u32,
+    /// Hashes scanned to find it.
+    pub hashes: u64,
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    Backend(BackendError),
+    Rpc(RpcError),
+    Coinbase(CoinbaseError),
+    /// `max_hashes` ran out first.
+    NotFound { hashes: u64 },
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::Backend(e) => e.fmt(f),
+            PipelineError::Rpc(e) => e.fmt(f),
+            PipelineError::Coinbase(e) => e.fmt(f),
+            PipelineError::NotFound { hashes } => write!(f, "no block found in {hashes} hashes"),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+impl From<BackendError> for PipelineError {
+    fn from(e: BackendError) -> Self {
+        PipelineError::Backend(e)
+    }
+}
+
+impl From<RpcError> for PipelineError {
+    fn from(e: RpcError) -> Self {
+        PipelineError::Rpc(e)
+    }
+}
+
+impl From<CoinbaseError> for PipelineError {
+    fn from(e: CoinbaseError) -> Self {
+        PipelineError::Coinbase(e)
+    }
+}
+
+/// Scan `work` until a nonce meets the block target or `max_hashes` have
+/// been tried. Candidates from the backend are re-checked on the host.
+pub fn find_block(
+    work: &mut TemplateWork,
+    backend: &mut dyn HashBackend,
+    now: u32,
+    max_hashes: u64,
+) -> Result<FoundBlock, PipelineError> {
+    let mut hashes = 0;
+    while hashes < max_hashes {
+        let unit = work.next_unit(backend.batch_size(), now);
+        let result = backend.scan(&unit)?;
+        hashes += unit.nonce_count as u64;
+        if let Some(nonce) = result.nonces.iter().copied().find(|&n| unit.verify(n)) {
+            return Ok(FoundBlock { coinbase: work.coinbase().clone(), header: work.header().clone(), nonce, hashes });
+        }
+    }
+    Err(PipelineError::NotFound { hashes })
+}
+
+/// Mine `template` to a block paying `payout` and hand it to `submitblock`.
+pub async fn mine_template(
+    rpc: &BitcoinRpc,
+    template: BlockTemplate,
+    backend: &mut dyn HashBackend,
+    payout: &Payout,
+    message: &[u8],
+    now: u32,
+    max_hashes: u64,
+) -> Result<(FoundBlock, SubmitOutcome), PipelineError> {
+    let mut work = TemplateWork::new(template, payout, message, now)?;
+    let found = find_block(&mut work, backend, now, max_hashes)?;
+    let outcome = try_and_submit_nonce(rpc, work.template(), &found.coinbase, &found.header, found.nonce).await?;
+    Ok((found, outcome))
+}
@@ -1,143 +1,878 @@
-use reqwest::Client;
-use serde_json::Value;
-use bitcoin::blockdata::block::{BlockHeader, Block as BtcBlock};
-use bitcoin::hashes::sha256d;
-use bitcoin::hash_types::TxMerkleNode;
-use bitcoin::consensus::encode::serialize;
-use bitcoin::hashes::Hash;
-use hex;
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::str::FromStr;
+use std::time::Duration;
 
-use crate::sha_helpers::{
-    double_sha256_bytes, target_from_bits, hash_le_target, serialize_block_header_bytes,
-};
-use crate::coinbase::{build_coinbase_from_template, insert_nonce_into_coinbase, assemble_block_hex};
-
-/// Fetches current block template from Bitcoin Core.
-pub async fn fetch_block_template(
-    client: &Client,
-    url: &str,
-    user: &str,
-    pass: &str,
-) -> Option<Value> {
-    let res = client
-        .post(url)
-        .basic_auth(user, Some(pass))
-        .json(&serde_json::json!({
-            "jsonrpc": "1.0",
-            "id": "rustminer",
-            "method": "getblocktemplate",
-            "params": [{"rules":["segwit"]}]
-        }))
-        .send()
-        .await
-        .ok()?;
-    res.json().await.ok()
-}
-
-/// Submits a raw block hex to Bitcoin Core.
-pub async fn submit_block(
-    client: &Client,
-    user: &str,
-    pass: &str,
-    block_hex: &str,
-) -> Result<(), reqwest::Error> {
-    let res = client
-        .post("http://127.0.0.1:8332")
-        .basic_auth(user, Some(pass))
-        .json(&serde_json::json!({
-            "jsonrpc":"1.0",
-            "id":"rustminer",
-            "method":"submitblock",
-            "params":[block_hex]
-        }))
-        .send()
-        .await?;
-
-    if res.status().is_success() {
-        println!("✅ Block submitted successfully");
-    } else {
-        println!("❌ Block submission failed: {:?}", res.text().await);
-    }
-    Ok(())
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::deserialize;
+use bitcoin::hash_types::{BlockHash, Txid, Wtxid};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::backend::hash_meets_target;
+use crate::header::HeaderWork;
+use crate::sha_helpers::target_from_bits;
+use crate::coinbase::{assemble_block_hex, block_merkle_root};
+
+// ----------------- Block Template -----------------
+/// A `getblocktemplate` result (BIP22/23). Built by `BlockTemplate::from_result`,
+/// which rejects anything the rest of the miner could not use, so consumers
+/// read fields without further checks.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct BlockTemplate {
+    pub version: u32,
+    #[serde(default)]
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub vbavailable: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub vbrequired: u32,
+    #[serde(rename = "previousblockhash", deserialize_with = "from_str")]
+    pub previous_block_hash: BlockHash,
+    pub transactions: Vec<TemplateTransaction>,
+    #[serde(default)]
+    pub coinbaseaux: BTreeMap<String, String>,
+    #[serde(rename = "coinbasevalue")]
+    pub coinbase_value: u64,
+    #[serde(default)]
+    pub longpollid: Option<String>,
+    /// Big-endian, as `target_from_bits` returns it.
+    #[serde(default, deserialize_with = "from_hex_target")]
+    pub target: Option<[u8; 32]>,
+    #[serde(default)]
+    pub mintime: Option<u32>,
+    #[serde(default)]
+    pub maxtime: Option<u32>,
+    #[serde(default)]
+    pub mutable: Vec<String>,
+    #[serde(default)]
+    pub sigoplimit: Option<u64>,
+    #[serde(default)]
+    pub sizelimit: Option<u64>,
+    #[serde(default)]
+    pub weightlimit: Option<u64>,
+    pub curtime: u32,
+    #[serde(deserialize_with = "from_hex_bits")]
+    pub bits: u32,
+    pub height: u32,
+    /// Output script of the SegWit commitment, when the template has one.
+    #[serde(default, deserialize_with = "from_hex_opt")]
+    pub default_witness_commitment: Option<Vec<u8>>,
 }
 
-/// Full validation + submit wrapper.
-/// Builds the block with nonce, computes double-SHA256 of header, compares to target,
-/// and calls `submitblock` only if valid.
-pub async fn try_and_submit_nonce(
-    client: &Client,
-    rpc_user: &str,
-    rpc_pass: &str,
-    template: &Value,
-    coinbase_tx: &bitcoin::Transaction,
-    nonce: u32,
-) -> Result<bool, reqwest::Error> {
-    // --- Build header fields from template ---
-    let version = template["result"]["version"].as_i64().unwrap_or(4) as i32;
-    let prevhash_str = template["result"]["previousblockhash"].as_str().unwrap_or("00");
-    let prevhash = sha256d::Hash::from_str(prevhash_str)
-        .unwrap_or_else(|_| sha256d::Hash::hash(&[0u8; 32]));
-    let bits_hex = template["result"]["bits"].as_str().unwrap_or("00000000");
-    let bits = u32::from_str_radix(bits_hex, 16).unwrap_or(0u32);
-    let time = template["result"]["curtime"].as_u64().unwrap_or(0) as u32;
-
-    // --- Construct coinbase with nonce inserted ---
-    let mut coinbase = coinbase_tx.clone();
-    insert_nonce_into_coinbase(&mut coinbase, nonce);
-
-    // --- Compute merkle root of all txs (coinbase + template txs) ---
-    let mut txs = vec![coinbase.clone()];
-    if let Some(txs_json) = template["result"]["transactions"].as_array() {
-        for txj in txs_json {
-            if let Some(data_str) = txj["data"].as_str() {
-                if let Ok(raw) = hex::decode(data_str) {
-                    if let Ok(tx) =
-                        bitcoin::consensus::deserialize::<bitcoin::Transaction>(&raw)
-                    {
-                        txs.push(tx);
-                    }
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct TemplateTransaction {
+    #[serde(deserialize_with = "from_hex")]
+    pub data: Vec<u8>,
+    #[serde(deserialize_with = "from_str")]
+    pub txid: Txid,
+    /// Witness txid; equal to `txid` for transactions without a witness.
+    #[serde(deserialize_with = "from_str")]
+    pub hash: Wtxid,
+    #[serde(default)]
+    pub fee: u64,
+    #[serde(default)]
+    pub sigops: u64,
+    #[serde(default)]
+    pub weight: u64,
+    /// 1-based indexes of earlier template transactions this one spends.
+    #[serde(default)]
+    pub depends: Vec<usize>,
+}
+
+impl TemplateTransaction {
+    pub fn transaction(&self) -> Transaction {
+        deserialize(&self.data).expect("checked by BlockTemplate::validate")
+    }
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Json(serde_json::Error),
+    Transaction { index: usize, reason: String },
+    Target,
+    /// `mintime` after `curtime` or `maxtime` before it.
+    Time { curtime: u32, mintime: Option<u32>, maxtime: Option<u32> },
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Json(e) => write!(f, "malformed block template: {e}"),
+            TemplateError::Transaction { index, reason } => {
+                write!(f, "block template transaction {index}: {reason}")
+            }
+            TemplateError::Target => write!(f, "block template target does not match its bits"),
+            TemplateError::Time { curtime, mintime, maxtime } => {
+                write!(f, "block template curtime {curtime} is outside mintime {mintime:?} / maxtime {maxtime:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl BlockTemplate {
+    /// Parse and validate a `getblocktemplate` result object.
+    pub fn from_result(result: Value) -> Result<Self, TemplateError> {
+        let template: Self = serde_json::from_value(result).map_err(TemplateError::Json)?;
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Every transaction decodes to its advertised txid and wtxid and only
+    /// depends on earlier ones; `target`, if given, agrees with `bits`;
+    /// `curtime` lies within `mintime` and `maxtime`.
+    fn validate(&self) -> Result<(), TemplateError> {
+        if self.mintime.is_some_and(|min| min > self.curtime) || self.maxtime.is_some_and(|max| max < self.curtime) {
+            return Err(TemplateError::Time { curtime: self.curtime, mintime: self.mintime, maxtime: self.maxtime });
+        }
+        for (index, entry) in self.transactions.iter().enumerate() {
+            let fail = |reason: String| TemplateError::Transaction { index, reason };
+            let tx: Transaction = deserialize(&entry.data).map_err(|e| fail(e.to_string()))?;
+            if tx.txid() != entry.txid {
+                return Err(fail(format!("data hashes to txid {}, not {}", tx.txid(), entry.txid)));
+            }
+            if tx.wtxid() != entry.hash {
+                return Err(fail(format!("data hashes to wtxid {}, not {}", tx.wtxid(), entry.hash)));
+            }
+            if let Some(dep) = entry.depends.iter().find(|&&d| d == 0 || d > index) {
+                return Err(fail(format!("depends on {dep}, which is not an earlier transaction")));
+            }
+        }
+        match self.target {
+            Some(target) if target != target_from_bits(self.bits) => Err(TemplateError::Target),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn txids(&self) -> impl Iterator<Item = Txid> + '_ {
+        self.transactions.iter().map(|tx| tx.txid)
+    }
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    T::from_str(&s).map_err(de::Error::custom)
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    hex::decode(s).map_err(de::Error::custom)
+}
+
+fn from_hex_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+    from_hex(deserializer).map(Some)
+}
+
+fn from_hex_target<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[u8; 32]>, D::Error> {
+    let bytes = from_hex(deserializer)?;
+    let target = bytes.try_into().map_err(|b: Vec<u8>| de::Error::invalid_length(b.len(), &"32 bytes"))?;
+    Ok(Some(target))
+}
+
+/// Compact target as eight hex digits, e.g. `"1d00ffff"`.
+fn from_hex_bits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    if s.len() != 8 {
+        return Err(de::Error::invalid_length(s.len(), &"8 hex digits"));
+    }
+    u32::from_str_radix(&s, 16).map_err(de::Error::custom)
+}
+
+// ----------------- JSON-RPC Client -----------------
+#[derive(Debug)]
+pub enum RpcError {
+    /// HTTP 401/403: wrong cookie or rpcuser/rpcpassword.
+    Auth,
+    /// Could not reach the node, or the connection dropped mid-call.
+    Connection(String),
+    Timeout,
+    /// A non-JSON reply with this HTTP status.
+    Http(u16, String),
+    /// A JSON-RPC error object, e.g. -10 while the node is in IBD.
+    Rpc { code: i64, message: String },
+    /// The reply was JSON but not the shape asked for.
+    Response(String),
+    Template(TemplateError),
+}
+
+/// Bitcoin Core error codes that mean "not ready yet": still loading,
+/// no peers, or in initial block download.
+pub const RPC_IN_WARMUP: i64 = -28;
+pub const RPC_CLIENT_NOT_CONNECTED: i64 = -9;
+pub const RPC_CLIENT_IN_INITIAL_DOWNLOAD: i64 = -10;
+
+impl RpcError {
+    /// Failures that may clear up on their own and are worth retrying.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RpcError::Connection(_) | RpcError::Timeout => true,
+            RpcError::Http(status, _) => *status >= 500,
+            RpcError::Rpc { code, .. } => {
+                matches!(*code, RPC_IN_WARMUP | RPC_CLIENT_NOT_CONNECTED | RPC_CLIENT_IN_INITIAL_DOWNLOAD)
+            }
+            RpcError::Auth | RpcError::Response(_) | RpcError::Template(_) => false,
+        }
+    }
+
+    fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            RpcError::Timeout
+        } else {
+            RpcError::Connection(e.to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Auth => write!(f, "RPC authentication failed"),
+            RpcError::Connection(msg) => write!(f, "RPC connection failed: {msg}"),
+            RpcError::Timeout => write!(f, "RPC call timed out"),
+            RpcError::Http(status, body) => write!(f, "RPC HTTP {status}: {body}"),
+            RpcError::Rpc { code, message } => write!(f, "RPC error {code}: {message}"),
+            RpcError::Response(msg) => write!(f, "unexpected RPC response: {msg}"),
+            RpcError::Template(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<TemplateError> for RpcError {
+    fn from(e: TemplateError) -> Self {
+        RpcError::Template(e)
+    }
+}
+
+/// Exponential backoff: attempt `n` waits up to `initial * 2^n`, capped at
+/// `max`, with the lower half jittered away so clients do not retry in step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub retries: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { initial: Duration::from_millis(250), max: Duration::from_secs(8), retries: 4 }
+    }
+}
+
+impl Backoff {
+    pub fn none() -> Self {
+        Self { retries: 0, ..Self::default() }
+    }
+
+    /// Delay before retry `attempt` (0-based), for `jitter` in `0.0..1.0`.
+    pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let ceiling = self.initial.saturating_mul(1 << attempt.min(16)).min(self.max);
+        ceiling.mul_f64(0.5 + 0.5 * jitter.clamp(0.0, 1.0))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct MiningInfo {
+    pub blocks: u64,
+    pub difficulty: f64,
+    #[serde(default)]
+    pub networkhashps: f64,
+    #[serde(default)]
+    pub pooledtx: u64,
+    pub chain: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BlockchainInfo {
+    pub chain: String,
+    pub blocks: u64,
+    pub headers: u64,
+    pub bestblockhash: String,
+    pub initialblockdownload: bool,
+    #[serde(default)]
+    pub verificationprogress: f64,
+}
+
+/// One batched round trip. The template fails on its own (e.g. during IBD)
+/// while the other two still report where the node is.
+#[derive(Debug)]
+pub struct NodeSnapshot {
+    pub template: Result<BlockTemplate, RpcError>,
+    pub mining: MiningInfo,
+    pub chain: BlockchainInfo,
+}
+
+/// Bitcoin Core JSON-RPC over HTTP with basic auth.
+#[derive(Clone, Debug)]
+pub struct BitcoinRpc {
+    client: Client,
+    url: String,
+    user: String,
+    pass: String,
+    timeout: Duration,
+    backoff: Backoff,
+}
+
+/// Mainnet default; regtest is 18443, testnet 18332, signet 38332.
+pub const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8332";
+
+impl BitcoinRpc {
+    pub fn new(url: &str, user: &str, pass: &str) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.to_string(),
+            user: user.to_string(),
+            pass: pass.to_string(),
+            timeout: Duration::from_secs(30),
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Default per-call timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, RpcError> {
+        self.call_with_timeout(method, params, self.timeout).await
+    }
+
+    /// `call` with its own timeout, retrying transient failures.
+    pub async fn call_with_timeout<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<T, RpcError> {
+        self.call_with_backoff(method, params, timeout, self.backoff).await
+    }
+
+    async fn call_with_backoff<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+        backoff: Backoff,
+    ) -> Result<T, RpcError> {
+        let request = serde_json::json!({ "jsonrpc": "1.0", "id": 0, "method": method, "params": params });
+        // Retry on the call's own error object too: -28/-10 clear up.
+        let result =
+            self.retrying(backoff, || async { response_result(self.post(&request, timeout).await?) }).await?;
+        serde_json::from_value(result).map_err(|e| RpcError::Response(format!("{method}: {e}")))
+    }
+
+    /// Several calls in one HTTP request. The outer error is for the round
+    /// trip; each call has its own result, in the order given.
+    pub async fn batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Value, RpcError>>, RpcError> {
+        let request: Vec<Value> = calls
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                serde_json::json!({ "jsonrpc": "1.0", "id": id, "method": method, "params": params })
+            })
+            .collect();
+        let request = Value::Array(request);
+        let reply = self.retrying(self.backoff, || self.post(&request, self.timeout)).await?;
+        split_batch(reply, calls.len())
+    }
+
+    pub async fn get_block_template(&self) -> Result<BlockTemplate, RpcError> {
+        let result: Value = self.call("getblocktemplate", gbt_params()).await?;
+        Ok(BlockTemplate::from_result(result)?)
+    }
+
+    /// BIP22 long poll: returns once the node has a template newer than the
+    /// one `longpollid` came with. `timeout` must cover the wait.
+    pub async fn long_poll_template(&self, longpollid: &str, timeout: Duration) -> Result<BlockTemplate, RpcError> {
+        let params = serde_json::json!([{ "rules": ["segwit"], "longpollid": longpollid }]);
+        let result: Value = self.call_with_timeout("getblocktemplate", params, timeout).await?;
+        Ok(BlockTemplate::from_result(result)?)
+    }
+
+    /// `getblocktemplate`, `getmininginfo` and `getblockchaininfo` in one
+    /// round trip.
+    pub async fn snapshot(&self) -> Result<NodeSnapshot, RpcError> {
+        let calls = [
+            ("getblocktemplate", gbt_params()),
+            ("getmininginfo", Value::Array(vec![])),
+            ("getblockchaininfo", Value::Array(vec![])),
+        ];
+        let mut replies = self.batch(&calls).await?.into_iter();
+        let mut next = || replies.next().expect("one reply per call");
+        let template = next().and_then(|t| Ok(BlockTemplate::from_result(t)?));
+        let mining = typed(next())?;
+        let chain = typed(next())?;
+        Ok(NodeSnapshot { template, mining, chain })
+    }
+
+    /// `submitblock`: `None` when accepted, otherwise the node's reason
+    /// (`"duplicate"`, `"high-hash"`, `"bad-txnmrklroot"`, ...). Sent once:
+    /// a timeout may still have reached the node, and a resend would come
+    /// back `"duplicate"` for a block it accepted.
+    pub async fn submit_block(&self, block_hex: &str) -> Result<Option<String>, RpcError> {
+        self.call_with_backoff("submitblock", serde_json::json!([block_hex]), self.timeout, Backoff::none()).await
+    }
+
+    /// BIP23 proposal: `None` if the node would accept `block_hex` apart
+    /// from its proof of work, otherwise the reason it would not. Sent once,
+    /// like `submit_block`.
+    pub async fn propose_block(&self, block_hex: &str) -> Result<Option<String>, RpcError> {
+        let params = serde_json::json!([{ "mode": "proposal", "data": block_hex, "rules": ["segwit"] }]);
+        self.call_with_backoff("getblocktemplate", params, self.timeout, Backoff::none()).await
+    }
+
+    async fn retrying<F, Fut>(&self, backoff: Backoff, mut attempt: F) -> Result<Value, RpcError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<Value, RpcError>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_transient() && retry < backoff.retries => {
+                    tokio::time::sleep(backoff.delay(retry, rand::random())).await;
+                    retry += 1;
                 }
+                reply => return reply,
             }
         }
     }
 
-    let mut hashes: Vec<sha256d::Hash> =
-        txs.iter().map(|t| t.txid().as_hash()).collect();
-    while hashes.len() > 1 {
-        if hashes.len() % 2 == 1 {
-            let last = hashes.last().cloned().unwrap();
-            hashes.push(last);
+    /// One HTTP round trip. Bitcoin Core answers RPC errors with a JSON body
+    /// under a 4xx/5xx status, so only a non-JSON body is an HTTP error.
+    async fn post(&self, body: &Value, timeout: Duration) -> Result<Value, RpcError> {
+        let res = self
+            .client
+            .post(&self.url)
+            .basic_auth(&self.user, Some(&self.pass))
+            .timeout(timeout)
+            .json(body)
+            .send()
+            .await
+            .map_err(RpcError::from_reqwest)?;
+        let status = res.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(RpcError::Auth);
         }
-        let mut new_hashes = Vec::with_capacity(hashes.len() / 2);
-        for i in (0..hashes.len()).step_by(2) {
-            let concat = [hashes[i].into_inner(), hashes[i + 1].into_inner()].concat();
-            new_hashes.push(sha256d::Hash::hash(&concat));
+        let text = res.text().await.map_err(RpcError::from_reqwest)?;
+        let reply = serde_json::from_str(&text).map_err(|_| RpcError::Http(status.as_u16(), text))?;
+        // A single call's error object is the interesting part, not the status.
+        match &reply {
+            Value::Object(_) if !status.is_success() && reply["error"].is_null() => {
+                Err(RpcError::Http(status.as_u16(), reply.to_string()))
+            }
+            _ => Ok(reply),
         }
-        hashes = new_hashes;
     }
-    let merkle_root = TxMerkleNode::from_inner(hashes[0].into_inner());
+}
+
+fn typed<T: DeserializeOwned>(reply: Result<Value, RpcError>) -> Result<T, RpcError> {
+    serde_json::from_value(reply?).map_err(|e| RpcError::Response(e.to_string()))
+}
+
+fn gbt_params() -> Value {
+    serde_json::json!([{ "rules": ["segwit"] }])
+}
+
+/// `result` of a single JSON-RPC reply, or its error object.
+fn response_result(mut reply: Value) -> Result<Value, RpcError> {
+    if !reply.is_object() {
+        return Err(RpcError::Response(format!("expected an object, got {reply}")));
+    }
+    let error = reply["error"].take();
+    if !error.is_null() {
+        return Err(RpcError::Rpc {
+            code: error["code"].as_i64().unwrap_or(0),
+            message: error["message"].as_str().map_or_else(|| error.to_string(), str::to_string),
+        });
+    }
+    Ok(reply["result"].take())
+}
 
-    // --- Build header and compute hash ---
-    let header = BlockHeader {
-        version,
-        prev_blockhash: prevhash.into(),
-        merkle_root,
-        time,
-        bits,
-        nonce,
+/// Batch replies may come back in any order; put them back in request order
+/// by `id`.
+fn split_batch(reply: Value, calls: usize) -> Result<Vec<Result<Value, RpcError>>, RpcError> {
+    let Value::Array(replies) = reply else {
+        // A failed batch is reported as one ordinary error reply.
+        let error = response_result(reply).err();
+        return Err(error.unwrap_or_else(|| RpcError::Response("batch reply is not an array".into())));
     };
-    let header_bytes = serialize_block_header_bytes(&header);
-    let hash_be = double_sha256_bytes(&header_bytes);
-    let target_be = target_from_bits(bits);
+    let mut slots: Vec<Option<Value>> = vec![None; calls];
+    for reply in replies {
+        let slot = reply["id"].as_u64().and_then(|id| slots.get_mut(id as usize));
+        match slot {
+            Some(slot @ None) => *slot = Some(reply),
+            _ => return Err(RpcError::Response(format!("unexpected batch reply id {}", reply["id"]))),
+        }
+    }
+    Ok(slots
+        .into_iter()
+        .enumerate()
+        .map(|(id, reply)| {
+            let reply = reply.ok_or_else(|| RpcError::Response(format!("no reply for batch id {id}")))?;
+            response_result(reply)
+        })
+        .collect())
+}
+
+// ----------------- Block Submission -----------------
+/// What became of a candidate block. Everything except `BelowTarget` names
+/// the block, and every rejection carries the node's reason.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubmitOutcome {
+    /// The hash does not meet the header's target; nothing was sent.
+    BelowTarget,
+    Accepted { block_hash: BlockHash },
+    /// The node already had it (`duplicate`, `duplicate-invalid`,
+    /// `duplicate-inconclusive`).
+    Duplicate { block_hash: BlockHash, reason: String },
+    /// Valid, but not on the node's best chain (yet).
+    Inconclusive { block_hash: BlockHash },
+    /// A reject reason such as `high-hash` or `bad-txnmrklroot`, a JSON-RPC
+    /// error, or a mismatch caught before sending.
+    Rejected { block_hash: BlockHash, reason: String },
+}
+
+impl SubmitOutcome {
+    /// Interpret a `submitblock` reply for `block_hash`.
+    pub fn from_reply(block_hash: BlockHash, reply: Result<Option<String>, RpcError>) -> Result<Self, RpcError> {
+        let reason = match reply {
+            Ok(None) => return Ok(SubmitOutcome::Accepted { block_hash }),
+            Ok(Some(reason)) => reason,
+            Err(RpcError::Rpc { code, message }) => format!("rpc error {code}: {message}"),
+            Err(e) => return Err(e),
+        };
+        Ok(match reason.as_str() {
+            "" | "null" => SubmitOutcome::Accepted { block_hash },
+            "inconclusive" => SubmitOutcome::Inconclusive { block_hash },
+            r if r.starts_with("duplicate") => SubmitOutcome::Duplicate { block_hash, reason },
+            _ => SubmitOutcome::Rejected { block_hash, reason },
+        })
+    }
+
+    pub fn block_hash(&self) -> Option<BlockHash> {
+        match self {
+            SubmitOutcome::BelowTarget => None,
+            SubmitOutcome::Accepted { block_hash }
+            | SubmitOutcome::Duplicate { block_hash, .. }
+            | SubmitOutcome::Inconclusive { block_hash }
+            | SubmitOutcome::Rejected { block_hash, .. } => Some(*block_hash),
+        }
+    }
+}
+
+impl std::fmt::Display for SubmitOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitOutcome::BelowTarget => write!(f, "below target, not submitted"),
+            SubmitOutcome::Accepted { block_hash } => write!(f, "block {block_hash} accepted"),
+            SubmitOutcome::Duplicate { block_hash, reason } => write!(f, "block {block_hash} already known: {reason}"),
+            SubmitOutcome::Inconclusive { block_hash } => write!(f, "block {block_hash} inconclusive"),
+            SubmitOutcome::Rejected { block_hash, reason } => write!(f, "block {block_hash} rejected: {reason}"),
+        }
+    }
+}
+
+/// Running totals of submitted blocks by outcome.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubmitCounts {
+    pub accepted: u64,
+    pub duplicate: u64,
+    pub inconclusive: u64,
+    pub rejected: u64,
+}
+
+impl SubmitCounts {
+    pub fn record(&mut self, outcome: &SubmitOutcome) {
+        match outcome {
+            SubmitOutcome::BelowTarget => {}
+            SubmitOutcome::Accepted { .. } => self.accepted += 1,
+            SubmitOutcome::Duplicate { .. } => self.duplicate += 1,
+            SubmitOutcome::Inconclusive { .. } => self.inconclusive += 1,
+            SubmitOutcome::Rejected { .. } => self.rejected += 1,
+        }
+    }
+}
+
+/// Whether the node accepts what is being mined, per BIP23 proposal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TemplateHealth {
+    /// Not proposed yet, or the node could not be asked.
+    #[default]
+    Unchecked,
+    Valid,
+    /// The node's reject reason. Work from this template is not mined.
+    Invalid(String),
+}
+
+impl TemplateHealth {
+    /// Interpret a proposal reply. A placeholder nonce is expected to miss
+    /// the target, so `high-hash` still counts as valid.
+    pub fn from_reply(reply: Result<Option<String>, RpcError>) -> Result<Self, RpcError> {
+        match reply {
+            Ok(None) => Ok(TemplateHealth::Valid),
+            Ok(Some(reason)) if reason == "high-hash" => Ok(TemplateHealth::Valid),
+            Ok(Some(reason)) => Ok(TemplateHealth::Invalid(reason)),
+            Err(RpcError::Rpc { code, message }) => Ok(TemplateHealth::Invalid(format!("rpc error {code}: {message}"))),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn is_minable(&self) -> bool {
+        !matches!(self, TemplateHealth::Invalid(_))
+    }
+}
+
+impl std::fmt::Display for TemplateHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateHealth::Unchecked => write!(f, "unchecked"),
+            TemplateHealth::Valid => write!(f, "valid"),
+            TemplateHealth::Invalid(reason) => write!(f, "invalid ({reason})"),
+        }
+    }
+}
+
+/// Propose the block `try_and_submit_nonce` would send for this coinbase
+/// and header, with nonce 0 in place of a solution.
+pub async fn propose_template(
+    rpc: &BitcoinRpc,
+    template: &BlockTemplate,
+    coinbase_tx: &bitcoin::Transaction,
+    header: &HeaderWork,
+) -> Result<TemplateHealth, RpcError> {
+    let block_hex = assemble_block_hex(template, coinbase_tx, header, 0);
+    TemplateHealth::from_reply(rpc.propose_block(&block_hex).await)
+}
+
+/// Full validation + submit wrapper.
+/// Rebuilds the merkle root from the coinbase, checks it against the hashed
+/// `header` (rolled version and time included), re-hashes with `nonce`,
+/// compares to target, and calls `submitblock` only if valid. `Err` is left
+/// for failures to reach the node; anything it answers is an outcome.
+pub async fn try_and_submit_nonce(
+    rpc: &BitcoinRpc,
+    template: &BlockTemplate,
+    coinbase_tx: &bitcoin::Transaction,
+    header: &HeaderWork,
+    nonce: u32,
+) -> Result<SubmitOutcome, RpcError> {
+    let mut solved = header.clone();
+    solved.set_nonce(nonce);
+    let block_hash = solved.to_header().block_hash();
+
+    if block_merkle_root(template, coinbase_tx) != header.to_header().merkle_root {
+        let reason = "coinbase does not match the hashed header's merkle root".to_string();
+        return Ok(SubmitOutcome::Rejected { block_hash, reason });
+    }
 
     // --- Compare ---
-    if hash_le_target(&hash_be, &target_be) {
-        let block_hex = assemble_block_hex(template, &coinbase, nonce);
-        submit_block(client, rpc_user, rpc_pass, &block_hex).await?;
-        println!("✅ Valid block! nonce = {nonce}");
-        Ok(true)
-    } else {
-        Ok(false)
+    if !hash_meets_target(&header.hash(nonce), &target_from_bits(header.bits())) {
+        return Ok(SubmitOutcome::BelowTarget);
+    }
+    let block_hex = assemble_block_hex(template, coinbase_tx, header, nonce);
+    SubmitOutcome::from_reply(block_hash, rpc.submit_block(&block_hex).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use bitcoin::consensus::serialize;
+    use serde_json::json;
+
+    fn transaction() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Default::default(),
+                sequence: 0xffff_fffe,
+                witness: vec![vec![1; 72], vec![2; 33]],
+            }],
+            output: vec![TxOut { value: 50_000, script_pubkey: Default::default() }],
+        }
+    }
+
+    fn parse(reply: Value) -> Result<BlockTemplate, RpcError> {
+        Ok(BlockTemplate::from_result(response_result(reply)?)?)
+    }
+
+    fn response(transactions: Value) -> Value {
+        json!({
+            "result": {
+                "version": 0x2000_0000,
+                "rules": ["csv", "!segwit", "taproot"],
+                "previousblockhash": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054",
+                "transactions": transactions,
+                "coinbasevalue": 625_000_000u64,
+                "longpollid": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054123",
+                "target": "0000000000000000000538940000000000000000000000000000000000000000",
+                "mintime": 1_689_999_000,
+                "mutable": ["time", "transactions", "prevblock"],
+                "curtime": 1_690_000_000,
+                "bits": "17053894",
+                "height": 800_000,
+                "default_witness_commitment": "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+            },
+            "error": null,
+            "id": "rustminer",
+        })
+    }
+
+    #[test]
+    fn parses_and_validates_transactions() {
+        let tx = transaction();
+        let entry = json!({
+            "data": hex::encode(serialize(&tx)),
+            "txid": tx.txid().to_string(),
+            "hash": tx.wtxid().to_string(),
+            "fee": 1_000,
+            "sigops": 1,
+            "weight": 400,
+            "depends": [],
+        });
+        let template = parse(response(json!([entry]))).unwrap();
+        assert_eq!(template.bits, 0x1705_3894);
+        assert_eq!(template.height, 800_000);
+        assert_eq!(template.default_witness_commitment.as_ref().map(Vec::len), Some(38));
+        assert_eq!(template.transactions[0].transaction(), tx);
+        assert_ne!(template.transactions[0].hash.as_hash(), tx.txid().as_hash());
+
+        let mut wrong_txid = entry.clone();
+        wrong_txid["txid"] = tx.wtxid().to_string().into();
+        let err = parse(response(json!([wrong_txid]))).unwrap_err();
+        assert!(matches!(err, RpcError::Template(TemplateError::Transaction { index: 0, .. })), "{err}");
+
+        let mut forward = entry.clone();
+        forward["depends"] = json!([2]);
+        let err = parse(response(json!([entry, forward]))).unwrap_err();
+        assert!(matches!(err, RpcError::Template(TemplateError::Transaction { index: 1, .. })), "{err}");
+    }
+
+    #[test]
+    fn refuses_curtime_outside_mintime_and_maxtime() {
+        let mut reply = response(json!([]));
+        reply["result"]["mintime"] = 1_690_000_001.into();
+        let err = parse(reply.clone()).unwrap_err();
+        assert!(matches!(err, RpcError::Template(TemplateError::Time { .. })), "{err}");
+
+        reply["result"]["mintime"] = 1_690_000_000.into();
+        reply["result"]["maxtime"] = 1_689_999_999.into();
+        let err = parse(reply.clone()).unwrap_err();
+        assert!(matches!(err, RpcError::Template(TemplateError::Time { .. })), "{err}");
+
+        reply["result"]["maxtime"] = 1_690_000_000.into();
+        parse(reply).unwrap();
+    }
+
+    #[test]
+    fn reports_rpc_errors_and_bad_fields() {
+        let ibd = json!({
+            "result": null,
+            "error": {"code": -10, "message": "Bitcoin Core is in initial sync and waiting for blocks..."},
+            "id": "rustminer",
+        });
+        match parse(ibd) {
+            Err(e @ RpcError::Rpc { code: -10, .. }) => assert!(e.is_transient() && e.to_string().contains("initial sync")),
+            other => panic!("{other:?}"),
+        }
+
+        let mut short_bits = response(json!([]));
+        short_bits["result"]["bits"] = "53894".into();
+        assert!(matches!(parse(short_bits), Err(RpcError::Template(TemplateError::Json(_)))));
+
+        let mut no_height = response(json!([]));
+        no_height["result"].as_object_mut().unwrap().remove("height");
+        assert!(matches!(parse(no_height), Err(RpcError::Template(TemplateError::Json(_)))));
+
+        let mut easier = response(json!([]));
+        easier["result"]["target"] = format!("{:064x}", 1u8 << 7).into();
+        assert!(matches!(parse(easier), Err(RpcError::Template(TemplateError::Target))));
+    }
+
+    #[test]
+    fn batch_replies_are_matched_by_id() {
+        let reply = json!([
+            {"result": null, "error": {"code": -10, "message": "in IBD"}, "id": 0},
+            {"result": {"chain": "main"}, "error": null, "id": 2},
+            {"result": 7, "error": null, "id": 1},
+        ]);
+        let split = split_batch(reply, 3).unwrap();
+        assert!(matches!(split[0], Err(RpcError::Rpc { code: RPC_CLIENT_IN_INITIAL_DOWNLOAD, .. })));
+        assert_eq!(split[1].as_ref().unwrap(), &json!(7));
+        assert_eq!(split[2].as_ref().unwrap()["chain"], "main");
+
+        let short = split_batch(json!([{"result": 1, "error": null, "id": 0}]), 2).unwrap();
+        assert!(matches!(short[1], Err(RpcError::Response(_))));
+        let refused = json!({"result": null, "error": {"code": -32700, "message": "Parse error"}, "id": null});
+        assert!(matches!(split_batch(refused, 1), Err(RpcError::Rpc { code: -32700, .. })));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_with_jitter() {
+        let backoff = Backoff { initial: Duration::from_millis(100), max: Duration::from_secs(1), retries: 5 };
+        assert_eq!(backoff.delay(0, 1.0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(0, 0.0), Duration::from_millis(50));
+        assert_eq!(backoff.delay(2, 1.0), Duration::from_millis(400));
+        assert_eq!(backoff.delay(4, 1.0), Duration::from_secs(1));
+        assert_eq!(backoff.delay(40, 0.5), Duration::from_millis(750));
+        assert!(!RpcError::Auth.is_transient() && RpcError::Timeout.is_transient());
+    }
+
+    #[test]
+    fn submit_replies_become_outcomes() {
+        let block_hash = BlockHash::from_str("00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054").unwrap();
+        let outcome = |reply| SubmitOutcome::from_reply(block_hash, reply).unwrap();
+        assert_eq!(outcome(Ok(None)), SubmitOutcome::Accepted { block_hash });
+        assert_eq!(outcome(Ok(Some("inconclusive".into()))), SubmitOutcome::Inconclusive { block_hash });
+        assert!(matches!(outcome(Ok(Some("duplicate-invalid".into()))), SubmitOutcome::Duplicate { .. }));
+
+        let rejected = outcome(Ok(Some("bad-txnmrklroot".into())));
+        assert_eq!(rejected.block_hash(), Some(block_hash));
+        assert_eq!(rejected.to_string(), format!("block {block_hash} rejected: bad-txnmrklroot"));
+        let decode = outcome(Err(RpcError::Rpc { code: -22, message: "Block decode failed".into() }));
+        assert!(matches!(&decode, SubmitOutcome::Rejected { reason, .. } if reason.contains("-22")));
+        assert!(matches!(SubmitOutcome::from_reply(block_hash, Err(RpcError::Timeout)), Err(RpcError::Timeout)));
+
+        let mut counts = SubmitCounts::default();
+        for o in [&rejected, &decode, &SubmitOutcome::Accepted { block_hash }, &SubmitOutcome::BelowTarget] {
+            counts.record(o);
+        }
+        assert_eq!((counts.accepted, counts.rejected), (1, 2));
+    }
+
+    #[test]
+    fn proposal_replies_set_template_health() {
+        assert_eq!(TemplateHealth::from_reply(Ok(None)).unwrap(), TemplateHealth::Valid);
+        assert_eq!(TemplateHealth::from_reply(Ok(Some("high-hash".into()))).unwrap(), TemplateHealth::Valid);
+        let bad = TemplateHealth::from_reply(Ok(Some("bad-cb-amount".into()))).unwrap();
+        assert_eq!(bad, TemplateHealth::Invalid("bad-cb-amount".into()));
+        assert!(!bad.is_minable());
+        let rpc = TemplateHealth::from_reply(Err(RpcError::Rpc { code: -22, message: "Block decode failed".into() }));
+        assert!(!rpc.unwrap().is_minable());
+        assert!(TemplateHealth::from_reply(Err(RpcError::Timeout)).is_err());
+        assert!(TemplateHealth::Unchecked.is_minable());
     }
 }
@@ -1,22 +1,20 @@
-use sha2::{Digest, Sha256};
-use byteorder::{LittleEndian, WriteBytesExt};
+use sha2::{compress256, Digest, Sha256};
 use bitcoin::hashes::{sha256d, Hash};
 use bitcoin::hash_types::TxMerkleNode;
 use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::blockdata::block::BlockHeader;
 use bitcoin::consensus::{deserialize, encode::serialize};
 use serde_json::Value;
-use std::str::FromStr;
-use std::convert::TryInto;
-use std::io::Write;
-use std::time::Instant;
-use rayon::prelude::*;
 use hex;
 
+use crate::coinbase::CoinbaseBuilder;
+use crate::header::HeaderWork;
+use crate::rpc::BlockTemplate;
+
 // ----------------- Double SHA256 -----------------
 pub fn double_sha256_bytes(data: &[u8]) -> [u8; 32] {
     let first = Sha256::digest(data);
-    let second = Sha256::digest(&first);
+    let second = Sha256::digest(first);
     let mut out = [0u8; 32];
     out.copy_from_slice(&second);
     out
@@ -29,15 +27,43 @@ pub fn target_from_bits(bits: u32) -> [u8; 32] {
     let mantissa = bits & 0x007fffff;
 
     if exponent <= 3 {
-        let value = (mantissa >> (8 * (3 - exponent))) as u32;
+        let value = mantissa >> (8 * (3 - exponent));
         let bytes = value.to_be_bytes();
         target[28..32].copy_from_slice(&bytes);
     } else if exponent <= 32 {
-        let offset = (exponent - 3) as usize;
+        // mantissa * 256^(exponent - 3), laid out big-endian
+        let offset = (32 - exponent) as usize;
         let mant_bytes = mantissa.to_be_bytes();
-        if offset + 3 <= 32 {
-            target[offset..offset + 3].copy_from_slice(&mant_bytes[1..4]);
-        }
+        target[offset..offset + 3].copy_from_slice(&mant_bytes[1..4]);
+    }
+    target
+}
+
+// ----------------- Difficulty -----------------
+/// Compact bits of the difficulty-1 target.
+pub const DIFF1_BITS: u32 = 0x1d00ffff;
+
+/// Difficulty of a big-endian target: diff-1 target / target.
+pub fn target_difficulty(target_be: &[u8; 32]) -> f64 {
+    let as_f64 = |t: &[u8; 32]| t.iter().fold(0.0f64, |acc, &b| acc * 256.0 + b as f64);
+    let target = as_f64(target_be);
+    if target == 0.0 { f64::INFINITY } else { as_f64(&target_from_bits(DIFF1_BITS)) / target }
+}
+
+/// Big-endian target for a (pool) difficulty: diff-1 target / `difficulty`.
+/// Exact to f64 precision, which is all a share target needs.
+pub fn difficulty_target(difficulty: f64) -> [u8; 32] {
+    let diff1 = target_from_bits(DIFF1_BITS).iter().fold(0.0f64, |acc, &b| acc * 256.0 + b as f64);
+    let mut value = diff1 / difficulty;
+    if value.is_nan() || value >= 2f64.powi(256) {
+        return [0xff; 32];
+    }
+    let mut target = [0u8; 32];
+    for (i, byte) in target.iter_mut().enumerate() {
+        let weight = 2f64.powi(8 * (31 - i as i32));
+        let b = (value / weight).floor().min(255.0);
+        *byte = b as u8;
+        value -= b * weight;
     }
     target
 }
@@ -66,17 +92,20 @@ pub fn digest_meets_target(digest_words: &[u32; 8], bits: u32) -> bool {
 }
 
 // ----------------- GPU Buffer Helpers -----------------
+#[cfg(feature = "metal")]
 pub fn aligned_u32_buffer(device: &metal::Device, count: usize, nibble_threads: bool) -> metal::Buffer {
     let scale = if nibble_threads { 16 } else { 1 };
     device.new_buffer((count * 4 * scale) as u64, metal::MTLResourceOptions::StorageModeShared)
 }
 
+#[cfg(feature = "metal")]
 pub fn aligned_f32_buffer(device: &metal::Device, count: usize, nibble_threads: bool) -> metal::Buffer {
     let scale = if nibble_threads { 16 } else { 1 };
     device.new_buffer((count * 4 * scale) as u64, metal::MTLResourceOptions::StorageModeShared)
 }
 
 /// Create a 16-bit aligned Metal buffer for `ushort` data.
+#[cfg(feature = "metal")]
 pub fn aligned_ushort_buffer(device: &metal::Device, count: usize, _nibble_threads: bool) -> metal::Buffer {
     device.new_buffer(
         (count * std::mem::size_of::<u16>()) as u64,
@@ -85,24 +114,40 @@ pub fn aligned_ushort_buffer(device: &metal::Device, count: usize, _nibble_threa
 }
 
 // ----------------- SHA256 Midstate & Schedule -----------------
+pub const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+    0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 compression-function state after absorbing one 64-byte block.
+pub fn sha256_midstate(block: &[u8; 64]) -> [u32; 8] {
+    let mut state = SHA256_IV;
+    compress256(&mut state, &[(*block).into()]);
+    state
+}
+
+/// Midstate of a header in the `[u32; 19]` word layout of `prepare_block_header`.
 pub fn compute_midstate(header_words: &[u32; 19]) -> [u32; 8] {
-    let mut first_block = vec![];
-    for &w in header_words.iter().take(16) {
-        first_block.write_u32::<LittleEndian>(w).unwrap();
-    }
-    let first_hash = Sha256::digest(&first_block);
-    let second_hash = Sha256::digest(&first_hash);
-    let mut midstate = [0u32; 8];
-    for (i, chunk) in second_hash.chunks(4).enumerate() {
-        midstate[i] = u32::from_le_bytes(chunk.try_into().unwrap());
-    }
-    midstate
+    HeaderWork::from_words(header_words).midstate()
 }
 
+/// Message schedule of the header's first block (bytes 0..64).
 pub fn precompute_schedule(header_words: &[u32; 19]) -> [u32; 64] {
     let mut w = [0u32; 64];
     for i in 0..16 {
-        w[i] = header_words[i].to_le();
+        // Header words are read little-endian; SHA-256 wants big-endian.
+        w[i] = header_words[i].swap_bytes();
     }
     for i in 16..64 {
         let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
@@ -114,7 +159,7 @@ pub fn precompute_schedule(header_words: &[u32; 19]) -> [u32; 64] {
 
 // ----------------- Sliding Entropy -----------------
 pub fn sliding_entropy(data: &[u8], window_bits: usize) -> Vec<f32> {
-    let window_bytes = (window_bits + 7) / 8;
+    let window_bytes = window_bits.div_ceil(8);
     let mut entropies = Vec::with_capacity(data.len().saturating_sub(window_bytes) + 1);
     for chunk in data.windows(window_bytes) {
         let mut counts = [0usize; 256];
@@ -155,122 +200,117 @@ pub fn merkle_root(tx_hashes: Vec<sha256d::Hash>) -> TxMerkleNode {
     TxMerkleNode::from_inner(hashes[0].into_inner())
 }
 
-// ----------------- Candidate Merkle Roots -----------------
-pub fn candidate_merkle_roots(template: &Value, num_candidates: u32) -> Vec<TxMerkleNode> {
-    let mut roots = Vec::new();
-    for extra_nonce in 0..num_candidates {
-        let mut coinbase = crate::coinbase::build_coinbase_from_template_with_height(template);
-        crate::coinbase::patch_insert_nonce_into_coinbase(&mut coinbase, extra_nonce);
-
-        let mut txs: Vec<Transaction> = vec![deserialize(
-            &hex::decode(
-                coinbase["result"]["transactions"][0]["data"].as_str().unwrap_or("")
-            ).unwrap()
-        ).unwrap()];
-
-        if let Some(tx_arr) = template["result"]["transactions"].as_array() {
-            for tx_json in tx_arr {
-                if let Ok(raw_tx) = hex::decode(tx_json["data"].as_str().unwrap_or("")) {
-                    txs.push(deserialize(&raw_tx).unwrap());
-                }
-            }
-        }
-
-        let tx_hashes: Vec<sha256d::Hash> = txs
-            .iter()
-            .map(|tx| sha256d::Hash::from_slice(&tx.txid().as_ref()).unwrap())
-            .collect();
-        let root = merkle_root(tx_hashes);
-        roots.push(root);
-    }
-    roots
+// ----------------- Coinbase Merkle Branch -----------------
+/// Sibling hashes on the path from the coinbase (leaf 0) to the merkle
+/// root. Built once per template from the other txids; afterwards each new
+/// coinbase costs one txid plus `steps.len()` merges.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerkleBranch {
+    pub steps: Vec<sha256d::Hash>,
 }
 
-// ----------------- Precompute Schedule with Coinbase Nonce -----------------
-pub fn precompute_schedule_with_nonce(header_words: &[u32; 19], coinbase: &Value) -> [u32; 64] {
-    let mut header_bytes = Vec::with_capacity(76);
-    for w in header_words.iter().take(16) {
-        header_bytes.extend_from_slice(&w.to_le_bytes());
+impl MerkleBranch {
+    /// `txids` are the non-coinbase transactions in block order.
+    pub fn from_txids(txids: &[sha256d::Hash]) -> Self {
+        let mut steps = Vec::new();
+        let mut level = txids.to_vec();
+        while let Some((&sibling, rest)) = level.split_first() {
+            steps.push(sibling);
+            // The coinbase path pairs with `sibling`; everything after it
+            // pairs up on its own, duplicating an odd last entry.
+            level = rest
+                .chunks(2)
+                .map(|pair| {
+                    let right = pair.get(1).unwrap_or(&pair[0]);
+                    sha256d::Hash::hash(&[pair[0].as_ref(), right.as_ref()].concat())
+                })
+                .collect();
+        }
+        Self { steps }
     }
 
-    if let Some(tx) = coinbase["result"]["transactions"].get(0) {
-        if let Some(vin) = tx["vin"].get(0) {
-            if let Some(script_hex) = vin["coinbase"].as_str() {
-                if let Ok(script_bytes) = hex::decode(script_hex) {
-                    header_bytes.extend_from_slice(&script_bytes);
-                }
-            }
-        }
+    pub fn root(&self, coinbase_txid: sha256d::Hash) -> TxMerkleNode {
+        let root = self.steps.iter().fold(coinbase_txid, |acc, step| {
+            sha256d::Hash::hash(&[acc.as_ref(), step.as_ref()].concat())
+        });
+        TxMerkleNode::from_inner(root.into_inner())
     }
+}
 
-    while header_bytes.len() < 64 { header_bytes.push(0); }
+// ----------------- Candidate Merkle Roots -----------------
+/// Merkle roots for extranonce counters `0..num_candidates` over one shared
+/// branch, for coinbases from `coinbase`.
+pub fn candidate_merkle_roots(template: &BlockTemplate, coinbase: &CoinbaseBuilder, num_candidates: u32) -> Vec<TxMerkleNode> {
+    let branch = MerkleBranch::from_txids(&crate::coinbase::template_txids(template));
+    (0..num_candidates as u64)
+        .map(|extranonce| {
+            let coinbase = coinbase.build_with_counter(&[], extranonce);
+            branch.root(coinbase.txid().as_hash())
+        })
+        .collect()
+}
 
-    let mut schedule = [0u32; 64];
-    for (i, chunk) in header_bytes.chunks(4).enumerate().take(16) {
-        let mut buf = [0u8; 4];
-        buf[..chunk.len()].copy_from_slice(chunk);
-        schedule[i] = u32::from_le_bytes(buf);
+// ----------------- Header Words with Coinbase Nonce -----------------
+/// The coinbase only reaches the header through the merkle root. Replace the
+/// root in `header_words` with the one over the `data` transactions in
+/// `coinbase["result"]["transactions"]` (coinbase first, as
+/// `template_json_with_coinbase` puts it); if none decode the words are
+/// returned unchanged.
+fn header_words_with_coinbase(header_words: &[u32; 19], coinbase: &Value) -> [u32; 19] {
+    let txids: Vec<sha256d::Hash> = coinbase["result"]["transactions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|tx| hex::decode(tx["data"].as_str()?).ok())
+        .filter_map(|raw| deserialize::<Transaction>(&raw).ok())
+        .map(|tx| tx.txid().as_hash())
+        .collect();
+    if txids.is_empty() {
+        return *header_words;
     }
 
-    for i in 16..64 {
-        let s0 = schedule[i - 15].rotate_right(7) ^ schedule[i - 15].rotate_right(18) ^ (schedule[i - 15] >> 3);
-        let s1 = schedule[i - 2].rotate_right(17) ^ schedule[i - 2].rotate_right(19) ^ (schedule[i - 2] >> 10);
-        schedule[i] = schedule[i - 16].wrapping_add(s0).wrapping_add(schedule[i - 7]).wrapping_add(s1);
-    }
+    let mut header = HeaderWork::from_words(header_words).to_header();
+    header.merkle_root = merkle_root(txids);
+    HeaderWork::from_header(&header).words()
+}
 
-    schedule
+pub fn precompute_schedule_with_nonce(header_words: &[u32; 19], coinbase: &Value) -> [u32; 64] {
+    precompute_schedule(&header_words_with_coinbase(header_words, coinbase))
 }
 
-// ----------------- Midstate with Coinbase Nonce -----------------
 pub fn compute_midstate_with_nonce(header_words: &[u32; 19], coinbase: &Value) -> [u32; 8] {
-    let mut header_bytes = Vec::with_capacity(64 + 32);
-    for &w in header_words.iter().take(16) { header_bytes.write_u32::<LittleEndian>(w).unwrap(); }
-
-    if let Some(tx) = coinbase["result"]["transactions"].get(0) {
-        if let Some(vin) = tx["vin"].get(0) {
-            if let Some(script_hex) = vin["coinbase"].as_str() {
-                if let Ok(script_bytes) = hex::decode(script_hex) {
-                    header_bytes.extend_from_slice(&script_bytes);
-                }
-            }
-        }
-    }
-
-    let first_hash = Sha256::digest(&header_bytes);
-    let second_hash = Sha256::digest(&first_hash);
-
-    let mut midstate = [0u32; 8];
-    for (i, chunk) in second_hash.chunks(4).enumerate() {
-        midstate[i] = u32::from_le_bytes(chunk.try_into().unwrap());
-    }
-    midstate
+    compute_midstate(&header_words_with_coinbase(header_words, coinbase))
 }
 
 // ----------------- Block Header Preparation -----------------
-pub fn prepare_block_header(template: &Value) -> [u32; 19] {
-    let mut header_words = [0u32; 19];
-    header_words[0] = template["result"]["version"].as_u64().unwrap_or(4) as u32;
-
-    let prev_hash_str = template["result"]["previousblockhash"].as_str().unwrap_or("00");
-    let prev_hash = sha256d::Hash::from_str(prev_hash_str).unwrap_or_else(|_| sha256d::Hash::hash(&[0u8; 32]));
-
-    for i in 0..8 {
-        if i < 2 {
-            header_words[1 + i] = u32::from_le_bytes(prev_hash[i*4..i*4+4].try_into().unwrap());
-        } else {
-            header_words[3 + i - 2] = 0;
-        }
-    }
+/// Header bytes 0..76 for `template` over `merkle_root`, as little-endian
+/// words: version, prevhash (8), merkle root (8), time, bits.
+pub fn prepare_block_header(template: &BlockTemplate, merkle_root: &TxMerkleNode) -> [u32; 19] {
+    let header = BlockHeader {
+        version: template.version as i32,
+        prev_blockhash: template.previous_block_hash,
+        merkle_root: *merkle_root,
+        time: template.curtime,
+        bits: template.bits,
+        nonce: 0,
+    };
+    HeaderWork::from_header(&header).words()
+}
 
-    if let Some(merkle) = template["result"]["merkleroot"].as_str() {
-        let merkle_bytes = hex::decode(merkle).unwrap_or_default();
-        for (i, chunk) in merkle_bytes.chunks(4).enumerate().take(8) {
-            let mut buf = [0u8; 4];
-            buf[..chunk.len()].copy_from_slice(chunk);
-            header_words[3 + i] = u32::from_le_bytes(buf);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_from_bits_places_the_mantissa_by_exponent() {
+        let cases = [
+            (0x1d00_ffff, "00000000ffff0000000000000000000000000000000000000000000000000000"),
+            (0x1705_3894, "0000000000000000000538940000000000000000000000000000000000000000"),
+            (0x207f_ffff, "7fffff0000000000000000000000000000000000000000000000000000000000"),
+            (0x0300_ffff, "000000000000000000000000000000000000000000000000000000000000ffff"),
+        ];
+        for (bits, target) in cases {
+            assert_eq!(hex::encode(target_from_bits(bits)), target, "{bits:08x}");
         }
     }
-
-    header_words[18] = template["result"]["curtime"].as_u64().unwrap_or(0) as u32 + 31;
-    header_words
 }
@@ -0,0 +1,366 @@
+// src/stratum/client.rs
+//! Pool side of the miner: one Stratum V1 connection.
+//!
+//! `StratumClient::connect` does the handshake (`mining.configure` when
+//! version rolling is wanted, `mining.subscribe`, `mining.authorize`).
+//! After that the pool's notifications and the replies to `mining.submit`
+//! come out of `next_event`. `StratumWork` turns a job into work units at
+//! the pool's share difficulty and the found nonces back into shares.
+
+use std::collections::{HashSet, VecDeque};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use super::{extranonce2_bytes, Job, StratumError};
+use crate::backend::WorkUnit;
+use crate::header::HeaderWork;
+use crate::sha_helpers::difficulty_target;
+use crate::version_rolling::VersionRolling;
+
+/// Sent as the user agent in `mining.subscribe`.
+pub const USER_AGENT: &str = concat!("rust_metal_miner/", env!("CARGO_PKG_VERSION"));
+
+/// Fewest rolling bits worth mining with, asked for in `mining.configure`
+/// and required of every mask the pool sets.
+pub const VERSION_ROLLING_MIN_BITS: u32 = 2;
+
+/// Nonces per header.
+const NONCE_SPACE: u64 = 1 << 32;
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// `host:port`, optionally prefixed with `stratum+tcp://`.
+    pub address: String,
+    pub user: String,
+    pub pass: String,
+    /// Ask for BIP310 version rolling inside this mask.
+    pub version_rolling: Option<VersionRolling>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    Job(Job),
+    /// Share difficulty for jobs from now on.
+    Difficulty(f64),
+    /// `mining.set_version_mask`: the rolling mask changed.
+    VersionMask(Option<VersionRolling>),
+    /// Reply to the `mining.submit` with this request id.
+    ShareResult { id: u64, accepted: bool, reason: Option<String> },
+    /// `client.reconnect`; empty fields mean the same host or port.
+    Reconnect { host: String, port: u16 },
+}
+
+/// One `mining.submit`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Share {
+    pub job_id: String,
+    pub extranonce2: Vec<u8>,
+    pub time: u32,
+    pub nonce: u32,
+    /// The header version's bits inside the rolling mask (BIP310).
+    pub version_bits: Option<u32>,
+}
+
+pub struct StratumClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+    user: String,
+    extranonce1: Vec<u8>,
+    extranonce2_size: usize,
+    /// Our mask, kept once the pool agreed to BIP310 rolling, so each
+    /// `mining.set_version_mask` is negotiated from it rather than from the
+    /// last agreed mask.
+    configured_rolling: Option<VersionRolling>,
+    version_rolling: Option<VersionRolling>,
+    pending: VecDeque<ClientEvent>,
+    submits: HashSet<u64>,
+}
+
+impl StratumClient {
+    pub async fn connect(config: &ClientConfig) -> Result<Self, StratumError> {
+        let address = config.address.trim_start_matches("stratum+tcp://");
+        let (read, writer) = TcpStream::connect(address).await?.into_split();
+        let mut client = Self {
+            lines: BufReader::new(read).lines(),
+            writer,
+            next_id: 1,
+            user: config.user.clone(),
+            extranonce1: Vec::new(),
+            extranonce2_size: 0,
+            configured_rolling: None,
+            version_rolling: None,
+            pending: VecDeque::new(),
+            submits: HashSet::new(),
+        };
+
+        if let Some(ours) = config.version_rolling {
+            let params = json!([
+                ["version-rolling"],
+                {
+                    "version-rolling.mask": format!("{:08x}", ours.mask()),
+                    "version-rolling.min-bit-count": VERSION_ROLLING_MIN_BITS,
+                }
+            ]);
+            // Pools without BIP310 answer with an error; mine without rolling.
+            if let Ok(result) = client.call("mining.configure", params).await {
+                if result["version-rolling"] == true {
+                    let mask = parse_mask(&result["version-rolling.mask"]).unwrap_or(0);
+                    client.configured_rolling = Some(ours);
+                    client.version_rolling = ours.negotiate(mask, VERSION_ROLLING_MIN_BITS);
+                }
+            }
+        }
+
+        let result = client.call("mining.subscribe", json!([USER_AGENT])).await?;
+        client.extranonce1 = result[1]
+            .as_str()
+            .and_then(|s| hex::decode(s).ok())
+            .ok_or_else(|| StratumError::Protocol(format!("mining.subscribe result {result}")))?;
+        client.extranonce2_size = result[2]
+            .as_u64()
+            .filter(|&n| (1..=8).contains(&n))
+            .ok_or_else(|| StratumError::Protocol(format!("mining.subscribe result {result}")))?
+            as usize;
+
+        let authorized = client.call("mining.authorize", json!([config.user, config.pass])).await?;
+        if authorized != true {
+            return Err(StratumError::Rejected(format!("mining.authorize for {}", config.user)));
+        }
+        Ok(client)
+    }
+
+    pub fn extranonce1(&self) -> &[u8] {
+        &self.extranonce1
+    }
+
+    pub fn extranonce2_size(&self) -> usize {
+        self.extranonce2_size
+    }
+
+    /// The mask the pool agreed to, if any.
+    pub fn version_rolling(&self) -> Option<VersionRolling> {
+        self.version_rolling
+    }
+
+    /// Next notification or submit reply. Cancel-safe, so it can sit in a
+    /// `select!` or be polled with a zero timeout between scans.
+    pub async fn next_event(&mut self) -> Result<ClientEvent, StratumError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            let message = self.read_message().await?;
+            if let Some(event) = self.handle(&message)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Send a share. The pool's verdict comes back as a
+    /// `ClientEvent::ShareResult` with the returned id.
+    pub async fn submit(&mut self, share: &Share) -> Result<u64, StratumError> {
+        let mut params = vec![
+            json!(self.user),
+            json!(share.job_id),
+            json!(hex::encode(&share.extranonce2)),
+            json!(format!("{:08x}", share.time)),
+            json!(format!("{:08x}", share.nonce)),
+        ];
+        if let Some(bits) = share.version_bits {
+            params.push(json!(format!("{bits:08x}")));
+        }
+        let id = self.send("mining.submit", Value::Array(params)).await?;
+        self.submits.insert(id);
+        Ok(id)
+    }
+
+    /// A request whose reply is awaited here; anything else that arrives in
+    /// the meantime is queued for `next_event`.
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value, StratumError> {
+        let id = self.send(method, params).await?;
+        loop {
+            let message = self.read_message().await?;
+            if message["id"] == id && message.get("method").is_none() {
+                if !message["error"].is_null() {
+                    return Err(StratumError::Rejected(format!("{method}: {}", message["error"])));
+                }
+                return Ok(message["result"].clone());
+            }
+            if let Some(event) = self.handle(&message)? {
+                self.pending.push_back(event);
+            }
+        }
+    }
+
+    async fn send(&mut self, method: &str, params: Value) -> Result<u64, StratumError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut line = json!({ "id": id, "method": method, "params": params }).to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(id)
+    }
+
+    async fn read_message(&mut self) -> Result<Value, StratumError> {
+        loop {
+            let line = self.lines.next_line().await?.ok_or(StratumError::Disconnected)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&line).map_err(|e| StratumError::Protocol(format!("{e}: {line}")));
+        }
+    }
+
+    fn handle(&mut self, message: &Value) -> Result<Option<ClientEvent>, StratumError> {
+        let params = &message["params"];
+        let event = match message["method"].as_str() {
+            Some("mining.notify") => ClientEvent::Job(Job::from_notify(params)?),
+            Some("mining.set_difficulty") => {
+                let difficulty = params[0].as_f64().filter(|d| *d > 0.0);
+                let difficulty = difficulty.ok_or_else(|| StratumError::Protocol(format!("set_difficulty {params}")))?;
+                ClientEvent::Difficulty(difficulty)
+            }
+            Some("mining.set_version_mask") => {
+                let mask = parse_mask(&params[0]).unwrap_or(0);
+                self.version_rolling = self.configured_rolling.and_then(|ours| ours.negotiate(mask, VERSION_ROLLING_MIN_BITS));
+                ClientEvent::VersionMask(self.version_rolling)
+            }
+            Some("client.reconnect") => ClientEvent::Reconnect {
+                host: params[0].as_str().unwrap_or_default().to_string(),
+                port: params[1].as_u64().or_else(|| params[1].as_str()?.parse().ok()).unwrap_or(0) as u16,
+            },
+            Some(_) => return Ok(None),
+            None => {
+                let Some(id) = message["id"].as_u64().filter(|id| self.submits.remove(id)) else {
+                    return Ok(None);
+                };
+                let reason = match &message["error"] {
+                    Value::Null => None,
+                    // [code, message, traceback] by convention.
+                    Value::Array(e) => Some(match e.get(1).and_then(Value::as_str) {
+                        Some(text) => text.to_string(),
+                        None => message["error"].to_string(),
+                    }),
+                    other => Some(other.to_string()),
+                };
+                ClientEvent::ShareResult { id, accepted: message["result"] == true, reason }
+            }
+        };
+        Ok(Some(event))
+    }
+}
+
+fn parse_mask(value: &Value) -> Option<u32> {
+    u32::from_str_radix(value.as_str()?, 16).ok()
+}
+
+/// Work units for one pool job at a fixed share difficulty. A used-up
+/// header rolls the version inside the agreed mask first, then
+/// `extranonce2`.
+pub struct StratumWork {
+    job: Job,
+    extranonce1: Vec<u8>,
+    extranonce2_size: usize,
+    extranonce2: u64,
+    difficulty: f64,
+    target: [u8; 32],
+    version_rolling: Option<VersionRolling>,
+    version_index: u64,
+    header: HeaderWork,
+    next_nonce: u64,
+}
+
+impl StratumWork {
+    pub fn new(
+        job: Job,
+        extranonce1: &[u8],
+        extranonce2_size: usize,
+        difficulty: f64,
+        version_rolling: Option<VersionRolling>,
+    ) -> Self {
+        let header = job.header(extranonce1, &extranonce2_bytes(0, extranonce2_size), job.version, job.time);
+        Self {
+            job,
+            extranonce1: extranonce1.to_vec(),
+            extranonce2_size,
+            extranonce2: 0,
+            difficulty,
+            target: difficulty_target(difficulty),
+            version_rolling,
+            version_index: 0,
+            header,
+            next_nonce: 0,
+        }
+    }
+
+    /// Work for `job` on `client`'s subscription.
+    pub fn for_client(job: Job, client: &StratumClient, difficulty: f64) -> Self {
+        Self::new(job, client.extranonce1(), client.extranonce2_size(), difficulty, client.version_rolling())
+    }
+
+    pub fn job(&self) -> &Job {
+        &self.job
+    }
+
+    pub fn difficulty(&self) -> f64 {
+        self.difficulty
+    }
+
+    pub fn header(&self) -> &HeaderWork {
+        &self.header
+    }
+
+    /// Roll within `rolling` from now on, after `mining.set_version_mask`.
+    /// Restarts at the job's version on a fresh extranonce2, so no header
+    /// mined so far is repeated and none falls outside the new mask.
+    pub fn set_version_rolling(&mut self, rolling: Option<VersionRolling>) {
+        self.version_rolling = rolling;
+        self.version_index = 0;
+        self.extranonce2 = self.extranonce2.wrapping_add(1);
+        self.header = self.job.header(&self.extranonce1, &self.extranonce2(), self.job.version, self.job.time);
+        self.next_nonce = 0;
+    }
+
+    /// Next `count` nonces at the share target.
+    pub fn next_unit(&mut self, count: u32) -> WorkUnit {
+        if self.next_nonce >= NONCE_SPACE {
+            match self.version_rolling {
+                Some(rolling) if self.version_index + 1 < rolling.rolls() => self.version_index += 1,
+                _ => {
+                    self.version_index = 0;
+                    self.extranonce2 = self.extranonce2.wrapping_add(1);
+                }
+            }
+            let version = match self.version_rolling {
+                Some(rolling) => rolling.version(self.job.version, self.version_index),
+                None => self.job.version,
+            };
+            self.header = self.job.header(&self.extranonce1, &self.extranonce2(), version, self.job.time);
+            self.next_nonce = 0;
+        }
+        let count = (count as u64).min(NONCE_SPACE - self.next_nonce) as u32;
+        let mut unit = self.header.work_unit(self.next_nonce as u32, count);
+        unit.target = self.target;
+        self.next_nonce += count as u64;
+        unit
+    }
+
+    /// The share for `nonce` under the header the last unit came from.
+    pub fn share(&self, nonce: u32) -> Share {
+        Share {
+            job_id: self.job.job_id.clone(),
+            extranonce2: self.extranonce2(),
+            time: self.header.time(),
+            nonce,
+            version_bits: self.version_rolling.map(|r| self.header.version() & r.mask()),
+        }
+    }
+
+    fn extranonce2(&self) -> Vec<u8> {
+        extranonce2_bytes(self.extranonce2, self.extranonce2_size)
+    }
+}
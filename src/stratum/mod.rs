@@ -0,0 +1,205 @@
+// src/stratum/mod.rs
+//! Stratum V1: newline-delimited JSON-RPC over TCP.
+//!
+//! A pool hands out jobs rather than templates. A `mining.notify` job
+//! carries the coinbase split around the extranonce slot (`coinb1`,
+//! `coinb2`), the merkle branch from the coinbase to the root, and the
+//! header fields as big-endian hex. The miner fills the slot with the
+//! pool's `extranonce1` and its own `extranonce2`, which gives the coinbase
+//! txid, the merkle root and from there the same `HeaderWork` the template
+//! path builds.
+//!
+//! `prevhash` is sent as eight 32-bit words, each byte-swapped relative to
+//! the header bytes.
+
+use bitcoin::hashes::{sha256d, Hash};
+use serde_json::Value;
+
+use crate::header::HeaderWork;
+use crate::sha_helpers::MerkleBranch;
+
+pub mod client;
+pub mod server;
+pub mod vardiff;
+
+#[derive(Debug)]
+pub enum StratumError {
+    Io(std::io::Error),
+    /// The peer closed the connection.
+    Disconnected,
+    /// A line that is not a Stratum message, or one with the wrong shape.
+    Protocol(String),
+    /// The pool answered a request with an error.
+    Rejected(String),
+}
+
+impl std::fmt::Display for StratumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StratumError::Io(e) => write!(f, "stratum connection failed: {e}"),
+            StratumError::Disconnected => write!(f, "stratum peer disconnected"),
+            StratumError::Protocol(msg) => write!(f, "stratum protocol error: {msg}"),
+            StratumError::Rejected(msg) => write!(f, "stratum request rejected: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for StratumError {}
+
+impl From<std::io::Error> for StratumError {
+    fn from(e: std::io::Error) -> Self {
+        StratumError::Io(e)
+    }
+}
+
+/// A `mining.notify` job.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    pub job_id: String,
+    /// Previous block hash in header byte order.
+    pub prev_hash: [u8; 32],
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+    pub merkle_branch: MerkleBranch,
+    pub version: u32,
+    pub bits: u32,
+    pub time: u32,
+    /// Work on earlier jobs is stale.
+    pub clean_jobs: bool,
+}
+
+impl Job {
+    /// Parse `mining.notify` params.
+    pub fn from_notify(params: &Value) -> Result<Self, StratumError> {
+        let field = |i: usize| params.get(i).ok_or_else(|| bad_notify(format!("missing param {i}")));
+        let text = |i: usize| field(i)?.as_str().ok_or_else(|| bad_notify(format!("param {i} is not a string")));
+        let bytes = |i: usize| hex::decode(text(i)?).map_err(|e| bad_notify(format!("param {i}: {e}")));
+        let word = |i: usize| u32::from_str_radix(text(i)?, 16).map_err(|e| bad_notify(format!("param {i}: {e}")));
+
+        let prev_hash = words_swapped(&bytes(1)?).ok_or_else(|| bad_notify("prevhash is not 32 bytes".into()))?;
+        let steps = field(4)?
+            .as_array()
+            .ok_or_else(|| bad_notify("merkle branch is not an array".into()))?
+            .iter()
+            .map(|step| {
+                let step = hex::decode(step.as_str().unwrap_or_default()).unwrap_or_default();
+                sha256d::Hash::from_slice(&step).map_err(|_| bad_notify("merkle step is not 32 bytes".into()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            job_id: text(0)?.to_string(),
+            prev_hash,
+            coinb1: bytes(2)?,
+            coinb2: bytes(3)?,
+            merkle_branch: MerkleBranch { steps },
+            version: word(5)?,
+            bits: word(6)?,
+            time: word(7)?,
+            clean_jobs: field(8)?.as_bool().unwrap_or(false),
+        })
+    }
+
+    /// `mining.notify` params for this job.
+    pub fn to_notify(&self) -> Value {
+        let branch: Vec<String> = self.merkle_branch.steps.iter().map(hex::encode).collect();
+        serde_json::json!([
+            self.job_id,
+            hex::encode(words_swapped(&self.prev_hash).expect("32 bytes")),
+            hex::encode(&self.coinb1),
+            hex::encode(&self.coinb2),
+            branch,
+            format!("{:08x}", self.version),
+            format!("{:08x}", self.bits),
+            format!("{:08x}", self.time),
+            self.clean_jobs,
+        ])
+    }
+
+    /// Serialized coinbase with the extranonce slot filled.
+    pub fn coinbase(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Vec<u8> {
+        [&self.coinb1[..], extranonce1, extranonce2, &self.coinb2[..]].concat()
+    }
+
+    /// Header for one extranonce2, with nonce 0.
+    pub fn header(&self, extranonce1: &[u8], extranonce2: &[u8], version: u32, time: u32) -> HeaderWork {
+        let txid = sha256d::Hash::hash(&self.coinbase(extranonce1, extranonce2));
+        let root = self.merkle_branch.root(txid);
+        let mut bytes = [0u8; 80];
+        bytes[..4].copy_from_slice(&version.to_le_bytes());
+        bytes[4..36].copy_from_slice(&self.prev_hash);
+        bytes[36..68].copy_from_slice(&root[..]);
+        bytes[68..72].copy_from_slice(&time.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        HeaderWork::from_bytes(&bytes)
+    }
+}
+
+fn bad_notify(msg: String) -> StratumError {
+    StratumError::Protocol(format!("mining.notify: {msg}"))
+}
+
+/// Byte-swap each 32-bit word; converts `prevhash` both ways.
+fn words_swapped(bytes: &[u8]) -> Option<[u8; 32]> {
+    let mut out: [u8; 32] = bytes.try_into().ok()?;
+    for word in out.chunks_mut(4) {
+        word.reverse();
+    }
+    Some(out)
+}
+
+/// `extranonce2` number `n` as the `size` bytes that go into the coinbase
+/// (little-endian, so the low bytes change first).
+pub fn extranonce2_bytes(n: u64, size: usize) -> Vec<u8> {
+    let mut bytes = n.to_le_bytes().to_vec();
+    bytes.resize(size, 0);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_helpers::{difficulty_target, target_from_bits, DIFF1_BITS};
+
+    #[test]
+    fn notify_round_trips_and_fills_the_header() {
+        let prev = "00000000000008a3a41b85b8b29ad444def299fee21793cd8b9e567eab02cd81";
+        let prev_hash: [u8; 32] = {
+            let mut b = hex::decode(prev).unwrap();
+            b.reverse();
+            b.try_into().unwrap()
+        };
+        let job = Job {
+            job_id: "1f".into(),
+            prev_hash,
+            coinb1: vec![1, 2, 3],
+            coinb2: vec![4, 5],
+            merkle_branch: MerkleBranch { steps: vec![sha256d::Hash::hash(b"tx")] },
+            version: 0x2000_0000,
+            bits: 0x1a44_b9f2,
+            time: 1_305_998_791,
+            clean_jobs: true,
+        };
+        let notify = job.to_notify();
+        // Pools send prevhash word-swapped.
+        assert_eq!(&notify[1].as_str().unwrap()[..8], "ab02cd81");
+        assert_eq!(Job::from_notify(&notify).unwrap(), job);
+
+        let header = job.header(&[0xaa], &[0xbb, 0xcc], 0x2000_2000, job.time + 1).to_header();
+        assert_eq!(header.prev_blockhash.to_string(), prev);
+        assert_eq!((header.version, header.time, header.bits), (0x2000_2000, job.time + 1, job.bits));
+        let txid = sha256d::Hash::hash(&[1, 2, 3, 0xaa, 0xbb, 0xcc, 4, 5]);
+        assert_eq!(header.merkle_root, job.merkle_branch.root(txid));
+
+        assert!(Job::from_notify(&serde_json::json!(["1f", "00"])).is_err());
+    }
+
+    #[test]
+    fn difficulty_maps_to_share_targets() {
+        assert_eq!(difficulty_target(1.0), target_from_bits(DIFF1_BITS));
+        assert_eq!(difficulty_target(256.0), target_from_bits(0x1c00_ffff));
+        let easy = difficulty_target(1.0 / 65536.0);
+        assert_eq!(&easy[..5], &[0, 0, 0xff, 0xff, 0][..]);
+        assert_eq!(difficulty_target(0.0), [0xff; 32]);
+        assert_eq!(extranonce2_bytes(0x0102, 4), [2, 1, 0, 0]);
+    }
+}
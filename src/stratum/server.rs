@@ -0,0 +1,622 @@
+// src/stratum/server.rs
+//! Stratum V1 server for solo mining against our own node.
+//!
+//! Jobs come from the node's templates: the coinbase from `coinbase.rs` is
+//! split around its extranonce push, and each connection gets its own
+//! `extranonce1` for the first half of it. Submitted shares are checked
+//! against the job they name; one that also meets the network target is
+//! rebuilt into a block and sent to `submitblock`.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bitcoin::hashes::Hash;
+use bitcoin::Transaction;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+
+use super::vardiff::{Vardiff, VardiffConfig};
+use super::{Job, StratumError};
+use crate::backend::hash_meets_target;
+use crate::coinbase::{CoinbaseBuilder, CoinbaseLayout, EXTRANONCE_SIZE};
+use crate::header::HeaderWork;
+use crate::longpoll::TemplateReceiver;
+use crate::ntime::NTimePolicy;
+use crate::payout::Payout;
+use crate::rpc::{try_and_submit_nonce, BitcoinRpc, BlockTemplate, SubmitOutcome};
+use crate::sha_helpers::{difficulty_target, target_from_bits};
+use crate::version_rolling::VersionRolling;
+
+/// Bytes of the extranonce slot the server assigns per connection; the
+/// miner fills the rest.
+pub const EXTRANONCE1_SIZE: usize = 4;
+pub const EXTRANONCE2_SIZE: usize = EXTRANONCE_SIZE - EXTRANONCE1_SIZE;
+
+/// Jobs kept for late shares while the previous block hash is unchanged.
+const JOBS_KEPT: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// `host:port` to listen on.
+    pub listen: String,
+    /// Where the coinbase pays to.
+    pub payout: Payout,
+    /// Tag in every coinbase scriptSig.
+    pub message: Vec<u8>,
+    /// Share difficulty a connection starts at.
+    pub difficulty: f64,
+    /// Retarget each connection's difficulty; `None` keeps `difficulty`.
+    pub vardiff: Option<VardiffConfig>,
+}
+
+/// Why a share was refused, with its conventional Stratum error code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShareReject {
+    /// The job is unknown or was replaced by a `clean_jobs` job.
+    Stale,
+    Duplicate,
+    LowDifficulty,
+    Unauthorized,
+    NotSubscribed,
+    Invalid(String),
+}
+
+impl ShareReject {
+    pub fn code(&self) -> i64 {
+        match self {
+            ShareReject::Invalid(_) => 20,
+            ShareReject::Stale => 21,
+            ShareReject::Duplicate => 22,
+            ShareReject::LowDifficulty => 23,
+            ShareReject::Unauthorized => 24,
+            ShareReject::NotSubscribed => 25,
+        }
+    }
+
+    /// The `error` member of the reply.
+    fn to_error(&self) -> Value {
+        json!([self.code(), self.to_string(), null])
+    }
+}
+
+impl std::fmt::Display for ShareReject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShareReject::Stale => write!(f, "Job not found (stale)"),
+            ShareReject::Duplicate => write!(f, "Duplicate share"),
+            ShareReject::LowDifficulty => write!(f, "Low difficulty share"),
+            ShareReject::Unauthorized => write!(f, "Unauthorized worker"),
+            ShareReject::NotSubscribed => write!(f, "Not subscribed"),
+            ShareReject::Invalid(reason) => write!(f, "Invalid share: {reason}"),
+        }
+    }
+}
+
+/// Share counts for one worker name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkerStats {
+    pub accepted: u64,
+    /// Sum of the difficulties accepted shares were credited at.
+    pub work: f64,
+    pub stale: u64,
+    pub duplicate: u64,
+    pub low_difficulty: u64,
+    pub invalid: u64,
+    /// Shares that met the network target and went to `submitblock`.
+    pub blocks: u64,
+}
+
+impl WorkerStats {
+    fn record(&mut self, verdict: &Result<f64, ShareReject>) {
+        match verdict {
+            Ok(difficulty) => {
+                self.accepted += 1;
+                self.work += difficulty;
+            }
+            Err(ShareReject::Stale) => self.stale += 1,
+            Err(ShareReject::Duplicate) => self.duplicate += 1,
+            Err(ShareReject::LowDifficulty) => self.low_difficulty += 1,
+            Err(_) => self.invalid += 1,
+        }
+    }
+}
+
+/// A job, the template it was built from and its coinbases.
+#[derive(Debug)]
+struct ServerJob {
+    job: Job,
+    template: Arc<BlockTemplate>,
+    coinbase: CoinbaseBuilder,
+}
+
+struct JobEntry {
+    job: Arc<ServerJob>,
+    /// Hashes of the shares accepted for this job.
+    seen: HashSet<[u8; 32]>,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: HashMap<String, JobEntry>,
+    /// Ids a job was re-sent under after a retarget, to the id it is kept
+    /// under in `jobs`.
+    reissued: HashMap<String, String>,
+    next_job: u64,
+    workers: BTreeMap<String, WorkerStats>,
+    blocks: Vec<SubmitOutcome>,
+    /// Templates no job could be made from, and why.
+    template_errors: Vec<String>,
+}
+
+struct Shared {
+    config: ServerConfig,
+    rpc: BitcoinRpc,
+    state: Mutex<State>,
+    /// The job every connection should be working on.
+    current: watch::Sender<Option<Arc<ServerJob>>>,
+    next_extranonce1: AtomicU32,
+}
+
+pub struct StratumServer {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl StratumServer {
+    /// Listen on `config.listen` and hand out jobs for every template
+    /// `templates` publishes.
+    pub async fn start(config: ServerConfig, rpc: BitcoinRpc, templates: TemplateReceiver) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(&config.listen).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config,
+            rpc,
+            state: Mutex::new(State::default()),
+            current: watch::channel(None).0,
+            next_extranonce1: AtomicU32::new(1),
+        });
+        let tasks = [tokio::spawn(make_jobs(templates, shared.clone())), tokio::spawn(accept(listener, shared.clone()))];
+        Ok(Self { local_addr, shared, tasks })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Share counts per worker name.
+    pub fn workers(&self) -> BTreeMap<String, WorkerStats> {
+        self.shared.state().workers.clone()
+    }
+
+    /// What the node said to each block found, in order.
+    pub fn blocks(&self) -> Vec<SubmitOutcome> {
+        self.shared.state().blocks.clone()
+    }
+
+    /// Why templates were skipped, in order. Miners keep the previous job.
+    pub fn template_errors(&self) -> Vec<String> {
+        self.shared.state().template_errors.clone()
+    }
+}
+
+impl Drop for StratumServer {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+async fn make_jobs(mut templates: TemplateReceiver, shared: Arc<Shared>) {
+    loop {
+        let template = templates.borrow_and_update().clone();
+        if let Some(template) = template {
+            shared.new_job(template);
+        }
+        if templates.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    // Dropping the set when this task is aborted closes every connection.
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((stream, _)) = accepted {
+                    connections.spawn(serve_connection(stream, shared.clone()));
+                }
+            }
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn new_job(&self, template: Arc<BlockTemplate>) {
+        let layout = CoinbaseLayout::new(&self.config.message).with_extranonce_sizes(EXTRANONCE1_SIZE, EXTRANONCE2_SIZE);
+        let coinbase = match CoinbaseBuilder::new(&template, &self.config.payout, layout) {
+            Ok(coinbase) => coinbase,
+            Err(e) => return self.state().template_errors.push(format!("template at height {}: {e}", template.height)),
+        };
+        let split = coinbase.split();
+        let prev_hash = template.previous_block_hash.as_hash().into_inner();
+
+        let mut state = self.state();
+        let clean = self.current.borrow().as_ref().is_none_or(|current| current.job.prev_hash != prev_hash);
+        if clean {
+            state.jobs.clear();
+            state.reissued.clear();
+        } else if state.jobs.len() >= JOBS_KEPT {
+            let oldest = state.jobs.keys().min_by_key(|id| u64::from_str_radix(id, 16).unwrap_or(0)).cloned();
+            let oldest = oldest.expect("jobs is not empty");
+            state.jobs.remove(&oldest);
+            state.reissued.retain(|_, id| *id != oldest);
+        }
+        state.next_job += 1;
+        let job = Job {
+            job_id: format!("{:x}", state.next_job),
+            prev_hash,
+            coinb1: split.coinb1,
+            coinb2: split.coinb2,
+            merkle_branch: split.merkle_branch,
+            version: template.version,
+            bits: template.bits,
+            time: template.curtime,
+            clean_jobs: clean,
+        };
+        let job = Arc::new(ServerJob { job, template, coinbase });
+        state.jobs.insert(job.job.job_id.clone(), JobEntry { job: job.clone(), seen: HashSet::new() });
+        drop(state);
+        self.current.send_replace(Some(job));
+    }
+
+    /// `job` under a fresh id and without `clean_jobs`, for a connection
+    /// whose difficulty just changed. Shares on either id are checked
+    /// against the same job, duplicates included.
+    fn reissue(&self, job: &Job) -> Option<Job> {
+        let mut state = self.state();
+        let base = state.reissued.get(&job.job_id).unwrap_or(&job.job_id).clone();
+        if !state.jobs.contains_key(&base) {
+            return None;
+        }
+        state.next_job += 1;
+        let job_id = format!("{:x}", state.next_job);
+        state.reissued.insert(job_id.clone(), base);
+        Some(Job { job_id, clean_jobs: false, ..job.clone() })
+    }
+
+    /// Check `mining.submit` params from `connection`. An accepted share
+    /// comes back with the difficulty it was checked at, and with the block
+    /// when it also meets the network target.
+    fn check_share(
+        &self,
+        connection: &Connection,
+        params: &Value,
+    ) -> Result<(f64, Option<BlockCandidate>), ShareReject> {
+        let extranonce1 = connection.extranonce1.ok_or(ShareReject::NotSubscribed)?;
+        let worker = params[0].as_str().unwrap_or_default();
+        if !connection.workers.contains(worker) {
+            return Err(ShareReject::Unauthorized);
+        }
+        let text = |i: usize| params[i].as_str().ok_or_else(|| ShareReject::Invalid(format!("param {i} is not a string")));
+        let word = |i: usize| {
+            let text = text(i)?;
+            u32::from_str_radix(text, 16).map_err(|_| ShareReject::Invalid(format!("param {i} is not a hex word")))
+        };
+
+        let job_id = text(1)?;
+        let mut state = self.state();
+        let base = state.reissued.get(job_id).cloned().unwrap_or_else(|| job_id.to_string());
+        let entry = state.jobs.get_mut(&base).ok_or(ShareReject::Stale)?;
+        let job = entry.job.clone();
+        let extranonce2 = hex::decode(text(2)?)
+            .ok()
+            .filter(|e| e.len() == EXTRANONCE2_SIZE)
+            .ok_or_else(|| ShareReject::Invalid(format!("extranonce2 is not {EXTRANONCE2_SIZE} bytes")))?;
+        let time = word(3)?;
+        let ntime = NTimePolicy::from_template(&job.template);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        if time < ntime.lower() || time > ntime.upper(now) {
+            return Err(ShareReject::Invalid("ntime out of range".into()));
+        }
+        let nonce = word(4)?;
+        let version = match params.get(5) {
+            Some(_) => {
+                let bits = word(5)?;
+                let rolling = connection.version_rolling.unwrap_or(VersionRolling::new(0));
+                let version = (job.job.version & !rolling.mask()) | bits;
+                if !rolling.allows(job.job.version, version) {
+                    return Err(ShareReject::Invalid("version bits outside the negotiated mask".into()));
+                }
+                version
+            }
+            None => job.job.version,
+        };
+
+        let header = job.job.header(&extranonce1, &extranonce2, version, time);
+        let hash = header.hash(nonce);
+        let solves_block = hash_meets_target(&hash, &target_from_bits(job.job.bits));
+        let difficulty = connection.share_difficulty(job_id);
+        if !solves_block && !hash_meets_target(&hash, &difficulty_target(difficulty)) {
+            return Err(ShareReject::LowDifficulty);
+        }
+        if !entry.seen.insert(hash) {
+            return Err(ShareReject::Duplicate);
+        }
+        let block = solves_block.then(|| {
+            BlockCandidate {
+                coinbase: job.coinbase.build(&[&extranonce1[..], &extranonce2].concat()),
+                template: job.template.clone(),
+                header,
+                nonce,
+            }
+        });
+        Ok((difficulty, block))
+    }
+}
+
+/// A share that meets the network target, ready for `try_and_submit_nonce`.
+struct BlockCandidate {
+    template: Arc<BlockTemplate>,
+    coinbase: Transaction,
+    header: HeaderWork,
+    nonce: u32,
+}
+
+/// Per-connection session state.
+struct Connection {
+    /// Set by `mining.subscribe`.
+    extranonce1: Option<[u8; EXTRANONCE1_SIZE]>,
+    workers: HashSet<String>,
+    difficulty: f64,
+    vardiff: Option<Vardiff>,
+    /// Difficulty in force when each recent job was sent.
+    job_difficulty: VecDeque<(String, f64)>,
+    /// The last job sent, re-sent under a new id after a retarget.
+    last_job: Option<Job>,
+    version_rolling: Option<VersionRolling>,
+}
+
+async fn serve_connection(stream: TcpStream, shared: Arc<Shared>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let mut jobs = shared.current.subscribe();
+    let vardiff = shared.config.vardiff.map(|config| Vardiff::new(config, shared.config.difficulty, Instant::now()));
+    let mut connection = Connection {
+        extranonce1: None,
+        workers: HashSet::new(),
+        difficulty: vardiff.as_ref().map_or(shared.config.difficulty, Vardiff::difficulty),
+        vardiff,
+        job_difficulty: VecDeque::new(),
+        last_job: None,
+        version_rolling: None,
+    };
+    // Idle miners are retargeted on this tick; unused without vardiff.
+    let tick = shared.config.vardiff.map_or(Duration::from_secs(60), |c| c.retarget_time / 4);
+    let mut ticker = tokio::time::interval(tick.max(Duration::from_millis(1)));
+    loop {
+        let messages = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => match connection.handle(&shared, &mut jobs, &line).await {
+                    Ok(messages) => messages,
+                    Err(_) => return,
+                },
+                _ => return,
+            },
+            changed = jobs.changed(), if !connection.workers.is_empty() => {
+                if changed.is_err() {
+                    return;
+                }
+                let job = jobs.borrow_and_update().clone();
+                job.map(|job| vec![connection.notify(&job.job)]).unwrap_or_default()
+            }
+            now = ticker.tick(), if connection.vardiff.is_some() => {
+                let changed = connection.vardiff.as_mut().and_then(|v| v.tick(now.into_std()));
+                connection.retarget(&shared, changed)
+            }
+        };
+        for message in messages {
+            if write.write_all(format!("{message}\n").as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// `mining.notify` for `job`, remembering the difficulty it goes out at.
+    fn notify(&mut self, job: &Job) -> Value {
+        if job.clean_jobs {
+            self.job_difficulty.clear();
+        }
+        if self.job_difficulty.len() >= JOBS_KEPT {
+            self.job_difficulty.pop_front();
+        }
+        self.job_difficulty.push_back((job.job_id.clone(), self.difficulty));
+        self.last_job = Some(job.clone());
+        json!({ "id": null, "method": "mining.notify", "params": job.to_notify() })
+    }
+
+    /// Share difficulty for a share on `job_id`: the difficulty the job
+    /// was sent at, or the current one for a job this connection was not
+    /// sent.
+    fn share_difficulty(&self, job_id: &str) -> f64 {
+        let sent_at = self.job_difficulty.iter().rev().find(|(id, _)| id == job_id).map(|(_, d)| *d);
+        sent_at.unwrap_or(self.difficulty)
+    }
+
+    /// `mining.set_difficulty` when vardiff moved the difficulty, then the
+    /// last job again under a fresh id so the new difficulty applies from
+    /// the miner's next share; shares on earlier ids keep theirs.
+    fn retarget(&mut self, shared: &Shared, changed: Option<f64>) -> Vec<Value> {
+        let Some(difficulty) = changed else { return Vec::new() };
+        self.difficulty = difficulty;
+        let mut messages = vec![json!({ "id": null, "method": "mining.set_difficulty", "params": [difficulty] })];
+        if let Some(job) = self.last_job.as_ref().and_then(|job| shared.reissue(job)) {
+            messages.push(self.notify(&job));
+        }
+        messages
+    }
+
+    /// Replies and notifications for one line from the miner.
+    async fn handle(
+        &mut self,
+        shared: &Shared,
+        jobs: &mut watch::Receiver<Option<Arc<ServerJob>>>,
+        line: &str,
+    ) -> Result<Vec<Value>, StratumError> {
+        if line.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let request: Value = serde_json::from_str(line).map_err(|e| StratumError::Protocol(format!("{e}: {line}")))?;
+        let id = request["id"].clone();
+        let params = &request["params"];
+        let reply = |result: Value, error: Value| json!({ "id": id, "result": result, "error": error });
+
+        let messages = match request["method"].as_str().unwrap_or_default() {
+            "mining.configure" => {
+                let mut result = json!({});
+                let asked = params[0].as_array().is_some_and(|ext| ext.iter().any(|e| e == "version-rolling"));
+                if asked {
+                    let requested = params[1]["version-rolling.mask"].as_str().and_then(|m| u32::from_str_radix(m, 16).ok());
+                    let min_bits = params[1]["version-rolling.min-bit-count"].as_u64().unwrap_or(1) as u32;
+                    self.version_rolling = VersionRolling::bip320().negotiate(requested.unwrap_or(0), min_bits);
+                    result["version-rolling"] = json!(self.version_rolling.is_some());
+                    if let Some(rolling) = self.version_rolling {
+                        result["version-rolling.mask"] = json!(format!("{:08x}", rolling.mask()));
+                    }
+                }
+                vec![reply(result, Value::Null)]
+            }
+            "mining.subscribe" => {
+                let extranonce1 = *self.extranonce1.get_or_insert_with(|| {
+                    shared.next_extranonce1.fetch_add(1, Ordering::Relaxed).to_le_bytes()
+                });
+                let subscription = hex::encode(extranonce1);
+                let subscriptions = json!([["mining.set_difficulty", subscription], ["mining.notify", subscription]]);
+                vec![reply(json!([subscriptions, subscription, EXTRANONCE2_SIZE]), Value::Null)]
+            }
+            "mining.authorize" => {
+                // Solo mining for whoever connects: any name is a worker.
+                let Some(worker) = params[0].as_str() else {
+                    return Ok(vec![reply(json!(false), ShareReject::Unauthorized.to_error())]);
+                };
+                let first = self.workers.is_empty();
+                self.workers.insert(worker.to_string());
+                let mut messages = vec![reply(json!(true), Value::Null)];
+                if first {
+                    messages.push(json!({ "id": null, "method": "mining.set_difficulty", "params": [self.difficulty] }));
+                    if let Some(job) = jobs.borrow_and_update().clone() {
+                        messages.push(self.notify(&job.job));
+                    }
+                }
+                messages
+            }
+            "mining.extranonce.subscribe" => vec![reply(json!(false), Value::Null)],
+            "mining.submit" => {
+                let worker = params[0].as_str().unwrap_or_default().to_string();
+                let verdict = shared.check_share(self, params);
+                let verdict = match verdict {
+                    Ok((difficulty, Some(block))) => {
+                        let outcome = try_and_submit_nonce(
+                            &shared.rpc,
+                            &block.template,
+                            &block.coinbase,
+                            &block.header,
+                            block.nonce,
+                        )
+                        .await;
+                        // Sent but unanswered: the node may still have it.
+                        let outcome = outcome.unwrap_or_else(|_| {
+                            let mut solved = block.header.clone();
+                            solved.set_nonce(block.nonce);
+                            SubmitOutcome::Inconclusive { block_hash: solved.to_header().block_hash() }
+                        });
+                        let mut state = shared.state();
+                        state.blocks.push(outcome);
+                        state.workers.entry(worker.clone()).or_default().blocks += 1;
+                        Ok(difficulty)
+                    }
+                    Ok((difficulty, None)) => Ok(difficulty),
+                    Err(reject) => Err(reject),
+                };
+                if !matches!(verdict, Err(ShareReject::Unauthorized | ShareReject::NotSubscribed)) {
+                    shared.state().workers.entry(worker).or_default().record(&verdict);
+                }
+                let mut messages = match &verdict {
+                    Ok(_) => vec![reply(json!(true), Value::Null)],
+                    Err(reject) => vec![reply(json!(false), reject.to_error())],
+                };
+                if let (Some(vardiff), Ok(credited)) = (self.vardiff.as_mut(), verdict) {
+                    let changed = vardiff.record_share(credited, Instant::now());
+                    messages.extend(self.retarget(shared, changed));
+                }
+                messages
+            }
+            method => vec![reply(Value::Null, json!([20, format!("Unknown method {method}"), null]))],
+        };
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha_helpers::MerkleBranch;
+
+    #[test]
+    fn shares_count_at_the_difficulty_their_job_was_sent_at() {
+        let job = |id: &str, clean_jobs| Job {
+            job_id: id.into(),
+            prev_hash: [0; 32],
+            coinb1: Vec::new(),
+            coinb2: Vec::new(),
+            merkle_branch: MerkleBranch { steps: Vec::new() },
+            version: 0x2000_0000,
+            bits: 0x207f_ffff,
+            time: 0,
+            clean_jobs,
+        };
+        let mut connection = Connection {
+            extranonce1: None,
+            workers: HashSet::new(),
+            difficulty: 8.0,
+            vardiff: None,
+            job_difficulty: VecDeque::new(),
+            last_job: None,
+            version_rolling: None,
+        };
+        connection.notify(&job("a", true));
+        connection.difficulty = 32.0;
+        assert_eq!(connection.share_difficulty("a"), 8.0);
+        connection.notify(&job("b", false));
+        assert_eq!(connection.share_difficulty("b"), 32.0);
+
+        // Old ids keep their difficulty after it drops; unknown ids get
+        // the current one.
+        connection.difficulty = 2.0;
+        assert_eq!(connection.share_difficulty("a"), 8.0);
+        assert_eq!(connection.share_difficulty("b"), 32.0);
+        assert_eq!(connection.share_difficulty("x"), 2.0);
+
+        connection.difficulty = 16.0;
+        connection.notify(&job("c", true));
+        assert_eq!(connection.job_difficulty.len(), 1);
+        assert_eq!(connection.share_difficulty("a"), 16.0);
+        assert_eq!(connection.last_job.as_ref().map(|j| j.job_id.as_str()), Some("c"));
+    }
+}
@@ -0,0 +1,188 @@
+// src/stratum/vardiff.rs
+//! Variable share difficulty per connection.
+//!
+//! Each accepted share is credited with the difficulty it was checked at,
+//! so the sum over a window is the work done in it. The ideal difficulty
+//! for the window is `work / elapsed * target_interval`. It moves the
+//! current one by at most `MAX_STEP` either way, and only when it is
+//! outside the hysteresis band, so a miner near the target keeps its
+//! difficulty.
+
+use std::time::{Duration, Instant};
+
+/// Largest factor a single retarget moves the difficulty by.
+const MAX_STEP: f64 = 4.0;
+
+/// Shares that end a window early; fast miners settle in a few of them.
+const SHARES_PER_RETARGET: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VardiffConfig {
+    /// Aim for one share per this interval.
+    pub target_interval: Duration,
+    /// Longest window before retargeting, shares or not.
+    pub retarget_time: Duration,
+    /// Keep the difficulty while the ideal one is within this fraction of
+    /// it (0.3 is ±30%).
+    pub hysteresis: f64,
+    pub min_difficulty: f64,
+    pub max_difficulty: f64,
+}
+
+impl Default for VardiffConfig {
+    fn default() -> Self {
+        Self {
+            target_interval: Duration::from_secs(10),
+            retarget_time: Duration::from_secs(60),
+            hysteresis: 0.3,
+            min_difficulty: 1.0,
+            max_difficulty: 2f64.powi(40),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Vardiff {
+    config: VardiffConfig,
+    difficulty: f64,
+    window_start: Instant,
+    window_shares: u32,
+    /// Sum of the credited difficulties in the window.
+    window_work: f64,
+}
+
+impl Vardiff {
+    /// Start at `difficulty`, clamped to the configured bounds.
+    pub fn new(config: VardiffConfig, difficulty: f64, now: Instant) -> Self {
+        Self {
+            difficulty: difficulty.clamp(config.min_difficulty, config.max_difficulty),
+            config,
+            window_start: now,
+            window_shares: 0,
+            window_work: 0.0,
+        }
+    }
+
+    pub fn difficulty(&self) -> f64 {
+        self.difficulty
+    }
+
+    /// Count an accepted share worth `difficulty`. Returns the new
+    /// difficulty when this share ends a window and it changes.
+    pub fn record_share(&mut self, difficulty: f64, now: Instant) -> Option<f64> {
+        self.window_shares += 1;
+        self.window_work += difficulty;
+        self.retarget(now)
+    }
+
+    /// Call periodically, so a miner that stops finding shares still gets
+    /// an easier difficulty.
+    pub fn tick(&mut self, now: Instant) -> Option<f64> {
+        self.retarget(now)
+    }
+
+    fn retarget(&mut self, now: Instant) -> Option<f64> {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < self.config.retarget_time && self.window_shares < SHARES_PER_RETARGET {
+            return None;
+        }
+        let elapsed = elapsed.as_secs_f64().max(1e-3);
+        let ideal = self.window_work / elapsed * self.config.target_interval.as_secs_f64();
+        let ratio = (ideal / self.difficulty).clamp(1.0 / MAX_STEP, MAX_STEP);
+        self.window_start = now;
+        self.window_shares = 0;
+        self.window_work = 0.0;
+
+        let band = 1.0 - self.config.hysteresis..=1.0 + self.config.hysteresis;
+        if band.contains(&ratio) {
+            return None;
+        }
+        let next = (self.difficulty * ratio).clamp(self.config.min_difficulty, self.config.max_difficulty);
+        if next == self.difficulty {
+            return None;
+        }
+        self.difficulty = next;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed shares from a miner doing `rate` difficulty-1 shares per second
+    /// for `seconds`, one share at a time at the current difficulty.
+    fn mine(vardiff: &mut Vardiff, start: Instant, rate: f64, seconds: f64) -> Instant {
+        let mut now = start;
+        let end = start + Duration::from_secs_f64(seconds);
+        loop {
+            now += Duration::from_secs_f64(vardiff.difficulty() / rate);
+            if now > end {
+                return end;
+            }
+            let difficulty = vardiff.difficulty();
+            vardiff.record_share(difficulty, now);
+        }
+    }
+
+    #[test]
+    fn settles_near_the_target_interval() {
+        let config = VardiffConfig::default();
+        let start = Instant::now();
+        let mut vardiff = Vardiff::new(config, 1.0, start);
+        // 5000 shares/s at difficulty 1 wants difficulty 50000 for one per 10 s.
+        let now = mine(&mut vardiff, start, 5000.0, 3600.0);
+        let settled = vardiff.difficulty();
+        assert!((50000.0 * 0.7..=50000.0 * 1.3).contains(&settled), "{settled}");
+
+        // Inside the band: nothing changes.
+        mine(&mut vardiff, now, 5000.0 * 1.1, 600.0);
+        assert_eq!(vardiff.difficulty(), settled);
+    }
+
+    #[test]
+    fn steps_are_bounded_and_idle_miners_get_easier_work() {
+        let config = VardiffConfig { min_difficulty: 16.0, max_difficulty: 1024.0, ..VardiffConfig::default() };
+        let start = Instant::now();
+        let mut vardiff = Vardiff::new(config, 1.0, start);
+        assert_eq!(vardiff.difficulty(), 16.0);
+
+        // Twenty shares in a second ends the window at most 4x up.
+        for i in 1..=20 {
+            let changed = vardiff.record_share(16.0, start + Duration::from_millis(50 * i));
+            assert_eq!(changed, (i == 20).then_some(64.0));
+        }
+        mine(&mut vardiff, start + Duration::from_secs(1), 1e9, 60.0);
+        assert_eq!(vardiff.difficulty(), 1024.0);
+
+        let mut now = start;
+        let mut vardiff = Vardiff::new(config, 1024.0, now);
+        assert_eq!(vardiff.tick(now + Duration::from_secs(30)), None);
+        now += config.retarget_time;
+        assert_eq!(vardiff.tick(now), Some(256.0));
+        for _ in 0..4 {
+            now += config.retarget_time;
+            vardiff.tick(now);
+        }
+        assert_eq!(vardiff.difficulty(), 16.0);
+    }
+
+    #[test]
+    fn band_is_thirty_percent_either_way() {
+        // One-window retargets where the ideal difficulty is the window's work.
+        let interval = Duration::from_secs(64);
+        let config = VardiffConfig { target_interval: interval, retarget_time: interval, ..VardiffConfig::default() };
+        let mut now = Instant::now();
+        let mut vardiff = Vardiff::new(config, 128.0, now);
+        let mut window = |vardiff: &mut Vardiff, work: f64| {
+            vardiff.record_share(work, now + Duration::from_secs(1));
+            now += interval;
+            vardiff.tick(now)
+        };
+
+        // 25% below and above stay; past 30% below moves.
+        assert_eq!(window(&mut vardiff, 96.0), None);
+        assert_eq!(window(&mut vardiff, 160.0), None);
+        assert_eq!(window(&mut vardiff, 88.0), Some(88.0));
+    }
+}
@@ -0,0 +1,562 @@
+// src/stratum_v2/client.rs
+//! Pool side of the miner: one Stratum V2 connection with one channel.
+//!
+//! `Sv2Client::connect` runs the Noise handshake, `SetupConnection` and
+//! opens the channel. After that jobs, target changes and share verdicts
+//! come out of `next_event`. `Sv2Work` turns a job into work units at the
+//! channel's target and the found nonces back into shares.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::hashes::{sha256d, Hash};
+use tokio::net::TcpStream;
+
+use super::messages::{Message, CHANNEL_MSG, MINING_PROTOCOL, REQUIRES_STANDARD_JOBS};
+use super::noise::{Authority, NoiseStream};
+use super::{target_from_u256, Sv2Error};
+use crate::backend::WorkUnit;
+use crate::header::HeaderWork;
+use crate::sha_helpers::MerkleBranch;
+use crate::stratum::client::USER_AGENT;
+use crate::stratum::extranonce2_bytes;
+use crate::version_rolling::VersionRolling;
+
+/// The only protocol version there is.
+const PROTOCOL_VERSION: u16 = 2;
+
+/// Nonces per header.
+const NONCE_SPACE: u64 = 1 << 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// Header-only jobs; the pool fixes the merkle root.
+    Standard,
+    /// Jobs with the coinbase; the miner rolls at least this many
+    /// extranonce bytes.
+    Extended { min_extranonce_size: u16 },
+}
+
+#[derive(Clone, Debug)]
+pub struct Sv2Config {
+    /// `host:port`, optionally prefixed with `stratum2+tcp://`.
+    pub address: String,
+    /// How the pool's certificate is checked.
+    pub authority: Authority,
+    pub user_identity: String,
+    /// Hashes per second, for the pool's initial target.
+    pub nominal_hash_rate: f32,
+    pub channel: ChannelKind,
+}
+
+/// The channel the pool opened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Channel {
+    pub id: u32,
+    pub kind: ChannelKind,
+    /// Share target, big-endian.
+    pub target: [u8; 32],
+    /// Extranonce bytes the pool fixes, right after the coinbase prefix.
+    pub extranonce_prefix: Vec<u8>,
+    /// Extranonce bytes the miner fills; zero on a standard channel.
+    pub extranonce_size: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobKind {
+    Standard { merkle_root: [u8; 32] },
+    Extended { coinbase_prefix: Vec<u8>, coinbase_suffix: Vec<u8>, merkle_path: MerkleBranch },
+}
+
+/// A job with its previous block, ready to mine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sv2Job {
+    pub job_id: u32,
+    /// Previous block hash in header byte order.
+    pub prev_hash: [u8; 32],
+    pub bits: u32,
+    /// Earliest allowed header time.
+    pub min_ntime: u32,
+    pub version: u32,
+    /// Whether the BIP320 version bits may be rolled.
+    pub version_rolling_allowed: bool,
+    pub kind: JobKind,
+}
+
+impl Sv2Job {
+    /// Serialized coinbase for an extended job; `None` for a standard one.
+    pub fn coinbase(&self, extranonce_prefix: &[u8], extranonce: &[u8]) -> Option<Vec<u8>> {
+        match &self.kind {
+            JobKind::Standard { .. } => None,
+            JobKind::Extended { coinbase_prefix, coinbase_suffix, .. } => {
+                Some([&coinbase_prefix[..], extranonce_prefix, extranonce, &coinbase_suffix[..]].concat())
+            }
+        }
+    }
+
+    /// Header for one extranonce (ignored on standard jobs), with nonce 0.
+    pub fn header(&self, extranonce_prefix: &[u8], extranonce: &[u8], version: u32, time: u32) -> HeaderWork {
+        let root = match &self.kind {
+            JobKind::Standard { merkle_root } => *merkle_root,
+            JobKind::Extended { merkle_path, .. } => {
+                let coinbase = self.coinbase(extranonce_prefix, extranonce).expect("extended job");
+                merkle_path.root(sha256d::Hash::hash(&coinbase)).as_hash().into_inner()
+            }
+        };
+        let mut bytes = [0u8; 80];
+        bytes[..4].copy_from_slice(&version.to_le_bytes());
+        bytes[4..36].copy_from_slice(&self.prev_hash);
+        bytes[36..68].copy_from_slice(&root);
+        bytes[68..72].copy_from_slice(&time.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        HeaderWork::from_bytes(&bytes)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sv2Event {
+    /// Work on earlier jobs is stale when the previous block changed.
+    Job { job: Sv2Job, clean: bool },
+    /// Share target (big-endian) for work from now on.
+    Target([u8; 32]),
+    /// The channel's extranonce prefix changed; work built before it no
+    /// longer matches the pool's coinbase.
+    ExtranoncePrefix(Vec<u8>),
+    /// Shares up to `last_sequence_number` were accepted.
+    SharesAccepted { last_sequence_number: u32, count: u32 },
+    ShareRejected { sequence_number: u32, reason: String },
+    /// The pool closed the channel; nothing more will be mined on it.
+    ChannelClosed { reason: String },
+    /// `Reconnect`; an empty host or port 0 means the same one.
+    Reconnect { host: String, port: u16 },
+}
+
+/// One `SubmitShares*`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sv2Share {
+    pub job_id: u32,
+    pub nonce: u32,
+    pub ntime: u32,
+    /// Full header version.
+    pub version: u32,
+    /// The miner's extranonce bytes; empty on a standard channel.
+    pub extranonce: Vec<u8>,
+}
+
+/// The block the pool's jobs build on.
+struct PrevHash {
+    prev_hash: [u8; 32],
+    min_ntime: u32,
+    bits: u32,
+}
+
+pub struct Sv2Client {
+    stream: NoiseStream,
+    channel: Channel,
+    prev: Option<PrevHash>,
+    /// Jobs waiting for their `SetNewPrevHash`, or for any previous block
+    /// at all.
+    future_jobs: HashMap<u32, (Option<u32>, u32, bool, JobKind)>,
+    next_sequence: u32,
+    pending: VecDeque<Sv2Event>,
+}
+
+impl Sv2Client {
+    pub async fn connect(config: &Sv2Config) -> Result<Self, Sv2Error> {
+        let address = config.address.trim_start_matches("stratum2+tcp://");
+        let tcp = TcpStream::connect(address).await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        let mut stream = NoiseStream::initiate(tcp, &config.authority, now).await?;
+
+        let (host, port) = address.rsplit_once(':').unwrap_or((address, "0"));
+        let flags = match config.channel {
+            ChannelKind::Standard => REQUIRES_STANDARD_JOBS,
+            ChannelKind::Extended { .. } => 0,
+        };
+        let setup = Message::SetupConnection {
+            protocol: MINING_PROTOCOL,
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            flags,
+            endpoint_host: host.to_string(),
+            endpoint_port: port.parse().unwrap_or(0),
+            vendor: "rust_metal_miner".into(),
+            hardware_version: String::new(),
+            firmware: USER_AGENT.into(),
+            device_id: config.user_identity.clone(),
+        };
+        stream.write_frame(&setup.to_frame()).await?;
+        match Message::from_frame(&stream.read_frame().await?)? {
+            Message::SetupConnectionSuccess { .. } => {}
+            Message::SetupConnectionError { error_code, .. } => {
+                return Err(Sv2Error::Rejected(format!("SetupConnection: {error_code}")));
+            }
+            other => return Err(unexpected(&other)),
+        }
+
+        let request_id = 1;
+        let open = match config.channel {
+            ChannelKind::Standard => Message::OpenStandardMiningChannel {
+                request_id,
+                user_identity: config.user_identity.clone(),
+                nominal_hash_rate: config.nominal_hash_rate,
+                max_target: [0xff; 32],
+            },
+            ChannelKind::Extended { min_extranonce_size } => Message::OpenExtendedMiningChannel {
+                request_id,
+                user_identity: config.user_identity.clone(),
+                nominal_hash_rate: config.nominal_hash_rate,
+                max_target: [0xff; 32],
+                min_extranonce_size,
+            },
+        };
+        stream.write_frame(&open.to_frame()).await?;
+        let channel = match Message::from_frame(&stream.read_frame().await?)? {
+            Message::OpenStandardMiningChannelSuccess { channel_id, target, extranonce_prefix, .. }
+                if config.channel == ChannelKind::Standard =>
+            {
+                Channel {
+                    id: channel_id,
+                    kind: config.channel,
+                    target: target_from_u256(&target),
+                    extranonce_prefix,
+                    extranonce_size: 0,
+                }
+            }
+            Message::OpenExtendedMiningChannelSuccess { channel_id, target, extranonce_size, extranonce_prefix, .. }
+                if config.channel != ChannelKind::Standard =>
+            {
+                Channel {
+                    id: channel_id,
+                    kind: config.channel,
+                    target: target_from_u256(&target),
+                    extranonce_prefix,
+                    extranonce_size: extranonce_size as usize,
+                }
+            }
+            Message::OpenMiningChannelError { error_code, .. } => {
+                return Err(Sv2Error::Rejected(format!("OpenMiningChannel: {error_code}")));
+            }
+            other => return Err(unexpected(&other)),
+        };
+
+        Ok(Self {
+            stream,
+            channel,
+            prev: None,
+            future_jobs: HashMap::new(),
+            next_sequence: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// Next job, target change or share verdict. Cancel-safe, so it can sit
+    /// in a `select!` or be polled with a zero timeout between scans.
+    pub async fn next_event(&mut self) -> Result<Sv2Event, Sv2Error> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            let frame = self.stream.read_frame().await?;
+            match Message::from_frame(&frame) {
+                Ok(message) => self.handle(message)?,
+                // Extensions this miner did not ask for are skipped.
+                Err(Sv2Error::Unsupported { extension_type, .. }) if extension_type & !CHANNEL_MSG != 0 => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Send a share. Returns its sequence number, which the pool's
+    /// `SharesAccepted` and `ShareRejected` refer to.
+    pub async fn submit(&mut self, share: &Sv2Share) -> Result<u32, Sv2Error> {
+        let sequence_number = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let message = match self.channel.kind {
+            ChannelKind::Standard => Message::SubmitSharesStandard {
+                channel_id: self.channel.id,
+                sequence_number,
+                job_id: share.job_id,
+                nonce: share.nonce,
+                ntime: share.ntime,
+                version: share.version,
+            },
+            ChannelKind::Extended { .. } => Message::SubmitSharesExtended {
+                channel_id: self.channel.id,
+                sequence_number,
+                job_id: share.job_id,
+                nonce: share.nonce,
+                ntime: share.ntime,
+                version: share.version,
+                extranonce: share.extranonce.clone(),
+            },
+        };
+        self.stream.write_frame(&message.to_frame()).await?;
+        Ok(sequence_number)
+    }
+
+    fn handle(&mut self, message: Message) -> Result<(), Sv2Error> {
+        match message {
+            Message::NewMiningJob { job_id, min_ntime, version, merkle_root, .. } => {
+                self.new_job(job_id, min_ntime, version, true, JobKind::Standard { merkle_root });
+            }
+            Message::NewExtendedMiningJob {
+                job_id,
+                min_ntime,
+                version,
+                version_rolling_allowed,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+                ..
+            } => {
+                let steps = merkle_path.iter().map(|step| sha256d::Hash::from_inner(*step)).collect();
+                let kind = JobKind::Extended {
+                    coinbase_prefix: coinbase_tx_prefix,
+                    coinbase_suffix: coinbase_tx_suffix,
+                    merkle_path: MerkleBranch { steps },
+                };
+                self.new_job(job_id, min_ntime, version, version_rolling_allowed, kind);
+            }
+            Message::SetNewPrevHash { job_id, prev_hash, min_ntime, nbits, .. } => {
+                // Future jobs for the old block are stale.
+                let mut future = std::mem::take(&mut self.future_jobs);
+                let Some((_, version, rolling, kind)) = future.remove(&job_id) else {
+                    return Err(Sv2Error::Protocol(format!("SetNewPrevHash names job {job_id}, which is not a future job")));
+                };
+                self.prev = Some(PrevHash { prev_hash, min_ntime, bits: nbits });
+                let job = self.job(job_id, None, version, rolling, kind);
+                self.pending.push_back(Sv2Event::Job { job, clean: true });
+            }
+            Message::SetTarget { maximum_target, .. } => {
+                self.channel.target = target_from_u256(&maximum_target);
+                self.pending.push_back(Sv2Event::Target(self.channel.target));
+            }
+            Message::SubmitSharesSuccess { last_sequence_number, new_submits_accepted_count, .. } => {
+                self.pending.push_back(Sv2Event::SharesAccepted {
+                    last_sequence_number,
+                    count: new_submits_accepted_count,
+                });
+            }
+            Message::SubmitSharesError { sequence_number, error_code, .. } => {
+                self.pending.push_back(Sv2Event::ShareRejected { sequence_number, reason: error_code });
+            }
+            Message::SetExtranoncePrefix { extranonce_prefix, .. } => {
+                self.channel.extranonce_prefix = extranonce_prefix.clone();
+                self.pending.push_back(Sv2Event::ExtranoncePrefix(extranonce_prefix));
+            }
+            Message::CloseChannel { reason_code, .. } => {
+                self.pending.push_back(Sv2Event::ChannelClosed { reason: reason_code });
+            }
+            Message::Reconnect { new_host, new_port } => {
+                self.pending.push_back(Sv2Event::Reconnect { host: new_host, port: new_port });
+            }
+            Message::UpdateChannelError { error_code, .. } => {
+                return Err(Sv2Error::Rejected(format!("UpdateChannel: {error_code}")));
+            }
+            // Jobs are not filtered by channel id, so the group's reach this
+            // channel already.
+            Message::SetGroupChannel { .. } => {}
+            other => return Err(unexpected(&other)),
+        }
+        Ok(())
+    }
+
+    fn new_job(&mut self, job_id: u32, min_ntime: Option<u32>, version: u32, rolling: bool, kind: JobKind) {
+        match (min_ntime, &self.prev) {
+            (Some(_), Some(_)) => {
+                let job = self.job(job_id, min_ntime, version, rolling, kind);
+                self.pending.push_back(Sv2Event::Job { job, clean: false });
+            }
+            _ => {
+                self.future_jobs.insert(job_id, (min_ntime, version, rolling, kind));
+            }
+        }
+    }
+
+    fn job(&self, job_id: u32, min_ntime: Option<u32>, version: u32, rolling: bool, kind: JobKind) -> Sv2Job {
+        let prev = self.prev.as_ref().expect("jobs start once the previous block is known");
+        Sv2Job {
+            job_id,
+            prev_hash: prev.prev_hash,
+            bits: prev.bits,
+            min_ntime: min_ntime.unwrap_or(prev.min_ntime).max(prev.min_ntime),
+            version,
+            version_rolling_allowed: rolling,
+            kind,
+        }
+    }
+}
+
+fn unexpected(message: &Message) -> Sv2Error {
+    Sv2Error::Protocol(format!("unexpected message {:#04x}", message.msg_type()))
+}
+
+/// Work units for one job at the channel's target. A used-up header rolls
+/// the BIP320 version bits first (when the job allows it), then the
+/// extranonce on an extended channel or the time on a standard one.
+pub struct Sv2Work {
+    job: Sv2Job,
+    extranonce_prefix: Vec<u8>,
+    extranonce_size: usize,
+    extranonce: u64,
+    target: [u8; 32],
+    version_rolling: Option<VersionRolling>,
+    version_index: u64,
+    header: HeaderWork,
+    next_nonce: u64,
+}
+
+impl Sv2Work {
+    pub fn new(job: Sv2Job, channel: &Channel) -> Self {
+        let extranonce_size = match channel.kind {
+            ChannelKind::Standard => 0,
+            ChannelKind::Extended { .. } => channel.extranonce_size,
+        };
+        let extranonce = extranonce2_bytes(0, extranonce_size);
+        let header = job.header(&channel.extranonce_prefix, &extranonce, job.version, job.min_ntime);
+        Self {
+            version_rolling: job.version_rolling_allowed.then(VersionRolling::bip320),
+            job,
+            extranonce_prefix: channel.extranonce_prefix.clone(),
+            extranonce_size,
+            extranonce: 0,
+            target: channel.target,
+            version_index: 0,
+            header,
+            next_nonce: 0,
+        }
+    }
+
+    pub fn job(&self) -> &Sv2Job {
+        &self.job
+    }
+
+    pub fn header(&self) -> &HeaderWork {
+        &self.header
+    }
+
+    pub fn target(&self) -> &[u8; 32] {
+        &self.target
+    }
+
+    /// A `SetTarget` applies to the shares of the current job too.
+    pub fn set_target(&mut self, target: [u8; 32]) {
+        self.target = target;
+    }
+
+    /// Next `count` nonces at the share target.
+    pub fn next_unit(&mut self, count: u32) -> WorkUnit {
+        if self.next_nonce >= NONCE_SPACE {
+            let mut time = self.header.time();
+            match self.version_rolling {
+                Some(rolling) if self.version_index + 1 < rolling.rolls() => self.version_index += 1,
+                _ => {
+                    self.version_index = 0;
+                    if self.extranonce_size > 0 {
+                        self.extranonce = self.extranonce.wrapping_add(1);
+                    } else {
+                        time = time.wrapping_add(1);
+                    }
+                }
+            }
+            let version = match self.version_rolling {
+                Some(rolling) => rolling.version(self.job.version, self.version_index),
+                None => self.job.version,
+            };
+            self.header = self.job.header(&self.extranonce_prefix, &self.extranonce(), version, time);
+            self.next_nonce = 0;
+        }
+        let count = (count as u64).min(NONCE_SPACE - self.next_nonce) as u32;
+        let mut unit = self.header.work_unit(self.next_nonce as u32, count);
+        unit.target = self.target;
+        self.next_nonce += count as u64;
+        unit
+    }
+
+    /// The share for `nonce` under the header the last unit came from.
+    pub fn share(&self, nonce: u32) -> Sv2Share {
+        Sv2Share {
+            job_id: self.job.job_id,
+            nonce,
+            ntime: self.header.time(),
+            version: self.header.version(),
+            extranonce: self.extranonce(),
+        }
+    }
+
+    fn extranonce(&self) -> Vec<u8> {
+        extranonce2_bytes(self.extranonce, self.extranonce_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stratum::Job;
+
+    #[test]
+    fn extended_jobs_build_the_same_header_as_v1() {
+        let job = Sv2Job {
+            job_id: 9,
+            prev_hash: [0x11; 32],
+            bits: 0x1a44_b9f2,
+            min_ntime: 1_700_000_000,
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            kind: JobKind::Extended {
+                coinbase_prefix: vec![1, 2, 3],
+                coinbase_suffix: vec![4, 5],
+                merkle_path: MerkleBranch { steps: vec![sha256d::Hash::hash(b"tx")] },
+            },
+        };
+        let v1 = Job {
+            job_id: "9".into(),
+            prev_hash: job.prev_hash,
+            coinb1: vec![1, 2, 3],
+            coinb2: vec![4, 5],
+            merkle_branch: MerkleBranch { steps: vec![sha256d::Hash::hash(b"tx")] },
+            version: job.version,
+            bits: job.bits,
+            time: job.min_ntime,
+            clean_jobs: true,
+        };
+        let channel = Channel {
+            id: 1,
+            kind: ChannelKind::Extended { min_extranonce_size: 4 },
+            target: [0xff; 32],
+            extranonce_prefix: vec![0xaa, 0xbb],
+            extranonce_size: 4,
+        };
+        let mut work = Sv2Work::new(job.clone(), &channel);
+        assert_eq!(work.header(), &v1.header(&[0xaa, 0xbb], &[0; 4], job.version, job.min_ntime));
+
+        // Past the nonce space the version rolls inside BIP320.
+        work.next_unit(u32::MAX);
+        work.next_unit(1);
+        work.next_unit(1);
+        let share = work.share(7);
+        assert_eq!(share.version, VersionRolling::bip320().version(job.version, 1));
+        assert_eq!((share.extranonce, share.ntime), (vec![0; 4], job.min_ntime));
+
+        // Without rolling a standard job moves the time instead.
+        let standard = Sv2Job {
+            version_rolling_allowed: false,
+            kind: JobKind::Standard { merkle_root: [0x22; 32] },
+            ..job
+        };
+        let channel = Channel { kind: ChannelKind::Standard, extranonce_size: 0, ..channel };
+        let mut work = Sv2Work::new(standard.clone(), &channel);
+        assert_eq!(&work.header().bytes()[36..68], &[0x22; 32]);
+        work.next_unit(u32::MAX);
+        work.next_unit(1);
+        work.next_unit(1);
+        let share = work.share(7);
+        assert_eq!((share.version, share.ntime), (standard.version, standard.min_ntime + 1));
+        assert!(share.extranonce.is_empty());
+    }
+}
@@ -0,0 +1,434 @@
+// src/stratum_v2/ellswift.rs
+//! ElligatorSwift (BIP324): 64-byte encodings of secp256k1 public keys
+//! that look like uniform random bytes, and the x-only ECDH over them
+//! that the Noise handshake uses. The secp256k1 in this tree predates its
+//! ellswift module, so the field arithmetic is here.
+//!
+//! An encoding is two field elements `u || t`, big-endian. Every 64-byte
+//! string decodes to some x-coordinate on the curve.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+
+/// Bytes of an encoded public key.
+pub const ENCODING_SIZE: usize = 64;
+
+/// The field prime, 2^256 - 2^32 - 977, as little-endian limbs.
+const P: [u64; 4] = [0xffff_fffe_ffff_fc2f, u64::MAX, u64::MAX, u64::MAX];
+
+/// 2^256 mod p.
+const R: u64 = 0x1_0000_03d1;
+
+/// p - 2, the inversion exponent.
+const P_MINUS_2: [u64; 4] = [0xffff_fffe_ffff_fc2d, u64::MAX, u64::MAX, u64::MAX];
+
+/// (p + 1) / 4, the square root exponent.
+const SQRT_EXPONENT: [u64; 4] = [0xffff_ffff_bfff_ff0c, u64::MAX, u64::MAX, 0x3fff_ffff_ffff_ffff];
+
+/// The square root of -3 that `(-3).sqrt()` returns.
+const MINUS_3_SQRT: Fe = Fe([0x7d8d_27ae_1cd5_f852, 0xc61f_6d15_da14_ecd4, 0x2337_70c2_a797_962c, 0x0a2d_2ba9_3507_f1df]);
+
+/// An element of the secp256k1 base field, fully reduced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Fe([u64; 4]);
+
+fn add_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut out = [0u64; 4];
+    let mut carry = false;
+    for i in 0..4 {
+        let (sum, c1) = a[i].overflowing_add(b[i]);
+        let (sum, c2) = sum.overflowing_add(carry as u64);
+        out[i] = sum;
+        carry = c1 || c2;
+    }
+    (out, carry)
+}
+
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut out = [0u64; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (diff, b1) = a[i].overflowing_sub(b[i]);
+        let (diff, b2) = diff.overflowing_sub(borrow as u64);
+        out[i] = diff;
+        borrow = b1 || b2;
+    }
+    (out, borrow)
+}
+
+impl Fe {
+    const ZERO: Fe = Fe([0; 4]);
+
+    const fn small(n: u64) -> Fe {
+        Fe([n, 0, 0, 0])
+    }
+
+    /// `limbs + carry * 2^256`, which must be below 2p, reduced mod p.
+    fn reduce(limbs: [u64; 4], carry: bool) -> Fe {
+        let limbs = if carry { add_limbs(&limbs, &[R, 0, 0, 0]).0 } else { limbs };
+        match sub_limbs(&limbs, &P) {
+            (diff, false) => Fe(diff),
+            _ => Fe(limbs),
+        }
+    }
+
+    /// Big-endian bytes, reduced mod p.
+    fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.rchunks(8)) {
+            *limb = u64::from_be_bytes(chunk.try_into().expect("8 bytes"));
+        }
+        Fe::reduce(limbs, false)
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.rchunks_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    fn pow(self, exponent: &[u64; 4]) -> Fe {
+        let mut result = Fe::small(1);
+        for limb in exponent.iter().rev() {
+            for bit in (0..64).rev() {
+                result = result * result;
+                if limb >> bit & 1 == 1 {
+                    result = result * self;
+                }
+            }
+        }
+        result
+    }
+
+    /// Multiplicative inverse; zero for zero.
+    fn inv(self) -> Fe {
+        self.pow(&P_MINUS_2)
+    }
+
+    fn sqrt(self) -> Option<Fe> {
+        let root = self.pow(&SQRT_EXPONENT);
+        (root * root == self).then_some(root)
+    }
+}
+
+impl Add for Fe {
+    type Output = Fe;
+    fn add(self, other: Fe) -> Fe {
+        let (sum, carry) = add_limbs(&self.0, &other.0);
+        Fe::reduce(sum, carry)
+    }
+}
+
+impl Neg for Fe {
+    type Output = Fe;
+    fn neg(self) -> Fe {
+        if self == Fe::ZERO { self } else { Fe(sub_limbs(&P, &self.0).0) }
+    }
+}
+
+impl Sub for Fe {
+    type Output = Fe;
+    fn sub(self, other: Fe) -> Fe {
+        self + -other
+    }
+}
+
+impl Mul for Fe {
+    type Output = Fe;
+    fn mul(self, other: Fe) -> Fe {
+        let mut wide = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let v = wide[i + j] as u128 + self.0[i] as u128 * other.0[j] as u128 + carry;
+                wide[i + j] = v as u64;
+                carry = v >> 64;
+            }
+            wide[i + 4] = carry as u64;
+        }
+        // high * 2^256 = high * R (mod p), folded twice.
+        let mut low = [0u64; 4];
+        let mut carry = 0u128;
+        for i in 0..4 {
+            let v = wide[i] as u128 + wide[i + 4] as u128 * R as u128 + carry;
+            low[i] = v as u64;
+            carry = v >> 64;
+        }
+        let top = carry * R as u128;
+        let (low, overflow) = add_limbs(&low, &[top as u64, (top >> 64) as u64, 0, 0]);
+        Fe::reduce(low, overflow)
+    }
+}
+
+impl Div for Fe {
+    type Output = Fe;
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn div(self, other: Fe) -> Fe {
+        self * other.inv()
+    }
+}
+
+fn is_valid_x(x: Fe) -> bool {
+    (x * x * x + Fe::small(7)).sqrt().is_some()
+}
+
+/// BIP324 `xswiftec`: the x-coordinate `(u, t)` stands for.
+fn xswiftec(u: Fe, t: Fe) -> Fe {
+    let u = if u == Fe::ZERO { Fe::small(1) } else { u };
+    let mut t = if t == Fe::ZERO { Fe::small(1) } else { t };
+    let (seven, two) = (Fe::small(7), Fe::small(2));
+    if u * u * u + t * t + seven == Fe::ZERO {
+        t = t + t;
+    }
+    let x = (u * u * u + seven - t * t) / (two * t);
+    let y = (x + t) / (MINUS_3_SQRT * u);
+    [u + Fe::small(4) * y * y, (-x / y - u) / two, (x / y - u) / two]
+        .into_iter()
+        .find(|&candidate| is_valid_x(candidate))
+        .expect("one of the three is on the curve")
+}
+
+/// BIP324 `xswiftec_inv`: a `t` with `xswiftec(u, t) == x`, for one of
+/// the eight `case`s, if that case has one.
+fn xswiftec_inv(x: Fe, u: Fe, case: u8) -> Option<Fe> {
+    let seven = Fe::small(7);
+    let (s, v) = if case & 2 == 0 {
+        if is_valid_x(-x - u) {
+            return None;
+        }
+        (-(u * u * u + seven) / (u * u + u * x + x * x), x)
+    } else {
+        let s = x - u;
+        if s == Fe::ZERO {
+            return None;
+        }
+        let r = (-s * (Fe::small(4) * (u * u * u + seven) + Fe::small(3) * s * u * u)).sqrt()?;
+        if case & 1 == 1 && r == Fe::ZERO {
+            return None;
+        }
+        (s, (r / s - u) / Fe::small(2))
+    };
+    let w = s.sqrt()?;
+    let half = Fe::small(2).inv();
+    let minus = u * (Fe::small(1) - MINUS_3_SQRT) * half + v;
+    let plus = u * (Fe::small(1) + MINUS_3_SQRT) * half + v;
+    Some(match case & 5 {
+        0 => -w * minus,
+        1 => w * plus,
+        4 => w * minus,
+        _ => -w * plus,
+    })
+}
+
+/// The x-coordinate an encoding stands for.
+pub fn decode(encoding: &[u8; ENCODING_SIZE]) -> [u8; 32] {
+    let u = Fe::from_bytes(encoding[..32].try_into().expect("32 bytes"));
+    let t = Fe::from_bytes(encoding[32..].try_into().expect("32 bytes"));
+    xswiftec(u, t).to_bytes()
+}
+
+/// A fresh random encoding of `x`, which must be a point's x-coordinate.
+pub fn encode(x: &[u8; 32]) -> [u8; ENCODING_SIZE] {
+    let x = Fe::from_bytes(x);
+    debug_assert!(is_valid_x(x), "not on the curve");
+    loop {
+        let u = Fe::from_bytes(&rand::random());
+        if u == Fe::ZERO {
+            continue;
+        }
+        if let Some(t) = xswiftec_inv(x, u, rand::random::<u8>() & 7) {
+            let mut encoding = [0u8; ENCODING_SIZE];
+            encoding[..32].copy_from_slice(&u.to_bytes());
+            encoding[32..].copy_from_slice(&t.to_bytes());
+            return encoding;
+        }
+    }
+}
+
+/// BIP324's x-only ECDH: the x-coordinate of `secret` times the point
+/// `theirs` decodes to, tagged-hashed after both encodings, the
+/// initiator's first.
+pub fn ecdh(secret: &SecretKey, theirs: &[u8; ENCODING_SIZE], ours: &[u8; ENCODING_SIZE], initiating: bool) -> [u8; 32] {
+    let point = PublicKey::from_slice(&[&[0x02][..], &decode(theirs)].concat()).expect("decoded x is on the curve");
+    let shared = SharedSecret::new_with_hash(&point, secret, |x, _| x.into());
+    let (initiator, responder) = if initiating { (ours, theirs) } else { (theirs, ours) };
+    let tag = sha256::Hash::hash(b"bip324_ellswift_xonly_ecdh");
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(initiator);
+    engine.input(responder);
+    engine.input(&shared[..]);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::Secp256k1;
+
+    fn fe(text: &str) -> Fe {
+        Fe::from_bytes(&hex::decode(text).unwrap().try_into().unwrap())
+    }
+
+    fn encoding(text: &str) -> [u8; ENCODING_SIZE] {
+        hex::decode(text).unwrap().try_into().unwrap()
+    }
+
+    // Rows from BIP324's `ellswift_decode_test_vectors.csv`,
+    // `xswiftec_inv_test_vectors.csv` and `ellswift_xdh_test_vectors.csv`.
+
+    #[test]
+    fn decodes_and_inverts_like_the_bip324_vectors() {
+        assert_eq!(Fe::small(3).neg().sqrt(), Some(MINUS_3_SQRT));
+        assert_eq!(Fe::small(7) * Fe::small(7).inv(), Fe::small(1));
+
+        let decodes = [
+            (
+                "00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+                "edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c",
+            ),
+            (
+                "000000000000000000000000000000000000000000000000000000000000000001d3475bf7655b0fb2d852921035b2ef607f49069b97454e6795251062741771",
+                "b5da00b73cd6560520e7c364086e7cd23a34bf60d0e707be9fc34d4cd5fdfa2c",
+            ),
+            (
+                "0a2d2ba93507f1df233770c2a797962cc61f6d15da14ecd47d8d27ae1cd5f853fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f",
+                "532167c11200b08c0e84a354e74dcc40f8b25f4fe686e30869526366278a0688",
+            ),
+            (
+                "4056a34a210eec7892e8820675c860099f857b26aad85470ee6d3cf1304a9dcf375e70374271f20b13c9986ed7d3c17799698cfc435dbed3a9f34b38c823c2b4",
+                "868aac2003b29dbcad1a3e803855e078a89d16543ac64392d122417298cec76e",
+            ),
+            (
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f4218f20ae6c646b363db68605822fb14264ca8d2587fdd6fbc750d587e76a7ee",
+                "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa9fffffd6b",
+            ),
+            (
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffefbb982fffffffffffffffffffffffffffffffffffffffffffffffffffffffff6d6db1f",
+                "1c92ccdfcf4ac550c28db57cff0c8515cb26936c786584a70114008d6c33a34b",
+            ),
+        ];
+        for (encoded, x) in decodes {
+            assert_eq!(hex::encode(decode(&encoding(encoded))), x, "{encoded}");
+        }
+
+        let inverses = [
+            (
+                "587c1a0cee91939e7f784d23b963004a3bf44f5d4e32a0081995ba20b0fca59e",
+                "2ea988530715e8d10363907ff25124524d471ba2454d5ce3be3f04194dfd3a3c",
+                [
+                    Some("cfd5a094aa0b9b8891b76c6ab9438f66aa1c095a65f9f70135e8171292245e74"),
+                    Some("a89057d7c6563f0d6efa19ae84412b8a7b47e791a191ecdfdf2af84fd97bc339"),
+                    Some("475d0ae9ef46920df07b34117be5a0817de1023e3cc32689e9be145b406b0aef"),
+                    Some("a0759178ad80232454f827ef05ea3e72ad8d75418e6d4cc1cd4f5306c5e7c453"),
+                    Some("302a5f6b55f464776e48939546bc709955e3f6a59a0608feca17e8ec6ddb9dbb"),
+                    Some("576fa82839a9c0f29105e6517bbed47584b8186e5e6e132020d507af268438f6"),
+                    Some("b8a2f51610b96df20f84cbee841a5f7e821efdc1c33cd9761641eba3bf94f140"),
+                    Some("5f8a6e87527fdcdbab07d810fa15c18d52728abe7192b33e32b0acf83a1837dc"),
+                ],
+            ),
+            (
+                "7c37bb9c5061dc07413f11acd5a34006e64c5c457fdb9a438f217255a961f50d",
+                "5c1a76b44568eb59d6789a7442d9ed7cdc6226b7752b4ff8eaf8e1a95736e507",
+                [
+                    None,
+                    None,
+                    Some("b94d30cd7dbff60b64620c17ca0fafaa40b3d1f52d077a60a2e0cafd145086c2"),
+                    None,
+                    None,
+                    None,
+                    Some("46b2cf32824009f49b9df3e835f05055bf4c2e0ad2f8859f5d1f3501ebaf756d"),
+                    None,
+                ],
+            ),
+            (
+                "e5bbb9ef360d0a501618f0067d36dceb75f5be9a620232aa9fd5139d0863fde5",
+                "e5bbb9ef360d0a501618f0067d36dceb75f5be9a620232aa9fd5139d0863fde5",
+                [None; 8],
+            ),
+        ];
+        for (u, x, expected) in inverses {
+            let (u, x) = (fe(u), fe(x));
+            for (case, t) in expected.into_iter().enumerate() {
+                let found = xswiftec_inv(x, u, case as u8);
+                assert_eq!(found, t.map(fe), "case {case}");
+                if let Some(t) = found {
+                    assert_eq!(xswiftec(u, t), x);
+                }
+            }
+        }
+
+        let secret = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let x = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret).serialize();
+        let x: [u8; 32] = x[1..].try_into().unwrap();
+        let (first, second) = (encode(&x), encode(&x));
+        assert_ne!(first, second);
+        assert_eq!((decode(&first), decode(&second)), (x, x));
+    }
+
+    #[test]
+    fn ecdh_matches_the_bip324_vectors() {
+        // (our secret, our encoding, their encoding, initiating, shared secret)
+        let vectors = [
+            (
+                "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7",
+                "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b",
+                "a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5",
+                true,
+                "c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592",
+            ),
+            (
+                "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f",
+                "a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140",
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000",
+                false,
+                "a0138f564f74d0ad70bc337dacc9d0bf1d2349364caf1188a1e6e8ddb3b7b184",
+            ),
+            (
+                "0286c41cd30913db0fdff7a64ebda5c8e3e7cef10f2aebc00a7650443cf4c60d",
+                "d1ee8a93a01130cbf299249a258f94feb5f469e7d0f2f28f69ee5e9aa8f9b54a60f2c3ff2d023634ec7f4127a96cc11662e402894cf1f694fb9a7eaa5f1d9244",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffff22d5e441524d571a52b3def126189d3f416890a99d4da6ede2b0cde1760ce2c3f98457ae",
+                true,
+                "250b93570d411149105ab8cb0bc5079914906306368c23e9d77c2a33265b994c",
+            ),
+            (
+                "6c77432d1fda31e9f942f8af44607e10f3ad38a65f8a4bddae823e5eff90dc38",
+                "d2685070c1e6376e633e825296634fd461fa9e5bdf2109bcebd735e5a91f3e587c5cb782abb797fbf6bb5074fd1542a474f2a45b673763ec2db7fb99b737bbb9",
+                "56bd0c06f10352c3a1a9f4b4c92f6fa2b26df124b57878353c1fc691c51abea77c8817daeeb9fa546b77c8daf79d89b22b0e1b87574ece42371f00237aa9d83a",
+                false,
+                "1918b741ef5f9d1d7670b050c152b4a4ead2c31be9aecb0681c0cd4324150853",
+            ),
+            (
+                "a6ec25127ca1aa4cf16b20084ba1e6516baae4d32422288e9b36d8bddd2de35a",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffff053d7ecca53e33e185a8b9be4e7699a97c6ff4c795522e5918ab7cd6b6884f67e683f3dc",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffa7730be30000000000000000000000000000000000000000000000000000000000000000",
+                true,
+                "dd210aa6629f20bb328e5d89daa6eb2ac3d1c658a725536ff154f31b536c23b2",
+            ),
+            (
+                "0af952659ed76f80f585966b95ab6e6fd68654672827878684c8b547b1b94f5a",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffc81017fd92fd31637c26c906b42092e11cc0d3afae8d9019d2578af22735ce7bc469c72d",
+                "9652d78baefc028cd37a6a92625b8b8f85fde1e4c944ad3f20e198bef8c02f19fffffffffffffffffffffffffffffffffffffffffffffffffffffffff2e91870",
+                false,
+                "3568f2aea2e14ef4ee4a3c2a8b8d31bc5e3187ba86db10739b4ff8ec92ff6655",
+            ),
+            (
+                "f90e080c64b05824c5a24b2501d5aeaf08af3872ee860aa80bdcd430f7b63494",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffff115173765dc202cf029ad3f15479735d57697af12b0131dd21430d5772e4ef11474d58b9",
+                "12a50f3fafea7c1eeada4cf8d33777704b77361453afc83bda91eef349ae044d20126c6200547ea5a6911776c05dee2a7f1a9ba7dfbabbbd273c3ef29ef46e46",
+                true,
+                "e25461fb0e4c162e18123ecde88342d54d449631e9b75a266fd9260c2bb2f41d",
+            ),
+        ];
+        for (secret, ours, theirs, initiating, shared) in vectors {
+            let secret = SecretKey::from_slice(&hex::decode(secret).unwrap()).unwrap();
+            assert_eq!(hex::encode(ecdh(&secret, &encoding(theirs), &encoding(ours), initiating)), shared);
+        }
+    }
+}
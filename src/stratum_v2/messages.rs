@@ -0,0 +1,711 @@
+// src/stratum_v2/messages.rs
+//! Frames and the messages this crate speaks.
+//!
+//! A frame is a 6-byte header (`extension_type: U16`, `msg_type: U8`,
+//! `msg_length: U24`) and the payload. Bit 15 of `extension_type` marks
+//! messages addressed to a channel. All integers are little-endian; `U256`
+//! fields are kept as the 32 bytes on the wire.
+
+use super::Sv2Error;
+
+pub const HEADER_SIZE: usize = 6;
+
+/// Bit of `extension_type` set on channel messages.
+pub const CHANNEL_MSG: u16 = 0x8000;
+
+/// `SetupConnection.protocol` for the Mining Protocol.
+pub const MINING_PROTOCOL: u8 = 0;
+
+/// `SetupConnection.flags` (Mining Protocol).
+pub const REQUIRES_STANDARD_JOBS: u32 = 1 << 0;
+pub const REQUIRES_VERSION_ROLLING: u32 = 1 << 2;
+
+pub mod msg_type {
+    pub const SETUP_CONNECTION: u8 = 0x00;
+    pub const SETUP_CONNECTION_SUCCESS: u8 = 0x01;
+    pub const SETUP_CONNECTION_ERROR: u8 = 0x02;
+    pub const OPEN_STANDARD_MINING_CHANNEL: u8 = 0x10;
+    pub const OPEN_STANDARD_MINING_CHANNEL_SUCCESS: u8 = 0x11;
+    pub const OPEN_MINING_CHANNEL_ERROR: u8 = 0x12;
+    pub const OPEN_EXTENDED_MINING_CHANNEL: u8 = 0x13;
+    pub const OPEN_EXTENDED_MINING_CHANNEL_SUCCESS: u8 = 0x14;
+    pub const NEW_MINING_JOB: u8 = 0x15;
+    pub const UPDATE_CHANNEL_ERROR: u8 = 0x17;
+    pub const CLOSE_CHANNEL: u8 = 0x18;
+    pub const SET_EXTRANONCE_PREFIX: u8 = 0x19;
+    pub const SUBMIT_SHARES_STANDARD: u8 = 0x1a;
+    pub const SUBMIT_SHARES_EXTENDED: u8 = 0x1b;
+    pub const SUBMIT_SHARES_SUCCESS: u8 = 0x1c;
+    pub const SUBMIT_SHARES_ERROR: u8 = 0x1d;
+    pub const NEW_EXTENDED_MINING_JOB: u8 = 0x1f;
+    pub const SET_NEW_PREV_HASH: u8 = 0x20;
+    pub const SET_TARGET: u8 = 0x21;
+    pub const RECONNECT: u8 = 0x25;
+    pub const SET_GROUP_CHANNEL: u8 = 0x26;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub extension_type: u16,
+    pub msg_type: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn header(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..2].copy_from_slice(&self.extension_type.to_le_bytes());
+        header[2] = self.msg_type;
+        header[3..].copy_from_slice(&(self.payload.len() as u32).to_le_bytes()[..3]);
+        header
+    }
+
+    /// `(extension_type, msg_type, payload length)` from a header.
+    pub fn parse_header(header: &[u8; HEADER_SIZE]) -> (u16, u8, usize) {
+        let length = u32::from_le_bytes([header[3], header[4], header[5], 0]);
+        (u16::from_le_bytes([header[0], header[1]]), header[2], length as usize)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    SetupConnection {
+        protocol: u8,
+        min_version: u16,
+        max_version: u16,
+        flags: u32,
+        endpoint_host: String,
+        endpoint_port: u16,
+        vendor: String,
+        hardware_version: String,
+        firmware: String,
+        device_id: String,
+    },
+    SetupConnectionSuccess {
+        used_version: u16,
+        flags: u32,
+    },
+    SetupConnectionError {
+        flags: u32,
+        error_code: String,
+    },
+    OpenStandardMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: [u8; 32],
+    },
+    OpenStandardMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: [u8; 32],
+        extranonce_prefix: Vec<u8>,
+        group_channel_id: u32,
+    },
+    OpenExtendedMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: [u8; 32],
+        min_extranonce_size: u16,
+    },
+    OpenExtendedMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: [u8; 32],
+        extranonce_size: u16,
+        extranonce_prefix: Vec<u8>,
+    },
+    OpenMiningChannelError {
+        request_id: u32,
+        error_code: String,
+    },
+    /// A header-only job. `min_ntime: None` is a future job, started by a
+    /// later `SetNewPrevHash`.
+    NewMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        min_ntime: Option<u32>,
+        version: u32,
+        merkle_root: [u8; 32],
+    },
+    NewExtendedMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        min_ntime: Option<u32>,
+        version: u32,
+        version_rolling_allowed: bool,
+        merkle_path: Vec<[u8; 32]>,
+        coinbase_tx_prefix: Vec<u8>,
+        coinbase_tx_suffix: Vec<u8>,
+    },
+    SetNewPrevHash {
+        channel_id: u32,
+        job_id: u32,
+        prev_hash: [u8; 32],
+        min_ntime: u32,
+        nbits: u32,
+    },
+    SetTarget {
+        channel_id: u32,
+        maximum_target: [u8; 32],
+    },
+    /// The pool refused an `UpdateChannel`.
+    UpdateChannelError {
+        channel_id: u32,
+        error_code: String,
+    },
+    CloseChannel {
+        channel_id: u32,
+        reason_code: String,
+    },
+    /// The pool's extranonce bytes for the channel from now on.
+    SetExtranoncePrefix {
+        channel_id: u32,
+        extranonce_prefix: Vec<u8>,
+    },
+    /// Connect again; an empty host or port 0 means the current one.
+    Reconnect {
+        new_host: String,
+        new_port: u16,
+    },
+    /// Standard channels whose jobs now come on `group_channel_id`.
+    SetGroupChannel {
+        group_channel_id: u32,
+        channel_ids: Vec<u32>,
+    },
+    SubmitSharesStandard {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+    },
+    SubmitSharesExtended {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+        extranonce: Vec<u8>,
+    },
+    SubmitSharesSuccess {
+        channel_id: u32,
+        last_sequence_number: u32,
+        new_submits_accepted_count: u32,
+        new_shares_sum: u64,
+    },
+    SubmitSharesError {
+        channel_id: u32,
+        sequence_number: u32,
+        error_code: String,
+    },
+}
+
+impl Message {
+    pub fn msg_type(&self) -> u8 {
+        use msg_type::*;
+        match self {
+            Message::SetupConnection { .. } => SETUP_CONNECTION,
+            Message::SetupConnectionSuccess { .. } => SETUP_CONNECTION_SUCCESS,
+            Message::SetupConnectionError { .. } => SETUP_CONNECTION_ERROR,
+            Message::OpenStandardMiningChannel { .. } => OPEN_STANDARD_MINING_CHANNEL,
+            Message::OpenStandardMiningChannelSuccess { .. } => OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+            Message::OpenExtendedMiningChannel { .. } => OPEN_EXTENDED_MINING_CHANNEL,
+            Message::OpenExtendedMiningChannelSuccess { .. } => OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+            Message::OpenMiningChannelError { .. } => OPEN_MINING_CHANNEL_ERROR,
+            Message::NewMiningJob { .. } => NEW_MINING_JOB,
+            Message::NewExtendedMiningJob { .. } => NEW_EXTENDED_MINING_JOB,
+            Message::SetNewPrevHash { .. } => SET_NEW_PREV_HASH,
+            Message::SetTarget { .. } => SET_TARGET,
+            Message::UpdateChannelError { .. } => UPDATE_CHANNEL_ERROR,
+            Message::CloseChannel { .. } => CLOSE_CHANNEL,
+            Message::SetExtranoncePrefix { .. } => SET_EXTRANONCE_PREFIX,
+            Message::Reconnect { .. } => RECONNECT,
+            Message::SetGroupChannel { .. } => SET_GROUP_CHANNEL,
+            Message::SubmitSharesStandard { .. } => SUBMIT_SHARES_STANDARD,
+            Message::SubmitSharesExtended { .. } => SUBMIT_SHARES_EXTENDED,
+            Message::SubmitSharesSuccess { .. } => SUBMIT_SHARES_SUCCESS,
+            Message::SubmitSharesError { .. } => SUBMIT_SHARES_ERROR,
+        }
+    }
+
+    /// Messages that carry a `channel_id` set the channel bit.
+    fn is_channel_message(&self) -> bool {
+        !matches!(self, Message::Reconnect { .. } | Message::SetGroupChannel { .. })
+            && self.msg_type() >= msg_type::NEW_MINING_JOB
+    }
+
+    pub fn to_frame(&self) -> Frame {
+        let mut w = Writer::default();
+        match self {
+            Message::SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                flags,
+                endpoint_host,
+                endpoint_port,
+                vendor,
+                hardware_version,
+                firmware,
+                device_id,
+            } => {
+                w.u8(*protocol).u16(*min_version).u16(*max_version).u32(*flags).str(endpoint_host);
+                w.u16(*endpoint_port).str(vendor).str(hardware_version).str(firmware).str(device_id);
+            }
+            Message::SetupConnectionSuccess { used_version, flags } => {
+                w.u16(*used_version).u32(*flags);
+            }
+            Message::SetupConnectionError { flags, error_code } => {
+                w.u32(*flags).str(error_code);
+            }
+            Message::OpenStandardMiningChannel { request_id, user_identity, nominal_hash_rate, max_target } => {
+                w.u32(*request_id).str(user_identity).f32(*nominal_hash_rate).u256(max_target);
+            }
+            Message::OpenStandardMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_prefix,
+                group_channel_id,
+            } => {
+                w.u32(*request_id).u32(*channel_id).u256(target).b0_32(extranonce_prefix).u32(*group_channel_id);
+            }
+            Message::OpenExtendedMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+                min_extranonce_size,
+            } => {
+                w.u32(*request_id).str(user_identity).f32(*nominal_hash_rate).u256(max_target);
+                w.u16(*min_extranonce_size);
+            }
+            Message::OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_size,
+                extranonce_prefix,
+            } => {
+                w.u32(*request_id).u32(*channel_id).u256(target).u16(*extranonce_size).b0_32(extranonce_prefix);
+            }
+            Message::OpenMiningChannelError { request_id, error_code } => {
+                w.u32(*request_id).str(error_code);
+            }
+            Message::NewMiningJob { channel_id, job_id, min_ntime, version, merkle_root } => {
+                w.u32(*channel_id).u32(*job_id).option_u32(*min_ntime).u32(*version).u256(merkle_root);
+            }
+            Message::NewExtendedMiningJob {
+                channel_id,
+                job_id,
+                min_ntime,
+                version,
+                version_rolling_allowed,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            } => {
+                w.u32(*channel_id).u32(*job_id).option_u32(*min_ntime).u32(*version);
+                w.bool(*version_rolling_allowed).seq_u256(merkle_path);
+                w.b0_64k(coinbase_tx_prefix).b0_64k(coinbase_tx_suffix);
+            }
+            Message::SetNewPrevHash { channel_id, job_id, prev_hash, min_ntime, nbits } => {
+                w.u32(*channel_id).u32(*job_id).u256(prev_hash).u32(*min_ntime).u32(*nbits);
+            }
+            Message::SetTarget { channel_id, maximum_target } => {
+                w.u32(*channel_id).u256(maximum_target);
+            }
+            Message::UpdateChannelError { channel_id, error_code } => {
+                w.u32(*channel_id).str(error_code);
+            }
+            Message::CloseChannel { channel_id, reason_code } => {
+                w.u32(*channel_id).str(reason_code);
+            }
+            Message::SetExtranoncePrefix { channel_id, extranonce_prefix } => {
+                w.u32(*channel_id).b0_32(extranonce_prefix);
+            }
+            Message::Reconnect { new_host, new_port } => {
+                w.str(new_host).u16(*new_port);
+            }
+            Message::SetGroupChannel { group_channel_id, channel_ids } => {
+                w.u32(*group_channel_id).seq_u32(channel_ids);
+            }
+            Message::SubmitSharesStandard { channel_id, sequence_number, job_id, nonce, ntime, version } => {
+                w.u32(*channel_id).u32(*sequence_number).u32(*job_id).u32(*nonce).u32(*ntime).u32(*version);
+            }
+            Message::SubmitSharesExtended {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+                extranonce,
+            } => {
+                w.u32(*channel_id).u32(*sequence_number).u32(*job_id).u32(*nonce).u32(*ntime).u32(*version);
+                w.b0_32(extranonce);
+            }
+            Message::SubmitSharesSuccess {
+                channel_id,
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } => {
+                w.u32(*channel_id).u32(*last_sequence_number).u32(*new_submits_accepted_count).u64(*new_shares_sum);
+            }
+            Message::SubmitSharesError { channel_id, sequence_number, error_code } => {
+                w.u32(*channel_id).u32(*sequence_number).str(error_code);
+            }
+        }
+        let extension_type = if self.is_channel_message() { CHANNEL_MSG } else { 0 };
+        Frame { extension_type, msg_type: self.msg_type(), payload: w.0 }
+    }
+
+    pub fn from_frame(frame: &Frame) -> Result<Self, Sv2Error> {
+        use msg_type::*;
+        if frame.extension_type & !CHANNEL_MSG != 0 {
+            return Err(Sv2Error::Unsupported { extension_type: frame.extension_type, msg_type: frame.msg_type });
+        }
+        let mut r = Reader { bytes: &frame.payload, pos: 0 };
+        let message = match frame.msg_type {
+            SETUP_CONNECTION => Message::SetupConnection {
+                protocol: r.u8()?,
+                min_version: r.u16()?,
+                max_version: r.u16()?,
+                flags: r.u32()?,
+                endpoint_host: r.str()?,
+                endpoint_port: r.u16()?,
+                vendor: r.str()?,
+                hardware_version: r.str()?,
+                firmware: r.str()?,
+                device_id: r.str()?,
+            },
+            SETUP_CONNECTION_SUCCESS => Message::SetupConnectionSuccess { used_version: r.u16()?, flags: r.u32()? },
+            SETUP_CONNECTION_ERROR => Message::SetupConnectionError { flags: r.u32()?, error_code: r.str()? },
+            OPEN_STANDARD_MINING_CHANNEL => Message::OpenStandardMiningChannel {
+                request_id: r.u32()?,
+                user_identity: r.str()?,
+                nominal_hash_rate: r.f32()?,
+                max_target: r.u256()?,
+            },
+            OPEN_STANDARD_MINING_CHANNEL_SUCCESS => Message::OpenStandardMiningChannelSuccess {
+                request_id: r.u32()?,
+                channel_id: r.u32()?,
+                target: r.u256()?,
+                extranonce_prefix: r.b0_32()?,
+                group_channel_id: r.u32()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL => Message::OpenExtendedMiningChannel {
+                request_id: r.u32()?,
+                user_identity: r.str()?,
+                nominal_hash_rate: r.f32()?,
+                max_target: r.u256()?,
+                min_extranonce_size: r.u16()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL_SUCCESS => Message::OpenExtendedMiningChannelSuccess {
+                request_id: r.u32()?,
+                channel_id: r.u32()?,
+                target: r.u256()?,
+                extranonce_size: r.u16()?,
+                extranonce_prefix: r.b0_32()?,
+            },
+            OPEN_MINING_CHANNEL_ERROR => Message::OpenMiningChannelError { request_id: r.u32()?, error_code: r.str()? },
+            NEW_MINING_JOB => Message::NewMiningJob {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                min_ntime: r.option_u32()?,
+                version: r.u32()?,
+                merkle_root: r.u256()?,
+            },
+            NEW_EXTENDED_MINING_JOB => Message::NewExtendedMiningJob {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                min_ntime: r.option_u32()?,
+                version: r.u32()?,
+                version_rolling_allowed: r.bool()?,
+                merkle_path: r.seq_u256()?,
+                coinbase_tx_prefix: r.b0_64k()?,
+                coinbase_tx_suffix: r.b0_64k()?,
+            },
+            SET_NEW_PREV_HASH => Message::SetNewPrevHash {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                prev_hash: r.u256()?,
+                min_ntime: r.u32()?,
+                nbits: r.u32()?,
+            },
+            SET_TARGET => Message::SetTarget { channel_id: r.u32()?, maximum_target: r.u256()? },
+            UPDATE_CHANNEL_ERROR => Message::UpdateChannelError { channel_id: r.u32()?, error_code: r.str()? },
+            CLOSE_CHANNEL => Message::CloseChannel { channel_id: r.u32()?, reason_code: r.str()? },
+            SET_EXTRANONCE_PREFIX => Message::SetExtranoncePrefix { channel_id: r.u32()?, extranonce_prefix: r.b0_32()? },
+            RECONNECT => Message::Reconnect { new_host: r.str()?, new_port: r.u16()? },
+            SET_GROUP_CHANNEL => Message::SetGroupChannel { group_channel_id: r.u32()?, channel_ids: r.seq_u32()? },
+            SUBMIT_SHARES_STANDARD => Message::SubmitSharesStandard {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                job_id: r.u32()?,
+                nonce: r.u32()?,
+                ntime: r.u32()?,
+                version: r.u32()?,
+            },
+            SUBMIT_SHARES_EXTENDED => Message::SubmitSharesExtended {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                job_id: r.u32()?,
+                nonce: r.u32()?,
+                ntime: r.u32()?,
+                version: r.u32()?,
+                extranonce: r.b0_32()?,
+            },
+            SUBMIT_SHARES_SUCCESS => Message::SubmitSharesSuccess {
+                channel_id: r.u32()?,
+                last_sequence_number: r.u32()?,
+                new_submits_accepted_count: r.u32()?,
+                new_shares_sum: r.u64()?,
+            },
+            SUBMIT_SHARES_ERROR => Message::SubmitSharesError {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                error_code: r.str()?,
+            },
+            msg_type => return Err(Sv2Error::Unsupported { extension_type: frame.extension_type, msg_type }),
+        };
+        if r.pos != frame.payload.len() {
+            return Err(Sv2Error::Protocol(format!("{} trailing bytes in message {:#04x}", frame.payload.len() - r.pos, frame.msg_type)));
+        }
+        Ok(message)
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn f32(&mut self, v: f32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn bool(&mut self, v: bool) -> &mut Self {
+        self.u8(v as u8)
+    }
+
+    fn u256(&mut self, v: &[u8; 32]) -> &mut Self {
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    /// `STR0_255`; longer strings are cut at 255 bytes.
+    fn str(&mut self, v: &str) -> &mut Self {
+        let bytes = &v.as_bytes()[..v.len().min(255)];
+        self.u8(bytes.len() as u8);
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn b0_32(&mut self, v: &[u8]) -> &mut Self {
+        debug_assert!(v.len() <= 32);
+        self.u8(v.len() as u8);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn b0_64k(&mut self, v: &[u8]) -> &mut Self {
+        debug_assert!(v.len() <= u16::MAX as usize);
+        self.u16(v.len() as u16);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn seq_u256(&mut self, v: &[[u8; 32]]) -> &mut Self {
+        debug_assert!(v.len() <= 255);
+        self.u8(v.len() as u8);
+        for item in v {
+            self.u256(item);
+        }
+        self
+    }
+
+    /// `SEQ0_64K[U32]`.
+    fn seq_u32(&mut self, v: &[u32]) -> &mut Self {
+        debug_assert!(v.len() <= u16::MAX as usize);
+        self.u16(v.len() as u16);
+        for item in v {
+            self.u32(*item);
+        }
+        self
+    }
+
+    /// `OPTION[U32]`, a `SEQ0_1`.
+    fn option_u32(&mut self, v: Option<u32>) -> &mut Self {
+        match v {
+            Some(v) => self.u8(1).u32(v),
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], Sv2Error> {
+        let end = self.pos + n;
+        let slice = self.bytes.get(self.pos..end).ok_or_else(|| Sv2Error::Protocol("message is truncated".into()))?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Sv2Error> {
+        Ok(self.take(N)?.try_into().expect("N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, Sv2Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Sv2Error> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Sv2Error> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, Sv2Error> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32, Sv2Error> {
+        self.array().map(f32::from_le_bytes)
+    }
+
+    fn bool(&mut self) -> Result<bool, Sv2Error> {
+        Ok(self.u8()? & 1 == 1)
+    }
+
+    fn u256(&mut self) -> Result<[u8; 32], Sv2Error> {
+        self.array()
+    }
+
+    fn str(&mut self) -> Result<String, Sv2Error> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| Sv2Error::Protocol("string is not UTF-8".into()))
+    }
+
+    fn b0_32(&mut self) -> Result<Vec<u8>, Sv2Error> {
+        let len = self.u8()? as usize;
+        if len > 32 {
+            return Err(Sv2Error::Protocol(format!("B0_32 of {len} bytes")));
+        }
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn b0_64k(&mut self) -> Result<Vec<u8>, Sv2Error> {
+        let len = self.u16()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn seq_u256(&mut self) -> Result<Vec<[u8; 32]>, Sv2Error> {
+        let count = self.u8()?;
+        (0..count).map(|_| self.u256()).collect()
+    }
+
+    fn seq_u32(&mut self) -> Result<Vec<u32>, Sv2Error> {
+        let count = self.u16()?;
+        (0..count).map(|_| self.u32()).collect()
+    }
+
+    fn option_u32(&mut self) -> Result<Option<u32>, Sv2Error> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => self.u32().map(Some),
+            n => Err(Sv2Error::Protocol(format!("OPTION with {n} elements"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip_through_frames() {
+        let job = Message::NewExtendedMiningJob {
+            channel_id: 7,
+            job_id: 3,
+            min_ntime: None,
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            merkle_path: vec![[0xaa; 32], [0xbb; 32]],
+            coinbase_tx_prefix: vec![1, 2, 3],
+            coinbase_tx_suffix: vec![4; 300],
+        };
+        let frame = job.to_frame();
+        assert_eq!(frame.header(), [0x00, 0x80, 0x1f, (frame.payload.len() & 0xff) as u8, 0x01, 0x00]);
+        assert_eq!(Frame::parse_header(&frame.header()), (CHANNEL_MSG, 0x1f, frame.payload.len()));
+        assert_eq!(Message::from_frame(&frame).unwrap(), job);
+
+        let setup = Message::SetupConnection {
+            protocol: MINING_PROTOCOL,
+            min_version: 2,
+            max_version: 2,
+            flags: REQUIRES_VERSION_ROLLING,
+            endpoint_host: "pool.example".into(),
+            endpoint_port: 3336,
+            vendor: "v".into(),
+            hardware_version: String::new(),
+            firmware: String::new(),
+            device_id: "d".into(),
+        };
+        let frame = setup.to_frame();
+        assert_eq!(frame.extension_type, 0);
+        assert_eq!(&frame.payload[..9], [0, 2, 0, 2, 0, 4, 0, 0, 0]);
+        assert_eq!(Message::from_frame(&frame).unwrap(), setup);
+
+        let mut short = frame.clone();
+        short.payload.pop();
+        assert!(matches!(Message::from_frame(&short), Err(Sv2Error::Protocol(_))));
+        let unknown = Frame { extension_type: 1, msg_type: 0x00, payload: Vec::new() };
+        assert!(matches!(Message::from_frame(&unknown), Err(Sv2Error::Unsupported { .. })));
+
+        // Reconnect and SetGroupChannel go to the connection, not a channel.
+        for (message, extension_type) in [
+            (Message::SetExtranoncePrefix { channel_id: 7, extranonce_prefix: vec![1, 2] }, CHANNEL_MSG),
+            (Message::CloseChannel { channel_id: 7, reason_code: "shutdown".into() }, CHANNEL_MSG),
+            (Message::UpdateChannelError { channel_id: 7, error_code: "max-target-out-of-range".into() }, CHANNEL_MSG),
+            (Message::Reconnect { new_host: "pool.example".into(), new_port: 3336 }, 0),
+            (Message::SetGroupChannel { group_channel_id: 2, channel_ids: vec![7, 8] }, 0),
+        ] {
+            let frame = message.to_frame();
+            assert_eq!(frame.extension_type, extension_type, "{message:?}");
+            assert_eq!(Message::from_frame(&frame).unwrap(), message);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+// src/stratum_v2/noise.rs
+//! Noise NX over secp256k1 with ElligatorSwift keys, and the encrypted
+//! frame stream after it.
+//!
+//! Ephemeral and static keys go over the wire as 64-byte ElligatorSwift
+//! encodings (`ellswift`), and DH is BIP324's x-only ECDH over them. The
+//! initiator sends its ephemeral key; the responder answers with its
+//! ephemeral key, its encrypted static key and a certificate: the static
+//! key (x-only) signed (BIP340) by the pool's authority key. The initiator
+//! checks it against the authority key it was given, unless it was
+//! explicitly told to trust any pool (`Authority::Insecure`).
+//!
+//! After the handshake each frame is sent as its encrypted header (6 + 16
+//! bytes) followed by its payload, encrypted in chunks of at most
+//! `MAX_CHUNK` plaintext bytes.
+
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{schnorrsig, Message as SecpMessage, PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::base58;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::ellswift::{self, ENCODING_SIZE};
+use super::messages::{Frame, HEADER_SIZE};
+use super::Sv2Error;
+
+const PROTOCOL_NAME: &[u8] = b"Noise_NX_Secp256k1+EllSwift_ChaChaPoly_SHA256";
+
+/// Bytes of the Poly1305 tag appended to each ciphertext.
+pub const TAG_SIZE: usize = 16;
+
+/// Largest plaintext chunk, so each encrypted chunk fits in 65535 bytes.
+pub const MAX_CHUNK: usize = u16::MAX as usize - TAG_SIZE;
+
+const CERTIFICATE_SIZE: usize = 2 + 4 + 4 + 64;
+
+/// Size of the responder's handshake message: ephemeral key, encrypted
+/// static key, encrypted certificate.
+const RESPONSE_SIZE: usize = ENCODING_SIZE + (ENCODING_SIZE + TAG_SIZE) + (CERTIFICATE_SIZE + TAG_SIZE);
+
+/// Version prefix of a base58check authority key.
+const AUTHORITY_KEY_VERSION: u16 = 1;
+
+/// How the initiator checks the responder's certificate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Authority {
+    /// The certificate must be signed by this x-only key and valid now.
+    Key([u8; 32]),
+    /// Accept any responder key. Anyone on the path can then pose as the
+    /// pool, so this has to be asked for explicitly.
+    Insecure,
+}
+
+impl Authority {
+    /// An authority key as pools publish it: base58check of a 2-byte
+    /// version (1, little-endian) and the x-only key. 64 hex digits are
+    /// taken as the bare x-only key.
+    pub fn parse(text: &str) -> Result<Self, Sv2Error> {
+        let text = text.trim();
+        let invalid = || Sv2Error::Handshake(format!("invalid authority key {text}"));
+        let key: [u8; 32] = match hex::decode(text) {
+            Ok(bytes) if bytes.len() == 32 => bytes.try_into().expect("32 bytes"),
+            _ => {
+                let bytes = base58::from_check(text).map_err(|_| invalid())?;
+                match bytes.split_at_checked(2) {
+                    Some((version, key)) if version == AUTHORITY_KEY_VERSION.to_le_bytes() => key.try_into().map_err(|_| invalid())?,
+                    _ => return Err(invalid()),
+                }
+            }
+        };
+        schnorrsig::PublicKey::from_slice(&key).map_err(|_| invalid())?;
+        Ok(Authority::Key(key))
+    }
+}
+
+/// A secp256k1 key pair.
+#[derive(Clone)]
+pub struct StaticKey {
+    secret: SecretKey,
+    public: [u8; 32],
+}
+
+impl StaticKey {
+    pub fn generate() -> Self {
+        loop {
+            if let Ok(key) = Self::from_secret(&rand::random::<[u8; 32]>()) {
+                return key;
+            }
+        }
+    }
+
+    pub fn from_secret(secret: &[u8; 32]) -> Result<Self, Sv2Error> {
+        let secret = SecretKey::from_slice(secret).map_err(|e| Sv2Error::Handshake(e.to_string()))?;
+        let public = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret).serialize();
+        Ok(Self { secret, public: public[1..].try_into().expect("32 bytes") })
+    }
+
+    /// x-only public key.
+    pub fn public(&self) -> [u8; 32] {
+        self.public
+    }
+
+    /// A fresh ElligatorSwift encoding of the public key.
+    pub fn ellswift(&self) -> [u8; ENCODING_SIZE] {
+        ellswift::encode(&self.public)
+    }
+
+    fn secret_bytes(&self) -> [u8; 32] {
+        self.secret[..].try_into().expect("32 bytes")
+    }
+
+    /// ECDH with the key `theirs` encodes, where `ours` is the encoding of
+    /// this key that the peer saw.
+    fn dh(&self, theirs: &[u8; ENCODING_SIZE], ours: &[u8; ENCODING_SIZE], initiating: bool) -> [u8; 32] {
+        ellswift::ecdh(&self.secret, theirs, ours, initiating)
+    }
+}
+
+/// The responder's signed static key (`SignatureNoiseMessage`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    pub version: u16,
+    pub valid_from: u32,
+    pub not_valid_after: u32,
+    pub signature: [u8; 64],
+}
+
+impl Certificate {
+    /// Sign `server_key` with the authority's secret key.
+    pub fn sign(authority: &StaticKey, server_key: &[u8; 32], valid_from: u32, not_valid_after: u32) -> Self {
+        let secp = Secp256k1::signing_only();
+        let keypair = schnorrsig::KeyPair::from_seckey_slice(&secp, &authority.secret_bytes()).expect("valid secret key");
+        let mut certificate = Self { version: 0, valid_from, not_valid_after, signature: [0; 64] };
+        let digest = certificate.digest(server_key);
+        let signature = secp.schnorrsig_sign_no_aux_rand(&digest, &keypair);
+        certificate.signature.copy_from_slice(&signature[..]);
+        certificate
+    }
+
+    /// Check the signature by `authority` over `server_key` and that `now`
+    /// is inside the validity window.
+    pub fn verify(&self, authority: &[u8; 32], server_key: &[u8; 32], now: u32) -> Result<(), Sv2Error> {
+        let authority = schnorrsig::PublicKey::from_slice(authority)
+            .map_err(|_| Sv2Error::Handshake("authority key is not on the curve".into()))?;
+        let signature = schnorrsig::Signature::from_slice(&self.signature)
+            .map_err(|_| Sv2Error::Handshake("malformed certificate signature".into()))?;
+        // secp256k1 0.20 only offers Schnorr verification on signing contexts.
+        Secp256k1::new()
+            .schnorrsig_verify(&signature, &self.digest(server_key), &authority)
+            .map_err(|_| Sv2Error::Handshake("certificate is not signed by the authority".into()))?;
+        if now < self.valid_from || now > self.not_valid_after {
+            return Err(Sv2Error::Handshake("certificate is outside its validity window".into()));
+        }
+        Ok(())
+    }
+
+    fn digest(&self, server_key: &[u8; 32]) -> SecpMessage {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.version.to_le_bytes());
+        engine.input(&self.valid_from.to_le_bytes());
+        engine.input(&self.not_valid_after.to_le_bytes());
+        engine.input(server_key);
+        SecpMessage::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("32 bytes")
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CERTIFICATE_SIZE);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&self.valid_from.to_le_bytes());
+        bytes.extend_from_slice(&self.not_valid_after.to_le_bytes());
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Sv2Error> {
+        if bytes.len() != CERTIFICATE_SIZE {
+            return Err(Sv2Error::Handshake(format!("certificate of {} bytes", bytes.len())));
+        }
+        Ok(Self {
+            version: u16::from_le_bytes([bytes[0], bytes[1]]),
+            valid_from: u32::from_le_bytes(bytes[2..6].try_into().expect("4 bytes")),
+            not_valid_after: u32::from_le_bytes(bytes[6..10].try_into().expect("4 bytes")),
+            signature: bytes[10..].try_into().expect("64 bytes"),
+        })
+    }
+}
+
+/// One direction's cipher and nonce counter.
+struct CipherState {
+    cipher: Option<ChaCha20Poly1305>,
+    nonce: u64,
+}
+
+impl CipherState {
+    fn empty() -> Self {
+        Self { cipher: None, nonce: 0 }
+    }
+
+    fn new(key: [u8; 32]) -> Self {
+        Self { cipher: Some(ChaCha20Poly1305::new(&key.into())), nonce: 0 }
+    }
+
+    /// 32 zero bits, then the counter little-endian.
+    fn next_nonce(counter: &mut u64) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_le_bytes());
+        *counter += 1;
+        nonce
+    }
+
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        match &self.cipher {
+            Some(cipher) => cipher
+                .encrypt(&Self::next_nonce(&mut self.nonce).into(), Payload { msg: plaintext, aad: ad })
+                .expect("ChaCha20-Poly1305 seals any frame-sized plaintext"),
+            None => plaintext.to_vec(),
+        }
+    }
+
+    fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Sv2Error> {
+        match &self.cipher {
+            Some(cipher) => cipher
+                .decrypt(&Self::next_nonce(&mut self.nonce).into(), Payload { msg: ciphertext, aad: ad })
+                .map_err(|_| Sv2Error::Decrypt),
+            None => Ok(ciphertext.to_vec()),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    for part in data {
+        engine.input(part);
+    }
+    hmac::Hmac::from_engine(engine).into_inner()
+}
+
+/// Noise HKDF with two outputs.
+fn hkdf(chaining_key: &[u8; 32], input: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let temp = hmac(chaining_key, &[input]);
+    let first = hmac(&temp, &[&[1]]);
+    let second = hmac(&temp, &[&first, &[2]]);
+    (first, second)
+}
+
+struct SymmetricState {
+    chaining_key: [u8; 32],
+    hash: [u8; 32],
+    cipher: CipherState,
+}
+
+impl SymmetricState {
+    fn new() -> Self {
+        let hash = sha256::Hash::hash(PROTOCOL_NAME).into_inner();
+        let mut state = Self { chaining_key: hash, hash, cipher: CipherState::empty() };
+        // Empty prologue.
+        state.mix_hash(&[]);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = sha256::Hash::hash(&[&self.hash[..], data].concat()).into_inner();
+    }
+
+    fn mix_key(&mut self, input: &[u8; 32]) {
+        let (chaining_key, key) = hkdf(&self.chaining_key, input);
+        self.chaining_key = chaining_key;
+        self.cipher = CipherState::new(key);
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = self.cipher.encrypt(&self.hash, plaintext);
+        self.mix_hash(&ciphertext);
+        ciphertext
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, Sv2Error> {
+        let plaintext = self.cipher.decrypt(&self.hash, ciphertext).map_err(|_| Sv2Error::Handshake("bad MAC".into()))?;
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    /// Initiator-to-responder and responder-to-initiator ciphers.
+    fn split(&self) -> (CipherState, CipherState) {
+        let (first, second) = hkdf(&self.chaining_key, &[]);
+        (CipherState::new(first), CipherState::new(second))
+    }
+}
+
+/// An encrypted SV2 connection.
+pub struct NoiseStream {
+    stream: TcpStream,
+    send: CipherState,
+    recv: CipherState,
+    /// Received bytes not yet decrypted.
+    buffer: Vec<u8>,
+    /// Header of the frame whose payload is still arriving.
+    header: Option<[u8; HEADER_SIZE]>,
+    remote_static: [u8; 32],
+}
+
+impl NoiseStream {
+    /// Handshake as the initiator. With `Authority::Key`, the responder's
+    /// certificate must be signed by it and valid at `now`.
+    pub async fn initiate(mut stream: TcpStream, authority: &Authority, now: u32) -> Result<Self, Sv2Error> {
+        let mut state = SymmetricState::new();
+        let ephemeral = StaticKey::generate();
+        let ephemeral_public = ephemeral.ellswift();
+        state.mix_hash(&ephemeral_public);
+        state.encrypt_and_hash(&[]);
+        stream.write_all(&ephemeral_public).await?;
+
+        let mut response = [0u8; RESPONSE_SIZE];
+        stream.read_exact(&mut response).await?;
+        let (remote_ephemeral, rest) = response.split_at(ENCODING_SIZE);
+        let remote_ephemeral: [u8; ENCODING_SIZE] = remote_ephemeral.try_into().expect("64 bytes");
+        state.mix_hash(&remote_ephemeral);
+        state.mix_key(&ephemeral.dh(&remote_ephemeral, &ephemeral_public, true));
+        let (encrypted_static, encrypted_certificate) = rest.split_at(ENCODING_SIZE + TAG_SIZE);
+        let remote_static: [u8; ENCODING_SIZE] =
+            state.decrypt_and_hash(encrypted_static)?.try_into().expect("64 bytes");
+        state.mix_key(&ephemeral.dh(&remote_static, &ephemeral_public, true));
+        let certificate = Certificate::decode(&state.decrypt_and_hash(encrypted_certificate)?)?;
+        let remote_static = ellswift::decode(&remote_static);
+        if let Authority::Key(authority) = authority {
+            certificate.verify(authority, &remote_static, now)?;
+        }
+
+        let (send, recv) = state.split();
+        Ok(Self { stream, send, recv, buffer: Vec::new(), header: None, remote_static })
+    }
+
+    /// Handshake as the responder, proving `key` with `certificate`.
+    pub async fn respond(mut stream: TcpStream, key: &StaticKey, certificate: &Certificate) -> Result<Self, Sv2Error> {
+        let mut state = SymmetricState::new();
+        let mut remote_ephemeral = [0u8; ENCODING_SIZE];
+        stream.read_exact(&mut remote_ephemeral).await?;
+        state.mix_hash(&remote_ephemeral);
+        state.decrypt_and_hash(&[])?;
+
+        let ephemeral = StaticKey::generate();
+        let ephemeral_public = ephemeral.ellswift();
+        state.mix_hash(&ephemeral_public);
+        state.mix_key(&ephemeral.dh(&remote_ephemeral, &ephemeral_public, false));
+        let static_public = key.ellswift();
+        let encrypted_static = state.encrypt_and_hash(&static_public);
+        state.mix_key(&key.dh(&remote_ephemeral, &static_public, false));
+        let encrypted_certificate = state.encrypt_and_hash(&certificate.encode());
+        stream.write_all(&[&ephemeral_public[..], &encrypted_static, &encrypted_certificate].concat()).await?;
+
+        let (recv, send) = state.split();
+        let remote_static = ellswift::decode(&remote_ephemeral);
+        Ok(Self { stream, send, recv, buffer: Vec::new(), header: None, remote_static })
+    }
+
+    /// The responder's x-only static key, as seen by the initiator. On the
+    /// responder side this is the initiator's ephemeral key.
+    pub fn remote_static(&self) -> [u8; 32] {
+        self.remote_static
+    }
+
+    pub async fn write_frame(&mut self, frame: &Frame) -> Result<(), Sv2Error> {
+        let mut bytes = self.send.encrypt(&[], &frame.header());
+        for chunk in frame.payload.chunks(MAX_CHUNK) {
+            bytes.extend(self.send.encrypt(&[], chunk));
+        }
+        self.stream.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    /// Next frame. Cancel-safe: partial reads stay buffered.
+    pub async fn read_frame(&mut self) -> Result<Frame, Sv2Error> {
+        const ENCRYPTED_HEADER: usize = HEADER_SIZE + TAG_SIZE;
+        loop {
+            if self.header.is_none() && self.buffer.len() >= ENCRYPTED_HEADER {
+                let sealed: Vec<u8> = self.buffer.drain(..ENCRYPTED_HEADER).collect();
+                let header = self.recv.decrypt(&[], &sealed)?;
+                self.header = Some(header.try_into().expect("header size"));
+            }
+            if let Some(header) = self.header {
+                let (extension_type, msg_type, length) = Frame::parse_header(&header);
+                let encrypted = length + length.div_ceil(MAX_CHUNK) * TAG_SIZE;
+                if self.buffer.len() >= encrypted {
+                    let sealed: Vec<u8> = self.buffer.drain(..encrypted).collect();
+                    let mut payload = Vec::with_capacity(length);
+                    for chunk in sealed.chunks(MAX_CHUNK + TAG_SIZE) {
+                        payload.extend(self.recv.decrypt(&[], chunk)?);
+                    }
+                    self.header = None;
+                    return Ok(Frame { extension_type, msg_type, payload });
+                }
+            }
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                return Err(Sv2Error::Disconnected);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_states_agree_and_certificates_check_out() {
+        let authority = StaticKey::generate();
+        let server = StaticKey::generate();
+        let certificate = Certificate::sign(&authority, &server.public(), 100, 200);
+        certificate.verify(&authority.public(), &server.public(), 150).unwrap();
+        assert!(certificate.verify(&authority.public(), &server.public(), 201).is_err());
+        assert!(certificate.verify(&server.public(), &server.public(), 150).is_err());
+        assert!(certificate.verify(&authority.public(), &authority.public(), 150).is_err());
+        assert_eq!(Certificate::decode(&certificate.encode()).unwrap(), certificate);
+
+        // DH is symmetric over the encodings each side sent.
+        let a = StaticKey::generate();
+        let b = StaticKey::generate();
+        let (a_public, b_public) = (a.ellswift(), b.ellswift());
+        assert_eq!(ellswift::decode(&a_public), a.public());
+        assert_eq!(a.dh(&b_public, &a_public, true), b.dh(&a_public, &b_public, false));
+
+        // A message sealed by one side's handshake state opens on the other's.
+        let (mut initiator, mut responder) = (SymmetricState::new(), SymmetricState::new());
+        for state in [&mut initiator, &mut responder] {
+            state.mix_hash(&a_public);
+            state.mix_key(&a.dh(&b_public, &a_public, true));
+        }
+        let sealed = initiator.encrypt_and_hash(b"certificate");
+        assert_eq!(responder.decrypt_and_hash(&sealed).unwrap(), b"certificate");
+        let (mut to_responder, _) = initiator.split();
+        let (mut from_initiator, _) = responder.split();
+        let sealed = to_responder.encrypt(&[], b"frame");
+        assert_eq!(from_initiator.decrypt(&[], &sealed).unwrap(), b"frame");
+        assert!(from_initiator.decrypt(&[], &sealed).is_err(), "nonces advance");
+    }
+    #[test]
+    fn authority_keys_parse_from_base58check_or_hex() {
+        let key = StaticKey::generate().public();
+        let published = base58::check_encode_slice(&[&[1, 0][..], &key].concat());
+        assert_eq!(Authority::parse(&published).unwrap(), Authority::Key(key));
+        assert_eq!(Authority::parse(&hex::encode(key)).unwrap(), Authority::Key(key));
+        assert!(Authority::parse(&base58::check_encode_slice(&[&[2, 0][..], &key].concat())).is_err());
+        assert!(Authority::parse(&published[1..]).is_err());
+        assert!(Authority::parse("").is_err());
+    }
+}
@@ -3,12 +3,7 @@
 use crate::adaptive::{MinerMetrics, UiMessage};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use std::time::{Duration, Instant};
-use metal::Buffer;
-
-// ✅ Fix constants import
-use crate::constants::{LANES, NONCES_PER_THREAD};
-use crate::sha_helpers::{aligned_u32_buffer, aligned_f32_buffer};
+use std::time::Duration;
 
 use crossterm::{
     event::{self, Event as CEvent, KeyCode},
@@ -19,9 +14,8 @@ use tui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
-    symbols,
     text::{Span, Spans},
-    widgets::{Block, Borders, Gauge, Paragraph, Wrap, Chart, Axis, Dataset},
+    widgets::{Block, Borders, Gauge, Paragraph, Wrap},
     Terminal,
 };
 
@@ -72,9 +66,11 @@ pub async fn run_ui(
                 )
                 .split(size);
 
+            let [_, m1, m5, m15] = m.hashrate_windows;
+            let b = m.submits;
             let header = Paragraph::new(format!(
-                "🧠 Rust Metal Miner — {:.3} MH/s | Total Hashes: {:>12}",
-                m.hashrate_mhs, m.total_hashes
+                "🧠 Rust Metal Miner — {:.3} MH/s (1m {:.3} 5m {:.3} 15m {:.3}) | Effective: {:.3} MH/s | Shares: {} | Blocks: {} ok {} dup {} inc {} rej | Template: {} | Total Hashes: {:>12}",
+                m.hashrate_mhs, m1, m5, m15, m.effective_mhs, m.shares, b.accepted, b.duplicate, b.inconclusive, b.rejected, m.template_health, m.total_hashes
             ))
             .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
             .block(Block::default().borders(Borders::ALL).title("Status"));
@@ -123,6 +119,10 @@ pub async fn run_ui(
                                 Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD),
                             ),
                             Span::raw(format!("POST={:.3}  FWHT={:.3}  CS={:.3}", p, fwh, c)),
+                            Span::raw(match m.lane_mhs.get(i) {
+                                Some(rate) => format!("  {:.3} MH/s", rate),
+                                None => String::new(),
+                            }),
                         ])
                     })
                     .collect()
@@ -0,0 +1,99 @@
+// src/version_rolling.rs
+//! BIP320 version rolling (overt ASICBoost).
+//!
+//! BIP320 reserves version bits 13..=28 (`0x1fffe000`) for miners. Flipping
+//! them changes the first header block, so every rolled version is a fresh
+//! midstate and another 2^32 nonces without touching the coinbase.
+//!
+//! Mask negotiation follows the BIP310 `mining.configure` rules, so Stratum
+//! code can use `VersionRolling::negotiate` as is.
+
+/// General-purpose version bits from BIP320.
+pub const BIP320_MASK: u32 = 0x1fff_e000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VersionRolling {
+    mask: u32,
+}
+
+impl VersionRolling {
+    /// Rolling restricted to `mask`; bits outside BIP320 are dropped.
+    pub fn new(mask: u32) -> Self {
+        Self { mask: mask & BIP320_MASK }
+    }
+
+    pub fn bip320() -> Self {
+        Self::new(BIP320_MASK)
+    }
+
+    /// BIP310: the peer asks for `requested_mask` and at least
+    /// `min_bit_count` bits; the result is the common mask, or `None` when
+    /// it is too small.
+    pub fn negotiate(&self, requested_mask: u32, min_bit_count: u32) -> Option<Self> {
+        let agreed = Self::new(self.mask & requested_mask);
+        (agreed.bit_count() >= min_bit_count).then_some(agreed)
+    }
+
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    pub fn bit_count(&self) -> u32 {
+        self.mask.count_ones()
+    }
+
+    /// Number of distinct versions, including `base` itself.
+    pub fn rolls(&self) -> u64 {
+        1 << self.bit_count()
+    }
+
+    /// The `n`th version derived from `base`: the low bits of `n` are spread
+    /// over the mask and XORed in, so `n == 0` gives `base` back.
+    pub fn version(&self, base: u32, n: u64) -> u32 {
+        let mut bits = 0u32;
+        let mut rest = self.mask;
+        let mut n = n;
+        while rest != 0 && n != 0 {
+            let low = rest & rest.wrapping_neg();
+            if n & 1 == 1 {
+                bits |= low;
+            }
+            rest &= !low;
+            n >>= 1;
+        }
+        base ^ bits
+    }
+
+    /// True when `version` only differs from `base` inside the mask.
+    pub fn allows(&self, base: u32, version: u32) -> bool {
+        (version ^ base) & !self.mask == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_stay_inside_the_mask_and_are_distinct() {
+        let vr = VersionRolling::new(0x0001_e000);
+        let base = 0x2000_0004;
+        let versions: Vec<u32> = (0..vr.rolls()).map(|n| vr.version(base, n)).collect();
+        assert_eq!(versions[0], base);
+        assert!(versions.iter().all(|&v| vr.allows(base, v)));
+        let mut unique = versions.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), 16);
+        assert!(!vr.allows(base, base | 0x4000_0000));
+    }
+
+    #[test]
+    fn negotiation_intersects_masks() {
+        let ours = VersionRolling::bip320();
+        assert_eq!(ours.negotiate(0xffff_ffff, 16).unwrap().mask(), BIP320_MASK);
+        assert_eq!(ours.negotiate(0x0000_6000, 2).unwrap().mask(), 0x0000_6000);
+        assert_eq!(ours.negotiate(0x0000_6000, 3), None);
+        assert_eq!(VersionRolling::new(0xffff_ffff), ours);
+    }
+}
@@ -0,0 +1,325 @@
+// src/work.rs
+//! Turns a block template into a stream of work units.
+//!
+//! One header covers 2^32 nonces. When they run out the generator bumps the
+//! extranonce in the coinbase scriptSig, which changes the coinbase txid and
+//! therefore the merkle root and midstate, and starts again from nonce 0.
+//! The template's own txids are folded into a `MerkleBranch` once, so a roll
+//! costs one coinbase txid plus log2(n) merges.
+//!
+//! Before the extranonce moves, a used-up header is re-issued under every
+//! rolled version when version rolling is enabled (a midstate recompute
+//! each), then under the next header time when `NTimePolicy` allows it (the
+//! time is in the tail, so the midstate is kept). The time also follows the
+//! wall clock on its own as it passes the header's time.
+
+use bitcoin::blockdata::transaction::Transaction;
+
+use crate::backend::WorkUnit;
+use crate::coinbase::{CoinbaseBuilder, CoinbaseError, CoinbaseLayout};
+use crate::header::HeaderWork;
+use crate::ntime::NTimePolicy;
+use crate::payout::Payout;
+use crate::rpc::BlockTemplate;
+use crate::sha_helpers::{prepare_block_header, MerkleBranch};
+use crate::version_rolling::VersionRolling;
+
+/// Nonces per header.
+const NONCE_SPACE: u64 = 1 << 32;
+
+pub struct TemplateWork {
+    template: BlockTemplate,
+    payout: Payout,
+    coinbase_builder: CoinbaseBuilder,
+    branch: MerkleBranch,
+    extranonce: u64,
+    coinbase: Transaction,
+    ntime: NTimePolicy,
+    /// Header at the template's own version and the current time.
+    base_header: HeaderWork,
+    header: HeaderWork,
+    version_rolling: Option<VersionRolling>,
+    version_index: u64,
+    /// Next nonce to hand out under the current extranonce.
+    next_nonce: u64,
+}
+
+impl TemplateWork {
+    /// Work for `template` paying `payout`, with `message` as the coinbase
+    /// tag and the header time set for `now` (Unix seconds). Fails when no
+    /// valid coinbase can be built for the template.
+    pub fn new(template: BlockTemplate, payout: &Payout, message: &[u8], now: u32) -> Result<Self, CoinbaseError> {
+        Self::with_extranonce(template, payout, CoinbaseLayout::new(message), 0, now)
+    }
+
+    fn with_extranonce(
+        template: BlockTemplate,
+        payout: &Payout,
+        layout: CoinbaseLayout,
+        extranonce: u64,
+        now: u32,
+    ) -> Result<Self, CoinbaseError> {
+        let coinbase_builder = CoinbaseBuilder::new(&template, payout, layout)?;
+        let branch = coinbase_builder.merkle_branch().clone();
+        let ntime = NTimePolicy::from_template(&template);
+        let (coinbase, header) = Self::build(&template, &coinbase_builder, &branch, extranonce);
+        let header = header.with_time(ntime.time_at(now));
+        Ok(Self {
+            template,
+            payout: payout.clone(),
+            coinbase_builder,
+            branch,
+            extranonce,
+            coinbase,
+            ntime,
+            base_header: header.clone(),
+            header,
+            version_rolling: None,
+            version_index: 0,
+            next_nonce: 0,
+        })
+    }
+
+    /// Roll the header version inside `rolling`'s mask before rolling the
+    /// extranonce.
+    pub fn with_version_rolling(mut self, rolling: Option<VersionRolling>) -> Self {
+        self.version_rolling = rolling;
+        self
+    }
+
+    /// Switch to `template` unless it is the one already being mined. The
+    /// extranonce keeps counting up so no header is ever scanned twice. On
+    /// error the current work is kept.
+    pub fn update(&mut self, template: BlockTemplate, now: u32) -> Result<(), CoinbaseError> {
+        if template != self.template {
+            let extranonce = self.extranonce.wrapping_add(1);
+            let layout = self.coinbase_builder.layout().clone();
+            *self = Self::with_extranonce(template, &self.payout, layout, extranonce, now)?
+                .with_version_rolling(self.version_rolling);
+        }
+        Ok(())
+    }
+
+    /// Next `count` nonces (fewer at the end of a header). Moves the time up
+    /// to `now` if the clock has passed it, and rolls version, time or
+    /// extranonce first if the current header is used up.
+    pub fn next_unit(&mut self, count: u32, now: u32) -> WorkUnit {
+        let time = self.ntime.time_at(now);
+        if time > self.header.time() {
+            self.set_time(time);
+        }
+        if self.next_nonce >= NONCE_SPACE {
+            match (self.version_rolling, self.ntime.roll(self.header.time(), now)) {
+                (Some(rolling), _) if self.version_index + 1 < rolling.rolls() => self.roll_version(rolling),
+                (_, Some(time)) => self.set_time(time),
+                _ => self.roll_extranonce(now),
+            }
+        }
+        let count = (count as u64).min(NONCE_SPACE - self.next_nonce) as u32;
+        let unit = self.header.work_unit(self.next_nonce as u32, count);
+        self.next_nonce += count as u64;
+        unit
+    }
+
+    pub fn roll_extranonce(&mut self, now: u32) {
+        self.extranonce = self.extranonce.wrapping_add(1);
+        (self.coinbase, self.base_header) =
+            Self::build(&self.template, &self.coinbase_builder, &self.branch, self.extranonce);
+        self.set_time(self.ntime.time_at(now));
+    }
+
+    /// Fresh header at `time`, starting again from the template's version.
+    fn set_time(&mut self, time: u32) {
+        self.base_header = self.base_header.with_time(time);
+        self.header = self.base_header.clone();
+        self.version_index = 0;
+        self.next_nonce = 0;
+    }
+
+    fn roll_version(&mut self, rolling: VersionRolling) {
+        self.version_index += 1;
+        let version = rolling.version(self.base_header.version(), self.version_index);
+        self.header = self.base_header.with_version(version);
+        self.next_nonce = 0;
+    }
+
+    pub fn template(&self) -> &BlockTemplate {
+        &self.template
+    }
+
+    pub fn extranonce(&self) -> u64 {
+        self.extranonce
+    }
+
+    /// Coinbase and header (with the rolled version and time) the last
+    /// `next_unit` came from.
+    pub fn coinbase(&self) -> &Transaction {
+        &self.coinbase
+    }
+
+    pub fn header(&self) -> &HeaderWork {
+        &self.header
+    }
+
+    fn build(
+        template: &BlockTemplate,
+        coinbase_builder: &CoinbaseBuilder,
+        branch: &MerkleBranch,
+        extranonce: u64,
+    ) -> (Transaction, HeaderWork) {
+        let coinbase = coinbase_builder.build_with_counter(&[], extranonce);
+        let root = branch.root(coinbase.txid().as_hash());
+        let header = HeaderWork::from_words(&prepare_block_header(template, &root));
+        (coinbase, header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coinbase::{assemble_block_hex, block_merkle_root};
+    use bitcoin::blockdata::block::Block;
+    use crate::sha_helpers::merkle_root;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    const NOW: u32 = 1_690_000_000;
+
+    fn template() -> BlockTemplate {
+        BlockTemplate::from_result(serde_json::json!({
+            "version": 0x2000_0000,
+            "previousblockhash": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054",
+            "height": 800_000,
+            "coinbasevalue": 625_000_000u64,
+            "curtime": 1_690_000_000,
+            "bits": "17053894",
+            "transactions": [],
+        }))
+        .unwrap()
+    }
+
+    fn payout() -> Payout {
+        Payout::parse("bc1qyux0tnvrusd9deq89h8er0ml4clhdwetp6ljp2").unwrap()
+    }
+
+    #[test]
+    fn branch_root_matches_full_tree() {
+        for n in 0..12u8 {
+            let txids: Vec<sha256d::Hash> = (0..n).map(|i| sha256d::Hash::hash(&[i])).collect();
+            let coinbase = sha256d::Hash::hash(b"coinbase");
+            let branch = MerkleBranch::from_txids(&txids);
+            let full: Vec<sha256d::Hash> = std::iter::once(coinbase).chain(txids).collect();
+            assert_eq!(branch.root(coinbase), merkle_root(full), "{n} txs");
+            assert!(branch.steps.len() <= (n as f32 + 1.0).log2().ceil() as usize);
+        }
+    }
+
+    #[test]
+    fn rolls_extranonce_when_nonce_space_runs_out() {
+        let mut work = TemplateWork::new(template(), &payout(), b"test", NOW).unwrap();
+        let first = work.next_unit(u32::MAX, NOW);
+        assert_eq!((first.nonce_start, first.nonce_count), (0, u32::MAX));
+        let last = work.next_unit(16, NOW);
+        assert_eq!((last.nonce_start, last.nonce_count), (u32::MAX, 1));
+        assert_eq!(work.extranonce(), 0);
+
+        let rolled = work.next_unit(16, NOW);
+        assert_eq!(work.extranonce(), 1);
+        assert_eq!(rolled.nonce_start, 0);
+        assert_ne!(rolled.midstate, first.midstate);
+        let root = block_merkle_root(work.template(), work.coinbase());
+        assert_eq!(work.header().to_header().merkle_root, root);
+    }
+
+    #[test]
+    fn update_keeps_work_for_the_same_template() {
+        let mut work = TemplateWork::new(template(), &payout(), b"test", NOW).unwrap();
+        work.next_unit(1000, NOW);
+        work.update(template(), NOW).unwrap();
+        assert_eq!(work.next_unit(1, NOW).nonce_start, 1000);
+
+        let mut next = template();
+        next.curtime = 1_690_000_001;
+        work.update(next, NOW).unwrap();
+        assert_eq!(work.extranonce(), 1);
+        assert_eq!(work.next_unit(1, NOW).nonce_start, 0);
+
+        // A template the payout does not fit leaves the work as it was.
+        let payout = Payout::parse("bc1qyux0tnvrusd9deq89h8er0ml4clhdwetp6ljp2=1000sat,script:6a").unwrap();
+        let mut work = TemplateWork::new(template(), &payout, b"test", NOW).unwrap();
+        assert_eq!(work.coinbase().output.iter().map(|o| o.value).collect::<Vec<_>>(), [1000, 624_999_000]);
+        let mut poor = template();
+        poor.coinbase_value = 999;
+        assert!(TemplateWork::new(poor.clone(), &payout, b"test", NOW).is_err());
+        assert!(work.update(poor, NOW).is_err());
+        assert_eq!(work.template(), &template());
+    }
+
+    #[test]
+    fn rolls_version_before_extranonce() {
+        let rolling = VersionRolling::new(0x0000_6000);
+        let mut work = TemplateWork::new(template(), &payout(), b"test", NOW).unwrap().with_version_rolling(Some(rolling));
+        let mut versions = Vec::new();
+        for _ in 0..4 {
+            let unit = work.next_unit(u32::MAX, NOW);
+            work.next_unit(1, NOW);
+            assert_eq!(work.extranonce(), 0);
+            assert_eq!(unit.midstate, work.header().midstate());
+            versions.push(work.header().version());
+        }
+        assert_eq!(versions, [0x2000_0000, 0x2000_2000, 0x2000_4000, 0x2000_6000]);
+
+        work.next_unit(1, NOW);
+        assert_eq!((work.extranonce(), work.header().version()), (1, 0x2000_0000));
+    }
+
+    #[test]
+    fn rolls_time_inside_the_template_window() {
+        let mut t = template();
+        t.mutable = vec!["time".into(), "transactions".into(), "prevblock".into()];
+        t.mintime = Some(NOW - 600);
+
+        // The header time follows the clock, without touching the midstate.
+        let mut work = TemplateWork::new(t, &payout(), b"test", NOW + 5).unwrap();
+        let unit = work.next_unit(u32::MAX, NOW + 5);
+        assert_eq!(work.header().time(), NOW + 5);
+        let later = work.next_unit(16, NOW + 9);
+        assert_eq!((work.header().time(), later.nonce_start), (NOW + 9, 0));
+        assert_eq!(later.midstate, unit.midstate);
+
+        // A used-up header rolls the time ahead before the extranonce.
+        work.next_unit(u32::MAX, NOW + 9);
+        work.next_unit(1, NOW + 9);
+        assert_eq!((work.header().time(), work.extranonce()), (NOW + 10, 0));
+
+        // Past the roll-ahead limit the extranonce moves and time resets.
+        let mut work = TemplateWork::new(work.template().clone(), &payout(), b"test", NOW).unwrap();
+        let mut headers = 0;
+        while work.extranonce() == 0 {
+            headers += 1;
+            work.next_unit(u32::MAX, NOW);
+            work.next_unit(1, NOW);
+        }
+        assert_eq!(headers, crate::ntime::MAX_ROLL_AHEAD + 2);
+        assert_eq!(work.header().time(), NOW);
+    }
+
+    #[test]
+    fn assembled_block_carries_the_hashed_header() {
+        let mut t = template();
+        t.mutable = vec!["time".into()];
+        let mut work = TemplateWork::new(t, &payout(), b"test", NOW).unwrap().with_version_rolling(Some(VersionRolling::new(0x2000)));
+        // Versions roll inside each time: (v0, t0) (v1, t0) (v0, t1) (v1, t1).
+        for _ in 0..4 {
+            work.next_unit(u32::MAX, NOW);
+            work.next_unit(1, NOW);
+        }
+        let header = work.header();
+        assert_eq!((header.version(), header.time()), (0x2000_2000, NOW + 1));
+
+        let hex = assemble_block_hex(work.template(), work.coinbase(), header, 7);
+        let block: Block = bitcoin::consensus::deserialize(&hex::decode(hex).unwrap()).unwrap();
+        let mut expected = header.clone();
+        expected.set_nonce(7);
+        assert_eq!(block.header, expected.to_header());
+    }
+}
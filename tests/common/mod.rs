@@ -0,0 +1,43 @@
+//! The block the stand-in pools hand out and their check of a submitted
+//! header, shared by the V1 and V2 client tests.
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::hash_types::{BlockHash, TxMerkleNode};
+use bitcoin::hashes::{sha256d, Hash};
+use rust_metal_miner::backend::hash_meets_target;
+use rust_metal_miner::sha_helpers::difficulty_target;
+
+/// About one share per million hashes.
+pub const DIFFICULTY: f64 = 1.0 / (1u64 << 20) as f64;
+/// The coinbase before and after the extranonce.
+pub const COINBASE_PREFIX: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0d03a0bb0d";
+pub const COINBASE_SUFFIX: &str = "ffffffff0100f2052a010000001976a914000000000000000000000000000000000000000088ac00000000";
+pub const VERSION: u32 = 0x2000_0000;
+pub const NBITS: u32 = 0x1a44_b9f2;
+
+/// The coinbase's merkle path: two other transactions.
+pub fn merkle_path() -> Vec<sha256d::Hash> {
+    [b"tx1", b"tx2"].map(|tx| sha256d::Hash::hash(tx)).to_vec()
+}
+
+/// Merkle root with `extranonce` (pool and miner bytes) in the coinbase.
+pub fn merkle_root(extranonce: &[u8]) -> [u8; 32] {
+    let coinbase = [hex::decode(COINBASE_PREFIX).unwrap(), extranonce.to_vec(), hex::decode(COINBASE_SUFFIX).unwrap()].concat();
+    merkle_path()
+        .iter()
+        .fold(sha256d::Hash::hash(&coinbase), |acc, step| sha256d::Hash::hash(&[&acc[..], &step[..]].concat()))
+        .into_inner()
+}
+
+/// Whether the header hashes under the share target.
+pub fn meets_share_target(version: u32, prev_hash: [u8; 32], merkle_root: [u8; 32], time: u32, nonce: u32) -> bool {
+    let header = BlockHeader {
+        version: version as i32,
+        prev_blockhash: BlockHash::from_inner(prev_hash),
+        merkle_root: TxMerkleNode::from_inner(merkle_root),
+        time,
+        bits: NBITS,
+        nonce,
+    };
+    hash_meets_target(&header.block_hash().into_inner(), &difficulty_target(DIFFICULTY))
+}
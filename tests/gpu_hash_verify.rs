@@ -176,3 +176,20 @@ fn fused_sha256_fwht_cs() {
 
     println!("✅ GPU and CPU double SHA256 match perfectly!");
 }
+
+#[cfg(feature = "metal")]
+#[test]
+fn metal_readback_matches_the_emulator() {
+    use rust_metal_miner::backend::{metal::MetalBackend, HashBackend, WorkUnit};
+    use rust_metal_miner::constants::LANES;
+    use rust_metal_miner::emulator::{FusedKernelEmulator, KernelBuffers};
+
+    let mut backend = MetalBackend::new().expect("Metal backend");
+    let work = WorkUnit::from_header_bytes(&[0x5a; 80], 0, backend.batch_size(), [0xff; 32]);
+    backend.scan(&work).expect("dispatch");
+
+    let gpu = backend.read_back();
+    let mut emulated = KernelBuffers::new(LANES);
+    FusedKernelEmulator::new().dispatch(&backend.kernel_inputs(), &mut emulated);
+    assert_eq!(gpu.diff(&emulated), vec![]);
+}
@@ -0,0 +1,144 @@
+//! The find→submit path end to end at regtest difficulty: template from
+//! `MockBitcoind`, CPU scan, `submitblock`, and the submitted bytes checked
+//! as a consensus block, through the library and through `--once`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::{Address, Network, Script, Txid};
+use rust_metal_miner::coinbase::WITNESS_RESERVED_VALUE;
+use rust_metal_miner::backend::select_backend;
+use rust_metal_miner::mock_bitcoind::{regtest_template, MockBitcoind, REGTEST_BITS, REGTEST_SUBSIDY};
+use rust_metal_miner::payout::Payout;
+use rust_metal_miner::pipeline::mine_template;
+use rust_metal_miner::rpc::{Backoff, BitcoinRpc, SubmitOutcome};
+use serde_json::{json, Value};
+
+const GENESIS: &str = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+const NOW: u32 = 1_700_000_000;
+
+/// A quarter to a regtest address, the rest to an anyone-can-spend script.
+fn payout() -> (Address, Payout) {
+    let address = Address::p2wsh(&Script::from(vec![0x51]), Network::Regtest);
+    let payout = Payout::parse(&format!("{address}=25%,script:51")).unwrap();
+    payout.check_network("regtest").unwrap();
+    (address, payout)
+}
+
+/// A height 1 template carrying one segwit transaction, without the
+/// node's `default_witness_commitment` so the miner has to compute it.
+fn segwit_template() -> Value {
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0x11; 32]), 0),
+            script_sig: Script::new(),
+            sequence: 0xffff_fffd,
+            witness: vec![vec![1; 72], vec![2; 33]],
+        }],
+        output: vec![TxOut { value: 10_000, script_pubkey: Script::from(vec![0x51]) }],
+    };
+    let mut template = regtest_template(1, GENESIS, NOW);
+    template["transactions"] = json!([{
+        "data": hex::encode(serialize(&tx)),
+        "txid": tx.txid().to_string(),
+        "hash": tx.wtxid().to_string(),
+        "fee": 1_000,
+        "sigops": 1,
+        "weight": tx.get_weight(),
+        "depends": [],
+    }]);
+    template.as_object_mut().unwrap().remove("default_witness_commitment");
+    template
+}
+
+/// Mine height 1 on the CPU backend and return the block the node got.
+async fn mine_regtest_block(template: Value) -> (Block, SubmitOutcome) {
+    let node = MockBitcoind::start().await.unwrap();
+    node.set_template(template);
+    let rpc = BitcoinRpc::new(node.url(), "user", "pass")
+        .with_timeout(Duration::from_secs(5))
+        .with_backoff(Backoff::none());
+
+    let template = rpc.get_block_template().await.unwrap();
+    let mut backend = select_backend(Some("cpu")).unwrap();
+    let (_, payout) = payout();
+    let (found, outcome) = mine_template(&rpc, template, backend.as_mut(), &payout, b"regtest", NOW, 1 << 24).await.unwrap();
+    assert!(found.hashes <= 1 << 24);
+
+    let submitted = node.submitted();
+    assert_eq!(submitted.len(), 1);
+    let block = bitcoin::consensus::deserialize(&hex::decode(&submitted[0]).unwrap()).unwrap();
+    (block, outcome)
+}
+
+#[tokio::test]
+async fn regtest_block_goes_from_template_to_submitblock() {
+    let (block, outcome) = mine_regtest_block(regtest_template(1, GENESIS, NOW)).await;
+    assert!(matches!(outcome, SubmitOutcome::Accepted { .. }), "{outcome}");
+    assert_eq!(outcome.block_hash(), Some(block.block_hash()));
+
+    let header = &block.header;
+    assert_eq!(header.prev_blockhash.to_string(), GENESIS);
+    assert_eq!(header.bits, REGTEST_BITS);
+    assert!(header.time >= NOW - 600 && header.time <= NOW + 7200);
+    header.validate_pow(&header.target()).unwrap();
+    assert!(block.check_merkle_root());
+
+    let coinbase = &block.txdata[0];
+    assert!(coinbase.is_coin_base());
+    let (address, _) = payout();
+    // The witness commitment comes last and pays nothing.
+    let paid: Vec<_> = coinbase.output[..2].iter().map(|o| (o.script_pubkey.clone(), o.value)).collect();
+    assert_eq!(paid, [(address.script_pubkey(), REGTEST_SUBSIDY / 4), (Script::from(vec![0x51]), REGTEST_SUBSIDY - REGTEST_SUBSIDY / 4)]);
+}
+
+#[tokio::test]
+async fn regtest_block_witness_commitment_checks_out() {
+    let (block, _) = mine_regtest_block(regtest_template(1, GENESIS, NOW)).await;
+    assert!(block.check_witness_commitment());
+    assert_eq!(block.txdata[0].input[0].witness, [WITNESS_RESERVED_VALUE.to_vec()]);
+}
+
+#[tokio::test]
+async fn segwit_block_commits_to_its_transactions() {
+    let (block, outcome) = mine_regtest_block(segwit_template()).await;
+    assert!(matches!(outcome, SubmitOutcome::Accepted { .. }), "{outcome}");
+    assert_eq!(block.txdata.len(), 2);
+    assert!(!block.txdata[1].input[0].witness.is_empty());
+    assert!(block.check_merkle_root());
+    assert!(block.check_witness_commitment());
+    let commitment = block.txdata[0].output.last().unwrap();
+    assert_eq!((commitment.value, &commitment.script_pubkey[..6]), (0, &[0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed][..]));
+}
+
+#[tokio::test]
+async fn once_mines_and_submits_a_block_from_the_node() {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+    let node = MockBitcoind::start().await.unwrap();
+    node.set_template(regtest_template(1, GENESIS, now));
+
+    let run = tokio::process::Command::new(env!("CARGO_BIN_EXE_rust_metal_miner"))
+        .arg("--once")
+        .env("BITCOIN_RPC_URL", node.url())
+        .env("BITCOIN_DATADIR", env!("CARGO_TARGET_TMPDIR"))
+        .env("MINER_BACKEND", "cpu")
+        .env("MINER_PAYOUT", "script:51")
+        .env_remove("STRATUM_URL")
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(60), run).await.unwrap().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+
+    let submitted = node.submitted();
+    assert_eq!(submitted.len(), 1, "{stdout}");
+    let block: Block = bitcoin::consensus::deserialize(&hex::decode(&submitted[0]).unwrap()).unwrap();
+    assert!(stdout.contains(&block.block_hash().to_string()), "{stdout}");
+    block.header.validate_pow(&block.header.target()).unwrap();
+    assert!(block.check_merkle_root() && block.check_witness_commitment());
+    assert_eq!(block.txdata[0].output[0].script_pubkey, Script::from(vec![0x51]));
+}
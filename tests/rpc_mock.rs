@@ -0,0 +1,136 @@
+//! `BitcoinRpc`, the long-poll feed and block submission against
+//! `MockBitcoind`.
+
+use std::time::Duration;
+
+use bitcoin::blockdata::block::Block;
+use rust_metal_miner::backend::hash_meets_target;
+use rust_metal_miner::longpoll::TemplateFeed;
+use rust_metal_miner::mock_bitcoind::{regtest_template, MockBitcoind, MockReply};
+use rust_metal_miner::payout::Payout;
+use rust_metal_miner::rpc::{
+    propose_template, try_and_submit_nonce, Backoff, BitcoinRpc, RpcError, SubmitOutcome, TemplateHealth,
+};
+use rust_metal_miner::sha_helpers::target_from_bits;
+use rust_metal_miner::work::TemplateWork;
+use serde_json::json;
+
+const GENESIS: &str = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+const NOW: u32 = 1_700_000_000;
+
+fn fast_retries() -> Backoff {
+    Backoff { initial: Duration::from_millis(1), max: Duration::from_millis(5), retries: 3 }
+}
+
+async fn node() -> (MockBitcoind, BitcoinRpc) {
+    let node = MockBitcoind::start().await.unwrap();
+    node.set_template(regtest_template(1, GENESIS, NOW));
+    let rpc = BitcoinRpc::new(node.url(), "user", "pass").with_backoff(fast_retries());
+    (node, rpc)
+}
+
+#[tokio::test]
+async fn snapshot_is_one_batched_round_trip() {
+    let (node, rpc) = node().await;
+    let snapshot = rpc.snapshot().await.unwrap();
+    let template = snapshot.template.unwrap();
+    assert_eq!((template.height, template.bits), (1, 0x207f_ffff));
+    assert_eq!(snapshot.chain.chain, "regtest");
+    assert_eq!(snapshot.mining.blocks, 0);
+    assert_eq!(node.calls(), ["getblocktemplate", "getmininginfo", "getblockchaininfo"]);
+}
+
+#[tokio::test]
+async fn node_failures_come_back_typed() {
+    let (node, rpc) = node().await;
+
+    node.require_auth("user", "other");
+    assert!(matches!(rpc.get_block_template().await, Err(RpcError::Auth)));
+    node.require_auth("user", "pass");
+    assert!(rpc.get_block_template().await.is_ok());
+
+    let ibd = MockReply::Error { code: -10, message: "Bitcoin Core is in initial sync".into() };
+    for _ in 0..4 {
+        node.script("getblocktemplate", ibd.clone());
+    }
+    let err = rpc.get_block_template().await.unwrap_err();
+    assert!(matches!(err, RpcError::Rpc { code: -10, .. }), "{err}");
+
+    let slow = rpc.clone().with_timeout(Duration::from_millis(50)).with_backoff(Backoff::none());
+    node.script("getblocktemplate", MockReply::Delayed(Duration::from_millis(500), Box::new(MockReply::Result(json!(null)))));
+    assert!(matches!(slow.get_block_template().await, Err(RpcError::Timeout)));
+
+    let gone = MockBitcoind::start().await.unwrap();
+    let url = gone.url().to_string();
+    drop(gone);
+    let refused = BitcoinRpc::new(&url, "user", "pass").with_backoff(Backoff::none());
+    assert!(matches!(refused.get_block_template().await, Err(RpcError::Connection(_))));
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let (node, rpc) = node().await;
+    node.script("getblocktemplate", MockReply::Status(503));
+    node.script("getblocktemplate", MockReply::Error { code: -28, message: "Loading block index...".into() });
+    assert_eq!(rpc.get_block_template().await.unwrap().height, 1);
+    assert_eq!(node.calls().len(), 3);
+
+    node.script("getblocktemplate", MockReply::Error { code: -8, message: "Invalid mode".into() });
+    assert!(rpc.get_block_template().await.is_err());
+    assert_eq!(node.calls().len(), 4);
+}
+
+#[tokio::test]
+async fn blocks_and_proposals_are_sent_once() {
+    let (node, rpc) = node().await;
+    node.script("submitblock", MockReply::Status(503));
+    assert!(matches!(rpc.submit_block("00").await, Err(RpcError::Http(503, _))));
+    node.script("getblocktemplate", MockReply::Status(503));
+    assert!(matches!(rpc.propose_block("00").await, Err(RpcError::Http(503, _))));
+    assert_eq!(node.calls(), ["submitblock", "getblocktemplate"]);
+}
+
+#[tokio::test]
+async fn long_poll_publishes_the_next_template() {
+    let (node, rpc) = node().await;
+    let mut feed = TemplateFeed::spawn(rpc, Duration::from_secs(30));
+    let first = tokio::time::timeout(Duration::from_secs(5), feed.templates.wait_for(Option::is_some))
+        .await
+        .unwrap()
+        .unwrap()
+        .clone()
+        .unwrap();
+    assert_eq!(first.height, 1);
+
+    // Let the feed park its long poll, then move the chain on.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    node.set_template(regtest_template(2, &"11".repeat(32), NOW + 1));
+    tokio::time::timeout(Duration::from_secs(5), feed.templates.changed()).await.unwrap().unwrap();
+    assert_eq!(feed.templates.borrow().as_ref().unwrap().height, 2);
+    assert!(node.calls().len() <= 3, "{:?}", node.calls());
+}
+
+#[tokio::test]
+async fn found_nonce_reaches_submitblock() {
+    let (node, rpc) = node().await;
+    let template = rpc.get_block_template().await.unwrap();
+    let mut work = TemplateWork::new(template, &Payout::parse("script:51").unwrap(), b"mock", NOW).unwrap();
+    work.next_unit(1 << 16, NOW);
+    let header = work.header().clone();
+    let target = target_from_bits(header.bits());
+    let nonce = (0..).find(|&n| hash_meets_target(&header.hash(n), &target)).unwrap();
+
+    assert_eq!(propose_template(&rpc, work.template(), work.coinbase(), &header).await.unwrap(), TemplateHealth::Valid);
+    assert_eq!(node.proposals().len(), 1);
+
+    let outcome = try_and_submit_nonce(&rpc, work.template(), work.coinbase(), &header, nonce).await.unwrap();
+    assert!(matches!(outcome, SubmitOutcome::Accepted { .. }), "{outcome}");
+    let submitted = node.submitted();
+    let block: Block = bitcoin::consensus::deserialize(&hex::decode(&submitted[0]).unwrap()).unwrap();
+    assert_eq!(block.header.nonce, nonce);
+    assert_eq!(Some(block.block_hash()), outcome.block_hash());
+
+    node.set_submit_result(json!("duplicate"));
+    let outcome = try_and_submit_nonce(&rpc, work.template(), work.coinbase(), &header, nonce).await.unwrap();
+    assert!(matches!(outcome, SubmitOutcome::Duplicate { .. }), "{outcome}");
+}
@@ -0,0 +1,167 @@
+//! `StratumClient` and `StratumWork` against a stand-in pool on a local TCP
+//! port. The pool checks shares with its own header reconstruction.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{merkle_path, merkle_root, meets_share_target, COINBASE_PREFIX, COINBASE_SUFFIX, DIFFICULTY, NBITS, VERSION};
+use rust_metal_miner::backend::{hash_meets_target, select_backend};
+use rust_metal_miner::sha_helpers::difficulty_target;
+use rust_metal_miner::stratum::client::{ClientConfig, ClientEvent, StratumClient, StratumWork};
+use rust_metal_miner::version_rolling::{VersionRolling, BIP320_MASK};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+const EXTRANONCE1: &str = "f000000f";
+const PREV_SWAPPED: &str = "ab02cd817eb3e4a8b29ad444def299fee21793cd8b9e567e0000000000000000";
+
+fn notify(job_id: &str, clean: bool) -> Value {
+    let branch: Vec<_> = merkle_path().into_iter().map(hex::encode).collect();
+    let (version, nbits) = (format!("{VERSION:08x}"), format!("{NBITS:08x}"));
+    json!({
+        "id": null,
+        "method": "mining.notify",
+        "params": [job_id, PREV_SWAPPED, COINBASE_PREFIX, COINBASE_SUFFIX, branch, version, nbits, "4dd7f5c7", clean],
+    })
+}
+
+/// The pool's own check of `mining.submit` params.
+fn share_is_valid(params: &Value) -> bool {
+    let text = |i: usize| params[i].as_str().unwrap();
+    let word = |i: usize| u32::from_str_radix(text(i), 16).unwrap();
+    let root = merkle_root(&hex::decode(format!("{EXTRANONCE1}{}", text(2))).unwrap());
+    let mut prev = hex::decode(PREV_SWAPPED).unwrap();
+    prev.chunks_mut(4).for_each(|w| w.reverse());
+    let version = match params.get(5) {
+        Some(bits) => (VERSION & !BIP320_MASK) | (u32::from_str_radix(bits.as_str().unwrap(), 16).unwrap() & BIP320_MASK),
+        None => VERSION,
+    };
+    meets_share_target(version, prev.try_into().unwrap(), root, word(3), word(4))
+}
+
+/// Accept one miner, answer the handshake, send a job, and report each
+/// submit's verdict on `verdicts`.
+async fn stand_in_pool(listener: TcpListener, verdicts: mpsc::UnboundedSender<(Value, bool)>) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        let request: Value = serde_json::from_str(&line).unwrap();
+        let id = request["id"].clone();
+        let mut replies = Vec::new();
+        match request["method"].as_str().unwrap() {
+            "mining.configure" => replies.push(json!({
+                "id": id, "error": null,
+                "result": { "version-rolling": true, "version-rolling.mask": "1fffe000" },
+            })),
+            "mining.subscribe" => replies.push(json!({
+                "id": id, "error": null,
+                "result": [[["mining.set_difficulty", "d1"], ["mining.notify", "n1"]], EXTRANONCE1, 4],
+            })),
+            "mining.authorize" => {
+                // Notifications may arrive before the reply they follow.
+                replies.push(json!({ "id": null, "method": "mining.set_difficulty", "params": [DIFFICULTY] }));
+                replies.push(json!({ "id": id, "error": null, "result": true }));
+                replies.push(notify("j1", true));
+            }
+            "mining.submit" => {
+                let ok = share_is_valid(&request["params"]);
+                verdicts.send((request["params"].clone(), ok)).unwrap();
+                replies.push(if ok {
+                    json!({ "id": id, "error": null, "result": true })
+                } else {
+                    json!({ "id": id, "error": [23, "Low difficulty share", null], "result": false })
+                });
+                replies.push(notify("j2", true));
+                if !ok {
+                    // Narrow the mask below the miner's minimum, then widen it again.
+                    replies.push(json!({ "id": null, "method": "mining.set_version_mask", "params": ["00002000"] }));
+                    replies.push(json!({ "id": null, "method": "mining.set_version_mask", "params": ["1fffe000"] }));
+                }
+            }
+            other => panic!("unexpected {other}"),
+        }
+        for reply in replies {
+            write.write_all(format!("{reply}\n").as_bytes()).await.unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn mines_and_submits_shares_to_a_pool() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("stratum+tcp://{}", listener.local_addr().unwrap());
+    let (verdict_tx, mut verdicts) = mpsc::unbounded_channel();
+    tokio::spawn(stand_in_pool(listener, verdict_tx));
+
+    let config = ClientConfig {
+        address,
+        user: "worker.1".into(),
+        pass: "x".into(),
+        version_rolling: Some(VersionRolling::new(0x0000_6000)),
+    };
+    let mut client = tokio::time::timeout(Duration::from_secs(5), StratumClient::connect(&config)).await.unwrap().unwrap();
+    assert_eq!(client.extranonce1(), hex::decode(EXTRANONCE1).unwrap());
+    assert_eq!(client.extranonce2_size(), 4);
+    assert_eq!(client.version_rolling().map(|r| r.mask()), Some(0x0000_6000));
+
+    assert_eq!(client.next_event().await.unwrap(), ClientEvent::Difficulty(DIFFICULTY));
+    let ClientEvent::Job(job) = client.next_event().await.unwrap() else { panic!("expected a job") };
+    assert!(job.clean_jobs);
+
+    // Use up the first header so the share comes from a rolled version.
+    let mut work = StratumWork::for_client(job, &client, DIFFICULTY);
+    work.next_unit(u32::MAX);
+    work.next_unit(1);
+    let mut backend = select_backend(Some("cpu")).unwrap();
+    let nonce = loop {
+        let unit = work.next_unit(1 << 14);
+        let result = backend.scan(&unit).unwrap();
+        if let Some(&n) = result.nonces.iter().find(|&&n| unit.verify(n)) {
+            break n;
+        }
+    };
+    let share = work.share(nonce);
+    assert_eq!(share.version_bits, Some(0x2000));
+    let id = client.submit(&share).await.unwrap();
+
+    let (params, ok) = verdicts.recv().await.unwrap();
+    assert!(ok, "{params}");
+    assert_eq!(params[0], "worker.1");
+    assert_eq!(client.next_event().await.unwrap(), ClientEvent::ShareResult { id, accepted: true, reason: None });
+    let ClientEvent::Job(next) = client.next_event().await.unwrap() else { panic!("expected a job") };
+    assert_eq!(next.job_id, "j2");
+
+    // A nonce that misses the share target is refused with the pool's reason.
+    let work = StratumWork::for_client(next, &client, DIFFICULTY);
+    let header = work.header();
+    let target = difficulty_target(DIFFICULTY);
+    let bad = (0..).find(|&n| !hash_meets_target(&header.hash(n), &target)).unwrap();
+    let id = client.submit(&work.share(bad)).await.unwrap();
+    assert!(!verdicts.recv().await.unwrap().1);
+    let reason = Some("Low difficulty share".to_string());
+    assert_eq!(client.next_event().await.unwrap(), ClientEvent::ShareResult { id, accepted: false, reason });
+    let ClientEvent::Job(job) = client.next_event().await.unwrap() else { panic!("expected a job") };
+
+    // Each new mask is negotiated from the configured one, not the last agreed.
+    let mut work = StratumWork::for_client(job, &client, DIFFICULTY);
+    assert_eq!(client.next_event().await.unwrap(), ClientEvent::VersionMask(None));
+    assert_eq!(client.version_rolling(), None);
+    work.set_version_rolling(None);
+    work.next_unit(u32::MAX);
+    work.next_unit(1);
+    work.next_unit(1);
+    assert_eq!(work.header().version(), VERSION);
+    assert_eq!(work.share(0).version_bits, None);
+
+    let widened = Some(VersionRolling::new(0x0000_6000));
+    assert_eq!(client.next_event().await.unwrap(), ClientEvent::VersionMask(widened));
+    work.set_version_rolling(client.version_rolling());
+    work.next_unit(u32::MAX);
+    work.next_unit(1);
+    work.next_unit(1);
+    assert_eq!(work.share(0).version_bits, Some(0x2000));
+}
@@ -0,0 +1,258 @@
+//! `StratumServer` fed by `MockBitcoind`, with `StratumClient` as the miner:
+//! jobs, share checks, per-worker counts and the block it submits.
+
+use std::time::Duration;
+
+use bitcoin::blockdata::block::Block;
+use rust_metal_miner::backend::hash_meets_target;
+use rust_metal_miner::longpoll::TemplateFeed;
+use rust_metal_miner::mock_bitcoind::{regtest_template, MockBitcoind, REGTEST_BITS};
+use rust_metal_miner::payout::Payout;
+use rust_metal_miner::rpc::{BitcoinRpc, SubmitOutcome};
+use rust_metal_miner::sha_helpers::{difficulty_target, target_from_bits};
+use rust_metal_miner::stratum::client::{ClientConfig, ClientEvent, StratumClient, StratumWork};
+use rust_metal_miner::stratum::server::{ServerConfig, StratumServer, WorkerStats};
+use rust_metal_miner::stratum::vardiff::VardiffConfig;
+use rust_metal_miner::stratum::Job;
+use rust_metal_miner::version_rolling::{VersionRolling, BIP320_MASK};
+
+const GENESIS: &str = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+const NOW: u32 = 1_700_000_000;
+
+fn payout() -> Payout {
+    Payout::parse("script:51").unwrap()
+}
+
+async fn connect(server: &StratumServer, user: &str) -> StratumClient {
+    let config = ClientConfig {
+        address: server.local_addr().to_string(),
+        user: user.into(),
+        pass: "x".into(),
+        version_rolling: Some(VersionRolling::bip320()),
+    };
+    tokio::time::timeout(Duration::from_secs(5), StratumClient::connect(&config)).await.unwrap().unwrap()
+}
+
+async fn event(client: &mut StratumClient) -> ClientEvent {
+    tokio::time::timeout(Duration::from_secs(5), client.next_event()).await.unwrap().unwrap()
+}
+
+async fn job(client: &mut StratumClient) -> Job {
+    loop {
+        if let ClientEvent::Job(job) = event(client).await {
+            return job;
+        }
+    }
+}
+
+/// Submit `share` and return the pool's reject reason, if any.
+async fn verdict(client: &mut StratumClient, share: &rust_metal_miner::stratum::client::Share) -> Option<String> {
+    let id = client.submit(share).await.unwrap();
+    match event(client).await {
+        ClientEvent::ShareResult { id: got, accepted, reason } => {
+            assert_eq!(got, id);
+            assert_eq!(accepted, reason.is_none());
+            reason
+        }
+        other => panic!("expected a share result, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn serves_jobs_checks_shares_and_submits_blocks() {
+    let node = MockBitcoind::start().await.unwrap();
+    let mut template = regtest_template(1, GENESIS, NOW);
+    template["maxtime"] = (NOW + 600).into();
+    node.set_template(template);
+    let rpc = BitcoinRpc::new(node.url(), "user", "pass");
+    let feed = TemplateFeed::spawn(rpc.clone(), Duration::from_secs(30));
+    // Difficulty 1 is far above regtest's, so only block solutions are shares.
+    let config = ServerConfig { listen: "127.0.0.1:0".into(), message: b"solo".to_vec(), difficulty: 1.0, vardiff: None, payout: payout() };
+    let server = StratumServer::start(config, rpc, feed.templates.clone()).await.unwrap();
+
+    let mut client = connect(&server, "rig.1").await;
+    let other = connect(&server, "rig.2").await;
+    assert_ne!(client.extranonce1(), other.extranonce1());
+    assert_eq!(client.extranonce2_size(), 4);
+    assert_eq!(client.version_rolling().map(|r| r.mask()), Some(BIP320_MASK));
+    assert_eq!(event(&mut client).await, ClientEvent::Difficulty(1.0));
+    let first = job(&mut client).await;
+    assert!(first.clean_jobs);
+    assert_eq!(first.bits, REGTEST_BITS);
+
+    let mut work = StratumWork::for_client(first.clone(), &client, 1.0);
+    let unit = work.next_unit(1 << 16);
+    let header = work.header().clone();
+    let block_target = target_from_bits(REGTEST_BITS);
+    let solves = |n: &u32| hash_meets_target(&header.hash(*n), &block_target);
+    let miss = (unit.nonce_start..).find(|n| !solves(n)).unwrap();
+    let hit = (unit.nonce_start..).find(solves).unwrap();
+
+    assert_eq!(verdict(&mut client, &work.share(miss)).await.as_deref(), Some("Low difficulty share"));
+    assert_eq!(verdict(&mut client, &work.share(hit)).await, None);
+    assert_eq!(verdict(&mut client, &work.share(hit)).await.as_deref(), Some("Duplicate share"));
+    let mut stranger = work.share(hit);
+    stranger.version_bits = Some(0x8000_0000);
+    assert!(verdict(&mut client, &stranger).await.unwrap().starts_with("Invalid share"));
+    // The template's mintime and maxtime bound the share's ntime.
+    for time in [NOW - 601, NOW + 601] {
+        let off_window = rust_metal_miner::stratum::client::Share { time, ..work.share(hit) };
+        assert_eq!(verdict(&mut client, &off_window).await.as_deref(), Some("Invalid share: ntime out of range"));
+    }
+
+    let outcomes = server.blocks();
+    assert!(matches!(outcomes[..], [SubmitOutcome::Accepted { .. }]), "{outcomes:?}");
+    let submitted = node.submitted();
+    let block: Block = bitcoin::consensus::deserialize(&hex::decode(&submitted[0]).unwrap()).unwrap();
+    assert_eq!(Some(block.block_hash()), outcomes[0].block_hash());
+    assert_eq!(block.header.prev_blockhash.to_string(), GENESIS);
+    assert!(block.check_merkle_root());
+    let script_sig = block.txdata[0].input[0].script_sig.as_bytes();
+    // height | extranonce1 | extranonce2 | tag
+    let extranonce = [client.extranonce1(), &work.share(hit).extranonce2[..]].concat();
+    assert!(script_sig.ends_with(&[&[8][..], &extranonce, &[4], b"solo"].concat()));
+
+    // A new block makes the old job stale.
+    node.set_template(regtest_template(2, &block.block_hash().to_string(), NOW + 1));
+    let second = job(&mut client).await;
+    assert!(second.clean_jobs);
+    assert_ne!(second.prev_hash, first.prev_hash);
+    let mut late = work.share(hit);
+    late.nonce = hit.wrapping_add(1);
+    assert_eq!(verdict(&mut client, &late).await.as_deref(), Some("Job not found (stale)"));
+
+    let mut unknown = StratumWork::for_client(second, &client, 1.0).share(0);
+    unknown.job_id = "nope".into();
+    assert_eq!(verdict(&mut client, &unknown).await.as_deref(), Some("Job not found (stale)"));
+
+    let stats = server.workers();
+    let expected = WorkerStats { accepted: 1, work: 1.0, stale: 2, duplicate: 1, low_difficulty: 1, invalid: 3, blocks: 1 };
+    assert_eq!(stats["rig.1"], expected);
+    assert!(!stats.contains_key("rig.2"));
+}
+
+async fn server_with_vardiff(node: &MockBitcoind, vardiff: VardiffConfig) -> (StratumServer, TemplateFeed) {
+    node.set_template(regtest_template(1, GENESIS, NOW));
+    let rpc = BitcoinRpc::new(node.url(), "user", "pass");
+    let feed = TemplateFeed::spawn(rpc.clone(), Duration::from_secs(30));
+    let config = ServerConfig { listen: "127.0.0.1:0".into(), message: b"solo".to_vec(), difficulty: 1.0, vardiff: Some(vardiff), payout: payout() };
+    let server = StratumServer::start(config, rpc, feed.templates.clone()).await.unwrap();
+    (server, feed)
+}
+
+#[tokio::test]
+async fn vardiff_raises_for_fast_workers_and_lowers_for_idle_ones() {
+    let node = MockBitcoind::start().await.unwrap();
+    let slow = VardiffConfig { retarget_time: Duration::from_secs(3600), min_difficulty: 1.0 / 1024.0, ..VardiffConfig::default() };
+    let (server, _feed) = server_with_vardiff(&node, slow).await;
+    let mut client = connect(&server, "rig.1").await;
+    assert_eq!(event(&mut client).await, ClientEvent::Difficulty(1.0));
+    let mut work = StratumWork::for_client(job(&mut client).await, &client, 1.0);
+    work.next_unit(1 << 16);
+    let header = work.header().clone();
+    let target = target_from_bits(REGTEST_BITS);
+
+    // Twenty quick shares end the window with at most a 4x step up.
+    let hits: Vec<u32> = (0..).filter(|n| hash_meets_target(&header.hash(*n), &target)).take(20).collect();
+    for (i, &nonce) in hits.iter().enumerate() {
+        let id = client.submit(&work.share(nonce)).await.unwrap();
+        assert_eq!(event(&mut client).await, ClientEvent::ShareResult { id, accepted: true, reason: None });
+        if i == 19 {
+            assert_eq!(event(&mut client).await, ClientEvent::Difficulty(4.0));
+        }
+    }
+    assert_eq!(server.workers()["rig.1"].accepted, 20);
+    drop(server);
+
+    let idle = VardiffConfig { retarget_time: Duration::from_millis(100), min_difficulty: 0.5, ..slow };
+    let (server, _feed) = server_with_vardiff(&node, idle).await;
+    let mut client = connect(&server, "rig.2").await;
+    assert_eq!(event(&mut client).await, ClientEvent::Difficulty(1.0));
+    job(&mut client).await;
+    assert_eq!(event(&mut client).await, ClientEvent::Difficulty(0.5));
+}
+
+#[tokio::test]
+async fn retarget_resends_the_job_and_credits_each_id_at_its_own_difficulty() {
+    // Mainnet-hard bits, so no share here is also a block.
+    let node = MockBitcoind::start().await.unwrap();
+    let mut template = regtest_template(1, GENESIS, NOW);
+    template["bits"] = "1d00ffff".into();
+    template["target"] = hex::encode(target_from_bits(0x1d00_ffff)).into();
+    node.set_template(template);
+    let rpc = BitcoinRpc::new(node.url(), "user", "pass");
+    let feed = TemplateFeed::spawn(rpc.clone(), Duration::from_secs(30));
+    let start = 1.0 / (1u64 << 24) as f64;
+    let vardiff = VardiffConfig { retarget_time: Duration::from_secs(3600), min_difficulty: start / 16.0, ..VardiffConfig::default() };
+    let config = ServerConfig { listen: "127.0.0.1:0".into(), message: b"solo".to_vec(), difficulty: start, vardiff: Some(vardiff), payout: payout() };
+    let server = StratumServer::start(config, rpc, feed.templates.clone()).await.unwrap();
+
+    // JSON may round the difficulty by an ulp on the way.
+    let difficulty = |event| match event {
+        ClientEvent::Difficulty(d) => d,
+        other => panic!("expected a difficulty, got {other:?}"),
+    };
+    let mut client = connect(&server, "rig.1").await;
+    assert!((difficulty(event(&mut client).await) / start - 1.0).abs() < 1e-12);
+    let first = job(&mut client).await;
+    let mut work = StratumWork::for_client(first.clone(), &client, start);
+    work.next_unit(1 << 16);
+    let header = work.header().clone();
+    let raised = start * 4.0;
+    let meets = |n: u32, d: f64| hash_meets_target(&header.hash(n), &difficulty_target(d));
+
+    // Twenty shares raise the difficulty mid-job; the job comes again
+    // under a new id, without clean_jobs.
+    let easy: Vec<u32> = (0..).filter(|&n| meets(n, start) && !meets(n, raised)).take(22).collect();
+    for &nonce in &easy[..20] {
+        assert_eq!(verdict(&mut client, &work.share(nonce)).await, None);
+    }
+    assert!((difficulty(event(&mut client).await) / raised - 1.0).abs() < 1e-12);
+    let resent = job(&mut client).await;
+    assert_ne!(resent.job_id, first.job_id);
+    assert!(!resent.clean_jobs);
+    assert_eq!((&resent.coinb1, &resent.coinb2), (&first.coinb1, &first.coinb2));
+
+    // The old id is still worth the old difficulty.
+    assert_eq!(verdict(&mut client, &work.share(easy[20])).await, None);
+
+    // The new id wants the new one, and shares its duplicates with the old.
+    let mut resent_work = StratumWork::for_client(resent, &client, raised);
+    resent_work.next_unit(1 << 16);
+    assert_eq!(verdict(&mut client, &resent_work.share(easy[21])).await.as_deref(), Some("Low difficulty share"));
+    let hard = (0..).find(|&n| meets(n, raised)).unwrap();
+    assert_eq!(verdict(&mut client, &resent_work.share(hard)).await, None);
+    assert_eq!(verdict(&mut client, &work.share(hard)).await.as_deref(), Some("Duplicate share"));
+
+    let stats = &server.workers()["rig.1"];
+    assert_eq!((stats.accepted, stats.duplicate, stats.low_difficulty), (22, 1, 1));
+    assert_eq!(stats.work, 21.0 * start + raised);
+}
+
+#[tokio::test]
+async fn skips_templates_the_payout_does_not_fit() {
+    let node = MockBitcoind::start().await.unwrap();
+    node.set_template(regtest_template(1, GENESIS, NOW));
+    let rpc = BitcoinRpc::new(node.url(), "user", "pass");
+    let feed = TemplateFeed::spawn(rpc.clone(), Duration::from_secs(30));
+    let payout = Payout::parse("script:51=6000000000sat,script:52").unwrap();
+    let config = ServerConfig { listen: "127.0.0.1:0".into(), message: b"solo".to_vec(), difficulty: 1.0, vardiff: None, payout };
+    let server = StratumServer::start(config, rpc, feed.templates.clone()).await.unwrap();
+
+    let errors = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let errors = server.template_errors();
+            if !errors.is_empty() {
+                return errors;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(errors[0].contains("exceed the coinbase value"), "{errors:?}");
+
+    let mut client = connect(&server, "rig.1").await;
+    assert_eq!(event(&mut client).await, ClientEvent::Difficulty(1.0));
+    assert!(tokio::time::timeout(Duration::from_millis(200), client.next_event()).await.is_err());
+}
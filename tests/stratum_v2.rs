@@ -0,0 +1,276 @@
+//! `Sv2Client` and `Sv2Work` against a stand-in SV2 pool on a local TCP
+//! port, speaking through the library's Noise responder. The pool checks
+//! shares with its own header reconstruction.
+
+mod common;
+
+use std::time::Duration;
+
+use bitcoin::hashes::Hash;
+use common::{merkle_path, merkle_root, meets_share_target, COINBASE_PREFIX, COINBASE_SUFFIX, DIFFICULTY, NBITS, VERSION};
+use rust_metal_miner::backend::{hash_meets_target, select_backend};
+use rust_metal_miner::sha_helpers::difficulty_target;
+use rust_metal_miner::stratum_v2::client::{ChannelKind, Sv2Client, Sv2Config, Sv2Event, Sv2Work};
+use rust_metal_miner::stratum_v2::messages::Message;
+use rust_metal_miner::stratum_v2::noise::{Authority, Certificate, NoiseStream, StaticKey};
+use rust_metal_miner::stratum_v2::{target_to_u256, Sv2Error};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+const PREFIX: [u8; 3] = [0xe0, 0x00, 0x0e];
+const NEW_PREFIX: [u8; 3] = [0xe1, 0x11, 0x1e];
+const PREV_HASH: [u8; 32] = [0x5a; 32];
+const NTIME: u32 = 1_700_000_000;
+const MERKLE_ROOT: [u8; 32] = [0x3c; 32];
+const JOB_ID: u32 = 1;
+
+/// The pool's own check of a submit against the job it sent and the
+/// channel's extranonce prefix.
+fn share_is_valid(share: &Message, prefix: &[u8]) -> bool {
+    let (root, job_id, nonce, ntime, version) = match share {
+        Message::SubmitSharesStandard { job_id, nonce, ntime, version, .. } => (MERKLE_ROOT, job_id, nonce, ntime, version),
+        Message::SubmitSharesExtended { job_id, nonce, ntime, version, extranonce, .. } => {
+            (merkle_root(&[prefix, extranonce].concat()), job_id, nonce, ntime, version)
+        }
+        other => panic!("unexpected {other:?}"),
+    };
+    *job_id == JOB_ID && *ntime >= NTIME && meets_share_target(*version, PREV_HASH, root, *ntime, *nonce)
+}
+
+/// What the pool does besides answering shares.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Shares,
+    /// Move the channel to `NEW_PREFIX` after the first accepted share.
+    NewPrefix,
+    /// Follow the job with a `SetNewPrevHash` for a job never sent.
+    UnknownPrevHash,
+}
+
+/// Accept one miner, answer the setup and the channel, send a future job
+/// and its `SetNewPrevHash`, and report each submit's verdict on `verdicts`.
+async fn stand_in_pool(
+    listener: TcpListener,
+    key: StaticKey,
+    certificate: Certificate,
+    script: Script,
+    verdicts: mpsc::UnboundedSender<bool>,
+) {
+    let (tcp, _) = listener.accept().await.unwrap();
+    let Ok(mut stream) = NoiseStream::respond(tcp, &key, &certificate).await else { return };
+    let mut prefix = Vec::new();
+    loop {
+        let frame = match stream.read_frame().await {
+            Ok(frame) => frame,
+            Err(_) => return,
+        };
+        let mut replies = Vec::new();
+        match Message::from_frame(&frame).unwrap() {
+            Message::SetupConnection { protocol: 0, min_version: 2, .. } => {
+                replies.push(Message::SetupConnectionSuccess { used_version: 2, flags: 0 });
+            }
+            Message::OpenStandardMiningChannel { request_id, .. } => {
+                replies.push(Message::OpenStandardMiningChannelSuccess {
+                    request_id,
+                    channel_id: 4,
+                    target: target_to_u256(&difficulty_target(DIFFICULTY)),
+                    extranonce_prefix: Vec::new(),
+                    group_channel_id: 0,
+                });
+                replies.push(Message::NewMiningJob {
+                    channel_id: 4,
+                    job_id: JOB_ID,
+                    min_ntime: None,
+                    version: VERSION,
+                    merkle_root: MERKLE_ROOT,
+                });
+                replies.push(Message::SetNewPrevHash { channel_id: 4, job_id: JOB_ID, prev_hash: PREV_HASH, min_ntime: NTIME, nbits: NBITS });
+                if script == Script::UnknownPrevHash {
+                    replies.push(Message::SetNewPrevHash { channel_id: 4, job_id: 9, prev_hash: [0x6b; 32], min_ntime: NTIME, nbits: NBITS });
+                }
+            }
+            Message::OpenExtendedMiningChannel { request_id, min_extranonce_size, .. } => {
+                prefix = PREFIX.to_vec();
+                replies.push(Message::OpenExtendedMiningChannelSuccess {
+                    request_id,
+                    channel_id: 5,
+                    target: target_to_u256(&difficulty_target(DIFFICULTY)),
+                    extranonce_size: min_extranonce_size,
+                    extranonce_prefix: prefix.clone(),
+                });
+                replies.push(Message::NewExtendedMiningJob {
+                    channel_id: 5,
+                    job_id: JOB_ID,
+                    min_ntime: None,
+                    version: VERSION,
+                    version_rolling_allowed: true,
+                    merkle_path: merkle_path().iter().map(|step| step.into_inner()).collect(),
+                    coinbase_tx_prefix: hex::decode(COINBASE_PREFIX).unwrap(),
+                    coinbase_tx_suffix: hex::decode(COINBASE_SUFFIX).unwrap(),
+                });
+                replies.push(Message::SetNewPrevHash { channel_id: 5, job_id: JOB_ID, prev_hash: PREV_HASH, min_ntime: NTIME, nbits: NBITS });
+            }
+            share @ (Message::SubmitSharesStandard { .. } | Message::SubmitSharesExtended { .. }) => {
+                let (channel_id, sequence_number) = match share {
+                    Message::SubmitSharesStandard { channel_id, sequence_number, .. }
+                    | Message::SubmitSharesExtended { channel_id, sequence_number, .. } => (channel_id, sequence_number),
+                    _ => unreachable!(),
+                };
+                let ok = share_is_valid(&share, &prefix);
+                verdicts.send(ok).unwrap();
+                replies.push(if ok {
+                    Message::SubmitSharesSuccess {
+                        channel_id,
+                        last_sequence_number: sequence_number,
+                        new_submits_accepted_count: 1,
+                        new_shares_sum: 1,
+                    }
+                } else {
+                    Message::SubmitSharesError { channel_id, sequence_number, error_code: "difficulty-too-low".into() }
+                });
+                if ok && script == Script::NewPrefix && prefix != NEW_PREFIX {
+                    prefix = NEW_PREFIX.to_vec();
+                    replies.push(Message::SetExtranoncePrefix { channel_id, extranonce_prefix: prefix.clone() });
+                }
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        for reply in replies {
+            stream.write_frame(&reply.to_frame()).await.unwrap();
+        }
+    }
+}
+
+async fn start_pool(authority: &StaticKey, script: Script) -> (String, mpsc::UnboundedReceiver<bool>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("stratum2+tcp://{}", listener.local_addr().unwrap());
+    let key = StaticKey::generate();
+    let certificate = Certificate::sign(authority, &key.public(), 0, u32::MAX);
+    let (verdict_tx, verdicts) = mpsc::unbounded_channel();
+    tokio::spawn(stand_in_pool(listener, key, certificate, script, verdict_tx));
+    (address, verdicts)
+}
+
+/// Scan `work` for a nonce under its target.
+fn find_share(work: &mut Sv2Work) -> u32 {
+    let mut backend = select_backend(Some("cpu")).unwrap();
+    loop {
+        let unit = work.next_unit(1 << 14);
+        let result = backend.scan(&unit).unwrap();
+        if let Some(&n) = result.nonces.iter().find(|&&n| unit.verify(n)) {
+            return n;
+        }
+    }
+}
+
+/// Mine the first job on `client`'s channel, submit one good and one bad
+/// share, and check the pool's verdicts come back.
+async fn mine_one_job(mut client: Sv2Client, mut verdicts: mpsc::UnboundedReceiver<bool>) {
+    let Sv2Event::Job { job, clean } = client.next_event().await.unwrap() else { panic!("expected a job") };
+    assert!(clean);
+    assert_eq!((job.job_id, job.prev_hash, job.bits, job.min_ntime), (JOB_ID, PREV_HASH, NBITS, NTIME));
+
+    // Use up the first header so the share comes from a rolled version.
+    let mut work = Sv2Work::new(job.clone(), client.channel());
+    work.next_unit(u32::MAX);
+    work.next_unit(1);
+    let nonce = find_share(&mut work);
+    let share = work.share(nonce);
+    assert_ne!(share.version, VERSION);
+    let sequence = client.submit(&share).await.unwrap();
+    assert!(verdicts.recv().await.unwrap());
+    let accepted = Sv2Event::SharesAccepted { last_sequence_number: sequence, count: 1 };
+    assert_eq!(client.next_event().await.unwrap(), accepted);
+
+    // A nonce that misses the share target is refused with the pool's code.
+    let work = Sv2Work::new(job, client.channel());
+    let bad = (0..).find(|&n| !hash_meets_target(&work.header().hash(n), work.target())).unwrap();
+    let sequence = client.submit(&work.share(bad)).await.unwrap();
+    assert!(!verdicts.recv().await.unwrap());
+    let rejected = Sv2Event::ShareRejected { sequence_number: sequence, reason: "difficulty-too-low".into() };
+    assert_eq!(client.next_event().await.unwrap(), rejected);
+}
+
+fn config(address: String, authority: Authority, channel: ChannelKind) -> Sv2Config {
+    Sv2Config { address, authority, user_identity: "worker.1".into(), nominal_hash_rate: 1e6, channel }
+}
+
+#[tokio::test]
+async fn mines_on_an_extended_channel() {
+    let authority = StaticKey::generate();
+    let (address, verdicts) = start_pool(&authority, Script::Shares).await;
+    let config = config(address, Authority::Key(authority.public()), ChannelKind::Extended { min_extranonce_size: 4 });
+    let client = tokio::time::timeout(Duration::from_secs(5), Sv2Client::connect(&config)).await.unwrap().unwrap();
+    let channel = client.channel();
+    assert_eq!((channel.id, &channel.extranonce_prefix[..], channel.extranonce_size), (5, &PREFIX[..], 4));
+    assert_eq!(channel.target, difficulty_target(DIFFICULTY));
+    mine_one_job(client, verdicts).await;
+}
+
+#[tokio::test]
+async fn mines_on_a_standard_channel() {
+    let authority = StaticKey::generate();
+    let (address, verdicts) = start_pool(&authority, Script::Shares).await;
+    let config = config(address, Authority::Key(authority.public()), ChannelKind::Standard);
+    let client = tokio::time::timeout(Duration::from_secs(5), Sv2Client::connect(&config)).await.unwrap().unwrap();
+    assert_eq!((client.channel().id, client.channel().extranonce_size), (4, 0));
+    mine_one_job(client, verdicts).await;
+}
+
+#[tokio::test]
+async fn refuses_a_pool_signed_by_another_authority() {
+    let (address, _verdicts) = start_pool(&StaticKey::generate(), Script::Shares).await;
+    let config = config(address, Authority::Key(StaticKey::generate().public()), ChannelKind::Standard);
+    match Sv2Client::connect(&config).await {
+        Err(Sv2Error::Handshake(reason)) => assert!(reason.contains("authority"), "{reason}"),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("connected to an unauthenticated pool"),
+    }
+}
+
+#[tokio::test]
+async fn insecure_mode_accepts_any_pool_key() {
+    let (address, verdicts) = start_pool(&StaticKey::generate(), Script::Shares).await;
+    let config = config(address, Authority::Insecure, ChannelKind::Standard);
+    let client = tokio::time::timeout(Duration::from_secs(5), Sv2Client::connect(&config)).await.unwrap().unwrap();
+    mine_one_job(client, verdicts).await;
+}
+
+#[tokio::test]
+async fn rebuilds_work_for_a_new_extranonce_prefix() {
+    let authority = StaticKey::generate();
+    let (address, mut verdicts) = start_pool(&authority, Script::NewPrefix).await;
+    let config = config(address, Authority::Key(authority.public()), ChannelKind::Extended { min_extranonce_size: 4 });
+    let mut client = tokio::time::timeout(Duration::from_secs(5), Sv2Client::connect(&config)).await.unwrap().unwrap();
+    let Sv2Event::Job { job, .. } = client.next_event().await.unwrap() else { panic!("expected a job") };
+    let mut work = Sv2Work::new(job, client.channel());
+    let nonce = find_share(&mut work);
+    let sequence = client.submit(&work.share(nonce)).await.unwrap();
+    assert!(verdicts.recv().await.unwrap());
+    let accepted = Sv2Event::SharesAccepted { last_sequence_number: sequence, count: 1 };
+    assert_eq!(client.next_event().await.unwrap(), accepted);
+
+    // The prefix changes mid-job; the same job is rebuilt under it.
+    assert_eq!(client.next_event().await.unwrap(), Sv2Event::ExtranoncePrefix(NEW_PREFIX.to_vec()));
+    assert_eq!(client.channel().extranonce_prefix, NEW_PREFIX);
+    let stale = work;
+    let mut work = Sv2Work::new(stale.job().clone(), client.channel());
+    assert_ne!(work.header().bytes()[36..68], stale.header().bytes()[36..68]);
+    let nonce = find_share(&mut work);
+    client.submit(&work.share(nonce)).await.unwrap();
+    assert!(verdicts.recv().await.unwrap());
+}
+
+#[tokio::test]
+async fn refuses_a_prev_hash_for_an_unknown_job() {
+    let authority = StaticKey::generate();
+    let (address, _verdicts) = start_pool(&authority, Script::UnknownPrevHash).await;
+    let config = config(address, Authority::Key(authority.public()), ChannelKind::Standard);
+    let mut client = tokio::time::timeout(Duration::from_secs(5), Sv2Client::connect(&config)).await.unwrap().unwrap();
+    let Sv2Event::Job { job, .. } = client.next_event().await.unwrap() else { panic!("expected a job") };
+    assert_eq!(job.job_id, JOB_ID);
+    match client.next_event().await {
+        Err(Sv2Error::Protocol(reason)) => assert!(reason.contains("job 9"), "{reason}"),
+        other => panic!("expected a protocol error, got {other:?}"),
+    }
+}